@@ -1,11 +1,15 @@
 use std::env;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::Duration;
 
-use bitbankutil_rs::bitbank_bot::{BitbankBotBuilder, BitbankEvent, BotContext, BotStrategy};
+use bitbankutil_rs::bitbank_bot::{
+    BitbankBotBuilder, BitbankEvent, BotContext, BotStrategy, DepthResync,
+};
 use bitbankutil_rs::bitbank_private::BitbankPrivateApiClient;
+use bitbankutil_rs::bitbank_public::BitbankPublicApiClient;
 use bitbankutil_rs::depth::Depth;
 use bitbankutil_rs::market_event::MarketDepthSnapshot;
 use bitbankutil_rs::order_domain::{DesiredLimitOrder, OpenOrder, OrderSide, OrderType};
+use bitbankutil_rs::pair_spec::{PairRegistry, PairSpec};
 use crypto_botters::generic_api_client::websocket::WebSocketConfig;
 use log::LevelFilter;
 use rust_decimal::prelude::*;
@@ -13,15 +17,16 @@ use rust_decimal::prelude::*;
 struct MyBot {
     bot_config: MyBotConfig,
     depth: MarketDepthSnapshot,
-    last_updated: u128,
+    last_updated: i64,
     last_bestbid: Decimal,
     last_bestask: Decimal,
 }
 
 struct MyBotConfig {
     pair: String,
+    pair_spec: PairSpec,
     tick_size: Decimal,
-    refresh_cycle: u128,
+    refresh_cycle: i64,
     lot: Decimal,
     max_lot: Decimal,
     bb_api_client: BitbankPrivateApiClient,
@@ -31,16 +36,16 @@ impl MyBot {
     fn new(
         bitbank_key: String,
         bitbank_secret: String,
-        pair: String,
-        tick_size: Decimal,
-        refresh_cycle: u128,
+        pair_spec: PairSpec,
+        refresh_cycle: i64,
         lot: Decimal,
         max_lot: Decimal,
     ) -> MyBot {
         MyBot {
             bot_config: MyBotConfig {
-                pair,
-                tick_size,
+                pair: pair_spec.pair.clone(),
+                tick_size: pair_spec.tick_size(),
+                pair_spec,
                 refresh_cycle,
                 lot,
                 max_lot,
@@ -53,12 +58,9 @@ impl MyBot {
         }
     }
 
-    async fn update_orders(&mut self) {
+    // `now`は`BotContext::now_millis`で取得した現在時刻（ミリ秒）。
+    async fn update_orders(&mut self, now: i64) {
         let now_inst = std::time::Instant::now();
-        let now: u128 = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis();
 
         assert!(self.last_updated <= now);
 
@@ -75,10 +77,7 @@ impl MyBot {
 
             // APIの呼び出し頻度が高くなりすぎないように、ここで `self.last_updated` を更新する
             // ここでは可変参照が必要である。
-            self.last_updated = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_millis();
+            self.last_updated = now;
 
             let bb_client2 = self.bot_config.bb_api_client.clone();
             let pair2 = self.bot_config.pair.clone();
@@ -124,7 +123,7 @@ impl MyBot {
             let jpy_asset = current_asset
                 .assets
                 .iter()
-                .find(|asset| asset.asset == "jpy".to_owned())
+                .find(|asset| asset.asset == "jpy")
                 .unwrap();
 
             let mut btc_locked_jpy_amount: Decimal = Decimal::zero();
@@ -158,8 +157,8 @@ impl MyBot {
                 jpy_amount
             );
 
-            let best_ask_price = self.depth.best_ask().unwrap().0.clone();
-            let best_bid_price = self.depth.best_bid().unwrap().0.clone();
+            let best_ask_price = *self.depth.best_ask().unwrap().0;
+            let best_bid_price = *self.depth.best_bid().unwrap().0;
 
             let has_bestask_order = active_orders_info.clone().orders.iter().any(|ord| {
                 let ord = OpenOrder::try_from(ord)
@@ -227,12 +226,12 @@ impl MyBot {
             log::info!("evaluated asset: {}", btc_amount * sell_price + jpy_amount);
             {
                 let bb_client = self.bot_config.bb_api_client.clone();
-                bitbankutil_rs::order_manager::place_wanna_orders_concurrent(
+                bitbankutil_rs::order_manager::place_wanna_orders_concurrent_with_pair_spec(
                     wanna_place_orders,
                     active_orders_info.orders,
                     btc_free_amount,
                     jpy_free_amount,
-                    self.bot_config.pair.clone(),
+                    &self.bot_config.pair_spec,
                     bb_client,
                 )
                 .await;
@@ -247,18 +246,51 @@ impl MyBot {
 
 impl BotStrategy for MyBot {
     type Event = BitbankEvent;
-    async fn handle_event(&mut self, event: Self::Event, _ctx: &BotContext<Self::Event>) {
+    async fn on_stop(&mut self, _ctx: &BotContext<Self::Event>) {
+        // 停止後に約定しないよう、このペアの注文をすべてキャンセルする
+        let active_orders = match self
+            .bot_config
+            .bb_api_client
+            .get_active_orders(Some(&self.bot_config.pair), None, None, None, None, None)
+            .await
+        {
+            Ok(active_orders) => active_orders,
+            Err(err) => {
+                log::error!("failed to get active orders before shutdown: {:?}", err);
+                return;
+            }
+        };
+
+        let open_orders = active_orders
+            .orders
+            .iter()
+            .filter_map(|order| OpenOrder::try_from(order).ok())
+            .collect::<Vec<_>>();
+        log::info!("cancelling {} orders before shutdown", open_orders.len());
+
+        if let Err(err) = bitbankutil_rs::order_manager::cancel_all_orders(
+            open_orders,
+            self.bot_config.bb_api_client.clone(),
+        )
+        .await
+        {
+            log::error!("failed to cancel orders before shutdown: {:?}", err);
+        }
+    }
+
+    async fn handle_event(&mut self, event: Self::Event, ctx: &BotContext<Self::Event>) {
+        let now = ctx.now_millis();
         match event {
             BitbankEvent::Transactions { transactions, .. } => {
                 log::debug!("transaction updated: {:?}", transactions);
-                self.update_orders().await;
+                self.update_orders(now).await;
             }
             BitbankEvent::DepthUpdated { depth, .. } => {
                 log::debug!("depth updated");
 
                 if depth.is_complete() {
-                    let bestask = depth.best_ask().unwrap().0.clone();
-                    let bestbid = depth.best_bid().unwrap().0.clone();
+                    let bestask = *depth.best_ask().unwrap().0;
+                    let bestbid = *depth.best_bid().unwrap().0;
 
                     if bestask != self.last_bestask || bestbid != self.last_bestbid {
                         log::debug!(
@@ -273,14 +305,36 @@ impl BotStrategy for MyBot {
 
                 self.depth = depth;
                 if self.depth.is_complete() {
-                    self.update_orders().await;
+                    self.update_orders(now).await;
                 }
             }
             BitbankEvent::CircuitBreakInfo { info, .. } => {
                 log::debug!("circuit break info updated: {:?}", info);
             }
-            // Tickerイベントはこの戦略では意図的に無視される。
-            BitbankEvent::Ticker { .. } => {}
+            BitbankEvent::DepthIntegrityError { pair, error } => {
+                log::warn!("depth for {} is inconsistent: {}", pair, error);
+                if error.requires_resnapshot() {
+                    // 新しい全体板が届くまで発注しない
+                    self.depth = std::mem::replace(&mut self.depth, MarketDepthSnapshot::empty())
+                        .into_stale();
+                }
+            }
+            BitbankEvent::Disconnected { pair, last_depth } => {
+                // 板を取り直すまでは古い板を保持するが、is_complete()がfalseなので発注はしない
+                log::warn!("websocket for {} disconnected", pair);
+                self.depth = last_depth.unwrap_or_else(MarketDepthSnapshot::empty);
+            }
+            BitbankEvent::Connected { pair } | BitbankEvent::Reconnected { pair } => {
+                log::info!("websocket for {} connected", pair);
+            }
+            BitbankEvent::FeedError { pair, error } => {
+                log::warn!("ignoring undecodable message for {}: {}", pair, error);
+            }
+            // Ticker、板の差分（スナップショットのみ購読）、足（購読していない）、Private Streamのイベントはこの戦略では意図的に無視される。
+            BitbankEvent::Ticker { .. }
+            | BitbankEvent::DepthDelta { .. }
+            | BitbankEvent::Candle { .. }
+            | BitbankEvent::Private(_) => {}
         }
     }
 }
@@ -295,9 +349,9 @@ async fn main() {
 
     let args: Vec<String> = env::args().collect();
 
-    if args.len() != 6_usize {
-        log::error!("there should be five arguments: pair(like `btc_jpy`), tick size(like: `1`), refresh_cycle(ms)(like `5000`), lot(like `0.0001`), max_lot(like `0.0005`).");
-        log::error!("example: cargo run --example best_mm xrp_jpy 0.001 300 1 5");
+    if args.len() != 5_usize {
+        log::error!("there should be four arguments: pair(like `btc_jpy`), refresh_cycle(ms)(like `5000`), lot(like `0.0001`), max_lot(like `0.0005`).");
+        log::error!("example: cargo run --example best_mm xrp_jpy 300 1 5");
         std::process::exit(-1);
     }
 
@@ -312,29 +366,74 @@ async fn main() {
     let wsc = wsc; // 不変にする
 
     let pair = args[1].clone();
-    let tick_size: Decimal = args[2].parse().unwrap();
-    let refresh_cycle: u128 = args[3].parse().unwrap();
-    let lot: Decimal = args[4].parse().unwrap();
-    let max_lot: Decimal = args[5].parse().unwrap();
+    let refresh_cycle: i64 = args[2].parse().unwrap();
+    let lot: Decimal = args[3].parse().unwrap();
+    let max_lot: Decimal = args[4].parse().unwrap();
 
     assert!(lot <= max_lot);
 
+    let pair_spec = load_pair_spec(&bitbank_key, &bitbank_secret, &pair).await;
+    log::info!(
+        "tick size: {}, min amount: {}",
+        pair_spec.tick_size(),
+        pair_spec.min_amount
+    );
+    assert!(lot >= pair_spec.min_amount);
+
     let bot = MyBot::new(
         bitbank_key,
         bitbank_secret,
-        pair.clone(),
-        tick_size,
+        pair_spec,
         refresh_cycle,
         lot,
         max_lot,
     );
 
-    let _runtime = BitbankBotBuilder::new(bot)
+    let runtime = BitbankBotBuilder::new(bot)
         .add_pair(pair)
         .websocket_config(wsc)
+        .depth_resync(DepthResync::Rest(BitbankPublicApiClient::new()))
         .spawn();
 
-    loop {
-        tokio::time::sleep(Duration::from_secs(3600)).await;
-    }
+    // Ctrl+CやSIGTERMで止めると、on_stopで注文をキャンセルしてから終了する
+    runtime
+        .run_until_shutdown_signal()
+        .await
+        .expect("bot actor panicked");
+}
+
+// 環境変数`BITBANK_PAIRS_CACHE`にパスが指定されていれば、そのキャッシュを使う。
+// キャッシュがない場合は`/spot/pairs`から取得し、パスが指定されていれば書き出す。
+async fn load_pair_spec(bitbank_key: &str, bitbank_secret: &str, pair: &str) -> PairSpec {
+    let cache_path = env::var("BITBANK_PAIRS_CACHE").ok();
+
+    let cached_registry = cache_path
+        .as_ref()
+        .and_then(|path| PairRegistry::load_json_file(path).ok());
+
+    let registry = match cached_registry {
+        Some(registry) => registry,
+        None => {
+            let bb_client = BitbankPrivateApiClient::new(
+                bitbank_key.to_owned(),
+                bitbank_secret.to_owned(),
+                None,
+            );
+            let pairs = bb_client
+                .get_pairs()
+                .await
+                .expect("failed to fetch /spot/pairs");
+            let registry = PairRegistry::try_from(&pairs).expect("invalid /spot/pairs response");
+
+            if let Some(path) = &cache_path {
+                if let Err(err) = registry.save_json_file(path) {
+                    log::warn!("failed to write pair cache to {}: {:?}", path, err);
+                }
+            }
+
+            registry
+        }
+    };
+
+    registry.spec(pair).expect("unknown pair").clone()
 }
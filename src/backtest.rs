@@ -0,0 +1,802 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::convert::Infallible;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::warn;
+use tokio::select;
+use tokio::sync::mpsc;
+
+use crate::bitbank_bot::{
+    BitbankInboundMessage, BitbankMarketEventConverter, BotContext, BotStrategy,
+};
+use crate::clock::{Clock, EventTimestamp, SimulatedClock};
+use crate::market_event::MarketEvent;
+use crate::order_domain::{BalanceSnapshot, Execution};
+use crate::paper_execution::{PaperEvent, PaperExecutionEngine, PaperOrderExecutor};
+use crate::private_event::PrivateEvent;
+use crate::replay::{MergedRecordings, ReplayReadError};
+
+/// 戦略が[`BotContext::emit`]で自分に送ったイベントを受け取るチャネルの大きさ。
+/// 受け取ったものは戦略を動かしながら内部のキューに移すので、1回の処理でこれより多く送っても止まらない。
+const BACKTEST_EVENT_BUFFER: usize = 1024;
+
+/// バックテスト中にペアごとの注文に使う[`PaperOrderExecutor`]。
+#[derive(Debug, Clone)]
+pub struct BacktestExecutors {
+    executors: BTreeMap<String, PaperOrderExecutor>,
+}
+
+impl BacktestExecutors {
+    // `pair`のペーパー口座がない場合は`None`。
+    pub fn get(&self, pair: &str) -> Option<PaperOrderExecutor> {
+        self.executors.get(pair).cloned()
+    }
+
+    pub fn pairs(&self) -> impl Iterator<Item = &str> {
+        self.executors.keys().map(String::as_str)
+    }
+}
+
+/// ある時点のペーパー口座の残高。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BalancePoint {
+    /// UNIXエポックからのミリ秒。
+    pub timestamp: i64,
+    pub pair: String,
+    pub balances: Vec<BalanceSnapshot>,
+}
+
+/// [`Backtest::run`]の結果。
+#[derive(Debug, Clone, Default)]
+pub struct BacktestResult {
+    /// 約定。起きた順。
+    pub fills: Vec<Execution>,
+    /// 開始時点と、注文・キャンセル・約定で残高が変わるたびのペアごとの残高。
+    pub balances: Vec<BalancePoint>,
+    /// ペーパー取引で起きたことすべて。起きた順。
+    pub paper_events: Vec<PaperEvent>,
+    /// 流した市場イベントの数。
+    pub market_events: u64,
+}
+
+impl BacktestResult {
+    // `pair`の最後の残高。
+    pub fn final_balances(&self, pair: &str) -> Option<&[BalanceSnapshot]> {
+        self.balances
+            .iter()
+            .rev()
+            .find(|point| point.pair == pair)
+            .map(|point| point.balances.as_slice())
+    }
+}
+
+/// ウォークフォワードの学習期間と検証期間の長さ。
+/// 学習期間の直後を検証期間とし、`step`ずつずらしながら繰り返す。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalkForwardConfig {
+    pub train: Duration,
+    pub test: Duration,
+    /// 次の期間までずらす幅。`test`と同じなら検証期間は重ならずに隙間なく並ぶ。
+    pub step: Duration,
+}
+
+impl WalkForwardConfig {
+    // `step`は`test`と同じ。
+    pub fn new(train: Duration, test: Duration) -> Self {
+        Self {
+            train,
+            test,
+            step: test,
+        }
+    }
+
+    pub fn step(mut self, step: Duration) -> Self {
+        self.step = step;
+        self
+    }
+
+    // `start`から`end`（含まない）までの期間を返す。最後の検証期間は`end`で切れることがある。
+    pub fn windows(&self, start: i64, end: i64) -> Vec<WalkForwardWindow> {
+        let train = self.train.as_millis() as i64;
+        let test = self.test.as_millis() as i64;
+        let step = (self.step.as_millis() as i64).max(1);
+
+        let mut windows = Vec::new();
+        let mut train_start = start;
+        while train_start + train < end {
+            let test_start = train_start + train;
+            windows.push(WalkForwardWindow {
+                train_start,
+                test_start,
+                test_end: (test_start + test).min(end),
+            });
+            train_start += step;
+        }
+        windows
+    }
+}
+
+/// 1つの学習・検証期間（UNIXエポックからのミリ秒）。学習期間は`train_start..test_start`、
+/// 検証期間は`test_start..test_end`で、どちらも終わりを含まない。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalkForwardWindow {
+    pub train_start: i64,
+    pub test_start: i64,
+    pub test_end: i64,
+}
+
+/// [`Backtest::run_walk_forward`]の、1つの期間の結果。
+#[derive(Debug, Clone)]
+pub struct WalkForwardResult {
+    pub window: WalkForwardWindow,
+    /// 検証期間のバックテストの結果。
+    pub result: BacktestResult,
+}
+
+/// 市場イベントの列を、ペーパー取引のエンジンと戦略に順番どおりに流すバックテスト。
+/// 各イベントはまずエンジンに渡し、それまでに出ていた注文を約定させてから戦略に渡すので、
+/// イベントを見て出した注文がそのイベント自身で約定することはない。
+/// 時計は[`Clock::Simulated`]で、[`BotContext::now_millis`]・タイマー・[`PaperEvent`]の時刻はイベントの時刻に従う。
+/// ペーパー口座はペアごとに別で、残高はペアの間で共有しない。
+pub struct Backtest<E> {
+    engines: BTreeMap<String, PaperExecutionEngine>,
+    private_event: Option<fn(PrivateEvent) -> E>,
+}
+
+impl<E> Backtest<E>
+where
+    E: From<MarketEvent> + Send + 'static,
+{
+    // `engines`はペアごとのペーパー口座の初期状態。実行のたびにcloneして使うので、何度実行しても同じ状態から始まる。
+    pub fn new(engines: impl IntoIterator<Item = PaperExecutionEngine>) -> Self {
+        Self {
+            engines: engines
+                .into_iter()
+                .map(|engine| (engine.config().pair.clone(), engine))
+                .collect(),
+            private_event: None,
+        }
+    }
+
+    // `events`をすべて流す。`make_strategy`にはペーパー口座の注文に使う[`BacktestExecutors`]が渡される。
+    // 最後のイベントの後に[`BotStrategy::on_stop`]を呼び、それより後に発火するタイマーは捨てる。
+    // イベントは1つずつ取り出して流すので、記録ファイルなどから読みながら渡せば全体をメモリに載せずに済む。
+    pub async fn run<S>(
+        &self,
+        events: impl IntoIterator<Item = MarketEvent>,
+        make_strategy: impl FnOnce(&BacktestExecutors) -> S,
+    ) -> BacktestResult
+    where
+        S: BotStrategy<Event = E>,
+    {
+        let events = events.into_iter().map(Ok::<_, Infallible>);
+        match self.try_run(events, make_strategy).await {
+            Ok(result) => result,
+            Err(never) => match never {},
+        }
+    }
+
+    // [`Self::run`]と同じだが、[`market_events_from_recordings`]のように読み出しに失敗しうるイベントを流す。
+    // エラーを受け取った時点で止めて、そのエラーを返す。
+    pub async fn try_run<S, Error>(
+        &self,
+        events: impl IntoIterator<Item = Result<MarketEvent, Error>>,
+        make_strategy: impl FnOnce(&BacktestExecutors) -> S,
+    ) -> Result<BacktestResult, Error>
+    where
+        S: BotStrategy<Event = E>,
+    {
+        let mut events = events.into_iter();
+        let clock = SimulatedClock::new();
+        // 開始前に出した注文や登録したタイマーも、最初に時刻を持つイベントの時刻から数える。
+        // そのため、それより前にある時刻を持たないイベント（接続など）だけを先に読んでおく
+        let mut leading = Vec::new();
+        for event in events.by_ref() {
+            let event = event?;
+            let timestamp = event.timestamp_millis();
+            leading.push(Ok(event));
+            if let Some(first) = timestamp {
+                clock.advance_to(first);
+                break;
+            }
+        }
+
+        let engines: BTreeMap<String, Arc<Mutex<PaperExecutionEngine>>> = self
+            .engines
+            .iter()
+            .map(|(pair, engine)| {
+                let mut engine = engine.clone().with_clock(Clock::Simulated(clock.clone()));
+                engine.drain_events();
+                (pair.clone(), Arc::new(Mutex::new(engine)))
+            })
+            .collect();
+        let executors = BacktestExecutors {
+            executors: engines
+                .iter()
+                .map(|(pair, engine)| {
+                    (
+                        pair.clone(),
+                        PaperOrderExecutor::from_shared(engine.clone()),
+                    )
+                })
+                .collect(),
+        };
+
+        let (event_tx, event_rx) = mpsc::channel(BACKTEST_EVENT_BUFFER);
+        let mut run = BacktestRun {
+            strategy: make_strategy(&executors),
+            context: BotContext::with_clock(event_tx, Clock::Simulated(clock.clone())),
+            event_rx,
+            emitted: VecDeque::new(),
+            engines,
+            result: BacktestResult::default(),
+        };
+        run.record_balances();
+
+        collect_emitted(
+            run.strategy.on_start(&run.context),
+            &mut run.event_rx,
+            &mut run.emitted,
+        )
+        .await;
+        run.handle_emitted().await;
+        run.collect_paper_events();
+
+        for event in leading.into_iter().chain(events) {
+            let event = event?;
+            if let Some(timestamp) = event.timestamp_millis() {
+                while let Some(timer_event) = run.context.pop_due_timer(&clock, timestamp) {
+                    run.handle(timer_event).await;
+                }
+                clock.advance_to(timestamp);
+            }
+
+            let mut fills = Vec::new();
+            for engine in run.engines.values() {
+                fills.extend(
+                    engine
+                        .lock()
+                        .expect("paper execution engine mutex poisoned")
+                        .apply_market_event(&event)
+                        .iter()
+                        .filter_map(PaperEvent::execution),
+                );
+            }
+            if let (Some(private_event), false) = (self.private_event, fills.is_empty()) {
+                run.handle(private_event(PrivateEvent::Executed(fills)))
+                    .await;
+            }
+
+            run.result.market_events += 1;
+            run.handle(E::from(event)).await;
+        }
+
+        collect_emitted(
+            run.strategy.on_stop(&run.context),
+            &mut run.event_rx,
+            &mut run.emitted,
+        )
+        .await;
+        run.handle_emitted().await;
+        run.collect_paper_events();
+        Ok(run.result)
+    }
+
+    // `events`を[`WalkForwardConfig::windows`]の期間ごとに分け、検証期間ごとに新しい戦略とペーパー口座で[`Self::run`]する。
+    // `make_strategy`には期間と学習期間のイベントが渡されるので、学習期間のデータでパラメータを決めてから戦略を作れる。
+    // 時刻を持たないイベントは直前のイベントと同じ時刻として扱う。
+    pub async fn run_walk_forward<S>(
+        &self,
+        events: &[MarketEvent],
+        config: WalkForwardConfig,
+        mut make_strategy: impl FnMut(&WalkForwardWindow, &[MarketEvent], &BacktestExecutors) -> S,
+    ) -> Vec<WalkForwardResult>
+    where
+        S: BotStrategy<Event = E>,
+    {
+        let times = event_times(events);
+        let (Some(start), Some(end)) = (times.iter().min(), times.iter().max()) else {
+            return Vec::new();
+        };
+
+        let mut results = Vec::new();
+        for window in config.windows(*start, end + 1) {
+            let events_between = |from: i64, to: i64| -> Vec<MarketEvent> {
+                events
+                    .iter()
+                    .zip(&times)
+                    .filter(|(_, time)| (from..to).contains(*time))
+                    .map(|(event, _)| event.clone())
+                    .collect()
+            };
+            let train = events_between(window.train_start, window.test_start);
+            let test = events_between(window.test_start, window.test_end);
+
+            let result = self
+                .run(test, |executors| make_strategy(&window, &train, executors))
+                .await;
+            results.push(WalkForwardResult { window, result });
+        }
+        results
+    }
+}
+
+impl<E> Backtest<E>
+where
+    E: From<MarketEvent> + From<PrivateEvent> + Send + 'static,
+{
+    // 約定を[`PrivateEvent::Executed`]として戦略にも渡す。ライブのPrivate Streamと同じく約定に反応する戦略を検証できる。
+    // 約定は、約定させた市場イベントより先に届く。
+    pub fn deliver_fills(mut self) -> Self {
+        self.private_event = Some(E::from);
+        self
+    }
+}
+
+/// 1回のバックテストの実行中の状態。
+struct BacktestRun<S: BotStrategy> {
+    strategy: S,
+    context: BotContext<S::Event>,
+    event_rx: mpsc::Receiver<S::Event>,
+    /// 戦略が送ったイベントのうち、まだ処理していないもの。送られた順。
+    emitted: VecDeque<S::Event>,
+    engines: BTreeMap<String, Arc<Mutex<PaperExecutionEngine>>>,
+    result: BacktestResult,
+}
+
+impl<S: BotStrategy> BacktestRun<S> {
+    // イベントを戦略に渡し、戦略が送ったイベントとペーパー取引の結果も処理する。
+    async fn handle(&mut self, event: S::Event) {
+        self.handle_one(event).await;
+        self.handle_emitted().await;
+        self.collect_paper_events();
+    }
+
+    // 戦略が[`BotContext::emit`]で送ったイベントを、送られた順に処理する。
+    async fn handle_emitted(&mut self) {
+        while let Some(event) = self.emitted.pop_front() {
+            self.handle_one(event).await;
+        }
+    }
+
+    async fn handle_one(&mut self, event: S::Event) {
+        collect_emitted(
+            self.strategy.handle_event(event, &self.context),
+            &mut self.event_rx,
+            &mut self.emitted,
+        )
+        .await;
+    }
+
+    // エンジンに溜まったペーパー取引のイベントを結果に移し、残高が変わったペアの残高を記録する。
+    fn collect_paper_events(&mut self) {
+        for (pair, engine) in &self.engines {
+            let mut engine = engine
+                .lock()
+                .expect("paper execution engine mutex poisoned");
+            let events = engine.drain_events();
+            if events.is_empty() {
+                continue;
+            }
+
+            self.result
+                .fills
+                .extend(events.iter().filter_map(PaperEvent::execution));
+            self.result.paper_events.extend(events);
+            self.result.balances.push(BalancePoint {
+                timestamp: engine.clock().now_millis(),
+                pair: pair.clone(),
+                balances: engine.balances(),
+            });
+        }
+    }
+
+    fn record_balances(&mut self) {
+        for (pair, engine) in &self.engines {
+            let engine = engine
+                .lock()
+                .expect("paper execution engine mutex poisoned");
+            self.result.balances.push(BalancePoint {
+                timestamp: engine.clock().now_millis(),
+                pair: pair.clone(),
+                balances: engine.balances(),
+            });
+        }
+    }
+}
+
+// `future`を動かしながら、戦略が送ったイベントをチャネルから`emitted`に移す。
+// 同じタスクでチャネルを読むので、戦略がチャネルの大きさを超えて送っても待ち続けることはない。
+async fn collect_emitted<E>(
+    future: impl Future<Output = ()>,
+    event_rx: &mut mpsc::Receiver<E>,
+    emitted: &mut VecDeque<E>,
+) {
+    tokio::pin!(future);
+    loop {
+        select! {
+            biased;
+            () = &mut future => break,
+            Some(event) = event_rx.recv() => emitted.push_back(event),
+        }
+    }
+    while let Ok(event) = event_rx.try_recv() {
+        emitted.push_back(event);
+    }
+}
+
+// 各イベントの時刻。時刻を持たないイベントは直前のイベントの時刻、先頭にある場合は最初の時刻にする。
+fn event_times(events: &[MarketEvent]) -> Vec<i64> {
+    let mut last = events
+        .iter()
+        .find_map(EventTimestamp::timestamp_millis)
+        .unwrap_or_default();
+    events
+        .iter()
+        .map(|event| {
+            if let Some(timestamp) = event.timestamp_millis() {
+                last = timestamp;
+            }
+            last
+        })
+        .collect()
+}
+
+// 記録ファイルのメッセージを読みながら、ペアごとにライブと同じ変換をして市場イベントにする。
+// 全体を読み込まずに1つずつ返すので、[`Backtest::try_run`]にそのまま渡せる。
+// 読み出せない行はエラーとして返す。市場イベントに変換できないメッセージはログに出して読み飛ばす。
+pub fn market_events_from_recordings(
+    recordings: MergedRecordings,
+) -> impl Iterator<Item = Result<MarketEvent, ReplayReadError>> {
+    let mut converters: HashMap<String, BitbankMarketEventConverter> = HashMap::new();
+    recordings.flat_map(move |res| {
+        let message = match res {
+            Ok(message) => message,
+            Err(err) => return vec![Err(err)],
+        };
+        let pair = message.pair.clone();
+        let converter = converters
+            .entry(pair.clone())
+            .or_insert_with(|| BitbankMarketEventConverter::new(pair.clone()));
+        let inbound = message
+            .into_inbound()
+            .unwrap_or_else(BitbankInboundMessage::DecodeError);
+        match converter.convert(inbound) {
+            Ok(converted) => converted.into_iter().map(Ok).collect(),
+            Err(err) => {
+                warn!(
+                    "skipping recorded message for {} that is not a valid market event: {:?}",
+                    pair, err
+                );
+                Vec::new()
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market_event::{MarketTicker, MarketTrade};
+    use crate::order_domain::{DesiredLimitOrder, OrderId, OrderSide};
+    use crate::order_executor::OrderExecutor;
+    use crate::paper_execution::{PaperExecutionConfig, PaperFeeSchedule};
+    use rust_decimal::Decimal;
+
+    enum TestEvent {
+        Market(MarketEvent),
+        Private(PrivateEvent),
+    }
+
+    impl From<MarketEvent> for TestEvent {
+        fn from(event: MarketEvent) -> Self {
+            Self::Market(event)
+        }
+    }
+
+    impl From<PrivateEvent> for TestEvent {
+        fn from(event: PrivateEvent) -> Self {
+            Self::Private(event)
+        }
+    }
+
+    fn engine(pair: &str, base_free: Decimal, jpy_free: Decimal) -> PaperExecutionEngine {
+        let base = pair.strip_suffix("_jpy").unwrap();
+        PaperExecutionEngine::new(
+            PaperExecutionConfig {
+                fee_schedule: PaperFeeSchedule::new(Decimal::ZERO, Decimal::ZERO),
+                ..PaperExecutionConfig::bitbank_spot_default(pair).unwrap()
+            },
+            vec![
+                BalanceSnapshot {
+                    asset: base.to_owned(),
+                    free_amount: base_free,
+                    locked_amount: Decimal::ZERO,
+                    onhand_amount: base_free,
+                },
+                BalanceSnapshot {
+                    asset: "jpy".to_owned(),
+                    free_amount: jpy_free,
+                    locked_amount: Decimal::ZERO,
+                    onhand_amount: jpy_free,
+                },
+            ],
+        )
+        .unwrap()
+    }
+
+    fn trade_event(pair: &str, side: OrderSide, price: i64, executed_at: i64) -> MarketEvent {
+        MarketEvent::Transactions {
+            pair: pair.to_owned(),
+            transactions: vec![MarketTrade {
+                amount: Decimal::ONE,
+                executed_at,
+                price: Decimal::new(price, 0),
+                side,
+                transaction_id: executed_at,
+            }],
+        }
+    }
+
+    fn ticker_event(timestamp: i64) -> MarketEvent {
+        MarketEvent::Ticker {
+            pair: "btc_jpy".to_owned(),
+            ticker: MarketTicker {
+                sell: None,
+                buy: None,
+                high: Decimal::ONE,
+                low: Decimal::ONE,
+                open: Decimal::ONE,
+                last: Decimal::ONE,
+                vol: Decimal::ONE,
+                timestamp,
+            },
+        }
+    }
+
+    /// 各ペアで最初の約定を見たら、その価格で1つ買い注文を出す戦略。
+    struct BuyAtFirstTrade {
+        executors: BacktestExecutors,
+        placed: Vec<String>,
+        fills_seen: Arc<Mutex<Vec<(i64, Execution)>>>,
+    }
+
+    impl BotStrategy for BuyAtFirstTrade {
+        type Event = TestEvent;
+
+        async fn handle_event(&mut self, event: Self::Event, ctx: &BotContext<Self::Event>) {
+            match event {
+                TestEvent::Market(MarketEvent::Transactions { pair, transactions }) => {
+                    if self.placed.contains(&pair) {
+                        return;
+                    }
+                    let executor = self.executors.get(&pair).unwrap();
+                    let order = DesiredLimitOrder::limit(
+                        pair.clone(),
+                        OrderSide::Buy,
+                        Decimal::ONE,
+                        transactions[0].price,
+                    );
+                    executor.place_order(order.into()).await.unwrap();
+                    self.placed.push(pair);
+                }
+                TestEvent::Private(PrivateEvent::Executed(executions)) => {
+                    let mut fills_seen = self.fills_seen.lock().unwrap();
+                    for execution in executions {
+                        fills_seen.push((ctx.now_millis(), execution));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn fills_only_on_later_trades_and_tracks_balances_per_pair() {
+        let backtest = Backtest::new([
+            engine("btc_jpy", Decimal::ZERO, Decimal::new(1_000, 0)),
+            engine("eth_jpy", Decimal::ZERO, Decimal::new(1_000, 0)),
+        ])
+        .deliver_fills();
+        let fills_seen = Arc::new(Mutex::new(Vec::new()));
+
+        let result = backtest
+            .run(
+                [
+                    // 注文のきっかけになった約定では約定しない
+                    trade_event("btc_jpy", OrderSide::Sell, 100, 1_000),
+                    trade_event("eth_jpy", OrderSide::Sell, 200, 1_500),
+                    trade_event("btc_jpy", OrderSide::Sell, 100, 2_000),
+                ],
+                |executors| BuyAtFirstTrade {
+                    executors: executors.clone(),
+                    placed: Vec::new(),
+                    fills_seen: fills_seen.clone(),
+                },
+            )
+            .await;
+
+        assert_eq!(result.market_events, 3);
+        assert_eq!(result.fills.len(), 1);
+        let fill = &result.fills[0];
+        assert_eq!(
+            (fill.pair.as_str(), fill.order_id, fill.executed_at),
+            ("btc_jpy", OrderId(1), 2_000)
+        );
+        assert_eq!(*fills_seen.lock().unwrap(), vec![(2_000, fill.clone())]);
+
+        assert!(matches!(
+            result.paper_events.as_slice(),
+            [
+                PaperEvent::OrderAccepted {
+                    timestamp: 1_000,
+                    ..
+                },
+                PaperEvent::OrderAccepted {
+                    timestamp: 1_500,
+                    ..
+                },
+                PaperEvent::OrderFilled {
+                    timestamp: 2_000,
+                    ..
+                },
+            ]
+        ));
+        assert_eq!(
+            result
+                .balances
+                .iter()
+                .map(|point| (point.timestamp, point.pair.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (1_000, "btc_jpy"),
+                (1_000, "eth_jpy"),
+                (1_000, "btc_jpy"),
+                (1_500, "eth_jpy"),
+                (2_000, "btc_jpy"),
+            ]
+        );
+
+        let btc = result.final_balances("btc_jpy").unwrap();
+        assert_eq!(btc[0].asset, "btc");
+        assert_eq!(btc[0].onhand_amount, Decimal::ONE);
+        assert_eq!(btc[1].onhand_amount, Decimal::new(900, 0));
+        let eth = result.final_balances("eth_jpy").unwrap();
+        assert_eq!(eth[1].locked_amount, Decimal::new(200, 0));
+    }
+
+    /// ティッカーを受け取ると、チャネルの大きさより多くのイベントを1回の処理で自分に送る戦略。
+    struct EmittingStrategy {
+        received: Arc<Mutex<usize>>,
+    }
+
+    impl BotStrategy for EmittingStrategy {
+        type Event = MarketEvent;
+
+        async fn handle_event(&mut self, event: Self::Event, ctx: &BotContext<Self::Event>) {
+            if let MarketEvent::Ticker { pair, .. } = event {
+                for _ in 0..BACKTEST_EVENT_BUFFER * 2 {
+                    ctx.emit(MarketEvent::Connected { pair: pair.clone() })
+                        .await
+                        .unwrap();
+                }
+            } else {
+                *self.received.lock().unwrap() += 1;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn strategy_can_emit_more_events_than_the_channel_holds() {
+        let backtest = Backtest::new([engine("btc_jpy", Decimal::ZERO, Decimal::ZERO)]);
+        let received = Arc::new(Mutex::new(0));
+
+        let events = [
+            MarketEvent::Connected {
+                pair: "btc_jpy".to_owned(),
+            },
+            ticker_event(1_000),
+        ];
+        let run = backtest.run(events, |_| EmittingStrategy {
+            received: received.clone(),
+        });
+        let result = tokio::time::timeout(Duration::from_secs(10), run)
+            .await
+            .expect("backtest deadlocked on emitted events");
+
+        assert_eq!(result.market_events, 2);
+        assert_eq!(*received.lock().unwrap(), 1 + BACKTEST_EVENT_BUFFER * 2);
+        // 時刻を持たない先頭のイベントを飛ばして、最初の時刻から始まる
+        assert_eq!(result.balances[0].timestamp, 1_000);
+    }
+
+    /// 受け取った市場イベントの数を数え、結果を共有の`Vec`に残す戦略。
+    struct CountingStrategy {
+        window: WalkForwardWindow,
+        train_events: usize,
+        test_events: usize,
+        counts: Arc<Mutex<Vec<(WalkForwardWindow, usize, usize)>>>,
+    }
+
+    impl BotStrategy for CountingStrategy {
+        type Event = MarketEvent;
+
+        async fn handle_event(&mut self, _event: Self::Event, _ctx: &BotContext<Self::Event>) {
+            self.test_events += 1;
+        }
+
+        async fn on_stop(&mut self, _ctx: &BotContext<Self::Event>) {
+            self.counts
+                .lock()
+                .unwrap()
+                .push((self.window, self.train_events, self.test_events));
+        }
+    }
+
+    #[test]
+    fn walk_forward_windows_roll_by_step() {
+        let config = WalkForwardConfig::new(Duration::from_secs(2), Duration::from_secs(1));
+        assert_eq!(
+            config
+                .windows(0, 4_500)
+                .iter()
+                .map(|window| (window.train_start, window.test_start, window.test_end))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, 2_000, 3_000),
+                (1_000, 3_000, 4_000),
+                (2_000, 4_000, 4_500)
+            ]
+        );
+        assert_eq!(
+            config.step(Duration::from_secs(2)).windows(0, 4_500).len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn walk_forward_runs_a_fresh_strategy_per_test_window() {
+        let backtest = Backtest::new([engine("btc_jpy", Decimal::ZERO, Decimal::ZERO)]);
+        let mut events: Vec<MarketEvent> = (0..8).map(|i| ticker_event(i * 500)).collect();
+        // 時刻を持たないイベントは直前のイベントと同じ期間に入る
+        events.insert(
+            5,
+            MarketEvent::Connected {
+                pair: "btc_jpy".to_owned(),
+            },
+        );
+        let counts = Arc::new(Mutex::new(Vec::new()));
+
+        let results = backtest
+            .run_walk_forward(
+                &events,
+                WalkForwardConfig::new(Duration::from_secs(2), Duration::from_secs(1)),
+                |window, train, _| CountingStrategy {
+                    window: *window,
+                    train_events: train.len(),
+                    test_events: 0,
+                    counts: counts.clone(),
+                },
+            )
+            .await;
+
+        assert_eq!(
+            results
+                .iter()
+                .map(|res| (res.window.test_start, res.result.market_events))
+                .collect::<Vec<_>>(),
+            vec![(2_000, 3), (3_000, 2)]
+        );
+        assert_eq!(
+            counts
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(window, train, test)| (window.train_start, *train, *test))
+                .collect::<Vec<_>>(),
+            vec![(0, 4, 3), (1_000, 5, 2)]
+        );
+    }
+}
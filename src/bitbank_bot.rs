@@ -1,30 +1,85 @@
+use crate::bitbank_private::BitbankPrivateApiClient;
+use crate::bitbank_private_stream::{subscribe_private_stream, BitbankPrivateStreamConfig};
+use crate::bitbank_public::BitbankPublicApiClient;
 use crate::bitbank_structs::{
     BitbankCircuitBreakInfo, BitbankDepth, BitbankDepthDiff, BitbankDepthWhole,
-    BitbankTickerResponse, BitbankTransactionDatum,
+    BitbankTickerResponse, BitbankTransactionDatum, DepthDelta, DepthIntegrityConfig,
+    DepthIntegrityError,
 };
+use crate::candle::{Candle, CandleAggregator};
+use crate::clock::{system_now_millis, Clock, EventTimestamp, SimulatedClock};
+use crate::event_queue::{BackpressureConfig, EventQueue, EventQueueMetrics, EventQueueStats};
 use crate::market_event::{
     MarketCircuitBreakInfo, MarketDepthSnapshot, MarketEvent, MarketEventConversionError,
     MarketTicker, MarketTrade,
 };
-use crate::websocket_handler::run_websocket;
+use crate::private_event::PrivateEvent;
+use crate::recorder::MarketDataRecorder;
+use crate::replay::{ReplaySource, ReplayStats};
+use crate::request_policy::RetryPolicy;
+use crate::supervision::{
+    catch_unwind, panic_message, supervise_feed, BotHealth, FeedExit, FeedStatus, HealthMonitor,
+    StrategyPanicPolicy, SupervisionConfig,
+};
+use crate::websocket_handler::{
+    run_websocket_with_recorder, WebSocketDecodeError, WebSocketMetrics, WebSocketStats,
+};
 use crypto_botters::bitbank::BitbankOption;
 use crypto_botters::generic_api_client::websocket::WebSocketConfig;
 use log::{error, trace, warn};
+use std::collections::{BTreeMap, HashMap};
 use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::select;
 use tokio::sync::{mpsc, oneshot};
-use tokio::task::{JoinError, JoinHandle};
+use tokio::task::{AbortHandle, JoinError, JoinHandle, JoinSet};
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+
+/// リプレイを流し終えた後、戦略のチャネルが空になったかどうかを確認する間隔。
+const REPLAY_DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(1);
+/// ライブのフィードで、約定が途切れても時間足を閉じるために時刻を確認する間隔。
+const CANDLE_CLOSE_INTERVAL: Duration = Duration::from_secs(1);
+/// 時間足を閉じるまでに、遅れて届く約定を待つ時間（ミリ秒）。
+const CANDLE_CLOSE_GRACE_MS: i64 = 1_000;
+
+/// イベントが起きた時刻を取り出す関数。[`Clock::Simulated`]を進めるのに使う。
+type EventTimeFn<E> = fn(&E) -> Option<i64>;
 
 /// 戦略がフォローアップイベントをランタイムに送り返すことを可能にする共有コンテキスト。
 /// コンテキストは基になる送信者をクローンするため、戦略は後で作業をスケジュールする必要がある場合に自由に保存できる。
 #[derive(Clone)]
 pub struct BotContext<E> {
     event_tx: mpsc::Sender<E>,
+    clock: Clock,
+    simulated_timers: Arc<Mutex<SimulatedTimers<E>>>,
+    /// [`Clock::System`]のときに動いているタイマーのタスク。戦略を再起動するときに止める。
+    live_timers: Arc<Mutex<Vec<AbortHandle>>>,
 }
 
 impl<E: Send + 'static> BotContext<E> {
     pub fn new(event_tx: mpsc::Sender<E>) -> Self {
-        Self { event_tx }
+        Self::with_clock(event_tx, Clock::System)
+    }
+
+    pub(crate) fn with_clock(event_tx: mpsc::Sender<E>, clock: Clock) -> Self {
+        Self {
+            event_tx,
+            clock,
+            simulated_timers: Arc::new(Mutex::new(SimulatedTimers::new())),
+            live_timers: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// 戦略とタイマーが使う時計。[`BitbankBotBuilder::clock`]で指定する。
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    /// 現在時刻（UNIXエポックからのミリ秒）。`SystemTime::now()`の代わりにこれを使うと、リプレイでも結果が再現する。
+    pub fn now_millis(&self) -> i64 {
+        self.clock.now_millis()
     }
 
     /// 内部イベント送信者のクローンを取得する。これは、戦略がBitbankのWebsocketを経由せずに
@@ -38,6 +93,262 @@ impl<E: Send + 'static> BotContext<E> {
     pub async fn emit(&self, event: E) -> Result<(), mpsc::error::SendError<E>> {
         self.event_tx.send(event).await
     }
+
+    /// `delay`後に`event`を一度だけランタイムに送る。イベントは市場データと同じアクターループで処理される。
+    /// 時計が[`Clock::Simulated`]の場合は、時計がその時刻を過ぎるイベントを受け取ったときに、そのイベントより先に届く。
+    pub fn schedule_after(&self, delay: Duration, event: E) -> TimerHandle {
+        if let Clock::Simulated(clock) = &self.clock {
+            return self.schedule_simulated(clock, delay, SimulatedTimerAction::Once(event));
+        }
+
+        let event_tx = self.event_tx.downgrade();
+        let task = tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            if let Some(tx) = event_tx.upgrade() {
+                let _ = tx.send(event).await;
+            }
+        });
+        self.track_live_timer(&task);
+
+        TimerHandle {
+            inner: TimerHandleInner::Task(task),
+        }
+    }
+
+    /// `period`ごとに`make_event`で作ったイベントをランタイムに送る。最初のイベントは`period`後に届く。
+    /// 戦略の処理が遅れてイベントが詰まった場合、遅れた分を取り戻そうとはせず、そこから`period`ごとに送る。
+    /// 時計が[`Clock::Simulated`]の場合は、イベントの間隔が空いても、時計の上で`period`ごとに1回ずつ届く。
+    pub fn schedule_every<F>(&self, period: Duration, mut make_event: F) -> TimerHandle
+    where
+        F: FnMut() -> E + Send + 'static,
+    {
+        if let Clock::Simulated(clock) = &self.clock {
+            let action = SimulatedTimerAction::Every {
+                period_ms: (period.as_millis() as i64).max(1),
+                make_event: Box::new(make_event),
+            };
+            return self.schedule_simulated(clock, period, action);
+        }
+
+        let event_tx = self.event_tx.downgrade();
+        let task = tokio::spawn(async move {
+            let mut interval = tokio::time::interval_at(Instant::now() + period, period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                interval.tick().await;
+                let Some(tx) = event_tx.upgrade() else {
+                    break;
+                };
+                if tx.send(make_event()).await.is_err() {
+                    break;
+                }
+            }
+        });
+        self.track_live_timer(&task);
+
+        TimerHandle {
+            inner: TimerHandleInner::Task(task),
+        }
+    }
+
+    // 終わったタイマーを取り除きながら、動いているタイマーのタスクを覚えておく。
+    fn track_live_timer(&self, task: &JoinHandle<()>) {
+        let mut live_timers = self.live_timers.lock().expect("live timers mutex poisoned");
+        live_timers.retain(|timer| !timer.is_finished());
+        live_timers.push(task.abort_handle());
+    }
+
+    // このコンテキストで登録された、まだ発火していないタイマーをすべてキャンセルする。
+    // 戦略を再起動する前に呼び、`on_start`で登録し直したタイマーと重ならないようにする。
+    pub(crate) fn cancel_timers(&self) {
+        for timer in self
+            .live_timers
+            .lock()
+            .expect("live timers mutex poisoned")
+            .drain(..)
+        {
+            timer.abort();
+        }
+        self.simulated_timers
+            .lock()
+            .expect("simulated timers mutex poisoned")
+            .cancel_all();
+    }
+
+    fn schedule_simulated(
+        &self,
+        clock: &SimulatedClock,
+        delay: Duration,
+        action: SimulatedTimerAction<E>,
+    ) -> TimerHandle {
+        let finished = Arc::new(AtomicBool::new(false));
+        self.simulated_timers
+            .lock()
+            .expect("simulated timers mutex poisoned")
+            .schedule(
+                clock,
+                delay.as_millis() as i64,
+                SimulatedTimer {
+                    action,
+                    finished: finished.clone(),
+                },
+            );
+        TimerHandle {
+            inner: TimerHandleInner::Simulated(finished),
+        }
+    }
+
+    // `until`までに発火するタイマーのうち最も早いもののイベントを返し、時計をその時刻まで進める。
+    // `make_event`がpanicしてもロックを汚さないよう、イベントはロックを外してから作る。
+    pub(crate) fn pop_due_timer(&self, clock: &SimulatedClock, until: i64) -> Option<E> {
+        let (deadline, id, timer) = self
+            .simulated_timers
+            .lock()
+            .expect("simulated timers mutex poisoned")
+            .pop_due(clock, until)?;
+
+        match timer.action {
+            SimulatedTimerAction::Once(event) => {
+                timer.finished.store(true, Ordering::Release);
+                Some(event)
+            }
+            SimulatedTimerAction::Every {
+                period_ms,
+                mut make_event,
+            } => {
+                let event = make_event();
+                self.simulated_timers
+                    .lock()
+                    .expect("simulated timers mutex poisoned")
+                    .scheduled
+                    .insert(
+                        (deadline + period_ms, id),
+                        SimulatedTimer {
+                            action: SimulatedTimerAction::Every {
+                                period_ms,
+                                make_event,
+                            },
+                            finished: timer.finished,
+                        },
+                    );
+                Some(event)
+            }
+        }
+    }
+}
+
+/// [`BotContext::schedule_after`]・[`BotContext::schedule_every`]で登録したタイマー。
+/// ハンドルをドロップしてもタイマーは止まらない。止めるには[`Self::cancel`]を呼ぶ。
+/// ランタイムが停止した後は、次に発火するときにタイマーも終了する。
+#[derive(Debug)]
+pub struct TimerHandle {
+    inner: TimerHandleInner,
+}
+
+#[derive(Debug)]
+enum TimerHandleInner {
+    Task(JoinHandle<()>),
+    /// 発火済み、またはキャンセルされたかどうか。
+    Simulated(Arc<AtomicBool>),
+}
+
+impl TimerHandle {
+    pub fn cancel(&self) {
+        match &self.inner {
+            TimerHandleInner::Task(task) => task.abort(),
+            TimerHandleInner::Simulated(finished) => finished.store(true, Ordering::Release),
+        }
+    }
+
+    // 一度きりのタイマーが発火した、またはキャンセルされたかどうか。
+    pub fn is_finished(&self) -> bool {
+        match &self.inner {
+            TimerHandleInner::Task(task) => task.is_finished(),
+            TimerHandleInner::Simulated(finished) => finished.load(Ordering::Acquire),
+        }
+    }
+}
+
+enum SimulatedTimerAction<E> {
+    Once(E),
+    Every {
+        period_ms: i64,
+        make_event: Box<dyn FnMut() -> E + Send>,
+    },
+}
+
+struct SimulatedTimer<E> {
+    action: SimulatedTimerAction<E>,
+    finished: Arc<AtomicBool>,
+}
+
+/// [`Clock::Simulated`]のときのタイマー。アクターがイベントを処理する前に、その時刻までに発火するものを取り出す。
+/// 同じ時刻に発火するタイマーは登録した順に並ぶ。
+struct SimulatedTimers<E> {
+    next_id: u64,
+    /// 時計が動き出す前に登録されたタイマーと、その遅延。最初のイベントの時刻から数える。
+    unstarted: Vec<(i64, u64, SimulatedTimer<E>)>,
+    scheduled: BTreeMap<(i64, u64), SimulatedTimer<E>>,
+}
+
+impl<E> SimulatedTimers<E> {
+    fn new() -> Self {
+        Self {
+            next_id: 0,
+            unstarted: Vec::new(),
+            scheduled: BTreeMap::new(),
+        }
+    }
+
+    fn schedule(&mut self, clock: &SimulatedClock, delay_ms: i64, timer: SimulatedTimer<E>) {
+        let id = self.next_id;
+        self.next_id += 1;
+        if clock.is_started() {
+            self.scheduled
+                .insert((clock.now_millis() + delay_ms, id), timer);
+        } else {
+            self.unstarted.push((delay_ms, id, timer));
+        }
+    }
+
+    fn cancel_all(&mut self) {
+        let unstarted = self.unstarted.drain(..).map(|(_, _, timer)| timer);
+        let scheduled = std::mem::take(&mut self.scheduled).into_values();
+        for timer in unstarted.chain(scheduled) {
+            timer.finished.store(true, Ordering::Release);
+        }
+    }
+
+    // `until`までに発火するタイマーのうち最も早いものを取り除いて返し、時計をその時刻まで進める。
+    // 繰り返しのタイマーは、呼び出し側がイベントを作ってから登録し直す。
+    fn pop_due(
+        &mut self,
+        clock: &SimulatedClock,
+        until: i64,
+    ) -> Option<(i64, u64, SimulatedTimer<E>)> {
+        if !clock.is_started() {
+            return None;
+        }
+        let now = clock.now_millis();
+        for (delay_ms, id, timer) in self.unstarted.drain(..) {
+            self.scheduled.insert((now + delay_ms, id), timer);
+        }
+
+        loop {
+            let entry = self.scheduled.first_entry()?;
+            if entry.key().0 > until {
+                return None;
+            }
+            let ((deadline, id), timer) = entry.remove_entry();
+            if timer.finished.load(Ordering::Acquire) {
+                continue;
+            }
+
+            clock.advance_to(deadline);
+            return Some((deadline, id, timer));
+        }
+    }
 }
 
 /// 戦略は、受信イベントの処理方法を表現するためにこのトレイトを実装する。
@@ -51,6 +362,26 @@ pub trait BotStrategy: Send + 'static {
         event: Self::Event,
         ctx: &BotContext<Self::Event>,
     ) -> impl std::future::Future<Output = ()> + Send;
+
+    /// 最初のイベントを処理する前に呼ばれる。状態の読み込みやタイマーの登録に使う。
+    /// [`StrategyPanicPolicy::Restart`]で再開するたびにも、それまでのタイマーをキャンセルしてから呼び直される。
+    /// 完了するまでイベントは処理されず、チャネルに溜まる。
+    fn on_start(
+        &mut self,
+        _ctx: &BotContext<Self::Event>,
+    ) -> impl std::future::Future<Output = ()> + Send {
+        async {}
+    }
+
+    /// ランタイムが停止するときに一度だけ呼ばれる。指値注文をすべてキャンセルするなどの後片付けに使う。
+    /// [`BitbankBotRuntime::shutdown`]（または[`BotHandle::shutdown`]）で停止した場合にのみ完了まで待つ。
+    /// ランタイムをドロップした場合は呼ばれない。
+    fn on_stop(
+        &mut self,
+        _ctx: &BotContext<Self::Event>,
+    ) -> impl std::future::Future<Output = ()> + Send {
+        async {}
+    }
 }
 
 /// ボットアクターを生かし続け、基本的なライフサイクル制御を提供するハンドル。
@@ -88,27 +419,64 @@ impl<E> Drop for BotHandle<E> {
     }
 }
 
-fn spawn_bot_actor<S>(mut strategy: S, buffer: usize) -> BotHandle<S::Event>
+fn spawn_bot_actor<S>(
+    mut strategy: S,
+    buffer: usize,
+    panic_policy: StrategyPanicPolicy,
+    health: HealthMonitor,
+    clock: Clock,
+    event_time: Option<EventTimeFn<S::Event>>,
+) -> BotHandle<S::Event>
 where
     S: BotStrategy,
 {
     let (event_tx, mut event_rx) = mpsc::channel(buffer);
     let (shutdown_tx, mut shutdown_rx) = oneshot::channel();
-    let context = BotContext::new(event_tx.clone());
+    let context = BotContext::with_clock(event_tx.clone(), clock);
 
     let join_handle = tokio::spawn(async move {
-        loop {
-            select! {
-                biased;
-                _ = &mut shutdown_rx => {
-                    trace!("bot actor received shutdown");
-                    break;
+        let mut restarts = 0;
+        let outcome = loop {
+            let res = catch_unwind(run_strategy(
+                &mut strategy,
+                &context,
+                event_time,
+                &mut event_rx,
+                &mut shutdown_rx,
+            ))
+            .await;
+            let Err(payload) = res else {
+                break Ok(());
+            };
+
+            let message = panic_message(&*payload);
+            match panic_policy {
+                StrategyPanicPolicy::Restart { max_restarts } if restarts < max_restarts => {
+                    restarts += 1;
+                    context.cancel_timers();
+                    error!(
+                        "strategy panicked: {}. restarting ({}/{})",
+                        message, restarts, max_restarts
+                    );
+                }
+                StrategyPanicPolicy::Escalate => {
+                    error!("strategy panicked: {}. escalating", message);
+                    health.actor_failed(message);
+                    std::panic::resume_unwind(payload);
                 }
-                maybe_event = event_rx.recv() => {
-                    let Some(event) = maybe_event else { break; };
-                    strategy.handle_event(event, &context).await;
+                StrategyPanicPolicy::Restart { .. } | StrategyPanicPolicy::StopRuntime => {
+                    error!("strategy panicked: {}. stopping bot", message);
+                    break Err(message);
                 }
             }
+        };
+
+        if let Err(payload) = catch_unwind(strategy.on_stop(&context)).await {
+            error!("strategy panicked in on_stop: {}", panic_message(&*payload));
+        }
+        match outcome {
+            Ok(()) => health.actor_stopped(),
+            Err(message) => health.actor_failed(message),
         }
     });
 
@@ -119,6 +487,44 @@ where
     }
 }
 
+// `on_start`を呼んでから、停止の指示を受けるかイベントが尽きるまでイベントを処理する。
+// 時計が[`Clock::Simulated`]の場合は、時刻を持つイベントの前にその時刻までのタイマーを処理し、時計を進める。
+async fn run_strategy<S>(
+    strategy: &mut S,
+    context: &BotContext<S::Event>,
+    event_time: Option<EventTimeFn<S::Event>>,
+    event_rx: &mut mpsc::Receiver<S::Event>,
+    shutdown_rx: &mut oneshot::Receiver<()>,
+) where
+    S: BotStrategy,
+{
+    strategy.on_start(context).await;
+
+    loop {
+        select! {
+            biased;
+            _ = &mut *shutdown_rx => {
+                trace!("bot actor received shutdown");
+                break;
+            }
+            maybe_event = event_rx.recv() => {
+                let Some(event) = maybe_event else { break; };
+                let timestamp = event_time.and_then(|event_time| event_time(&event));
+                if let (Clock::Simulated(clock), Some(timestamp)) = (&context.clock, timestamp) {
+                    if !clock.is_started() {
+                        clock.advance_to(timestamp);
+                    }
+                    while let Some(timer_event) = context.pop_due_timer(clock, timestamp) {
+                        strategy.handle_event(timer_event, context).await;
+                    }
+                    clock.advance_to(timestamp);
+                }
+                strategy.handle_event(event, context).await;
+            }
+        }
+    }
+}
+
 /// BitbankのWebSocket接続から転送された生メッセージ。
 #[derive(Debug, Clone)]
 pub enum BitbankInboundMessage {
@@ -127,6 +533,37 @@ pub enum BitbankInboundMessage {
     DepthDiff(BitbankDepthDiff),
     DepthWhole(BitbankDepthWhole),
     CircuitBreakInfo(BitbankCircuitBreakInfo),
+    /// 解釈できなかったメッセージ。フィードは止めずに戦略へ[`BitbankEvent::FeedError`]として渡す。
+    DecodeError(WebSocketDecodeError),
+    /// WebSocketの接続が確立した。
+    Connected,
+    /// WebSocketが切断され、再接続を始めた。最初の接続に失敗した場合にも送られる。
+    Disconnected,
+    /// 再接続が完了した。
+    Reconnected,
+}
+
+/// 板の更新をどの形で戦略に渡すか。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DepthEventMode {
+    /// 更新のたびに板全体を[`BitbankEvent::DepthUpdated`]で渡す。
+    #[default]
+    Snapshot,
+    /// 差分は変化した気配だけを[`BitbankEvent::DepthDelta`]で渡す。
+    /// 全体板を受け取ったとき（接続直後や再接続後など）は`DepthUpdated`で渡すので、戦略はそこで板を置き換える。
+    Delta,
+    /// 差分ごとに`DepthDelta`と`DepthUpdated`の両方を渡す。
+    Both,
+}
+
+/// 再接続後に板をどうやって取り直すか。
+#[derive(Clone, Default)]
+pub enum DepthResync {
+    /// WebSocketで次の`depth_whole`が届くのを待つ。
+    #[default]
+    WaitForSnapshot,
+    /// `depth_whole`を待ちつつ、REST APIの`get_depth`でも取得する。先に届いた方で板が完全になる。
+    Rest(BitbankPublicApiClient),
 }
 
 /// ボット戦略に公開される高レベルのイベント。`DepthUpdated`は、
@@ -149,6 +586,72 @@ pub enum BitbankEvent {
         pair: String,
         info: MarketCircuitBreakInfo,
     },
+    /// WebSocketのメッセージを解釈できなかった。フィードは動き続けるので、どう扱うかは戦略が決める。
+    FeedError {
+        pair: String,
+        error: WebSocketDecodeError,
+    },
+    /// 板の差分。[`DepthEventMode::Delta`]または[`DepthEventMode::Both`]の場合のみ発生する。
+    DepthDelta {
+        pair: String,
+        delta: DepthDelta,
+    },
+    /// 板の差分に問題があった。`error.requires_resnapshot()`の場合、
+    /// 新しい全体板を受け取るまで`DepthUpdated`は発生せず、それまでに受け取った板は信頼できない。
+    DepthIntegrityError {
+        pair: String,
+        error: DepthIntegrityError,
+    },
+    /// WebSocketの接続が確立した。
+    Connected {
+        pair: String,
+    },
+    /// WebSocketが切断された。新しい全体板を受け取るまで`DepthUpdated`は発生しない。
+    /// `last_depth`は切断前の板（完全だった場合のみ）で、古い可能性がある板として`is_complete() == false`になっている。
+    /// 板の再取得を待つ間に気配値を参照したい戦略はこれを保持しておく。
+    Disconnected {
+        pair: String,
+        last_depth: Option<MarketDepthSnapshot>,
+    },
+    /// 再接続が完了した。板は新しい全体板を受け取った時点で`DepthUpdated`として届く。
+    Reconnected {
+        pair: String,
+    },
+    /// 足が閉じた。[`BitbankBotBuilder::candles`]で登録したペアのみ発生する。
+    /// 同じペアに複数の足を登録した場合は`candle.kind`で区別する。
+    Candle {
+        pair: String,
+        candle: Candle,
+    },
+    /// Private Streamからのイベント。[`BitbankBotBuilder::private_stream`]を呼んだ場合のみ発生する。
+    Private(PrivateEvent),
+}
+
+impl From<PrivateEvent> for BitbankEvent {
+    fn from(event: PrivateEvent) -> Self {
+        Self::Private(event)
+    }
+}
+
+impl EventTimestamp for BitbankEvent {
+    fn timestamp_millis(&self) -> Option<i64> {
+        match self {
+            BitbankEvent::Ticker { ticker, .. } => Some(ticker.timestamp),
+            BitbankEvent::Transactions { transactions, .. } => {
+                transactions.iter().map(|trade| trade.executed_at).max()
+            }
+            BitbankEvent::DepthUpdated { depth, .. } => Some(depth.last_timestamp()),
+            BitbankEvent::CircuitBreakInfo { info, .. } => Some(info.timestamp),
+            BitbankEvent::DepthDelta { delta, .. } => Some(delta.timestamp),
+            BitbankEvent::Candle { candle, .. } => Some(candle.close_time),
+            BitbankEvent::FeedError { .. }
+            | BitbankEvent::DepthIntegrityError { .. }
+            | BitbankEvent::Connected { .. }
+            | BitbankEvent::Disconnected { .. }
+            | BitbankEvent::Reconnected { .. }
+            | BitbankEvent::Private(_) => None,
+        }
+    }
 }
 
 impl From<MarketEvent> for BitbankEvent {
@@ -160,32 +663,61 @@ impl From<MarketEvent> for BitbankEvent {
             }
             MarketEvent::DepthUpdated { pair, depth } => Self::DepthUpdated { pair, depth },
             MarketEvent::CircuitBreakInfo { pair, info } => Self::CircuitBreakInfo { pair, info },
+            MarketEvent::FeedError { pair, error } => Self::FeedError { pair, error },
+            MarketEvent::DepthDelta { pair, delta } => Self::DepthDelta { pair, delta },
+            MarketEvent::DepthIntegrityError { pair, error } => {
+                Self::DepthIntegrityError { pair, error }
+            }
+            MarketEvent::Connected { pair } => Self::Connected { pair },
+            MarketEvent::Disconnected { pair, last_depth } => {
+                Self::Disconnected { pair, last_depth }
+            }
+            MarketEvent::Reconnected { pair } => Self::Reconnected { pair },
+            MarketEvent::Candle { pair, candle } => Self::Candle { pair, candle },
         }
     }
 }
 
 #[derive(Debug)]
-struct BitbankMarketEventConverter {
+pub(crate) struct BitbankMarketEventConverter {
     pair: String,
     depth: BitbankDepth,
+    depth_events: DepthEventMode,
+    candles: Vec<CandleAggregator>,
 }
 
 impl BitbankMarketEventConverter {
-    fn new(pair: String) -> Self {
+    pub(crate) fn new(pair: String) -> Self {
+        Self::with_integrity_config(pair, DepthIntegrityConfig::default())
+    }
+
+    fn with_integrity_config(pair: String, integrity: DepthIntegrityConfig) -> Self {
         Self {
             pair,
-            depth: BitbankDepth::new(),
+            depth: BitbankDepth::with_integrity_config(integrity),
+            depth_events: DepthEventMode::default(),
+            candles: Vec::new(),
         }
     }
 
-    fn convert(
+    fn with_candles(mut self, candles: Vec<CandleAggregator>) -> Self {
+        self.candles = candles;
+        self
+    }
+
+    fn with_depth_events(mut self, depth_events: DepthEventMode) -> Self {
+        self.depth_events = depth_events;
+        self
+    }
+
+    pub(crate) fn convert(
         &mut self,
         message: BitbankInboundMessage,
-    ) -> Result<Option<MarketEvent>, MarketEventConversionError> {
+    ) -> Result<Vec<MarketEvent>, MarketEventConversionError> {
         let event = match message {
             BitbankInboundMessage::Ticker(ticker) => Some(MarketEvent::Ticker {
                 pair: self.pair.clone(),
-                ticker: ticker.into(),
+                ticker: ticker.try_into()?,
             }),
             BitbankInboundMessage::Transactions(transactions) => {
                 let mut transactions = transactions
@@ -193,86 +725,331 @@ impl BitbankMarketEventConverter {
                     .map(MarketTrade::try_from)
                     .collect::<Result<Vec<_>, _>>()?;
                 transactions.sort_by_key(|trade| (trade.executed_at, trade.transaction_id));
-                Some(MarketEvent::Transactions {
+                let candles = self.candle_events(&transactions);
+                let mut events = vec![MarketEvent::Transactions {
                     pair: self.pair.clone(),
                     transactions,
-                })
+                }];
+                events.extend(candles);
+                return Ok(events);
             }
             BitbankInboundMessage::DepthDiff(depth_diff) => {
-                self.depth.insert_diff(depth_diff);
-                if self.depth.is_complete() {
-                    Some(MarketEvent::DepthUpdated {
-                        pair: self.pair.clone(),
-                        depth: MarketDepthSnapshot::from(&self.depth),
-                    })
-                } else {
-                    None
-                }
+                let res = self.depth.insert_diff(depth_diff);
+                return Ok(self.depth_diff_events(res));
             }
             BitbankInboundMessage::DepthWhole(depth_whole) => {
-                self.depth.update_whole(depth_whole);
-                if self.depth.is_complete() {
-                    Some(MarketEvent::DepthUpdated {
-                        pair: self.pair.clone(),
-                        depth: MarketDepthSnapshot::from(&self.depth),
-                    })
-                } else {
-                    None
-                }
+                let res = self.depth.update_whole(depth_whole);
+                self.depth_whole_event(res)
             }
             BitbankInboundMessage::CircuitBreakInfo(info) => Some(MarketEvent::CircuitBreakInfo {
                 pair: self.pair.clone(),
-                info: info.into(),
+                info: info.try_into()?,
+            }),
+            BitbankInboundMessage::DecodeError(error) => Some(MarketEvent::FeedError {
+                pair: self.pair.clone(),
+                error,
+            }),
+            BitbankInboundMessage::Connected => Some(MarketEvent::Connected {
+                pair: self.pair.clone(),
+            }),
+            BitbankInboundMessage::Disconnected => Some(MarketEvent::Disconnected {
+                pair: self.pair.clone(),
+                last_depth: self.reset_depth(),
             }),
+            BitbankInboundMessage::Reconnected => Some(MarketEvent::Reconnected {
+                pair: self.pair.clone(),
+            }),
+        };
+
+        Ok(event.into_iter().collect())
+    }
+
+    // 時間足で、`now`（ミリ秒）までに終わった作成中の足を閉じてイベントにする。
+    // 約定が途切れても足が閉じるよう、ライブのフィードが定期的に呼ぶ。
+    pub(crate) fn close_candles(&mut self, now: i64) -> Vec<MarketEvent> {
+        self.candles
+            .iter_mut()
+            .filter_map(|aggregator| aggregator.close_until(now))
+            .map(|candle| MarketEvent::Candle {
+                pair: self.pair.clone(),
+                candle,
+            })
+            .collect()
+    }
+
+    // 約定を足に反映し、閉じた足をイベントにする。
+    fn candle_events(&mut self, transactions: &[MarketTrade]) -> Vec<MarketEvent> {
+        let mut events = Vec::new();
+        for trade in transactions {
+            for aggregator in &mut self.candles {
+                if let Some(candle) = aggregator.push_trade(trade) {
+                    events.push(MarketEvent::Candle {
+                        pair: self.pair.clone(),
+                        candle,
+                    });
+                }
+            }
+        }
+        events
+    }
+
+    // 差分の反映結果を`depth_events`に従ってイベントにする。板が不完全な間は何も出さない。
+    fn depth_diff_events(
+        &self,
+        res: Result<Option<DepthDelta>, DepthIntegrityError>,
+    ) -> Vec<MarketEvent> {
+        let delta = match res {
+            Err(error) => {
+                return vec![MarketEvent::DepthIntegrityError {
+                    pair: self.pair.clone(),
+                    error,
+                }]
+            }
+            Ok(Some(delta)) if self.depth.is_complete() => delta,
+            Ok(_) => return Vec::new(),
         };
 
-        Ok(event)
+        let mut events = Vec::new();
+        if self.depth_events != DepthEventMode::Snapshot {
+            events.push(MarketEvent::DepthDelta {
+                pair: self.pair.clone(),
+                delta,
+            });
+        }
+        if self.depth_events != DepthEventMode::Delta {
+            events.push(self.depth_snapshot_event());
+        }
+        events
+    }
+
+    // 全体板の反映結果をイベントにする。全体板は差分として表せないので、常にスナップショットを渡す。
+    fn depth_whole_event(&self, res: Result<(), DepthIntegrityError>) -> Option<MarketEvent> {
+        match res {
+            Err(error) => Some(MarketEvent::DepthIntegrityError {
+                pair: self.pair.clone(),
+                error,
+            }),
+            Ok(()) if self.depth.is_complete() => Some(self.depth_snapshot_event()),
+            Ok(()) => None,
+        }
+    }
+
+    fn depth_snapshot_event(&self) -> MarketEvent {
+        MarketEvent::DepthUpdated {
+            pair: self.pair.clone(),
+            depth: MarketDepthSnapshot::from(&self.depth),
+        }
+    }
+
+    // 切断中に取りこぼした差分があるかもしれないので、板を捨てて新しい全体板を待つ。
+    // 捨てる前の板が完全だった場合、古い板として返す。
+    fn reset_depth(&mut self) -> Option<MarketDepthSnapshot> {
+        let integrity = self.depth.integrity_config();
+        let depth = std::mem::replace(
+            &mut self.depth,
+            BitbankDepth::with_integrity_config(integrity),
+        );
+        depth
+            .is_complete()
+            .then(|| MarketDepthSnapshot::from(&depth).into_stale())
     }
 }
 
-async fn run_bitbank_pair_feed<E>(
+fn new_pair_converter(
     pair: String,
+    depth_integrity: DepthIntegrityConfig,
+    depth_events: DepthEventMode,
+    candles: Vec<CandleAggregator>,
+) -> BitbankMarketEventConverter {
+    BitbankMarketEventConverter::with_integrity_config(pair, depth_integrity)
+        .with_depth_events(depth_events)
+        .with_candles(candles)
+}
+
+async fn run_bitbank_pair_feed<E>(
+    converter: BitbankMarketEventConverter,
     client_options: Vec<BitbankOption>,
     websocket_config: WebSocketConfig,
+    depth_resync: DepthResync,
+    recorder: Option<MarketDataRecorder>,
+    websocket_metrics: WebSocketMetrics,
+    event_queue: EventQueue,
     event_tx: mpsc::Sender<E>,
-) where
+    status: FeedStatus,
+) -> FeedExit
+where
     E: From<MarketEvent> + Send + 'static,
 {
     let (inbound_tx, mut inbound_rx) = mpsc::channel::<BitbankInboundMessage>(128);
-    let ws_task = tokio::spawn(run_websocket(
-        pair.clone(),
+    let resync_tx = inbound_tx.downgrade();
+    let ws_task = tokio::spawn(run_websocket_with_recorder(
+        converter.pair.clone(),
         client_options,
         websocket_config,
         inbound_tx,
+        recorder,
+        websocket_metrics,
     ));
 
-    let mut converter = BitbankMarketEventConverter::new(pair.clone());
-    while let Some(message) = inbound_rx.recv().await {
-        let event = match converter.convert(message) {
-            Ok(event) => event,
+    let exit = forward_pair_feed(
+        converter,
+        &mut inbound_rx,
+        &depth_resync,
+        resync_tx,
+        Some(CANDLE_CLOSE_INTERVAL),
+        event_queue,
+        &event_tx,
+        Some(&status),
+    )
+    .await;
+
+    ws_task.abort();
+    exit
+}
+
+// 受け取ったメッセージをイベントにして`event_queue`に積み、戦略が受け取れるようになったものから渡す。
+// 戦略が遅い間は`event_queue`の方針に従ってイベントが間引かれる。それでも`event_queue`が一杯になったら、
+// 戦略に渡して空きができるまで`inbound_rx`から受け取らない。
+// `candle_close_interval`を指定すると、その間隔で実時刻を確認し、約定が途切れていても終わった時間足を閉じる。
+// `status`を指定すると、WebSocketが接続・再接続できたときにフィードが稼働中になったことを伝える。
+async fn forward_pair_feed<E>(
+    mut converter: BitbankMarketEventConverter,
+    inbound_rx: &mut mpsc::Receiver<BitbankInboundMessage>,
+    depth_resync: &DepthResync,
+    resync_tx: mpsc::WeakSender<BitbankInboundMessage>,
+    candle_close_interval: Option<Duration>,
+    mut event_queue: EventQueue,
+    event_tx: &mpsc::Sender<E>,
+    status: Option<&FeedStatus>,
+) -> FeedExit
+where
+    E: From<MarketEvent> + Send + 'static,
+{
+    let pair = converter.pair.clone();
+    let mut candle_close = candle_close_interval.map(|period| {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        interval
+    });
+    loop {
+        select! {
+            _ = tick(&mut candle_close) => {
+                for event in converter.close_candles(system_now_millis() - CANDLE_CLOSE_GRACE_MS) {
+                    event_queue.push(event);
+                }
+            }
+            message = inbound_rx.recv(), if !event_queue.is_full() => {
+                let Some(message) = message else { break; };
+
+                if let (
+                    BitbankInboundMessage::Connected | BitbankInboundMessage::Reconnected,
+                    Some(status),
+                ) = (&message, status)
+                {
+                    status.connected();
+                }
+                if let (BitbankInboundMessage::Reconnected, DepthResync::Rest(client)) =
+                    (&message, depth_resync)
+                {
+                    spawn_depth_resync(client.clone(), pair.clone(), resync_tx.clone());
+                }
+
+                match converter.convert(message) {
+                    Ok(events) => events.into_iter().for_each(|event| event_queue.push(event)),
+                    Err(err) => warn!(
+                        "bitbank feed for pair {} dropped an invalid market event: {:?}",
+                        pair, err
+                    ),
+                }
+            }
+            permit = event_tx.reserve(), if !event_queue.is_empty() => {
+                let Ok(permit) = permit else {
+                    warn!(
+                        "bitbank feed for pair {} stopped because downstream receiver closed",
+                        pair
+                    );
+                    return FeedExit::DownstreamClosed;
+                };
+                if let Some(event) = event_queue.pop() {
+                    permit.send(event.into());
+                }
+            }
+        }
+    }
+
+    // 入力が終わったら、残っているイベントを渡してから終了する
+    while let Some(event) = event_queue.pop() {
+        if event_tx.send(event.into()).await.is_err() {
+            return FeedExit::DownstreamClosed;
+        }
+    }
+    FeedExit::Ended
+}
+
+// `interval`の次の時刻まで待つ。`None`の場合は終わらない。
+async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+// REST APIで全体板を取得し、WebSocketで受け取った`depth_whole`と同じようにフィードに流す。
+fn spawn_depth_resync(
+    client: BitbankPublicApiClient,
+    pair: String,
+    inbound_tx: mpsc::WeakSender<BitbankInboundMessage>,
+) {
+    tokio::spawn(async move {
+        let depth = match client.get_depth(&pair).await {
+            Ok(depth) => depth,
             Err(err) => {
-                warn!(
-                    "bitbank feed for pair {} dropped an invalid market event: {:?}",
-                    pair, err
-                );
-                continue;
+                warn!("failed to resync depth for pair {} via REST: {}", pair, err);
+                return;
             }
         };
 
-        if let Some(event) = event {
-            if event_tx.send(event.into()).await.is_err() {
-                warn!(
-                    "bitbank feed for pair {} stopped because downstream receiver closed",
-                    pair
-                );
-                break;
-            }
+        if let Some(tx) = inbound_tx.upgrade() {
+            let _ = tx.send(BitbankInboundMessage::DepthWhole(depth)).await;
+        }
+    });
+}
+
+async fn run_bitbank_private_feed<E>(
+    api_client: BitbankPrivateApiClient,
+    config: BitbankPrivateStreamConfig,
+    event_tx: mpsc::Sender<E>,
+    status: FeedStatus,
+) -> FeedExit
+where
+    E: From<PrivateEvent> + Send + 'static,
+{
+    let (private_tx, mut private_rx) = mpsc::channel::<PrivateEvent>(128);
+    let stream_task = tokio::spawn(subscribe_private_stream(
+        api_client,
+        config,
+        private_tx,
+        Some(status),
+    ));
+
+    let mut exit = FeedExit::Ended;
+    while let Some(event) = private_rx.recv().await {
+        if event_tx.send(event.into()).await.is_err() {
+            warn!("bitbank private feed stopped because downstream receiver closed");
+            exit = FeedExit::DownstreamClosed;
+            break;
         }
     }
 
-    ws_task.abort();
+    stream_task.abort();
+    exit
 }
 
+type PrivateFeedSpawner<E> =
+    Box<dyn FnOnce(mpsc::Sender<E>, RetryPolicy, HealthMonitor) -> JoinHandle<()> + Send>;
+
 fn duplicate_bitbank_options(options: &[BitbankOption]) -> Vec<BitbankOption> {
     options
         .iter()
@@ -305,6 +1082,17 @@ where
     default_options: Vec<BitbankOption>,
     websocket_config: WebSocketConfig,
     buffer_size: usize,
+    depth_resync: DepthResync,
+    depth_integrity: DepthIntegrityConfig,
+    depth_events: DepthEventMode,
+    candles: HashMap<String, Vec<CandleAggregator>>,
+    supervision: SupervisionConfig,
+    backpressure: BackpressureConfig,
+    recorder: Option<MarketDataRecorder>,
+    replay: Option<ReplaySource>,
+    clock: Clock,
+    event_time: Option<EventTimeFn<E>>,
+    private_feed: Option<PrivateFeedSpawner<E>>,
     _marker: PhantomData<E>,
 }
 
@@ -320,6 +1108,17 @@ where
             default_options: Vec::new(),
             websocket_config: WebSocketConfig::default(),
             buffer_size: 128,
+            depth_resync: DepthResync::default(),
+            depth_integrity: DepthIntegrityConfig::default(),
+            depth_events: DepthEventMode::default(),
+            candles: HashMap::new(),
+            supervision: SupervisionConfig::default(),
+            backpressure: BackpressureConfig::default(),
+            recorder: None,
+            replay: None,
+            clock: Clock::System,
+            event_time: None,
+            private_feed: None,
             _marker: PhantomData,
         }
     }
@@ -349,30 +1148,245 @@ where
         self
     }
 
-    pub fn spawn(self) -> BitbankBotRuntime<E> {
-        if self.pairs.is_empty() {
-            warn!("spawning a Bitbank bot without any subscribed pair");
-        }
+    /// 再接続後の板の取り直し方。デフォルトは[`DepthResync::WaitForSnapshot`]。
+    pub fn depth_resync(mut self, depth_resync: DepthResync) -> Self {
+        self.depth_resync = depth_resync;
+        self
+    }
 
-        let actor = spawn_bot_actor(self.strategy, self.buffer_size);
-        let event_tx = actor.event_sender();
-        let mut feed_handles = Vec::new();
+    /// 板の整合性チェックの設定。問題があった場合は[`BitbankEvent::DepthIntegrityError`]が発生する。
+    pub fn depth_integrity(mut self, depth_integrity: DepthIntegrityConfig) -> Self {
+        self.depth_integrity = depth_integrity;
+        self
+    }
+
+    /// 板の更新を[`BitbankEvent::DepthUpdated`]と[`BitbankEvent::DepthDelta`]のどちらで受け取るか。
+    /// デフォルトは[`DepthEventMode::Snapshot`]。
+    pub fn depth_events(mut self, depth_events: DepthEventMode) -> Self {
+        self.depth_events = depth_events;
+        self
+    }
+
+    /// `pair`の約定から足を作り、閉じるたびに[`BitbankEvent::Candle`]を発生させる。
+    /// 時間足は、約定が途切れていても期間が終わってから約1秒後に閉じる（リプレイでは次の約定を受け取ったとき）。
+    /// 起動時から指標を計算したい場合は、渡す前に[`CandleAggregator::seed_from_rest`]などで履歴を入れておく。
+    pub fn candles(mut self, pair: impl Into<String>, aggregator: CandleAggregator) -> Self {
+        self.candles
+            .entry(pair.into())
+            .or_default()
+            .push(aggregator);
+        self
+    }
+
+    /// 戦略やフィードタスクがpanicしたときの扱い。デフォルトでは、戦略がpanicすると停止し、
+    /// フィードは再起動し続ける。
+    pub fn supervision(mut self, supervision: SupervisionConfig) -> Self {
+        self.supervision = supervision;
+        self
+    }
+
+    /// 戦略の処理が追いつかないときに、ペアごとのイベントをどう間引くか。
+    /// デフォルトは[`BackpressureConfig::default`]で、約定はすべて届け、板は最新のものだけを届け、板の差分はまとめる。
+    pub fn backpressure(mut self, backpressure: BackpressureConfig) -> Self {
+        self.backpressure = backpressure;
+        self
+    }
+
+    /// すべてのペアについて、WebSocketで受け取った生のメッセージを`recorder`に記録する。
+    /// 記録したファイルは[`RecordingReader`](crate::recorder::RecordingReader)で読み出せる。
+    pub fn recorder(mut self, recorder: MarketDataRecorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// WebSocketに接続する代わりに、`replay`の記録を各ペアのフィードに流す。戦略にはライブと同じイベントが届くので、
+    /// 同じ戦略を過去のデータで動かせる。記録にあっても`add_pair`していないペアのメッセージは捨てる。
+    /// [`ReplaySpeed::AsFastAsPossible`](crate::replay::ReplaySpeed::AsFastAsPossible)で流す場合、
+    /// 間引かれるイベントが実行ごとに変わらないよう`backpressure(BackpressureConfig::deliver_all())`も指定するとよい。
+    /// Private Streamは置き換えない。流し終わるのは[`BitbankBotRuntime::replay_finished`]で待てる。
+    pub fn replay(mut self, replay: ReplaySource) -> Self {
+        self.replay = Some(replay);
+        self
+    }
+
+    pub fn spawn(mut self) -> BitbankBotRuntime<E> {
+        if self.pairs.is_empty() && self.private_feed.is_none() {
+            warn!("spawning a Bitbank bot without any subscribed pair");
+        }
+
+        let health = HealthMonitor::new();
+        let actor = spawn_bot_actor(
+            self.strategy,
+            self.buffer_size,
+            self.supervision.strategy_panic,
+            health.clone(),
+            self.clock.clone(),
+            self.event_time,
+        );
+        let event_tx = actor.event_sender();
+        let event_queue_metrics = EventQueueMetrics::new();
+        let websocket_metrics = WebSocketMetrics::new();
+        let mut feed_handles = Vec::new();
+
+        let replay = self.replay.take().map(|source| {
+            let mut senders = HashMap::new();
+            let mut feeds = JoinSet::new();
+            for pair in std::mem::take(&mut self.pairs) {
+                let (inbound_tx, mut inbound_rx) = mpsc::channel(128);
+                let resync_tx = inbound_tx.downgrade();
+                senders.insert(pair.clone(), inbound_tx);
+
+                let converter = new_pair_converter(
+                    pair.clone(),
+                    self.depth_integrity,
+                    self.depth_events,
+                    self.candles.remove(&pair).unwrap_or_default(),
+                );
+                let event_queue = EventQueue::new(self.backpressure, event_queue_metrics.clone());
+                let tx = event_tx.clone();
+                feeds.spawn(async move {
+                    forward_pair_feed(
+                        converter,
+                        &mut inbound_rx,
+                        &DepthResync::WaitForSnapshot,
+                        resync_tx,
+                        None,
+                        event_queue,
+                        &tx,
+                        None,
+                    )
+                    .await;
+                });
+            }
+            tokio::spawn(run_replay(source, senders, feeds, event_tx.clone()))
+        });
 
         for pair in self.pairs {
-            let pair_options = duplicate_bitbank_options(&self.default_options);
+            let default_options = duplicate_bitbank_options(&self.default_options);
             let tx = event_tx.clone();
             let config = self.websocket_config.clone();
-            let handle = tokio::spawn(async move {
-                run_bitbank_pair_feed(pair, pair_options, config, tx).await;
-            });
-            feed_handles.push(handle);
+            let depth_resync = self.depth_resync.clone();
+            let candles = self.candles.remove(&pair).unwrap_or_default();
+            let depth_integrity = self.depth_integrity;
+            let depth_events = self.depth_events;
+            let backpressure = self.backpressure;
+            let recorder = self.recorder.clone();
+            let metrics = event_queue_metrics.clone();
+            let websocket_metrics = websocket_metrics.clone();
+            let feed_pair = pair.clone();
+
+            // panicしたり入力が終わったりした場合は板や足を作り直して再接続する
+            let spawn_feed = move |status| {
+                run_bitbank_pair_feed(
+                    new_pair_converter(
+                        feed_pair.clone(),
+                        depth_integrity,
+                        depth_events,
+                        candles.clone(),
+                    ),
+                    duplicate_bitbank_options(&default_options),
+                    config.clone(),
+                    depth_resync.clone(),
+                    recorder.clone(),
+                    websocket_metrics.clone(),
+                    EventQueue::new(backpressure, metrics.clone()),
+                    tx.clone(),
+                    status,
+                )
+            };
+            feed_handles.push(tokio::spawn(supervise_feed(
+                pair,
+                self.supervision.feed_restart.clone(),
+                health.clone(),
+                spawn_feed,
+            )));
+        }
+
+        if let Some(spawn_private_feed) = self.private_feed {
+            feed_handles.push(spawn_private_feed(
+                event_tx.clone(),
+                self.supervision.feed_restart.clone(),
+                health.clone(),
+            ));
         }
 
         BitbankBotRuntime {
             bot_handle: Some(actor),
             feed_handles,
+            replay,
+            health,
+            event_queue_metrics,
+            websocket_metrics,
+        }
+    }
+}
+
+// リプレイを流し終えたら、各ペアのフィードと戦略のチャネルが空になるまで待つ。
+async fn run_replay<E>(
+    source: ReplaySource,
+    senders: HashMap<String, mpsc::Sender<BitbankInboundMessage>>,
+    mut feeds: JoinSet<()>,
+    event_tx: mpsc::Sender<E>,
+) -> ReplayStats {
+    let stats = source.run(senders).await;
+    while let Some(res) = feeds.join_next().await {
+        if let Err(err) = res {
+            error!("replay feed stopped unexpectedly: {}", err);
         }
     }
+
+    // 最後のイベントを戦略が受け取るまで待つ。受け取った後に停止を指示しても、処理中のイベントは最後まで処理される
+    while !event_tx.is_closed() && event_tx.capacity() < event_tx.max_capacity() {
+        tokio::time::sleep(REPLAY_DRAIN_POLL_INTERVAL).await;
+    }
+    stats
+}
+
+impl<S, E> BitbankBotBuilder<S, E>
+where
+    S: BotStrategy<Event = E>,
+    E: From<MarketEvent> + EventTimestamp + Send + 'static,
+{
+    /// 戦略とタイマーが使う時計。デフォルトは[`Clock::System`]。
+    /// [`Clock::simulated`]を指定すると、時計は戦略が受け取るイベントの時刻で進み、タイマーもその時刻に合わせて
+    /// ほかのイベントと決まった順序で発火する。[`Self::replay`]と組み合わせると、同じ記録から毎回同じ結果が得られる。
+    /// [`PaperExecutionEngine::with_clock`](crate::paper_execution::PaperExecutionEngine::with_clock)にも同じ時計を渡すこと。
+    pub fn clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self.event_time = Some(E::timestamp_millis);
+        self
+    }
+}
+
+impl<S, E> BitbankBotBuilder<S, E>
+where
+    S: BotStrategy<Event = E>,
+    E: From<MarketEvent> + From<PrivateEvent> + Send + 'static,
+{
+    /// Private Streamを購読し、注文・約定・資産の変化をマーケットデータと同じイベントストリームに流す。
+    /// チャンネル・トークンは`api_client`で取得し、期限切れや切断時には自動で再取得・再接続する。
+    pub fn private_stream(
+        mut self,
+        api_client: BitbankPrivateApiClient,
+        config: BitbankPrivateStreamConfig,
+    ) -> Self {
+        self.private_feed = Some(Box::new(move |event_tx, feed_restart, health| {
+            tokio::spawn(supervise_feed(
+                "private".to_owned(),
+                feed_restart,
+                health,
+                move |status| {
+                    run_bitbank_private_feed(
+                        api_client.clone(),
+                        config.clone(),
+                        event_tx.clone(),
+                        status,
+                    )
+                },
+            ))
+        }));
+        self
+    }
 }
 
 /// アクターとフィードタスクを生かし続けるランタイム。ランタイムをドロップすると
@@ -380,6 +1394,10 @@ where
 pub struct BitbankBotRuntime<E> {
     bot_handle: Option<BotHandle<E>>,
     feed_handles: Vec<JoinHandle<()>>,
+    replay: Option<JoinHandle<ReplayStats>>,
+    health: HealthMonitor,
+    event_queue_metrics: EventQueueMetrics,
+    websocket_metrics: WebSocketMetrics,
 }
 
 impl<E: Send + 'static> BitbankBotRuntime<E> {
@@ -390,16 +1408,108 @@ impl<E: Send + 'static> BitbankBotRuntime<E> {
             .event_sender()
     }
 
+    pub fn health(&self) -> BotHealth {
+        self.health.health()
+    }
+
+    // フィードと戦略のチャネルに溜まっているイベントの数と、間引いたイベントの数。
+    pub fn event_queue_stats(&self) -> EventQueueStats {
+        let mut stats = self.event_queue_metrics.stats();
+        if let Some(bot_handle) = &self.bot_handle {
+            stats.channel_len = bot_handle.event_tx.max_capacity() - bot_handle.event_tx.capacity();
+        }
+        stats
+    }
+
+    // すべてのペアのWebSocketで受け取ったメッセージ、解釈できなかったメッセージ、捨てたメッセージの数。
+    pub fn websocket_stats(&self) -> WebSocketStats {
+        self.websocket_metrics.stats()
+    }
+
+    // 戦略が停止する（panicした場合を含む）まで待ち、最終的な状態を返す。
+    // [`Self::shutdown`]と違い、停止を指示はしない。
+    pub async fn stopped(&self) -> BotHealth {
+        self.health.stopped().await
+    }
+
+    // [`BitbankBotBuilder::replay`]で渡した記録をすべて戦略が受け取るまで待つ。
+    // リプレイしていない場合と、すでに待ち終えた場合は`None`。
+    pub async fn replay_finished(&mut self) -> Option<ReplayStats> {
+        let res = self.replay.as_mut()?.await;
+        self.replay = None;
+        match res {
+            Ok(stats) => Some(stats),
+            Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+            Err(_) => None,
+        }
+    }
+
+    // フィードを止めてから[`BotStrategy::on_stop`]の完了を待つ。
     pub async fn shutdown(mut self) -> Result<(), JoinError> {
         for handle in &self.feed_handles {
             handle.abort();
         }
+        if let Some(replay) = &self.replay {
+            replay.abort();
+        }
         if let Some(bot_handle) = self.bot_handle.take() {
             bot_handle.shutdown().await
         } else {
             Ok(())
         }
     }
+
+    // SIGINT（Ctrl+C）またはSIGTERMを受け取るか、戦略が停止する（リプレイの場合は流し終える）まで動かし、
+    // その後[`Self::shutdown`]する。
+    pub async fn run_until_shutdown_signal(mut self) -> Result<(), JoinError> {
+        let health = self.health.clone();
+        let replay_finished = async {
+            match self.replay_finished().await {
+                Some(stats) => stats,
+                None => std::future::pending().await,
+            }
+        };
+        select! {
+            res = shutdown_signal() => match res {
+                Ok(signal) => log::info!("received {}. shutting down bitbank bot", signal),
+                Err(err) => log::error!(
+                    "failed to listen for shutdown signals: {}. shutting down bitbank bot",
+                    err
+                ),
+            },
+            health = health.stopped() => {
+                log::warn!("bitbank bot stopped by itself: {:?}", health);
+            }
+            stats = replay_finished => {
+                log::info!(
+                    "replay finished: {} messages replayed, {} skipped, {} unreadable",
+                    stats.replayed,
+                    stats.skipped,
+                    stats.errors.len()
+                );
+            }
+        }
+        self.shutdown().await
+    }
+}
+
+// SIGINTまたはSIGTERM（Unix以外ではCtrl+Cのみ）を待ち、受け取ったシグナルの名前を返す。
+pub async fn shutdown_signal() -> std::io::Result<&'static str> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut sigterm = signal(SignalKind::terminate())?;
+        select! {
+            res = tokio::signal::ctrl_c() => res.map(|()| "SIGINT"),
+            _ = sigterm.recv() => Ok("SIGTERM"),
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await.map(|()| "Ctrl+C")
+    }
 }
 
 impl<E> Drop for BitbankBotRuntime<E> {
@@ -407,6 +1517,9 @@ impl<E> Drop for BitbankBotRuntime<E> {
         for handle in &self.feed_handles {
             handle.abort();
         }
+        if let Some(replay) = &self.replay {
+            replay.abort();
+        }
         if let Some(bot_handle) = self.bot_handle.take() {
             drop(bot_handle);
         }
@@ -425,8 +1538,8 @@ pub async fn forward_bitbank_messages<E>(
 {
     let mut converter = BitbankMarketEventConverter::new(pair.clone());
     while let Some(message) = inbound_rx.recv().await {
-        let event = match converter.convert(message) {
-            Ok(event) => event,
+        let events = match converter.convert(message) {
+            Ok(events) => events,
             Err(err) => {
                 error!(
                     "forwarder dropping invalid market event while replaying {}: {:?}",
@@ -436,13 +1549,13 @@ pub async fn forward_bitbank_messages<E>(
             }
         };
 
-        if let Some(event) = event {
+        for event in events {
             if event_tx.send(event.into()).await.is_err() {
                 error!(
                     "forwarder stopping: downstream closed while replaying {}",
                     pair
                 );
-                break;
+                return;
             }
         }
     }
@@ -451,13 +1564,360 @@ pub async fn forward_bitbank_messages<E>(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::candle::{BarKind, CandleInterval};
     use crate::depth::Depth;
     use crate::order_domain::OrderSide;
     use rust_decimal::Decimal;
     use serde_json::Number;
+    use std::sync::{Arc, Mutex};
+
+    struct RecordingStrategy {
+        events_tx: mpsc::UnboundedSender<&'static str>,
+    }
+
+    impl BotStrategy for RecordingStrategy {
+        type Event = &'static str;
+
+        async fn handle_event(&mut self, event: Self::Event, _ctx: &BotContext<Self::Event>) {
+            let _ = self.events_tx.send(event);
+        }
+    }
+
+    struct LifecycleStrategy {
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl BotStrategy for LifecycleStrategy {
+        type Event = &'static str;
+
+        async fn handle_event(&mut self, event: Self::Event, _ctx: &BotContext<Self::Event>) {
+            self.calls.lock().unwrap().push(event.to_owned());
+        }
+
+        async fn on_start(&mut self, ctx: &BotContext<Self::Event>) {
+            self.calls.lock().unwrap().push("start".to_owned());
+            ctx.emit("from on_start").await.unwrap();
+        }
+
+        async fn on_stop(&mut self, _ctx: &BotContext<Self::Event>) {
+            // 停止時の後片付け（注文のキャンセルなど）が完了するまで待たれることを確認する
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            self.calls.lock().unwrap().push("stop".to_owned());
+        }
+    }
+
+    #[tokio::test]
+    async fn actor_runs_lifecycle_hooks_around_events() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let actor = spawn_bot_actor(
+            LifecycleStrategy {
+                calls: calls.clone(),
+            },
+            8,
+            StrategyPanicPolicy::StopRuntime,
+            HealthMonitor::new(),
+            Clock::System,
+            None,
+        );
+        actor.event_sender().send("event").await.unwrap();
+
+        while calls.lock().unwrap().len() < 3 {
+            tokio::task::yield_now().await;
+        }
+        actor.shutdown().await.unwrap();
+
+        let mut calls = calls.lock().unwrap().clone();
+        assert_eq!(calls.first().map(String::as_str), Some("start"));
+        assert_eq!(calls.last().map(String::as_str), Some("stop"));
+        calls[1..3].sort();
+        assert_eq!(calls[1..3], ["event", "from on_start"]);
+    }
+
+    struct PanickingStrategy {
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl BotStrategy for PanickingStrategy {
+        type Event = &'static str;
+
+        async fn handle_event(&mut self, event: Self::Event, _ctx: &BotContext<Self::Event>) {
+            if event == "panic" {
+                panic!("strategy bug");
+            }
+            self.calls.lock().unwrap().push(event.to_owned());
+        }
+
+        async fn on_start(&mut self, _ctx: &BotContext<Self::Event>) {
+            self.calls.lock().unwrap().push("start".to_owned());
+        }
+
+        async fn on_stop(&mut self, _ctx: &BotContext<Self::Event>) {
+            self.calls.lock().unwrap().push("stop".to_owned());
+        }
+    }
+
+    #[tokio::test]
+    async fn actor_restarts_strategy_after_panic_up_to_limit() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let health = HealthMonitor::new();
+        let actor = spawn_bot_actor(
+            PanickingStrategy {
+                calls: calls.clone(),
+            },
+            8,
+            StrategyPanicPolicy::Restart { max_restarts: 1 },
+            health.clone(),
+            Clock::System,
+            None,
+        );
+        let event_tx = actor.event_sender();
+        for event in ["panic", "after restart", "panic", "never handled"] {
+            event_tx.send(event).await.unwrap();
+        }
+
+        assert_eq!(
+            health.stopped().await,
+            BotHealth::Failed {
+                message: "strategy bug".to_owned()
+            }
+        );
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec!["start", "start", "after restart", "stop"]
+        );
+        assert!(actor.shutdown().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn actor_escalates_panic_to_shutdown() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let health = HealthMonitor::new();
+        let actor = spawn_bot_actor(
+            PanickingStrategy {
+                calls: calls.clone(),
+            },
+            8,
+            StrategyPanicPolicy::Escalate,
+            health.clone(),
+            Clock::System,
+            None,
+        );
+        actor.event_sender().send("panic").await.unwrap();
+
+        assert!(health.stopped().await.is_stopped());
+        assert!(actor.shutdown().await.unwrap_err().is_panic());
+        assert_eq!(*calls.lock().unwrap(), vec!["start"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn timers_deliver_events_through_the_actor_until_cancelled() {
+        let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+        let actor = spawn_bot_actor(
+            RecordingStrategy { events_tx },
+            8,
+            StrategyPanicPolicy::StopRuntime,
+            HealthMonitor::new(),
+            Clock::System,
+            None,
+        );
+        let ctx = BotContext::new(actor.event_sender());
+
+        let once = ctx.schedule_after(Duration::from_millis(10), "once");
+        let cancelled = ctx.schedule_after(Duration::from_millis(10), "cancelled");
+        cancelled.cancel();
+        let every = ctx.schedule_every(Duration::from_millis(5), || "tick");
+
+        let mut received = Vec::new();
+        while received.iter().filter(|event| **event == "tick").count() < 3
+            || !received.contains(&"once")
+        {
+            received.push(events_rx.recv().await.unwrap());
+        }
+        every.cancel();
+        assert!(!received.contains(&"cancelled"));
+
+        // キャンセル後に送られていたものを読み捨ててから、もう届かないことを確認する
+        tokio::time::advance(Duration::from_millis(20)).await;
+        tokio::task::yield_now().await;
+        assert!(once.is_finished());
+        while events_rx.try_recv().is_ok() {}
+        tokio::time::advance(Duration::from_millis(20)).await;
+        tokio::task::yield_now().await;
+        assert!(events_rx.try_recv().is_err());
+
+        actor.shutdown().await.unwrap();
+    }
+
+    struct TimedEvent(&'static str, Option<i64>);
+
+    fn timed_event_time(event: &TimedEvent) -> Option<i64> {
+        event.1
+    }
+
+    struct ClockStrategy {
+        log: Arc<Mutex<Vec<(&'static str, i64)>>>,
+    }
+
+    impl BotStrategy for ClockStrategy {
+        type Event = TimedEvent;
+
+        async fn on_start(&mut self, ctx: &BotContext<Self::Event>) {
+            ctx.schedule_every(Duration::from_millis(100), || TimedEvent("tick", None));
+            ctx.schedule_after(Duration::from_millis(150), TimedEvent("once", None));
+            ctx.schedule_after(Duration::from_millis(150), TimedEvent("cancelled", None))
+                .cancel();
+        }
+
+        async fn handle_event(&mut self, event: Self::Event, ctx: &BotContext<Self::Event>) {
+            self.log.lock().unwrap().push((event.0, ctx.now_millis()));
+        }
+    }
+
+    #[tokio::test]
+    async fn simulated_clock_fires_timers_between_events_by_event_time() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let actor = spawn_bot_actor(
+            ClockStrategy { log: log.clone() },
+            8,
+            StrategyPanicPolicy::StopRuntime,
+            HealthMonitor::new(),
+            Clock::simulated(),
+            Some(timed_event_time),
+        );
+
+        let event_tx = actor.event_sender();
+        for event in [
+            TimedEvent("a", Some(1_000)),
+            TimedEvent("b", None),
+            TimedEvent("c", Some(1_250)),
+        ] {
+            event_tx.send(event).await.unwrap();
+        }
+        while log.lock().unwrap().len() < 6 {
+            tokio::task::yield_now().await;
+        }
+        actor.shutdown().await.unwrap();
+
+        // タイマーは時計が最初のイベントの時刻から動き出してから数える
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                ("a", 1_000),
+                ("b", 1_000),
+                ("tick", 1_100),
+                ("once", 1_150),
+                ("tick", 1_200),
+                ("c", 1_250),
+            ]
+        );
+    }
+
+    struct RestartingTimerStrategy {
+        log: Arc<Mutex<Vec<(&'static str, i64)>>>,
+        panicked: bool,
+    }
+
+    impl BotStrategy for RestartingTimerStrategy {
+        type Event = TimedEvent;
+
+        async fn on_start(&mut self, ctx: &BotContext<Self::Event>) {
+            ctx.schedule_every(Duration::from_millis(100), || TimedEvent("tick", None));
+        }
+
+        async fn handle_event(&mut self, event: Self::Event, ctx: &BotContext<Self::Event>) {
+            if event.0 == "panic" && !self.panicked {
+                self.panicked = true;
+                panic!("strategy bug");
+            }
+            self.log.lock().unwrap().push((event.0, ctx.now_millis()));
+        }
+    }
+
+    fn spawn_restarting_timer_strategy(
+        clock: Clock,
+        log: Arc<Mutex<Vec<(&'static str, i64)>>>,
+    ) -> BotHandle<TimedEvent> {
+        spawn_bot_actor(
+            RestartingTimerStrategy {
+                log,
+                panicked: false,
+            },
+            8,
+            StrategyPanicPolicy::Restart { max_restarts: 1 },
+            HealthMonitor::new(),
+            clock,
+            Some(timed_event_time),
+        )
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restart_cancels_live_timers_scheduled_by_on_start() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let actor = spawn_restarting_timer_strategy(Clock::System, log.clone());
+        actor
+            .event_sender()
+            .send(TimedEvent("panic", None))
+            .await
+            .unwrap();
+
+        // 再起動前のタイマーが残っていると、1周期に2回ずつ届く
+        tokio::time::sleep(Duration::from_millis(350)).await;
+        actor.shutdown().await.unwrap();
+
+        let ticks = log
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(event, _)| *event == "tick")
+            .count();
+        assert_eq!(ticks, 3);
+    }
+
+    #[tokio::test]
+    async fn restart_cancels_simulated_timers_scheduled_by_on_start() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let actor = spawn_restarting_timer_strategy(Clock::simulated(), log.clone());
+
+        let event_tx = actor.event_sender();
+        for event in [
+            TimedEvent("a", Some(1_000)),
+            TimedEvent("panic", Some(1_050)),
+            TimedEvent("b", Some(1_350)),
+        ] {
+            event_tx.send(event).await.unwrap();
+        }
+        while !log.lock().unwrap().iter().any(|(event, _)| *event == "b") {
+            tokio::task::yield_now().await;
+        }
+        actor.shutdown().await.unwrap();
+
+        // 再起動した時刻から数え直したタイマーだけが発火する
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![("a", 1_000), ("tick", 1_150), ("tick", 1_250), ("b", 1_350)]
+        );
+    }
+
+    // イベントが高々1つであることを確認して取り出す。
+    fn convert_one(
+        converter: &mut BitbankMarketEventConverter,
+        message: BitbankInboundMessage,
+    ) -> Option<MarketEvent> {
+        let mut events = converter.convert(message).unwrap();
+        assert!(
+            events.len() <= 1,
+            "expected at most one event: {:?}",
+            events
+        );
+        events.pop()
+    }
 
     fn depth_whole() -> BitbankDepthWhole {
-        serde_json::from_value(serde_json::json!({
+        serde_json::from_value(depth_whole_json()).unwrap()
+    }
+
+    fn depth_whole_json() -> serde_json::Value {
+        serde_json::json!({
             "asks": [["101", "1.5"]],
             "bids": [["100", "2.0"]],
             "asks_over": "0",
@@ -468,8 +1928,7 @@ mod tests {
             "bid_market": "0",
             "timestamp": 1234,
             "sequenceId": "10"
-        }))
-        .unwrap()
+        })
     }
 
     #[test]
@@ -488,14 +1947,13 @@ mod tests {
             s: "9".to_owned(),
         };
 
-        let event = converter
-            .convert(BitbankInboundMessage::DepthDiff(diff))
-            .unwrap();
+        let event = convert_one(&mut converter, BitbankInboundMessage::DepthDiff(diff));
         assert!(event.is_none());
 
-        let event = converter
-            .convert(BitbankInboundMessage::DepthWhole(depth_whole()))
-            .unwrap();
+        let event = convert_one(
+            &mut converter,
+            BitbankInboundMessage::DepthWhole(depth_whole()),
+        );
 
         let Some(MarketEvent::DepthUpdated { pair, depth }) = event else {
             panic!("expected depth update");
@@ -507,6 +1965,218 @@ mod tests {
         assert_eq!(depth.last_timestamp(), 1234);
     }
 
+    #[test]
+    fn bitbank_converter_emits_depth_deltas_by_mode() {
+        let diff = |s: &str, bid: &str| BitbankDepthDiff {
+            a: vec![],
+            b: vec![vec![bid.to_owned(), "0.5".to_owned()]],
+            ao: None,
+            bu: None,
+            au: None,
+            bo: None,
+            am: None,
+            bm: None,
+            t: 1300,
+            s: s.to_owned(),
+        };
+
+        let mut converter = BitbankMarketEventConverter::new("btc_jpy".to_owned())
+            .with_depth_events(DepthEventMode::Delta);
+        // 全体板はスナップショットで渡す
+        let event = convert_one(
+            &mut converter,
+            BitbankInboundMessage::DepthWhole(depth_whole()),
+        );
+        assert!(matches!(event, Some(MarketEvent::DepthUpdated { .. })));
+
+        let event = convert_one(
+            &mut converter,
+            BitbankInboundMessage::DepthDiff(diff("11", "99")),
+        );
+        let Some(MarketEvent::DepthDelta { delta, .. }) = event else {
+            panic!("expected depth delta");
+        };
+        assert_eq!(delta.bids, vec![(Decimal::new(99, 0), Decimal::new(5, 1))]);
+        assert!(!delta.best_price_changed);
+
+        let event = convert_one(
+            &mut converter,
+            BitbankInboundMessage::DepthDiff(diff("12", "100.5")),
+        );
+        let Some(MarketEvent::DepthDelta { delta, .. }) = event else {
+            panic!("expected depth delta");
+        };
+        assert!(delta.best_price_changed);
+
+        let mut converter = BitbankMarketEventConverter::new("btc_jpy".to_owned())
+            .with_depth_events(DepthEventMode::Both);
+        converter
+            .convert(BitbankInboundMessage::DepthWhole(depth_whole()))
+            .unwrap();
+        let events = converter
+            .convert(BitbankInboundMessage::DepthDiff(diff("11", "99")))
+            .unwrap();
+        assert!(matches!(
+            events.as_slice(),
+            [
+                MarketEvent::DepthDelta { .. },
+                MarketEvent::DepthUpdated { .. }
+            ]
+        ));
+    }
+
+    #[test]
+    fn bitbank_converter_resets_depth_on_disconnect() {
+        let mut converter = BitbankMarketEventConverter::new("btc_jpy".to_owned());
+        convert_one(
+            &mut converter,
+            BitbankInboundMessage::DepthWhole(depth_whole()),
+        );
+
+        let event = convert_one(&mut converter, BitbankInboundMessage::Disconnected);
+        let Some(MarketEvent::Disconnected {
+            last_depth: Some(last_depth),
+            ..
+        }) = event
+        else {
+            panic!("expected disconnected with last depth");
+        };
+        assert!(!last_depth.is_complete());
+        assert_eq!(last_depth.best_ask().unwrap().0, &Decimal::new(101, 0));
+
+        let event = convert_one(&mut converter, BitbankInboundMessage::Reconnected);
+        assert_eq!(
+            event,
+            Some(MarketEvent::Reconnected {
+                pair: "btc_jpy".to_owned()
+            })
+        );
+
+        // 新しい全体板を受け取るまで板は更新されない
+        let diff = BitbankDepthDiff {
+            a: vec![vec!["102".to_owned(), "0.5".to_owned()]],
+            b: vec![],
+            ao: None,
+            bu: None,
+            au: None,
+            bo: None,
+            am: None,
+            bm: None,
+            t: 1300,
+            s: "11".to_owned(),
+        };
+        let event = convert_one(&mut converter, BitbankInboundMessage::DepthDiff(diff));
+        assert!(event.is_none());
+
+        let event = convert_one(
+            &mut converter,
+            BitbankInboundMessage::DepthWhole(depth_whole()),
+        );
+        assert!(matches!(event, Some(MarketEvent::DepthUpdated { .. })));
+    }
+
+    #[tokio::test]
+    async fn pair_feed_coalesces_depth_while_strategy_is_slow() {
+        let (inbound_tx, mut inbound_rx) = mpsc::channel(64);
+        let (event_tx, mut event_rx) = mpsc::channel::<MarketEvent>(1);
+        let resync_tx = inbound_tx.downgrade();
+        let metrics = EventQueueMetrics::new();
+        let event_queue = EventQueue::new(BackpressureConfig::default(), metrics.clone());
+
+        for timestamp in 1..=50 {
+            let mut depth = depth_whole();
+            depth.timestamp = timestamp;
+            inbound_tx
+                .send(BitbankInboundMessage::DepthWhole(depth))
+                .await
+                .unwrap();
+        }
+        drop(inbound_tx);
+
+        // 戦略が何も受け取らないうちにフィードが全メッセージを処理する
+        let feed = tokio::spawn(async move {
+            forward_pair_feed(
+                BitbankMarketEventConverter::new("btc_jpy".to_owned()),
+                &mut inbound_rx,
+                &DepthResync::WaitForSnapshot,
+                resync_tx,
+                None,
+                event_queue,
+                &event_tx,
+                None,
+            )
+            .await;
+        });
+
+        let mut timestamps = Vec::new();
+        while let Some(event) = event_rx.recv().await {
+            let MarketEvent::DepthUpdated { depth, .. } = event else {
+                panic!("expected depth");
+            };
+            timestamps.push(depth.last_timestamp());
+        }
+        feed.await.unwrap();
+
+        assert_eq!(timestamps.last(), Some(&50));
+        assert!(timestamps.len() < 50);
+        let stats = metrics.stats();
+        assert_eq!(stats.dropped_total() + stats.delivered, 50);
+        assert_eq!(stats.queued, 0);
+    }
+
+    #[cfg(feature = "mock-server")]
+    #[tokio::test]
+    async fn pair_feed_resyncs_depth_via_rest_after_reconnect() {
+        let server = crate::mock_server::MockBitbankServer::new();
+        let depth_resync = DepthResync::Rest(server.public_client());
+        let (inbound_tx, mut inbound_rx) = mpsc::channel(8);
+        let (event_tx, mut event_rx) = mpsc::channel::<MarketEvent>(8);
+        let resync_tx = inbound_tx.downgrade();
+
+        let feed = tokio::spawn(async move {
+            forward_pair_feed(
+                BitbankMarketEventConverter::new("btc_jpy".to_owned()),
+                &mut inbound_rx,
+                &depth_resync,
+                resync_tx,
+                None,
+                EventQueue::new(BackpressureConfig::deliver_all(), EventQueueMetrics::new()),
+                &event_tx,
+                None,
+            )
+            .await;
+        });
+
+        inbound_tx
+            .send(BitbankInboundMessage::Disconnected)
+            .await
+            .unwrap();
+        inbound_tx
+            .send(BitbankInboundMessage::Reconnected)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            event_rx.recv().await,
+            Some(MarketEvent::Disconnected {
+                last_depth: None,
+                ..
+            })
+        ));
+        assert!(matches!(
+            event_rx.recv().await,
+            Some(MarketEvent::Reconnected { .. })
+        ));
+        let Some(MarketEvent::DepthUpdated { depth, .. }) = event_rx.recv().await else {
+            panic!("expected depth resynced via REST");
+        };
+        assert!(depth.is_complete());
+        assert_eq!(depth.best_bid().unwrap().0, &Decimal::new(4999900, 0));
+
+        drop(inbound_tx);
+        feed.await.unwrap();
+    }
+
     #[test]
     fn bitbank_converter_maps_transactions_to_domain_side() {
         let mut converter = BitbankMarketEventConverter::new("btc_jpy".to_owned());
@@ -518,9 +2188,10 @@ mod tests {
             transaction_id: 42,
         };
 
-        let event = converter
-            .convert(BitbankInboundMessage::Transactions(vec![trade]))
-            .unwrap();
+        let event = convert_one(
+            &mut converter,
+            BitbankInboundMessage::Transactions(vec![trade]),
+        );
 
         let Some(MarketEvent::Transactions { pair, transactions }) = event else {
             panic!("expected transactions");
@@ -555,11 +2226,10 @@ mod tests {
             transaction_id: 1,
         };
 
-        let event = converter
-            .convert(BitbankInboundMessage::Transactions(vec![
-                newest, middle, oldest,
-            ]))
-            .unwrap();
+        let event = convert_one(
+            &mut converter,
+            BitbankInboundMessage::Transactions(vec![newest, middle, oldest]),
+        );
 
         let Some(MarketEvent::Transactions { transactions, .. }) = event else {
             panic!("expected transactions");
@@ -571,6 +2241,118 @@ mod tests {
         assert_eq!(ids, vec![1, 2, 3]);
     }
 
+    #[test]
+    fn bitbank_converter_emits_closed_candles_after_transactions() {
+        let mut converter = BitbankMarketEventConverter::new("btc_jpy".to_owned())
+            .with_candles(vec![CandleAggregator::new(BarKind::Tick(2))]);
+        let trade = |transaction_id, price| BitbankTransactionDatum {
+            amount: Decimal::new(1, 0),
+            executed_at: 1000 * transaction_id,
+            price: Decimal::new(price, 0),
+            side: "buy".to_owned(),
+            transaction_id,
+        };
+
+        let events = converter
+            .convert(BitbankInboundMessage::Transactions(vec![
+                trade(1, 100),
+                trade(2, 110),
+                trade(3, 90),
+            ]))
+            .unwrap();
+
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0], MarketEvent::Transactions { .. }));
+        let MarketEvent::Candle { pair, candle } = &events[1] else {
+            panic!("expected candle");
+        };
+        assert_eq!(pair, "btc_jpy");
+        assert_eq!((candle.open_time, candle.close_time), (1000, 2000));
+        assert_eq!(candle.high, Decimal::new(110, 0));
+        assert_eq!(
+            converter.candles[0].current().unwrap().open,
+            Decimal::new(90, 0)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pair_feed_closes_time_bars_on_a_quiet_market() {
+        let converter = BitbankMarketEventConverter::new("btc_jpy".to_owned()).with_candles(vec![
+            CandleAggregator::new(BarKind::Time(CandleInterval::OneMinute)),
+        ]);
+        let (inbound_tx, mut inbound_rx) = mpsc::channel(8);
+        let (event_tx, mut event_rx) = mpsc::channel::<MarketEvent>(8);
+        let resync_tx = inbound_tx.downgrade();
+
+        let feed = tokio::spawn(async move {
+            forward_pair_feed(
+                converter,
+                &mut inbound_rx,
+                &DepthResync::WaitForSnapshot,
+                resync_tx,
+                Some(CANDLE_CLOSE_INTERVAL),
+                EventQueue::new(BackpressureConfig::deliver_all(), EventQueueMetrics::new()),
+                &event_tx,
+                None,
+            )
+            .await;
+        });
+
+        // 2分前に約定が1件あったきり、次の約定は来ない
+        let executed_at = system_now_millis() - 120_000;
+        inbound_tx
+            .send(BitbankInboundMessage::Transactions(vec![
+                BitbankTransactionDatum {
+                    amount: Decimal::new(1, 0),
+                    executed_at,
+                    price: Decimal::new(100, 0),
+                    side: "buy".to_owned(),
+                    transaction_id: 1,
+                },
+            ]))
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            event_rx.recv().await,
+            Some(MarketEvent::Transactions { .. })
+        ));
+        let Some(MarketEvent::Candle { candle, .. }) = event_rx.recv().await else {
+            panic!("expected candle");
+        };
+        assert_eq!(
+            candle.open_time,
+            CandleInterval::OneMinute.bar_start(executed_at)
+        );
+        assert_eq!(candle.close, Decimal::new(100, 0));
+
+        drop(inbound_tx);
+        feed.await.unwrap();
+        assert!(event_rx.recv().await.is_none());
+    }
+
+    #[test]
+    fn bitbank_converter_forwards_decode_errors_as_feed_errors() {
+        let mut converter = BitbankMarketEventConverter::new("btc_jpy".to_owned());
+        let error = WebSocketDecodeError::UnknownRoom {
+            room_name: "new_room_btc_jpy".to_owned(),
+            raw: serde_json::Value::Null,
+        };
+
+        let event = convert_one(
+            &mut converter,
+            BitbankInboundMessage::DecodeError(error.clone()),
+        );
+
+        assert_eq!(
+            event,
+            Some(MarketEvent::FeedError {
+                pair: "btc_jpy".to_owned(),
+                error,
+            })
+        );
+    }
+
     #[test]
     fn bitbank_converter_rejects_unknown_transaction_side() {
         let mut converter = BitbankMarketEventConverter::new("btc_jpy".to_owned());
@@ -592,6 +2374,101 @@ mod tests {
         ));
     }
 
+    struct EventLogStrategy {
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl BotStrategy for EventLogStrategy {
+        type Event = BitbankEvent;
+
+        async fn handle_event(&mut self, event: Self::Event, _ctx: &BotContext<Self::Event>) {
+            let name = match event {
+                BitbankEvent::Connected { pair } => format!("connected {}", pair),
+                BitbankEvent::DepthUpdated { pair, .. } => format!("depth {}", pair),
+                BitbankEvent::Transactions { pair, transactions } => {
+                    format!("transactions {} {}", pair, transactions.len())
+                }
+                other => format!("{:?}", other),
+            };
+            self.events.lock().unwrap().push(name);
+        }
+    }
+
+    #[tokio::test]
+    async fn bot_replays_recorded_messages_instead_of_websocket() {
+        use crate::recorder::{RecordedMessage, RecordedPayload};
+        use crate::replay::ReplaySpeed;
+
+        let dir =
+            std::env::temp_dir().join(format!("bitbankutil-bot-replay-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("bitbank-0.jsonl");
+        let envelope = |room_name: &str, data: serde_json::Value| {
+            RecordedPayload::WebSocket(serde_json::json!(
+                ["message", {"room_name": room_name, "message": {"data": data}}]
+            ))
+        };
+        let records = [
+            ("btc_jpy", RecordedPayload::Connected),
+            ("eth_jpy", RecordedPayload::Connected),
+            (
+                "btc_jpy",
+                envelope("depth_whole_btc_jpy", depth_whole_json()),
+            ),
+            (
+                "btc_jpy",
+                envelope(
+                    "transactions_btc_jpy",
+                    serde_json::json!({"transactions": [{
+                        "transaction_id": 1, "side": "buy", "price": "101",
+                        "amount": "0.1", "executed_at": 1235
+                    }]}),
+                ),
+            ),
+        ];
+        let lines: Vec<String> = records
+            .into_iter()
+            .enumerate()
+            .map(|(i, (pair, payload))| {
+                serde_json::to_string(&RecordedMessage {
+                    received_at: i as i64,
+                    pair: pair.to_owned(),
+                    payload,
+                })
+                .unwrap()
+            })
+            .collect();
+        std::fs::write(&path, lines.join("\n")).unwrap();
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut runtime = BitbankBotBuilder::new(EventLogStrategy {
+            events: events.clone(),
+        })
+        .add_pair("btc_jpy")
+        .backpressure(BackpressureConfig::deliver_all())
+        .replay(
+            ReplaySource::open([&path])
+                .unwrap()
+                .speed(ReplaySpeed::AsFastAsPossible),
+        )
+        .spawn();
+
+        let stats = runtime.replay_finished().await.unwrap();
+        assert_eq!((stats.replayed, stats.skipped), (3, 1));
+        assert!(runtime.replay_finished().await.is_none());
+        runtime.shutdown().await.unwrap();
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                "connected btc_jpy",
+                "depth btc_jpy",
+                "transactions btc_jpy 1"
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[tokio::test]
     async fn forward_bitbank_messages_emits_market_events() {
         let (inbound_tx, mut inbound_rx) = mpsc::channel(4);
@@ -619,6 +2496,6 @@ mod tests {
             panic!("expected ticker");
         };
         assert_eq!(pair, "btc_jpy");
-        assert_eq!(ticker.last, "100");
+        assert_eq!(ticker.last, Decimal::new(100, 0));
     }
 }
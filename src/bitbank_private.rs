@@ -1,24 +1,146 @@
 use crate::bitbank_structs::{
-    BitbankActiveOrdersResponse, BitbankApiResponse, BitbankAssetsData, BitbankCancelOrderResponse,
+    BitbankActiveOrdersResponse, BitbankAssetsData, BitbankCancelOrderResponse,
     BitbankCancelOrdersResponse, BitbankChannelAndTokenResponse, BitbankCreateOrderResponse,
-    BitbankGetOrderResponse, BitbankSpotStatusResponse, BitbankTradeHistoryResponse,
+    BitbankDepositConfirmation, BitbankDepositDatum, BitbankDepositHistoryResponse,
+    BitbankDepositOriginatorsResponse, BitbankGetOrderResponse, BitbankMarginPositionsResponse,
+    BitbankOrdersInfoResponse, BitbankPairsResponse, BitbankSpotStatusResponse,
+    BitbankTradeHistoryResponse, BitbankUnconfirmedDepositsResponse,
+    BitbankWithdrawalAccountResponse, BitbankWithdrawalDatum, BitbankWithdrawalHistoryResponse,
 };
+use crate::error::BitbankError;
+use crate::request_policy::{EndpointClass, RateLimiter, RequestPolicy, RetryPolicy};
+use crate::transport::{BitbankTransport, CryptoBottersTransport};
 use crypto_botters::{
-    bitbank::{BitbankHandleError, BitbankHttpUrl, BitbankOption},
+    bitbank::{BitbankHttpUrl, BitbankOption},
     Client, GetOptions,
 };
+use std::collections::BTreeSet;
+use std::sync::Arc;
 use std::time::Instant;
 
-fn validate_post_order_args(side: &str, r#type: &str, post_only: Option<bool>) {
+// `cancel_orders`や`orders_info`で一度に指定できる注文IDの最大数。
+const MAX_ORDER_IDS_PER_REQUEST: usize = 30;
+
+// 入出金履歴で一度に取得できる最大件数。
+const MAX_HISTORY_COUNT: usize = 100;
+
+fn validate_post_order_args(
+    side: &str,
+    r#type: &str,
+    post_only: Option<bool>,
+    position_side: Option<&str>,
+) {
     assert!(side == "buy" || side == "sell");
+    assert!(
+        position_side.is_none() || position_side == Some("long") || position_side == Some("short")
+    );
     assert!(r#type == "limit" || r#type == "market" || r#type == "stop" || r#type == "stop_limit");
     // post_onlyはlimit注文でのみ指定できる。
     assert!(post_only.is_none() || r#type == "limit");
 }
 
+/// `since`/`end`で期間を指定する履歴APIで、1ページに収まらない件数を取得するためのカーソル。
+/// 取得したページを[`Self::advance`]に渡すと、`end`をページ内の最も古いタイムスタンプまで遡らせる。
+/// 同じタイムスタンプのエントリがページの境界をまたいでも、`uuid`で重複を取り除く。
+/// 1ページすべてが同じタイムスタンプだった場合は、そのタイムスタンプの残りを取得する方法がないため、
+/// エントリを黙って取りこぼさずに[`BitbankError::HistoryPageOverflow`]を返して終了する。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryCursor {
+    since: Option<u64>,
+    end: Option<u64>,
+    seen_uuids: BTreeSet<String>,
+    is_finished: bool,
+}
+
+/// [`HistoryCursor`]でページングできる履歴のエントリ。
+pub trait HistoryEntry {
+    fn uuid(&self) -> &str;
+    fn timestamp(&self) -> u64;
+}
+
+impl HistoryEntry for BitbankDepositDatum {
+    fn uuid(&self) -> &str {
+        &self.uuid
+    }
+
+    fn timestamp(&self) -> u64 {
+        self.found_at.as_u64().unwrap_or_default()
+    }
+}
+
+impl HistoryEntry for BitbankWithdrawalDatum {
+    fn uuid(&self) -> &str {
+        &self.uuid
+    }
+
+    fn timestamp(&self) -> u64 {
+        self.requested_at.as_u64().unwrap_or_default()
+    }
+}
+
+impl HistoryCursor {
+    pub fn new(since: Option<u64>, end: Option<u64>) -> Self {
+        Self {
+            since,
+            end,
+            seen_uuids: BTreeSet::new(),
+            is_finished: false,
+        }
+    }
+
+    pub fn since(&self) -> Option<u64> {
+        self.since
+    }
+
+    pub fn end(&self) -> Option<u64> {
+        self.end
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.is_finished
+    }
+
+    // ページを受け取ってカーソルを進め、まだ見ていないエントリだけを返す。
+    pub fn advance<T: HistoryEntry>(
+        &mut self,
+        page: Vec<T>,
+        page_size: usize,
+    ) -> Result<Vec<T>, BitbankError> {
+        let is_last_page = page.len() < page_size;
+        let oldest_timestamp = page.iter().map(HistoryEntry::timestamp).min();
+        let newest_timestamp = page.iter().map(HistoryEntry::timestamp).max();
+
+        if !is_last_page && oldest_timestamp == newest_timestamp {
+            // `end`を同じタイムスタンプにすると同じページが返り、その前に進めると残りを取りこぼす。
+            self.is_finished = true;
+            return Err(BitbankError::HistoryPageOverflow {
+                timestamp: oldest_timestamp.unwrap_or_default(),
+            });
+        }
+
+        let new_entries = page
+            .into_iter()
+            .filter(|entry| self.seen_uuids.insert(entry.uuid().to_owned()))
+            .collect::<Vec<_>>();
+
+        if is_last_page {
+            self.is_finished = true;
+        } else if new_entries.is_empty() {
+            // 新しいエントリが1件もない場合、同じページを取得し続けることになるので終了する。
+            self.is_finished = true;
+        } else {
+            self.end = oldest_timestamp;
+        }
+
+        Ok(new_entries)
+    }
+}
+
 #[derive(Clone)]
 pub struct BitbankPrivateApiClient {
-    client: Client,
+    transport: Arc<dyn BitbankTransport>,
+    withdrawal_enabled: bool,
+    request_policy: RequestPolicy,
 }
 
 impl BitbankPrivateApiClient {
@@ -48,27 +170,59 @@ impl BitbankPrivateApiClient {
         assert_ne!(<crypto_botters::Client as GetOptions<crypto_botters::bitbank::BitbankOptions>>::default_options(&client).key, Some("".to_owned()));
         assert_ne!(<crypto_botters::Client as GetOptions<crypto_botters::bitbank::BitbankOptions>>::default_options(&client).secret, Some("".to_owned()));
 
-        BitbankPrivateApiClient { client }
+        Self::with_transport(Arc::new(CryptoBottersTransport::new(client)))
+    }
+
+    // bitbank以外（テスト用のモックなど）にリクエストを送るクライアントを作る。
+    pub fn with_transport(transport: Arc<dyn BitbankTransport>) -> BitbankPrivateApiClient {
+        BitbankPrivateApiClient {
+            transport,
+            withdrawal_enabled: false,
+            request_policy: RequestPolicy::default(),
+        }
+    }
+
+    // レート制限を差し替える。同じ`RateLimiter`を渡したクライアント同士は、制限を共有する。
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.request_policy.rate_limiter = rate_limiter;
+        self
+    }
+
+    // 失敗したリクエストの再送を有効にする。デフォルトでは再送しない。
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.request_policy.retry = retry_policy;
+        self
+    }
+
+    pub fn rate_limiter(&self) -> &RateLimiter {
+        &self.request_policy.rate_limiter
+    }
+
+    // `request_withdrawal`を有効にする。戦略が誤って資金を移動しないよう、出金はデフォルトで無効になっている。
+    pub fn enable_withdrawal(mut self) -> Self {
+        self.withdrawal_enabled = true;
+        self
+    }
+
+    pub fn is_withdrawal_enabled(&self) -> bool {
+        self.withdrawal_enabled
     }
 
     // ポジションを確認するために使用する。
-    pub async fn get_assets(&self) -> Result<BitbankAssetsData, Option<BitbankHandleError>> {
+    pub async fn get_assets(&self) -> Result<BitbankAssetsData, BitbankError> {
         let start_time = Instant::now();
-        let res: Result<
-            BitbankApiResponse,
-            crypto_botters::generic_api_client::http::RequestError<
-                &str,
-                crypto_botters::bitbank::BitbankHandleError,
-            >,
-        > = self
-            .client
-            .get_no_query("/user/assets", [BitbankOption::Default])
+        let res = self
+            .request_policy
+            .execute(EndpointClass::Query, "get_assets", || async move {
+                let res = self.transport.get("/user/assets", None).await;
+                crate::response_handler::handle_response("get_assets", res)
+            })
             .await;
 
         let duration = start_time.elapsed();
         log::debug!("get_assets request took {:?}", duration);
 
-        crate::response_handler::handle_response("get_assets", res)
+        res
     }
 
     // 注文情報を取得する。 https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#fetch-order-information
@@ -76,27 +230,31 @@ impl BitbankPrivateApiClient {
         &self,
         pair: &str,
         order_id: u64,
-    ) -> Result<BitbankGetOrderResponse, Option<BitbankHandleError>> {
+    ) -> Result<BitbankGetOrderResponse, BitbankError> {
         let start_time = Instant::now();
-        let res: Result<
-            BitbankApiResponse,
-            crypto_botters::generic_api_client::http::RequestError<&str, BitbankHandleError>,
-        > = self
-            .client
-            .get(
-                "/user/spot/order",
-                Some(&serde_json::json!({"pair": pair, "order_id": order_id})),
-                [BitbankOption::Default],
-            )
+        let res = self
+            .request_policy
+            .execute(EndpointClass::Query, "get_order", || async move {
+                let res = self
+                    .transport
+                    .get(
+                        "/user/spot/order",
+                        Some(&serde_json::json!({"pair": pair, "order_id": order_id})),
+                    )
+                    .await;
+                crate::response_handler::handle_response("get_order", res)
+            })
             .await;
 
         let duration = start_time.elapsed();
         log::debug!("get_order request took {:?}", duration);
 
-        crate::response_handler::handle_response("get_order", res)
+        res
     }
 
     // 新規注文を作成する。 https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#create-new-order
+    // `position_side`に"long"または"short"を指定すると信用取引の注文になる。現物取引の場合は`None`。
+    #[allow(clippy::too_many_arguments)]
     pub async fn post_order(
         &self,
         pair: &str,
@@ -106,9 +264,10 @@ impl BitbankPrivateApiClient {
         r#type: &str,
         post_only: Option<bool>,
         trigger_price: Option<&str>,
-    ) -> Result<BitbankCreateOrderResponse, Option<BitbankHandleError>> {
+        position_side: Option<&str>,
+    ) -> Result<BitbankCreateOrderResponse, BitbankError> {
         let start_time = Instant::now();
-        validate_post_order_args(side, r#type, post_only);
+        validate_post_order_args(side, r#type, post_only, position_side);
 
         let mut body_map = serde_json::Map::new();
 
@@ -133,22 +292,26 @@ impl BitbankPrivateApiClient {
             );
         }
 
-        let res: Result<
-            BitbankApiResponse,
-            crypto_botters::generic_api_client::http::RequestError<&str, BitbankHandleError>,
-        > = self
-            .client
-            .post(
-                "/user/spot/order",
-                Some(&serde_json::Value::Object(body_map)),
-                [BitbankOption::Default],
-            )
+        if let Some(position_side) = position_side {
+            body_map.insert(
+                "position_side".to_string(),
+                serde_json::json!(position_side),
+            );
+        }
+        let body = &serde_json::Value::Object(body_map);
+
+        let res = self
+            .request_policy
+            .execute(EndpointClass::Update, "post_order", || async move {
+                let res = self.transport.post("/user/spot/order", Some(body)).await;
+                crate::response_handler::handle_response("post_order", res)
+            })
             .await;
 
         let duration = start_time.elapsed();
         log::debug!("post_order request took {:?}", duration);
 
-        crate::response_handler::handle_response("post_order", res)
+        res
     }
 
     // 取引履歴を取得する: https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#fetch-trade-history
@@ -160,7 +323,7 @@ impl BitbankPrivateApiClient {
         since: Option<i64>,    // 開始Unixタイムスタンプ
         end: Option<i64>,      // 終了Unixタイムスタンプ
         order: Option<&str>,   // 履歴の順序 (`asc`または`desc`、デフォルトは`desc`)
-    ) -> Result<BitbankTradeHistoryResponse, Option<BitbankHandleError>> {
+    ) -> Result<BitbankTradeHistoryResponse, BitbankError> {
         let start_time = Instant::now();
         let mut request_body = serde_json::Map::new();
 
@@ -186,24 +349,23 @@ impl BitbankPrivateApiClient {
             request_body.insert("order".to_string(), serde_json::json!(order));
         }
 
-        let request_body = request_body; // 不変にする
-
-        let res: Result<
-            BitbankApiResponse,
-            crypto_botters::generic_api_client::http::RequestError<&str, BitbankHandleError>,
-        > = self
-            .client
-            .get(
-                "/user/spot/trade_history",
-                Some(&serde_json::Value::Object(request_body)),
-                [BitbankOption::Default],
-            )
+        let request_body = &serde_json::Value::Object(request_body);
+
+        let res = self
+            .request_policy
+            .execute(EndpointClass::Query, "get_trade_history", || async move {
+                let res = self
+                    .transport
+                    .get("/user/spot/trade_history", Some(request_body))
+                    .await;
+                crate::response_handler::handle_response("get_trade_history", res)
+            })
             .await;
 
         let duration = start_time.elapsed();
         log::debug!("trade_history request took {:?}", duration);
 
-        crate::response_handler::handle_response("get_trade_history", res)
+        res
     }
 
     // 注文をキャンセルする。 https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#cancel-order
@@ -211,24 +373,26 @@ impl BitbankPrivateApiClient {
         &self,
         pair: &str,
         order_id: u64,
-    ) -> Result<BitbankCancelOrderResponse, Option<BitbankHandleError>> {
+    ) -> Result<BitbankCancelOrderResponse, BitbankError> {
         let start_time = Instant::now();
-        let res: Result<
-            BitbankApiResponse,
-            crypto_botters::generic_api_client::http::RequestError<&str, BitbankHandleError>,
-        > = self
-            .client
-            .post(
-                "/user/spot/cancel_order",
-                Some(&serde_json::json!({"pair": pair, "order_id": order_id})),
-                [BitbankOption::Default],
-            )
+        let res = self
+            .request_policy
+            .execute(EndpointClass::Update, "post_cancel_order", || async move {
+                let res = self
+                    .transport
+                    .post(
+                        "/user/spot/cancel_order",
+                        Some(&serde_json::json!({"pair": pair, "order_id": order_id})),
+                    )
+                    .await;
+                crate::response_handler::handle_response("post_cancel_order", res)
+            })
             .await;
 
         let duration = start_time.elapsed();
         log::debug!("post_cancel_order request took {:?}", duration);
 
-        crate::response_handler::handle_response("post_cancel_order", res)
+        res
     }
 
     // 複数の注文をキャンセルする。 https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#cancel-multiple-orders
@@ -236,32 +400,62 @@ impl BitbankPrivateApiClient {
         &self,
         pair: &str,
         order_ids: Vec<u64>,
-    ) -> Result<BitbankCancelOrdersResponse, Option<BitbankHandleError>> {
+    ) -> Result<BitbankCancelOrdersResponse, BitbankError> {
         let start_time = Instant::now();
-        assert!(!order_ids.is_empty() && order_ids.len() <= 30_usize);
-
-        let res: Result<
-            BitbankApiResponse,
-            crypto_botters::generic_api_client::http::RequestError<&str, BitbankHandleError>,
-        > = self
-            .client
-            .post(
-                "/user/spot/cancel_orders",
-                Some(&serde_json::json!({"pair": pair, "order_ids": order_ids})),
-                [BitbankOption::Default],
-            )
+        assert!(!order_ids.is_empty() && order_ids.len() <= MAX_ORDER_IDS_PER_REQUEST);
+        let order_ids = &order_ids;
+
+        let res = self
+            .request_policy
+            .execute(EndpointClass::Update, "post_cancel_orders", || async move {
+                let res = self
+                    .transport
+                    .post(
+                        "/user/spot/cancel_orders",
+                        Some(&serde_json::json!({"pair": pair, "order_ids": order_ids})),
+                    )
+                    .await;
+                crate::response_handler::handle_response("post_cancel_orders", res)
+            })
             .await;
 
         let duration = start_time.elapsed();
         log::debug!("post_cancel_orders request took {:?}", duration);
 
-        crate::response_handler::handle_response("post_cancel_orders", res)
+        res
     }
 
-    // TODO
     // 複数の注文を取得する。 https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#fetch-multiple-orders
-    pub fn post_orders_info(&self) {
-        todo!();
+    // 1リクエストで指定できる注文IDの上限を超える場合は、複数のリクエストに分割して順番に送信する。
+    pub async fn post_orders_info(
+        &self,
+        pair: &str,
+        order_ids: Vec<u64>,
+    ) -> Result<Vec<BitbankGetOrderResponse>, BitbankError> {
+        let start_time = Instant::now();
+        let mut orders = Vec::with_capacity(order_ids.len());
+
+        for chunk in order_ids.chunks(MAX_ORDER_IDS_PER_REQUEST) {
+            let response: BitbankOrdersInfoResponse = self
+                .request_policy
+                .execute(EndpointClass::Query, "post_orders_info", || async move {
+                    let res = self
+                        .transport
+                        .post(
+                            "/user/spot/orders_info",
+                            Some(&serde_json::json!({"pair": pair, "order_ids": chunk})),
+                        )
+                        .await;
+                    crate::response_handler::handle_response("post_orders_info", res)
+                })
+                .await?;
+            orders.extend(response.orders);
+        }
+
+        let duration = start_time.elapsed();
+        log::debug!("post_orders_info request took {:?}", duration);
+
+        Ok(orders)
     }
 
     // 有効な注文を取得する。 https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#fetch-active-orders
@@ -273,7 +467,7 @@ impl BitbankPrivateApiClient {
         end_id: Option<u64>,
         since: Option<u64>,
         end: Option<u64>,
-    ) -> Result<BitbankActiveOrdersResponse, Option<BitbankHandleError>> {
+    ) -> Result<BitbankActiveOrdersResponse, BitbankError> {
         let start_time = Instant::now();
         let mut request_body = serde_json::Map::new();
         if let Some(pair) = pair {
@@ -295,64 +489,437 @@ impl BitbankPrivateApiClient {
             request_body.insert("end".to_string(), serde_json::json!(end));
         }
 
-        let request_body = request_body; // 不変にする
-
-        let res: Result<
-            BitbankApiResponse,
-            crypto_botters::generic_api_client::http::RequestError<&str, BitbankHandleError>,
-        > = self
-            .client
-            .get(
-                "/user/spot/active_orders",
-                Some(&serde_json::Value::Object(request_body)),
-                [BitbankOption::Default],
-            )
+        let request_body = &serde_json::Value::Object(request_body);
+
+        let res = self
+            .request_policy
+            .execute(EndpointClass::Query, "get_active_orders", || async move {
+                let res = self
+                    .transport
+                    .get("/user/spot/active_orders", Some(request_body))
+                    .await;
+                crate::response_handler::handle_response("get_active_orders", res)
+            })
             .await;
 
         let duration = start_time.elapsed();
         log::debug!("get_active_orders request took {:?}", duration);
 
-        crate::response_handler::handle_response("get_active_orders", res)
+        res
     }
 
-    // 取引所のステータスを取得する。 https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#get-exchange-status
-    pub async fn get_status(
+    // 信用取引の建玉を取得する。 https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#get-margin-positions
+    pub async fn get_margin_positions(
+        &self,
+    ) -> Result<BitbankMarginPositionsResponse, BitbankError> {
+        let start_time = Instant::now();
+
+        let res = self
+            .request_policy
+            .execute(
+                EndpointClass::Query,
+                "get_margin_positions",
+                || async move {
+                    let res = self.transport.get("/user/margin/positions", None).await;
+                    crate::response_handler::handle_response("get_margin_positions", res)
+                },
+            )
+            .await;
+
+        let duration = start_time.elapsed();
+        log::debug!("get_margin_positions request took {:?}", duration);
+
+        res
+    }
+
+    // 入金履歴を取得する。 https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#fetch-deposit-history
+    pub async fn get_deposit_history(
+        &self,
+        asset: &str,        // 通貨コード
+        count: Option<u64>, // 取得件数 (最大100)
+        since: Option<u64>, // 開始Unixタイムスタンプ
+        end: Option<u64>,   // 終了Unixタイムスタンプ
+    ) -> Result<BitbankDepositHistoryResponse, BitbankError> {
+        let start_time = Instant::now();
+        let request_body =
+            &serde_json::Value::Object(history_request_body(asset, count, since, end));
+
+        let res = self
+            .request_policy
+            .execute(EndpointClass::Query, "get_deposit_history", || async move {
+                let res = self
+                    .transport
+                    .get("/user/deposit_history", Some(request_body))
+                    .await;
+                crate::response_handler::handle_response("get_deposit_history", res)
+            })
+            .await;
+
+        let duration = start_time.elapsed();
+        log::debug!("get_deposit_history request took {:?}", duration);
+
+        res
+    }
+
+    // `since`から`end`までの入金履歴を、上限件数ごとにページングしてすべて取得する。
+    pub async fn get_all_deposit_history(
         &self,
-    ) -> Result<BitbankSpotStatusResponse, Option<BitbankHandleError>> {
+        asset: &str,
+        since: Option<u64>,
+        end: Option<u64>,
+    ) -> Result<Vec<BitbankDepositDatum>, BitbankError> {
+        let mut cursor = HistoryCursor::new(since, end);
+        let mut deposits = Vec::new();
+
+        while !cursor.is_finished() {
+            let page = self
+                .get_deposit_history(
+                    asset,
+                    Some(MAX_HISTORY_COUNT as u64),
+                    cursor.since(),
+                    cursor.end(),
+                )
+                .await?;
+            deposits.extend(cursor.advance(page.deposits, MAX_HISTORY_COUNT)?);
+        }
+
+        Ok(deposits)
+    }
+
+    // 未確認の入金を取得する。 https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#fetch-unconfirmed-deposits
+    pub async fn get_unconfirmed_deposits(
+        &self,
+    ) -> Result<BitbankUnconfirmedDepositsResponse, BitbankError> {
         let start_time = Instant::now();
 
-        let res: Result<
-            BitbankApiResponse,
-            crypto_botters::generic_api_client::http::RequestError<&str, BitbankHandleError>,
-        > = self
-            .client
-            .get_no_query("/spot/status", [BitbankOption::Default])
+        let res = self
+            .request_policy
+            .execute(
+                EndpointClass::Query,
+                "get_unconfirmed_deposits",
+                || async move {
+                    let res = self.transport.get("/user/unconfirmed_deposits", None).await;
+                    crate::response_handler::handle_response("get_unconfirmed_deposits", res)
+                },
+            )
+            .await;
+
+        let duration = start_time.elapsed();
+        log::debug!("get_unconfirmed_deposits request took {:?}", duration);
+
+        res
+    }
+
+    // 入金元の一覧を取得する。 https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#fetch-deposit-originators
+    pub async fn get_deposit_originators(
+        &self,
+    ) -> Result<BitbankDepositOriginatorsResponse, BitbankError> {
+        let start_time = Instant::now();
+
+        let res = self
+            .request_policy
+            .execute(
+                EndpointClass::Query,
+                "get_deposit_originators",
+                || async move {
+                    let res = self.transport.get("/user/deposit_originators", None).await;
+                    crate::response_handler::handle_response("get_deposit_originators", res)
+                },
+            )
+            .await;
+
+        let duration = start_time.elapsed();
+        log::debug!("get_deposit_originators request took {:?}", duration);
+
+        res
+    }
+
+    // 未確認の入金を、入金元を指定して確認する。 https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#confirm-deposits
+    // レスポンス本体は空オブジェクトなので、そのまま返す。
+    pub async fn post_confirm_deposits(
+        &self,
+        deposits: Vec<BitbankDepositConfirmation>,
+    ) -> Result<serde_json::Value, BitbankError> {
+        let start_time = Instant::now();
+        assert!(!deposits.is_empty());
+        let deposits = &deposits;
+
+        let res = self
+            .request_policy
+            .execute(
+                EndpointClass::Update,
+                "post_confirm_deposits",
+                || async move {
+                    let res = self
+                        .transport
+                        .post(
+                            "/user/confirm_deposits",
+                            Some(&serde_json::json!({"deposits": deposits})),
+                        )
+                        .await;
+                    crate::response_handler::handle_response("post_confirm_deposits", res)
+                },
+            )
+            .await;
+
+        let duration = start_time.elapsed();
+        log::debug!("post_confirm_deposits request took {:?}", duration);
+
+        res
+    }
+
+    // 未確認の入金をすべて、同じ入金元で確認する。 https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#confirm-all-deposits
+    pub async fn post_confirm_deposits_all(
+        &self,
+        originator_uuid: &str,
+    ) -> Result<serde_json::Value, BitbankError> {
+        let start_time = Instant::now();
+
+        let res = self
+            .request_policy
+            .execute(
+                EndpointClass::Update,
+                "post_confirm_deposits_all",
+                || async move {
+                    let res = self
+                        .transport
+                        .post(
+                            "/user/confirm_deposits_all",
+                            Some(&serde_json::json!({"originator_uuid": originator_uuid})),
+                        )
+                        .await;
+                    crate::response_handler::handle_response("post_confirm_deposits_all", res)
+                },
+            )
+            .await;
+
+        let duration = start_time.elapsed();
+        log::debug!("post_confirm_deposits_all request took {:?}", duration);
+
+        res
+    }
+
+    // 登録済みの出金先アカウントを取得する。 https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#get-withdrawal-accounts
+    pub async fn get_withdrawal_account(
+        &self,
+        asset: &str,
+    ) -> Result<BitbankWithdrawalAccountResponse, BitbankError> {
+        let start_time = Instant::now();
+
+        let res = self
+            .request_policy
+            .execute(
+                EndpointClass::Query,
+                "get_withdrawal_account",
+                || async move {
+                    let res = self
+                        .transport
+                        .get(
+                            "/user/withdrawal_account",
+                            Some(&serde_json::json!({"asset": asset})),
+                        )
+                        .await;
+                    crate::response_handler::handle_response("get_withdrawal_account", res)
+                },
+            )
+            .await;
+
+        let duration = start_time.elapsed();
+        log::debug!("get_withdrawal_account request took {:?}", duration);
+
+        res
+    }
+
+    // 出金をリクエストする。 https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#new-withdrawal-request
+    // `enable_withdrawal`で明示的に有効にしたクライアントでのみ実行できる。
+    pub async fn request_withdrawal(
+        &self,
+        asset: &str,
+        account_uuid: &str,
+        amount: &str,
+        otp_token: Option<&str>,
+        sms_token: Option<&str>,
+    ) -> Result<BitbankWithdrawalDatum, BitbankError> {
+        if !self.withdrawal_enabled {
+            log::error!(
+                "request_withdrawal was called on a client without enable_withdrawal. asset: {}, amount: {}",
+                asset,
+                amount
+            );
+            return Err(BitbankError::WithdrawalDisabled);
+        }
+
+        let start_time = Instant::now();
+        let mut request_body = serde_json::Map::new();
+
+        request_body.insert("asset".to_string(), serde_json::json!(asset));
+        request_body.insert("uuid".to_string(), serde_json::json!(account_uuid));
+        request_body.insert("amount".to_string(), serde_json::json!(amount));
+
+        if let Some(otp_token) = otp_token {
+            request_body.insert("otp_token".to_string(), serde_json::json!(otp_token));
+        }
+        if let Some(sms_token) = sms_token {
+            request_body.insert("sms_token".to_string(), serde_json::json!(sms_token));
+        }
+        let request_body = &serde_json::Value::Object(request_body);
+
+        let res = self
+            .request_policy
+            .execute(EndpointClass::Update, "request_withdrawal", || async move {
+                let res = self
+                    .transport
+                    .post("/user/request_withdrawal", Some(request_body))
+                    .await;
+                crate::response_handler::handle_response("request_withdrawal", res)
+            })
+            .await;
+
+        let duration = start_time.elapsed();
+        log::debug!("request_withdrawal request took {:?}", duration);
+
+        res
+    }
+
+    // 出金履歴を取得する。 https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#fetch-withdrawal-history
+    pub async fn get_withdrawal_history(
+        &self,
+        asset: &str,        // 通貨コード
+        count: Option<u64>, // 取得件数 (最大100)
+        since: Option<u64>, // 開始Unixタイムスタンプ
+        end: Option<u64>,   // 終了Unixタイムスタンプ
+    ) -> Result<BitbankWithdrawalHistoryResponse, BitbankError> {
+        let start_time = Instant::now();
+        let request_body =
+            &serde_json::Value::Object(history_request_body(asset, count, since, end));
+
+        let res = self
+            .request_policy
+            .execute(
+                EndpointClass::Query,
+                "get_withdrawal_history",
+                || async move {
+                    let res = self
+                        .transport
+                        .get("/user/withdrawal_history", Some(request_body))
+                        .await;
+                    crate::response_handler::handle_response("get_withdrawal_history", res)
+                },
+            )
+            .await;
+
+        let duration = start_time.elapsed();
+        log::debug!("get_withdrawal_history request took {:?}", duration);
+
+        res
+    }
+
+    // `since`から`end`までの出金履歴を、上限件数ごとにページングしてすべて取得する。
+    pub async fn get_all_withdrawal_history(
+        &self,
+        asset: &str,
+        since: Option<u64>,
+        end: Option<u64>,
+    ) -> Result<Vec<BitbankWithdrawalDatum>, BitbankError> {
+        let mut cursor = HistoryCursor::new(since, end);
+        let mut withdrawals = Vec::new();
+
+        while !cursor.is_finished() {
+            let page = self
+                .get_withdrawal_history(
+                    asset,
+                    Some(MAX_HISTORY_COUNT as u64),
+                    cursor.since(),
+                    cursor.end(),
+                )
+                .await?;
+            withdrawals.extend(cursor.advance(page.withdrawals, MAX_HISTORY_COUNT)?);
+        }
+
+        Ok(withdrawals)
+    }
+
+    // 取引所のステータスを取得する。 https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#get-exchange-status
+    pub async fn get_status(&self) -> Result<BitbankSpotStatusResponse, BitbankError> {
+        let start_time = Instant::now();
+
+        let res = self
+            .request_policy
+            .execute(EndpointClass::Query, "get_status", || async move {
+                let res = self.transport.get("/spot/status", None).await;
+                crate::response_handler::handle_response("get_status", res)
+            })
             .await;
 
         let duration = start_time.elapsed();
         log::debug!("get_status request took {:?}", duration);
 
-        crate::response_handler::handle_response("get_status", res)
+        res
+    }
+
+    // 通貨ペアの設定情報（呼値・数量の桁数や最小注文数量など）を取得する。 https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#get-all-pairs-info
+    pub async fn get_pairs(&self) -> Result<BitbankPairsResponse, BitbankError> {
+        let start_time = Instant::now();
+
+        let res = self
+            .request_policy
+            .execute(EndpointClass::Query, "get_pairs", || async move {
+                let res = self.transport.get("/spot/pairs", None).await;
+                crate::response_handler::handle_response("get_pairs", res)
+            })
+            .await;
+
+        let duration = start_time.elapsed();
+        log::debug!("get_pairs request took {:?}", duration);
+
+        res
     }
 
     // プライベートストリーム用のチャンネルとトークンを取得する。 cf: https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#private-stream
     pub async fn get_channel_and_token(
         &self,
-    ) -> Result<BitbankChannelAndTokenResponse, Option<BitbankHandleError>> {
+    ) -> Result<BitbankChannelAndTokenResponse, BitbankError> {
         let start_time = Instant::now();
 
-        let res: Result<
-            BitbankApiResponse,
-            crypto_botters::generic_api_client::http::RequestError<&str, BitbankHandleError>,
-        > = self
-            .client
-            .get_no_query("/user/subscribe", [BitbankOption::Default])
+        let res = self
+            .request_policy
+            .execute(
+                EndpointClass::Query,
+                "get_channel_and_token",
+                || async move {
+                    let res = self.transport.get("/user/subscribe", None).await;
+                    crate::response_handler::handle_response("get_channel_and_token", res)
+                },
+            )
             .await;
+
         let duration = start_time.elapsed();
         log::debug!("get_channel_and_token request took {:?}", duration);
 
-        crate::response_handler::handle_response("get_channel_and_token", res)
+        res
+    }
+}
+
+fn history_request_body(
+    asset: &str,
+    count: Option<u64>,
+    since: Option<u64>,
+    end: Option<u64>,
+) -> serde_json::Map<String, serde_json::Value> {
+    let mut request_body = serde_json::Map::new();
+    request_body.insert("asset".to_string(), serde_json::json!(asset));
+
+    if let Some(count) = count {
+        request_body.insert("count".to_string(), serde_json::json!(count));
     }
+    if let Some(since) = since {
+        request_body.insert("since".to_string(), serde_json::json!(since));
+    }
+    if let Some(end) = end {
+        request_body.insert("end".to_string(), serde_json::json!(end));
+    }
+
+    request_body
 }
 
 // テスト成功時に標準出力を表示したい場合は、`RUST_LOG=debug cargo test -- --nocapture` を実行してください。
@@ -364,12 +931,105 @@ mod tests {
 
     #[test]
     fn validate_post_order_args_accepts_false_post_only_for_limit_order() {
-        validate_post_order_args("buy", "limit", Some(false));
+        validate_post_order_args("buy", "limit", Some(false), None);
     }
 
     #[test]
     #[should_panic]
     fn validate_post_order_args_rejects_post_only_for_non_limit_order() {
-        validate_post_order_args("buy", "market", Some(false));
+        validate_post_order_args("buy", "market", Some(false), None);
+    }
+
+    #[test]
+    fn validate_post_order_args_accepts_margin_position_side() {
+        validate_post_order_args("sell", "limit", Some(true), Some("short"));
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    struct Entry(&'static str, u64);
+
+    impl HistoryEntry for Entry {
+        fn uuid(&self) -> &str {
+            self.0
+        }
+
+        fn timestamp(&self) -> u64 {
+            self.1
+        }
+    }
+
+    #[test]
+    fn history_cursor_moves_end_back_and_skips_duplicates_on_page_boundary() {
+        let mut cursor = HistoryCursor::new(Some(100), None);
+
+        let first = cursor
+            .advance(vec![Entry("c", 300), Entry("b", 200)], 2)
+            .unwrap();
+        assert_eq!(first, vec![Entry("c", 300), Entry("b", 200)]);
+        assert_eq!(cursor.end(), Some(200));
+        assert_eq!(cursor.since(), Some(100));
+        assert!(!cursor.is_finished());
+
+        let second = cursor
+            .advance(vec![Entry("b", 200), Entry("a", 150)], 2)
+            .unwrap();
+        assert_eq!(second, vec![Entry("a", 150)]);
+        assert_eq!(cursor.end(), Some(150));
+        assert!(!cursor.is_finished());
+
+        let third = cursor
+            .advance(vec![Entry("b", 200), Entry("a", 150)], 2)
+            .unwrap();
+        assert!(third.is_empty());
+        assert!(cursor.is_finished());
+    }
+
+    #[test]
+    fn history_cursor_fails_instead_of_skipping_entries_sharing_one_timestamp() {
+        let mut cursor = HistoryCursor::new(None, None);
+
+        let first = cursor
+            .advance(vec![Entry("e", 300), Entry("d", 200)], 2)
+            .unwrap();
+        assert_eq!(first.len(), 2);
+        assert_eq!(cursor.end(), Some(200));
+
+        // 200のエントリは"d", "c", "b"の3件あり、`end = 200`では2ページ目も200だけで埋まる
+        let second = cursor.advance(vec![Entry("d", 200), Entry("c", 200)], 2);
+        assert!(matches!(
+            second,
+            Err(BitbankError::HistoryPageOverflow { timestamp: 200 })
+        ));
+        assert!(cursor.is_finished());
+        assert_eq!(cursor.end(), Some(200));
+    }
+
+    #[test]
+    fn history_cursor_finishes_on_short_page() {
+        let mut cursor = HistoryCursor::new(None, None);
+
+        let entries = cursor.advance(vec![Entry("a", 100)], 2).unwrap();
+
+        assert_eq!(entries, vec![Entry("a", 100)]);
+        assert!(cursor.is_finished());
+        assert_eq!(cursor.end(), None);
+    }
+
+    #[tokio::test]
+    async fn request_withdrawal_is_refused_without_opt_in() {
+        let client = BitbankPrivateApiClient::new("key".to_owned(), "secret".to_owned(), None);
+
+        assert!(!client.is_withdrawal_enabled());
+        let res = client
+            .request_withdrawal("btc", "account-uuid", "0.1", None, None)
+            .await;
+        assert!(matches!(res, Err(BitbankError::WithdrawalDisabled)));
+        assert!(client.enable_withdrawal().is_withdrawal_enabled());
+    }
+
+    #[test]
+    #[should_panic]
+    fn validate_post_order_args_rejects_unknown_position_side() {
+        validate_post_order_args("sell", "limit", Some(true), Some("flat"));
     }
 }
@@ -0,0 +1,341 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::sync::mpsc;
+
+use crate::bitbank_private::BitbankPrivateApiClient;
+use crate::bitbank_structs::private_stream_struct::BitbankPrivateStreamMessage;
+use crate::private_event::PrivateEvent;
+use crate::supervision::FeedStatus;
+
+// bitbankのPrivate Streamで使われるPubNubのsubscribe key。
+// cf: https://github.com/bitbankinc/bitbank-api-docs/blob/master/private-stream.md
+pub const BITBANK_PUBNUB_SUBSCRIBE_KEY: &str = "sub-c-ecebae8e-dd60-11e6-b6b1-02ee2ddab7fe";
+pub const PUBNUB_ORIGIN: &str = "https://ps.pndsn.com";
+
+/// Private Stream購読の設定。
+#[derive(Debug, Clone)]
+pub struct BitbankPrivateStreamConfig {
+    pub subscribe_key: String,
+    pub origin: String,
+    /// PubNubに送るクライアントの識別子。
+    pub uuid: String,
+    /// long pollingのタイムアウト。PubNubは最大で約310秒応答を保留するので、それより長くする。
+    pub request_timeout: Duration,
+    /// 再接続の待ち時間の初期値。失敗が続くと`max_reconnect_delay`まで倍々に伸ばす。
+    pub reconnect_delay: Duration,
+    pub max_reconnect_delay: Duration,
+}
+
+impl Default for BitbankPrivateStreamConfig {
+    fn default() -> Self {
+        Self {
+            subscribe_key: BITBANK_PUBNUB_SUBSCRIBE_KEY.to_owned(),
+            origin: PUBNUB_ORIGIN.to_owned(),
+            uuid: "bitbankutil_rs".to_owned(),
+            request_timeout: Duration::from_secs(330),
+            reconnect_delay: Duration::from_secs(1),
+            max_reconnect_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct PubNubSubscribeResponse {
+    t: PubNubTimetoken,
+    m: Vec<PubNubMessage>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct PubNubTimetoken {
+    t: String,
+    r: i64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct PubNubMessage {
+    c: String,
+    d: serde_json::Value,
+}
+
+/// 次に購読を再開する位置。再接続・トークン更新の後も同じ位置から購読することで、メッセージの取りこぼしを防ぐ。
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SubscribeCursor {
+    timetoken: String,
+    region: Option<i64>,
+}
+
+impl SubscribeCursor {
+    fn initial() -> Self {
+        Self {
+            timetoken: "0".to_owned(),
+            region: None,
+        }
+    }
+}
+
+enum SubscribeError {
+    // トークンの期限切れなど、チャンネル・トークンの再取得が必要なエラー。
+    Unauthorized,
+    Other(String),
+}
+
+// Private Streamを購読し、受け取ったメッセージを[`PrivateEvent`]として`tx`に送り続ける。
+// `tx`の受信側が閉じられると終了する。
+pub async fn run_private_stream(
+    api_client: BitbankPrivateApiClient,
+    config: BitbankPrivateStreamConfig,
+    tx: mpsc::Sender<PrivateEvent>,
+) {
+    subscribe_private_stream(api_client, config, tx, None).await
+}
+
+// [`run_private_stream`]と同じ。`status`を指定すると、最初に購読できたときにフィードが稼働中になったことを伝える。
+pub(crate) async fn subscribe_private_stream(
+    api_client: BitbankPrivateApiClient,
+    config: BitbankPrivateStreamConfig,
+    tx: mpsc::Sender<PrivateEvent>,
+    mut status: Option<FeedStatus>,
+) {
+    let http_client = match reqwest::Client::builder()
+        .timeout(config.request_timeout)
+        .build()
+    {
+        Ok(http_client) => http_client,
+        Err(err) => {
+            log::error!("failed to build http client for private stream: {:?}", err);
+            return;
+        }
+    };
+
+    let mut cursor = SubscribeCursor::initial();
+    let mut reconnect_delay = config.reconnect_delay;
+
+    loop {
+        let channel_and_token = match api_client.get_channel_and_token().await {
+            Ok(channel_and_token) => channel_and_token,
+            Err(err) => {
+                log::error!(
+                    "failed to get private stream channel and token: {:?}. retry after {:?}",
+                    err,
+                    reconnect_delay
+                );
+                tokio::time::sleep(reconnect_delay).await;
+                reconnect_delay = next_reconnect_delay(reconnect_delay, &config);
+                continue;
+            }
+        };
+
+        log::debug!(
+            "subscribing private stream channel: {}",
+            channel_and_token.pubnub_channel
+        );
+
+        loop {
+            let response = subscribe_once(
+                &http_client,
+                &config,
+                &channel_and_token.pubnub_channel,
+                &channel_and_token.pubnub_token,
+                &cursor,
+            )
+            .await;
+
+            let response = match response {
+                Ok(response) => response,
+                Err(SubscribeError::Unauthorized) => {
+                    // 取得し直したトークンも拒否され続ける場合に、REST APIを叩き続けないよう待つ。
+                    log::info!(
+                        "private stream token was rejected. refreshing token after {:?}",
+                        reconnect_delay
+                    );
+                    tokio::time::sleep(reconnect_delay).await;
+                    reconnect_delay = next_reconnect_delay(reconnect_delay, &config);
+                    break;
+                }
+                Err(SubscribeError::Other(err)) => {
+                    log::warn!(
+                        "private stream subscribe failed: {}. retry after {:?}",
+                        err,
+                        reconnect_delay
+                    );
+                    tokio::time::sleep(reconnect_delay).await;
+                    reconnect_delay = next_reconnect_delay(reconnect_delay, &config);
+                    continue;
+                }
+            };
+
+            reconnect_delay = config.reconnect_delay;
+            if let Some(status) = status.take() {
+                status.connected();
+            }
+            cursor = SubscribeCursor {
+                timetoken: response.t.t.clone(),
+                region: Some(response.t.r),
+            };
+
+            for event in events_from_subscribe_response(response, &channel_and_token.pubnub_channel)
+            {
+                if tx.send(event).await.is_err() {
+                    log::debug!("private stream stopped because receiver hung up");
+                    return;
+                }
+            }
+        }
+    }
+}
+
+async fn subscribe_once(
+    http_client: &reqwest::Client,
+    config: &BitbankPrivateStreamConfig,
+    channel: &str,
+    token: &str,
+    cursor: &SubscribeCursor,
+) -> Result<PubNubSubscribeResponse, SubscribeError> {
+    let url = format!(
+        "{}/v2/subscribe/{}/{}/0",
+        config.origin, config.subscribe_key, channel
+    );
+    let mut query = vec![
+        ("tt", cursor.timetoken.clone()),
+        ("uuid", config.uuid.clone()),
+        ("auth", token.to_owned()),
+    ];
+    if let Some(region) = cursor.region {
+        query.push(("tr", region.to_string()));
+    }
+
+    let response = http_client
+        .get(url)
+        .query(&query)
+        .send()
+        .await
+        .map_err(|err| SubscribeError::Other(err.to_string()))?;
+
+    let status = response.status();
+    if status == reqwest::StatusCode::FORBIDDEN || status == reqwest::StatusCode::UNAUTHORIZED {
+        return Err(SubscribeError::Unauthorized);
+    }
+    if !status.is_success() {
+        return Err(SubscribeError::Other(format!(
+            "unexpected status {}",
+            status
+        )));
+    }
+
+    let body = response
+        .text()
+        .await
+        .map_err(|err| SubscribeError::Other(err.to_string()))?;
+    serde_json::from_str(&body).map_err(|err| SubscribeError::Other(err.to_string()))
+}
+
+fn events_from_subscribe_response(
+    response: PubNubSubscribeResponse,
+    channel: &str,
+) -> Vec<PrivateEvent> {
+    let mut events = Vec::new();
+
+    for message in response.m {
+        if message.c != channel {
+            log::debug!("ignoring message from other channel: {}", message.c);
+            continue;
+        }
+
+        let stream_message: BitbankPrivateStreamMessage = match serde_json::from_value(message.d) {
+            Ok(stream_message) => stream_message,
+            Err(err) => {
+                log::warn!("dropping invalid private stream message: {:?}", err);
+                continue;
+            }
+        };
+
+        let method = stream_message.method.clone();
+        let conversion = PrivateEvent::from_stream_message(stream_message);
+        for err in &conversion.errors {
+            log::warn!(
+                "dropping private stream {} item that cannot be converted: {:?}",
+                method,
+                err
+            );
+        }
+        match conversion.event {
+            Some(event) => events.push(event),
+            None if conversion.errors.is_empty() => {
+                log::debug!("ignoring unsupported private stream method: {}", method)
+            }
+            None => {}
+        }
+    }
+
+    events
+}
+
+fn next_reconnect_delay(current: Duration, config: &BitbankPrivateStreamConfig) -> Duration {
+    (current * 2).min(config.max_reconnect_delay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn converts_pubnub_envelope_into_private_events() {
+        let response: PubNubSubscribeResponse = serde_json::from_value(json!({
+            "t": {"t": "17100000000000000", "r": 12},
+            "m": [
+                {
+                    "a": "1",
+                    "f": 0,
+                    "i": "publisher",
+                    "p": {"t": "17100000000000000", "r": 12},
+                    "k": BITBANK_PUBNUB_SUBSCRIBE_KEY,
+                    "c": "user_channel",
+                    "d": {
+                        "method": "spot_order_invalidation",
+                        "params": [{"order_id": [10]}]
+                    }
+                },
+                {
+                    "c": "user_channel",
+                    "d": {"method": "dealer_order_new", "params": []}
+                },
+                {
+                    "c": "other_channel",
+                    "d": {
+                        "method": "spot_order_invalidation",
+                        "params": [{"order_id": [11]}]
+                    }
+                },
+                {
+                    "c": "user_channel",
+                    "d": "not a bitbank message"
+                }
+            ]
+        }))
+        .unwrap();
+
+        let events = events_from_subscribe_response(response, "user_channel");
+
+        assert_eq!(events.len(), 1);
+        assert!(matches!(
+            events[0],
+            PrivateEvent::OrdersInvalidated(ref order_ids)
+                if order_ids == &vec![crate::order_domain::OrderId(10)]
+        ));
+    }
+
+    #[test]
+    fn reconnect_delay_doubles_up_to_maximum() {
+        let config = BitbankPrivateStreamConfig {
+            reconnect_delay: Duration::from_secs(1),
+            max_reconnect_delay: Duration::from_secs(3),
+            ..BitbankPrivateStreamConfig::default()
+        };
+
+        let delay = next_reconnect_delay(config.reconnect_delay, &config);
+        assert_eq!(delay, Duration::from_secs(2));
+        assert_eq!(next_reconnect_delay(delay, &config), Duration::from_secs(3));
+    }
+}
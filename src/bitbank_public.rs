@@ -1,18 +1,25 @@
+use std::sync::Arc;
 use std::time::Instant;
 
 use crypto_botters::{
-    bitbank::{BitbankHandleError, BitbankHttpUrl, BitbankOption},
+    bitbank::{BitbankHttpUrl, BitbankOption},
     Client,
 };
 
 use crate::bitbank_structs::{
-    BitbankApiResponse, BitbankCandlestickResponse, BitbankCircuitBreakInfo, BitbankDepthWhole,
+    BitbankCandlestickResponse, BitbankCircuitBreakInfo, BitbankDepthWhole, BitbankPairsResponse,
     BitbankTickerResponse, BitbankTickersDatum, BitbankTransactionsData,
 };
+use crate::error::BitbankError;
+use crate::request_policy::{EndpointClass, RateLimiter, RequestPolicy, RetryPolicy};
+use crate::transport::{BitbankTransport, CryptoBottersTransport};
 
 #[derive(Clone)]
 pub struct BitbankPublicApiClient {
-    client: Client,
+    transport: Arc<dyn BitbankTransport>,
+    // `/spot/pairs`のように、認証は不要だがPrivate APIと同じホストにあるエンドポイント用。
+    api_transport: Arc<dyn BitbankTransport>,
+    request_policy: RequestPolicy,
 }
 
 impl Default for BitbankPublicApiClient {
@@ -26,82 +33,109 @@ impl BitbankPublicApiClient {
         let mut client = Client::new();
         let opt = BitbankOption::HttpUrl(BitbankHttpUrl::Public);
         client.update_default_option(opt);
-        BitbankPublicApiClient { client }
+
+        let mut api_client = Client::new();
+        api_client.update_default_option(BitbankOption::HttpUrl(BitbankHttpUrl::Private));
+        api_client.update_default_option(BitbankOption::HttpAuth(false));
+
+        BitbankPublicApiClient {
+            transport: Arc::new(CryptoBottersTransport::new(client)),
+            api_transport: Arc::new(CryptoBottersTransport::new(api_client)),
+            request_policy: RequestPolicy::default(),
+        }
+    }
+
+    // bitbank以外（テスト用のモックなど）にリクエストを送るクライアントを作る。
+    // Private APIと同じホストにあるエンドポイントも`transport`に送る。
+    pub fn with_transport(transport: Arc<dyn BitbankTransport>) -> BitbankPublicApiClient {
+        BitbankPublicApiClient {
+            api_transport: transport.clone(),
+            transport,
+            request_policy: RequestPolicy::default(),
+        }
+    }
+
+    // レート制限を差し替える。同じ`RateLimiter`を渡したクライアント同士は、制限を共有する。
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.request_policy.rate_limiter = rate_limiter;
+        self
+    }
+
+    // 失敗したリクエストの再送を有効にする。デフォルトでは再送しない。
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.request_policy.retry = retry_policy;
+        self
+    }
+
+    pub fn rate_limiter(&self) -> &RateLimiter {
+        &self.request_policy.rate_limiter
     }
 
     // https://github.com/bitbankinc/bitbank-api-docs/blob/master/public-api.md#ticker
-    pub async fn get_ticker(
-        &self,
-        pair: &str,
-    ) -> Result<BitbankTickerResponse, Option<BitbankHandleError>> {
+    pub async fn get_ticker(&self, pair: &str) -> Result<BitbankTickerResponse, BitbankError> {
         let start_time = Instant::now();
-        let res: Result<
-            BitbankApiResponse,
-            crypto_botters::generic_api_client::http::RequestError<
-                &str,
-                crypto_botters::bitbank::BitbankHandleError,
-            >,
-        > = self
-            .client
-            .get(
-                &format!("/{}/ticker", pair),
-                Some(&serde_json::json!({"pair": pair})),
-                [BitbankOption::Default],
-            )
+        let res = self
+            .request_policy
+            .execute(EndpointClass::Query, "get_ticker", || async move {
+                let res = self
+                    .transport
+                    .get(
+                        &format!("/{}/ticker", pair),
+                        Some(&serde_json::json!({"pair": pair})),
+                    )
+                    .await;
+                crate::response_handler::handle_response("get_ticker", res)
+            })
             .await;
 
         let duration = start_time.elapsed();
         log::debug!("get_ticker request took {:?}", duration);
 
-        crate::response_handler::handle_response("get_ticker", res)
+        res
     }
 
     // https://github.com/bitbankinc/bitbank-api-docs/blob/master/public-api.md#tickers
-    pub async fn get_tickers(
-        &self,
-    ) -> Result<Vec<BitbankTickersDatum>, Option<BitbankHandleError>> {
+    pub async fn get_tickers(&self) -> Result<Vec<BitbankTickersDatum>, BitbankError> {
         let start_time = Instant::now();
-        let res: Result<
-            BitbankApiResponse,
-            crypto_botters::generic_api_client::http::RequestError<&str, BitbankHandleError>,
-        > = self
-            .client
-            .get_no_query("/tickers", [BitbankOption::Default])
+        let res = self
+            .request_policy
+            .execute(EndpointClass::Query, "get_tickers", || async move {
+                let res = self.transport.get("/tickers", None).await;
+                crate::response_handler::handle_response("get_tickers", res)
+            })
             .await;
 
         let duration = start_time.elapsed();
         log::debug!("get_tickers request took {:?}", duration);
 
-        crate::response_handler::handle_response("get_tickers", res)
+        res
     }
 
     // https://github.com/bitbankinc/bitbank-api-docs/blob/master/public-api.md#tickersjpy
-    pub async fn get_tickers_jpy(
-        &self,
-    ) -> Result<Vec<BitbankTickersDatum>, Option<BitbankHandleError>> {
+    pub async fn get_tickers_jpy(&self) -> Result<Vec<BitbankTickersDatum>, BitbankError> {
         let start_time = Instant::now();
-        let res: Result<
-            BitbankApiResponse,
-            crypto_botters::generic_api_client::http::RequestError<&str, BitbankHandleError>,
-        > = self
-            .client
-            .get_no_query("/tickers_jpy", [BitbankOption::Default])
+        let res = self
+            .request_policy
+            .execute(EndpointClass::Query, "get_tickers_jpy", || async move {
+                let res = self.transport.get("/tickers_jpy", None).await;
+                crate::response_handler::handle_response("get_tickers_jpy", res)
+            })
             .await;
 
         let duration = start_time.elapsed();
         log::debug!("get_tickers_jpy request took {:?}", duration);
 
-        crate::response_handler::handle_response("get_tickers_jpy", res)
+        res
     }
 
     pub async fn get_transactions(
         &self,
         pair: &str,
         yyyymmdd: Option<&str>,
-    ) -> Result<BitbankTransactionsData, Option<BitbankHandleError>> {
+    ) -> Result<BitbankTransactionsData, BitbankError> {
         let start_time = Instant::now();
 
-        let url = {
+        let url = &{
             if let Some(yyyymmdd) = yyyymmdd {
                 format!("/{}/transactions/{}", pair, yyyymmdd)
             } else {
@@ -109,41 +143,40 @@ impl BitbankPublicApiClient {
             }
         };
 
-        let res: Result<
-            BitbankApiResponse,
-            crypto_botters::generic_api_client::http::RequestError<&str, BitbankHandleError>,
-        > = self
-            .client
-            .get_no_query(&url, [BitbankOption::Default])
+        let res = self
+            .request_policy
+            .execute(EndpointClass::Query, "get_transactions", || async move {
+                let res = self.transport.get(url, None).await;
+                crate::response_handler::handle_response("get_transactions", res)
+            })
             .await;
 
         let duration = start_time.elapsed();
         log::debug!("get_transactions request took {:?}", duration);
 
-        crate::response_handler::handle_response("get_transactions", res)
+        res
     }
 
-    pub async fn get_depth(
-        &self,
-        pair: &str,
-    ) -> Result<BitbankDepthWhole, Option<BitbankHandleError>> {
+    pub async fn get_depth(&self, pair: &str) -> Result<BitbankDepthWhole, BitbankError> {
         let start_time = Instant::now();
-        let res: Result<
-            BitbankApiResponse,
-            crypto_botters::generic_api_client::http::RequestError<&str, BitbankHandleError>,
-        > = self
-            .client
-            .get(
-                &format!("/{}/depth", pair),
-                Some(&serde_json::json!({"pair": pair})),
-                [BitbankOption::Default],
-            )
+        let res = self
+            .request_policy
+            .execute(EndpointClass::Query, "get_depth", || async move {
+                let res = self
+                    .transport
+                    .get(
+                        &format!("/{}/depth", pair),
+                        Some(&serde_json::json!({"pair": pair})),
+                    )
+                    .await;
+                crate::response_handler::handle_response("get_depth", res)
+            })
             .await;
 
         let duration = start_time.elapsed();
         log::debug!("get_depth request took {:?}", duration);
 
-        crate::response_handler::handle_response("get_depth", res)
+        res
     }
 
     // https://github.com/bitbankinc/bitbank-api-docs/blob/master/public-api.md#candlestick
@@ -152,42 +185,67 @@ impl BitbankPublicApiClient {
         pair: &str,
         candle_type: &str,
         yyyy: &str,
-    ) -> Result<BitbankCandlestickResponse, Option<BitbankHandleError>> {
+    ) -> Result<BitbankCandlestickResponse, BitbankError> {
         let start_time = Instant::now();
-        let url = format!("/{}/candlestick/{}/{}", pair, candle_type, yyyy);
-        let res: Result<
-            BitbankApiResponse,
-            crypto_botters::generic_api_client::http::RequestError<&str, BitbankHandleError>,
-        > = self
-            .client
-            .get_no_query(&url, [BitbankOption::Default])
+        let url = &format!("/{}/candlestick/{}/{}", pair, candle_type, yyyy);
+        let res = self
+            .request_policy
+            .execute(EndpointClass::Query, "get_candlestick", || async move {
+                let res = self.transport.get(url, None).await;
+                crate::response_handler::handle_response("get_candlestick", res)
+            })
             .await;
 
         let duration = start_time.elapsed();
         log::debug!("get_candlestick request took {:?}", duration);
 
-        crate::response_handler::handle_response("get_candlestick", res)
+        res
     }
 
     pub async fn get_circuit_break_info(
         &self,
         pair: &str,
-    ) -> Result<BitbankCircuitBreakInfo, Option<BitbankHandleError>> {
+    ) -> Result<BitbankCircuitBreakInfo, BitbankError> {
         let start_time = Instant::now();
-        let res: Result<
-            BitbankApiResponse,
-            crypto_botters::generic_api_client::http::RequestError<&str, BitbankHandleError>,
-        > = self
-            .client
-            .get(
-                &format!("/{}/circuit_break_info", pair),
-                Some(&serde_json::json!({"pair": pair})),
-                [BitbankOption::Default],
+        let res = self
+            .request_policy
+            .execute(
+                EndpointClass::Query,
+                "get_circuit_break_info",
+                || async move {
+                    let res = self
+                        .transport
+                        .get(
+                            &format!("/{}/circuit_break_info", pair),
+                            Some(&serde_json::json!({"pair": pair})),
+                        )
+                        .await;
+                    crate::response_handler::handle_response("get_circuit_break_info", res)
+                },
             )
             .await;
+
         let duration = start_time.elapsed();
         log::debug!("get_circuit_break_info request took {:?}", duration);
 
-        crate::response_handler::handle_response("get_circuit_break_info", res)
+        res
+    }
+
+    // 通貨ペアの設定情報（呼値・数量の桁数や最小注文数量など）を取得する。認証は不要。
+    // https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#get-all-pairs-info
+    pub async fn get_pairs(&self) -> Result<BitbankPairsResponse, BitbankError> {
+        let start_time = Instant::now();
+        let res = self
+            .request_policy
+            .execute(EndpointClass::Query, "get_pairs", || async move {
+                let res = self.api_transport.get("/spot/pairs", None).await;
+                crate::response_handler::handle_response("get_pairs", res)
+            })
+            .await;
+
+        let duration = start_time.elapsed();
+        log::debug!("get_pairs request took {:?}", duration);
+
+        res
     }
 }
@@ -3,9 +3,11 @@ use serde::Deserialize;
 use serde_json::Number;
 use std::collections::BTreeMap;
 use std::fmt;
+use std::sync::Arc;
 
 use crate::depth::Depth;
 
+pub mod private_stream_struct;
 pub mod websocket_struct;
 
 /// Bitbank APIの標準レスポンス。
@@ -349,6 +351,16 @@ pub struct BitbankCancelOrdersResponse {
     pub orders: Vec<BitbankCancelOrderResponse>,
 }
 
+/// 複数注文取得レスポンス。
+///
+/// 仕様: <https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#fetch-multiple-orders>
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "strict-validation", serde(deny_unknown_fields))]
+pub struct BitbankOrdersInfoResponse {
+    /// 注文情報の一覧。
+    pub orders: Vec<BitbankGetOrderResponse>,
+}
+
 /// アクティブ注文レスポンス。
 #[derive(Deserialize, Debug, Clone)]
 #[cfg_attr(feature = "strict-validation", serde(deny_unknown_fields))]
@@ -357,6 +369,261 @@ pub struct BitbankActiveOrdersResponse {
     pub orders: Vec<BitbankGetOrderResponse>,
 }
 
+/// 信用取引の建玉のエントリ。
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "strict-validation", serde(deny_unknown_fields))]
+pub struct BitbankMarginPositionDatum {
+    /// 通貨ペア。
+    pub pair: String,
+    /// "long" または "short"。
+    pub position_side: String,
+    /// 建玉数量。
+    pub open_amount: String,
+    /// 商品種別。
+    pub product: String,
+    /// 平均建値。
+    pub average_price: String,
+    /// 未払いの手数料額。
+    pub unrealized_fee_amount: String,
+    /// 未払いの金利額。
+    pub unrealized_interest_amount: String,
+    /// ロック中の数量。Private Streamの`margin_position_update`にのみ含まれる。
+    pub locked_amount: Option<String>,
+}
+
+/// 信用取引の通知（追証など）。
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "strict-validation", serde(deny_unknown_fields))]
+pub struct BitbankMarginNotice {
+    /// 通知の種類。通知がない場合はNull。
+    pub what: Option<String>,
+    /// 発生時のUnixタイムスタンプ（ミリ秒）。
+    pub occurred_at: Option<Number>,
+    /// 不足額。
+    pub amount: Option<String>,
+    /// 期限のUnixタイムスタンプ（ミリ秒）。
+    pub due_date_at: Option<Number>,
+}
+
+/// 信用取引の未払金。
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "strict-validation", serde(deny_unknown_fields))]
+pub struct BitbankMarginPayables {
+    /// 未払金の合計額。
+    pub amount: String,
+}
+
+/// ロスカットの基準となる保証金維持率。
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "strict-validation", serde(deny_unknown_fields))]
+pub struct BitbankLosscutThreshold {
+    /// 個人口座の基準。
+    pub individual: String,
+    /// 法人口座の基準。
+    pub company: String,
+}
+
+/// 信用取引の建玉一覧レスポンス。
+///
+/// 仕様: <https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#get-margin-positions>
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "strict-validation", serde(deny_unknown_fields))]
+pub struct BitbankMarginPositionsResponse {
+    /// 追証などの通知。
+    pub notice: BitbankMarginNotice,
+    /// 未払金。
+    pub payables: BitbankMarginPayables,
+    /// 建玉の一覧。
+    pub positions: Vec<BitbankMarginPositionDatum>,
+    /// ロスカット基準。
+    pub losscut_threshold: BitbankLosscutThreshold,
+}
+
+/// 入金履歴のエントリ。
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "strict-validation", serde(deny_unknown_fields))]
+pub struct BitbankDepositDatum {
+    /// 入金ID。
+    pub uuid: String,
+    /// 通貨コード。
+    pub asset: String,
+    /// ネットワーク。JPYでは未定義。
+    pub network: Option<String>,
+    /// 入金数量。
+    pub amount: String,
+    /// トランザクションID。JPYでは未定義。
+    pub txid: Option<String>,
+    /// ステータス: `FOUND`、`CONFIRMED`、`DONE`。
+    pub status: String,
+    /// 入金を検知したUnixタイムスタンプ（ミリ秒）。
+    pub found_at: Number,
+    /// 入金が確定したUnixタイムスタンプ（ミリ秒）。未確定の場合はNull。
+    pub confirmed_at: Option<Number>,
+}
+
+/// 入金履歴レスポンス。
+///
+/// 仕様: <https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#fetch-deposit-history>
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "strict-validation", serde(deny_unknown_fields))]
+pub struct BitbankDepositHistoryResponse {
+    /// 入金履歴の一覧。
+    pub deposits: Vec<BitbankDepositDatum>,
+}
+
+/// 未確認入金のエントリ。
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "strict-validation", serde(deny_unknown_fields))]
+pub struct BitbankUnconfirmedDepositDatum {
+    /// 入金ID。
+    pub uuid: String,
+    /// 通貨コード。
+    pub asset: String,
+    /// 入金数量。
+    pub amount: String,
+    /// ネットワーク。
+    pub network: Option<String>,
+    /// トランザクションID。
+    pub txid: Option<String>,
+    /// 入金を検知したUnixタイムスタンプ（ミリ秒）。
+    pub created_at: Number,
+}
+
+/// 未確認入金一覧レスポンス。
+///
+/// 仕様: <https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#fetch-unconfirmed-deposits>
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "strict-validation", serde(deny_unknown_fields))]
+pub struct BitbankUnconfirmedDepositsResponse {
+    /// 未確認入金の一覧。
+    pub deposits: Vec<BitbankUnconfirmedDepositDatum>,
+}
+
+/// 入金元（送付人）情報のエントリ。
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "strict-validation", serde(deny_unknown_fields))]
+pub struct BitbankDepositOriginatorDatum {
+    /// 入金元ID。
+    pub uuid: String,
+    /// ラベル。
+    pub label: String,
+    /// 入金の種類。
+    pub deposit_type: String,
+    /// 入金の目的。
+    pub deposit_purpose: String,
+    /// ステータス。
+    pub status: String,
+    /// 送付人の種類。
+    pub originator_type: Option<String>,
+    /// 送付人の名前。
+    pub originator_name: Option<String>,
+}
+
+/// 入金元一覧レスポンス。
+///
+/// 仕様: <https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#fetch-deposit-originators>
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "strict-validation", serde(deny_unknown_fields))]
+pub struct BitbankDepositOriginatorsResponse {
+    /// 入金元の一覧。
+    pub deposit_originators: Vec<BitbankDepositOriginatorDatum>,
+}
+
+/// 入金確認リクエストの1件分。
+#[derive(serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct BitbankDepositConfirmation {
+    /// 確認する入金ID。
+    pub uuid: String,
+    /// 入金元ID。
+    pub originator_uuid: String,
+}
+
+/// 出金先アカウントのエントリ。
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "strict-validation", serde(deny_unknown_fields))]
+pub struct BitbankWithdrawalAccountDatum {
+    /// 出金先アカウントID。
+    pub uuid: String,
+    /// ラベル。
+    pub label: String,
+    /// ネットワーク。JPYでは未定義。
+    pub network: Option<String>,
+    /// 出金先アドレス。JPYでは未定義。
+    pub address: Option<String>,
+    /// 宛先タグ。対応する通貨でのみ存在する。
+    pub destination_tag: Option<serde_json::Value>,
+    /// 銀行名。JPYでのみ存在する。
+    pub bank_name: Option<String>,
+    /// 支店名。JPYでのみ存在する。
+    pub branch_name: Option<String>,
+    /// 口座種別。JPYでのみ存在する。
+    pub account_type: Option<String>,
+    /// 口座番号。JPYでのみ存在する。
+    pub account_number: Option<String>,
+    /// 口座名義。JPYでのみ存在する。
+    pub account_owner: Option<String>,
+}
+
+/// 出金先アカウント一覧レスポンス。
+///
+/// 仕様: <https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#get-withdrawal-accounts>
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "strict-validation", serde(deny_unknown_fields))]
+pub struct BitbankWithdrawalAccountResponse {
+    /// 出金先アカウントの一覧。
+    pub accounts: Vec<BitbankWithdrawalAccountDatum>,
+}
+
+/// 出金のエントリ。出金リクエストと出金履歴で共通。
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "strict-validation", serde(deny_unknown_fields))]
+pub struct BitbankWithdrawalDatum {
+    /// 出金ID。
+    pub uuid: String,
+    /// 通貨コード。
+    pub asset: String,
+    /// 出金先アカウントID。
+    pub account_uuid: String,
+    /// 出金数量。
+    pub amount: String,
+    /// 出金手数料。
+    pub fee: String,
+    /// ラベル。
+    pub label: Option<String>,
+    /// 出金先アドレス。JPYでは未定義。
+    pub address: Option<String>,
+    /// ネットワーク。JPYでは未定義。
+    pub network: Option<String>,
+    /// トランザクションID。
+    pub txid: Option<String>,
+    /// 宛先タグ。対応する通貨でのみ存在する。
+    pub destination_tag: Option<serde_json::Value>,
+    /// 銀行名。JPYでのみ存在する。
+    pub bank_name: Option<String>,
+    /// 支店名。JPYでのみ存在する。
+    pub branch_name: Option<String>,
+    /// 口座種別。JPYでのみ存在する。
+    pub account_type: Option<String>,
+    /// 口座番号。JPYでのみ存在する。
+    pub account_number: Option<String>,
+    /// 口座名義。JPYでのみ存在する。
+    pub account_owner: Option<String>,
+    /// ステータス: `CONFIRMING`、`EXAMINING`、`SENDING`、`DONE`、`REJECTED`、`CANCELED`、`CONFIRM_TIMEOUT`。
+    pub status: String,
+    /// 出金をリクエストしたUnixタイムスタンプ（ミリ秒）。
+    pub requested_at: Number,
+}
+
+/// 出金履歴レスポンス。
+///
+/// 仕様: <https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#fetch-withdrawal-history>
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "strict-validation", serde(deny_unknown_fields))]
+pub struct BitbankWithdrawalHistoryResponse {
+    /// 出金履歴の一覧。
+    pub withdrawals: Vec<BitbankWithdrawalDatum>,
+}
+
 /// WebSocket用のチャンネル・トークン情報。
 #[derive(Deserialize, Debug, Clone)]
 #[cfg_attr(feature = "strict-validation", serde(deny_unknown_fields))]
@@ -387,6 +654,96 @@ pub struct BitbankSpotStatusResponse {
     pub statuses: Vec<BitbankSpotStatus>,
 }
 
+/// 通貨ペアの設定情報。
+///
+/// 仕様: <https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#get-all-pairs-info>
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "strict-validation", serde(deny_unknown_fields))]
+pub struct BitbankPairDatum {
+    /// 通貨ペア名。
+    pub name: String,
+    /// ベース通貨。
+    pub base_asset: String,
+    /// クオート通貨。
+    pub quote_asset: String,
+    /// ベース通貨建てのメイカー手数料率。
+    pub maker_fee_rate_base: String,
+    /// ベース通貨建てのテイカー手数料率。
+    pub taker_fee_rate_base: String,
+    /// クオート通貨建てのメイカー手数料率。
+    pub maker_fee_rate_quote: String,
+    /// クオート通貨建てのテイカー手数料率。
+    pub taker_fee_rate_quote: String,
+    /// 信用新規建てのメイカー手数料率。信用取引に対応していないペアではNull。
+    pub margin_open_maker_fee_rate_quote: Option<String>,
+    /// 信用新規建てのテイカー手数料率。信用取引に対応していないペアではNull。
+    pub margin_open_taker_fee_rate_quote: Option<String>,
+    /// 信用決済のメイカー手数料率。信用取引に対応していないペアではNull。
+    pub margin_close_maker_fee_rate_quote: Option<String>,
+    /// 信用決済のテイカー手数料率。信用取引に対応していないペアではNull。
+    pub margin_close_taker_fee_rate_quote: Option<String>,
+    /// ロングポジションの日次利息率。
+    pub margin_long_interest: Option<String>,
+    /// ショートポジションの日次利息率。
+    pub margin_short_interest: Option<String>,
+    /// 現在の個人向け保証金率。
+    pub margin_current_individual_ratio: Option<Number>,
+    /// 現在の個人向け保証金率の適用期限（ミリ秒）。
+    pub margin_current_individual_until: Option<Number>,
+    /// 現在の法人向け保証金率。
+    pub margin_current_company_ratio: Option<Number>,
+    /// 現在の法人向け保証金率の適用期限（ミリ秒）。
+    pub margin_current_company_until: Option<Number>,
+    /// 次回の個人向け保証金率。
+    pub margin_next_individual_ratio: Option<Number>,
+    /// 次回の個人向け保証金率の適用期限（ミリ秒）。
+    pub margin_next_individual_until: Option<Number>,
+    /// 次回の法人向け保証金率。
+    pub margin_next_company_ratio: Option<Number>,
+    /// 次回の法人向け保証金率の適用期限（ミリ秒）。
+    pub margin_next_company_until: Option<Number>,
+    /// 最小注文数量。
+    pub unit_amount: String,
+    /// 指値注文の最大数量。
+    pub limit_max_amount: String,
+    /// 成行注文の最大数量。
+    pub market_max_amount: String,
+    /// 成行注文の許容乖離率。
+    pub market_allowance_rate: String,
+    /// 価格の小数点以下の桁数。
+    pub price_digits: Number,
+    /// 数量の小数点以下の桁数。
+    pub amount_digits: Number,
+    /// ペアが有効かどうか。
+    pub is_enabled: bool,
+    /// 注文受付の停止フラグ。
+    pub stop_order: bool,
+    /// 注文受付・キャンセルの停止フラグ。
+    pub stop_order_and_cancel: bool,
+    /// 成行注文の停止フラグ。
+    pub stop_market_order: bool,
+    /// 逆指値注文の停止フラグ。
+    pub stop_stop_order: bool,
+    /// 逆指値指値注文の停止フラグ。
+    pub stop_stop_limit_order: bool,
+    /// 信用ロング注文の停止フラグ。
+    pub stop_margin_long_order: bool,
+    /// 信用ショート注文の停止フラグ。
+    pub stop_margin_short_order: bool,
+    /// 買い注文の停止フラグ。
+    pub stop_buy_order: bool,
+    /// 売り注文の停止フラグ。
+    pub stop_sell_order: bool,
+}
+
+/// 通貨ペア設定一覧レスポンス。
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "strict-validation", serde(deny_unknown_fields))]
+pub struct BitbankPairsResponse {
+    /// 通貨ペア一覧。
+    pub pairs: Vec<BitbankPairDatum>,
+}
+
 #[allow(non_snake_case)]
 #[derive(Deserialize, Debug, Clone)]
 #[cfg_attr(feature = "strict-validation", serde(deny_unknown_fields))]
@@ -478,28 +835,225 @@ pub struct BitbankDepthWhole {
     sequenceId: String,
 }
 
+/// [`BitbankDepth`]の整合性チェックの設定。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DepthIntegrityConfig {
+    /// 連続する差分のシーケンスIDの差の上限。`Some(1)`なら連番であることを要求する。
+    /// bitbankのシーケンスIDは連続するとは限らないので、デフォルトでは検査しない。
+    pub max_sequence_gap: Option<u64>,
+    /// 最良買い気配が最良売り気配以上になっていないか検査する。
+    /// サーキットブレイク中の板寄せでは板が交差しうるので、その間も使う場合は無効にすること。
+    pub check_crossed_book: bool,
+}
+
+impl Default for DepthIntegrityConfig {
+    fn default() -> Self {
+        Self {
+            max_sequence_gap: None,
+            check_crossed_book: true,
+        }
+    }
+}
+
+/// 板の差分・全体板を反映できなかった、または板を信頼できなくなった理由。
+#[derive(Debug, Clone, PartialEq)]
+pub enum DepthIntegrityError {
+    /// シーケンスIDを数値として解釈できなかった。
+    InvalidSequenceId(String),
+    /// 反映済みの差分と同じシーケンスIDの差分を受け取った。差分は無視され、板はそのまま使える。
+    DuplicateSequence { sequence_id: u64 },
+    /// 反映済みの差分より古い差分を受け取った。
+    OutOfOrder {
+        sequence_id: u64,
+        last_sequence_id: u64,
+    },
+    /// シーケンスIDが[`DepthIntegrityConfig::max_sequence_gap`]を超えて飛んだ。
+    SequenceGap {
+        sequence_id: u64,
+        last_sequence_id: u64,
+    },
+    /// 最良買い気配が最良売り気配以上になった。
+    CrossedBook {
+        best_bid: Decimal,
+        best_ask: Decimal,
+    },
+    /// 価格・数量を解釈できない気配があった。
+    InvalidLevel(Vec<String>),
+}
+
+impl DepthIntegrityError {
+    // 板が信頼できなくなり、新しい全体板が必要かどうか。
+    pub fn requires_resnapshot(&self) -> bool {
+        !matches!(self, DepthIntegrityError::DuplicateSequence { .. })
+    }
+}
+
+impl fmt::Display for DepthIntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DepthIntegrityError::InvalidSequenceId(id) => write!(f, "invalid sequence id: {}", id),
+            DepthIntegrityError::DuplicateSequence { sequence_id } => {
+                write!(f, "duplicate depth diff: {}", sequence_id)
+            }
+            DepthIntegrityError::OutOfOrder {
+                sequence_id,
+                last_sequence_id,
+            } => write!(
+                f,
+                "out-of-order depth diff: {} after {}",
+                sequence_id, last_sequence_id
+            ),
+            DepthIntegrityError::SequenceGap {
+                sequence_id,
+                last_sequence_id,
+            } => write!(
+                f,
+                "depth diff sequence gap: {} after {}",
+                sequence_id, last_sequence_id
+            ),
+            DepthIntegrityError::CrossedBook { best_bid, best_ask } => write!(
+                f,
+                "crossed book: best bid {} >= best ask {}",
+                best_bid, best_ask
+            ),
+            DepthIntegrityError::InvalidLevel(level) => write!(f, "invalid level: {:?}", level),
+        }
+    }
+}
+
+impl std::error::Error for DepthIntegrityError {}
+
+/// 解釈済みの板差分（価格、数量）。
+#[derive(Debug, Clone)]
+struct DepthLevels {
+    asks: Vec<(Decimal, Decimal)>,
+    bids: Vec<(Decimal, Decimal)>,
+}
+
+impl DepthLevels {
+    fn parse(asks: &[Vec<String>], bids: &[Vec<String>]) -> Result<Self, DepthIntegrityError> {
+        Ok(Self {
+            asks: asks
+                .iter()
+                .map(|level| parse_level(level))
+                .collect::<Result<_, _>>()?,
+            bids: bids
+                .iter()
+                .map(|level| parse_level(level))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+fn parse_level(level: &[String]) -> Result<(Decimal, Decimal), DepthIntegrityError> {
+    let invalid = || DepthIntegrityError::InvalidLevel(level.to_vec());
+    let [price, amount] = level else {
+        return Err(invalid());
+    };
+    let price = price.parse::<Decimal>().map_err(|_| invalid())?;
+    let amount = amount.parse::<Decimal>().map_err(|_| invalid())?;
+    if amount < Decimal::ZERO {
+        return Err(invalid());
+    }
+    Ok((price, amount))
+}
+
+fn parse_sequence_id(sequence_id: &str) -> Result<u64, DepthIntegrityError> {
+    sequence_id
+        .parse::<u64>()
+        .map_err(|_| DepthIntegrityError::InvalidSequenceId(sequence_id.to_owned()))
+}
+
+// 数量が0の気配は削除し、それ以外は上書きする。
+// スナップショットと共有している板に気配を反映する。気配がなければ、共有したままコピーしない。
+fn apply_shared_levels(book: &mut SharedLevels, levels: &[(Decimal, Decimal)]) {
+    if !levels.is_empty() {
+        apply_levels(Arc::make_mut(book), levels);
+    }
+}
+
+fn apply_levels(book: &mut BTreeMap<Decimal, Decimal>, levels: &[(Decimal, Decimal)]) {
+    for (price, amount) in levels {
+        if amount.is_zero() {
+            book.remove(price);
+        } else {
+            book.insert(*price, *amount);
+        }
+    }
+}
+
+/// 板の片側（価格、数量）。スナップショットとの間で共有する。
+pub(crate) type SharedLevels = Arc<BTreeMap<Decimal, Decimal>>;
+
+/// [`BitbankDepth::insert_diff`]で板に反映された差分。
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepthDelta {
+    /// 変化した売り気配（価格、新しい数量）。数量0はその価格の気配が消えたことを表す。
+    pub asks: Vec<(Decimal, Decimal)>,
+    /// 変化した買い気配（価格、新しい数量）。
+    pub bids: Vec<(Decimal, Decimal)>,
+    /// 最良売り気配または最良買い気配の価格が変わったか。まとめた差分では、途中のどれかで変わったか。
+    pub best_price_changed: bool,
+    pub sequence_id: u64,
+    /// Unixタイムスタンプ（ミリ秒）。
+    pub timestamp: i64,
+}
+
+impl DepthDelta {
+    // この差分の後に`later`を反映したのと同じ変化になるよう、1つの差分にまとめる。
+    // シーケンスIDとタイムスタンプは`later`のものになる。
+    pub fn merge(&mut self, later: DepthDelta) {
+        merge_levels(&mut self.asks, later.asks);
+        merge_levels(&mut self.bids, later.bids);
+        self.best_price_changed |= later.best_price_changed;
+        self.sequence_id = later.sequence_id;
+        self.timestamp = later.timestamp;
+    }
+}
+
+// 同じ価格の気配は`later`の数量で上書きし、新しい価格の気配は後ろに足す。
+fn merge_levels(levels: &mut Vec<(Decimal, Decimal)>, later: Vec<(Decimal, Decimal)>) {
+    for (price, amount) in later {
+        match levels
+            .iter_mut()
+            .find(|(level_price, _)| *level_price == price)
+        {
+            Some(level) => level.1 = amount,
+            None => levels.push((price, amount)),
+        }
+    }
+}
+
 /// 板情報（差分と全体の統合管理）。
+///
+/// 差分のシーケンスIDを検査し、古い差分や重複した差分は板に反映しない。
+/// 板が信頼できなくなった場合は[`DepthIntegrityError`]を返して`is_complete()`を`false`にし、次の全体板で復帰する。
 #[derive(Debug, Clone)]
 pub struct BitbankDepth {
     /// 差分バッファ（シーケンスID順）。
-    diff_buffer: BTreeMap<String, BitbankDepthDiff>,
-    /// 価格、数量。
-    asks: BTreeMap<Decimal, f64>,
+    diff_buffer: BTreeMap<u64, DepthLevels>,
+    /// 価格、数量。スナップショットと共有し、更新時に共有されていればコピーする。
+    asks: SharedLevels,
     /// 価格、数量。
-    bids: BTreeMap<Decimal, f64>,
+    bids: SharedLevels,
 
-    /// 全体板を受信済みか。
+    /// 全体板を受信済みで、その後の差分に問題がないか。
     is_complete: bool,
     /// 最後に反映したタイムスタンプ。
     last_timestamp: i64,
+    /// 最後に受け取った差分のシーケンスID。
+    last_sequence_id: Option<u64>,
+    /// 最後に反映した全体板のシーケンスID。これより古い差分は全体板に含まれている。
+    snapshot_sequence_id: Option<u64>,
+    integrity: DepthIntegrityConfig,
 }
 
 impl Depth for BitbankDepth {
-    fn asks(&self) -> &BTreeMap<Decimal, f64> {
+    fn asks(&self) -> &BTreeMap<Decimal, Decimal> {
         &self.asks
     }
 
-    fn bids(&self) -> &BTreeMap<Decimal, f64> {
+    fn bids(&self) -> &BTreeMap<Decimal, Decimal> {
         &self.bids
     }
 }
@@ -520,12 +1074,19 @@ impl Default for BitbankDepth {
 
 impl BitbankDepth {
     pub fn new() -> Self {
+        Self::with_integrity_config(DepthIntegrityConfig::default())
+    }
+
+    pub fn with_integrity_config(integrity: DepthIntegrityConfig) -> Self {
         BitbankDepth {
             diff_buffer: BTreeMap::new(),
-            asks: BTreeMap::new(),
-            bids: BTreeMap::new(),
+            asks: Arc::new(BTreeMap::new()),
+            bids: Arc::new(BTreeMap::new()),
             last_timestamp: 0,
             is_complete: false,
+            last_sequence_id: None,
+            snapshot_sequence_id: None,
+            integrity,
         }
     }
 
@@ -537,100 +1098,343 @@ impl BitbankDepth {
         self.last_timestamp
     }
 
-    pub fn insert_diff(&mut self, diff: BitbankDepthDiff) {
-        for ask in &diff.a {
-            let price = &ask[0].parse::<Decimal>().unwrap();
-            let amount = ask[1].parse::<f64>().unwrap();
+    pub fn last_sequence_id(&self) -> Option<u64> {
+        self.last_sequence_id
+    }
 
-            if amount == f64::zero() {
-                self.asks.remove(price);
-            } else {
-                self.asks.insert(*price, amount);
-            }
-        }
+    pub fn integrity_config(&self) -> DepthIntegrityConfig {
+        self.integrity
+    }
 
-        for bid in &diff.b {
-            let price = &bid[0].parse::<Decimal>().unwrap();
-            let amount = bid[1].parse::<f64>().unwrap();
+    // 差分を反映し、板の変化を返す。全体板に含まれている古い差分は無視して`None`を返す。
+    // エラーが`requires_resnapshot()`の場合、次の全体板まで板は不完全になる。
+    pub fn insert_diff(
+        &mut self,
+        diff: BitbankDepthDiff,
+    ) -> Result<Option<DepthDelta>, DepthIntegrityError> {
+        let sequence_id = match parse_sequence_id(&diff.s) {
+            Ok(sequence_id) => sequence_id,
+            Err(err) => return self.invalidate(err),
+        };
+        let levels = match DepthLevels::parse(&diff.a, &diff.b) {
+            Ok(levels) => levels,
+            Err(err) => return self.invalidate(err),
+        };
 
-            if amount == f64::zero() {
-                self.bids.remove(price);
-            } else {
-                self.bids.insert(*price, amount);
+        // 全体板に含まれている差分
+        if self
+            .snapshot_sequence_id
+            .is_some_and(|snapshot| sequence_id < snapshot)
+        {
+            return Ok(None);
+        }
+
+        if let Some(last_sequence_id) = self.last_sequence_id {
+            if sequence_id == last_sequence_id {
+                return Err(DepthIntegrityError::DuplicateSequence { sequence_id });
+            }
+            if sequence_id < last_sequence_id {
+                // 次の全体板の後で正しい順序で反映できるよう、バッファには入れておく
+                self.diff_buffer.insert(sequence_id, levels);
+                return self.invalidate(DepthIntegrityError::OutOfOrder {
+                    sequence_id,
+                    last_sequence_id,
+                });
             }
         }
 
+        let best_prices = self.best_prices();
+        apply_shared_levels(&mut self.asks, &levels.asks);
+        apply_shared_levels(&mut self.bids, &levels.bids);
+
         if self.last_timestamp < diff.t {
             self.last_timestamp = diff.t;
         }
-        self.diff_buffer.insert(diff.s.clone(), diff);
-    }
-
-    pub fn update_whole(&mut self, whole: BitbankDepthWhole) {
-        let seq = whole.sequenceId.clone();
-
-        // `diff_buffer`に残っている、シーケンスIDが`whole`のシーケンスID以下のdiff項目を削除する。
-        let keys_to_remove: Vec<String> = self
-            .diff_buffer
-            .iter()
-            .filter(|(key, _)| key < &&seq)
-            .map(|(key, _)| key.clone())
-            .collect();
+        let delta = DepthDelta {
+            asks: levels.asks.clone(),
+            bids: levels.bids.clone(),
+            best_price_changed: best_prices != self.best_prices(),
+            sequence_id,
+            timestamp: diff.t,
+        };
+        self.diff_buffer.insert(sequence_id, levels);
+        let last_sequence_id = self.last_sequence_id.replace(sequence_id);
 
-        for key in keys_to_remove {
-            self.diff_buffer.remove(&key);
+        if let (Some(last_sequence_id), Some(max_gap)) =
+            (last_sequence_id, self.integrity.max_sequence_gap)
+        {
+            if sequence_id - last_sequence_id > max_gap {
+                return self.invalidate(DepthIntegrityError::SequenceGap {
+                    sequence_id,
+                    last_sequence_id,
+                });
+            }
         }
 
-        self.asks.clear();
-        self.bids.clear();
-
-        for ask in whole.asks {
-            let price = &ask[0].parse::<Decimal>().unwrap();
-            let amount = ask[1].parse::<f64>().unwrap();
+        self.check_crossed_book().map(|()| Some(delta))
+    }
 
-            assert_ne!(amount, f64::zero());
-            self.asks.insert(*price, amount);
+    // 全体板で置き換え、それより新しい差分をバッファから反映し直す。
+    pub fn update_whole(&mut self, whole: BitbankDepthWhole) -> Result<(), DepthIntegrityError> {
+        let seq = match parse_sequence_id(&whole.sequenceId) {
+            Ok(seq) => seq,
+            Err(err) => return self.invalidate(err),
+        };
+        let levels = match DepthLevels::parse(&whole.asks, &whole.bids) {
+            Ok(levels) => levels,
+            Err(err) => return self.invalidate(err),
+        };
+        if let Some((price, amount)) = levels
+            .asks
+            .iter()
+            .chain(levels.bids.iter())
+            .find(|(_, amount)| amount.is_zero())
+        {
+            return self.invalidate(DepthIntegrityError::InvalidLevel(vec![
+                price.to_string(),
+                amount.to_string(),
+            ]));
         }
 
-        for bid in whole.bids {
-            let price = &bid[0].parse::<Decimal>().unwrap();
-            let amount = bid[1].parse::<f64>().unwrap();
+        // `diff_buffer`に残っている、シーケンスIDが`whole`のシーケンスIDより小さいdiff項目を削除する。
+        self.diff_buffer = self.diff_buffer.split_off(&seq);
 
-            assert_ne!(amount, f64::zero());
-            self.bids.insert(*price, amount);
-        }
+        self.asks = Arc::new(levels.asks.into_iter().collect());
+        self.bids = Arc::new(levels.bids.into_iter().collect());
 
         if self.last_timestamp < whole.timestamp {
             self.last_timestamp = whole.timestamp;
         }
+        self.snapshot_sequence_id = Some(seq);
 
         self.process_diff_buffer();
         self.is_complete = true;
+
+        self.check_crossed_book()
     }
 
     fn process_diff_buffer(&mut self) {
-        for (_sequence_id, depth_diff) in self.diff_buffer.iter() {
-            for ask in &depth_diff.a {
-                let price = &ask[0].parse::<Decimal>().unwrap();
-                let amount = ask[1].parse::<f64>().unwrap();
-
-                if amount == f64::zero() {
-                    self.asks.remove(price);
-                } else {
-                    self.asks.insert(*price, amount);
-                }
-            }
+        for levels in self.diff_buffer.values() {
+            apply_shared_levels(&mut self.asks, &levels.asks);
+            apply_shared_levels(&mut self.bids, &levels.bids);
+        }
+    }
+
+    fn best_prices(&self) -> (Option<Decimal>, Option<Decimal>) {
+        (
+            self.best_ask().map(|(price, _)| *price),
+            self.best_bid().map(|(price, _)| *price),
+        )
+    }
+
+    // 板を共有する。次に板が更新されるまで、コピーせずにスナップショットを作れる。
+    pub(crate) fn shared_levels(&self) -> (SharedLevels, SharedLevels) {
+        (Arc::clone(&self.asks), Arc::clone(&self.bids))
+    }
 
-            for bid in &depth_diff.b {
-                let price = &bid[0].parse::<Decimal>().unwrap();
-                let amount = bid[1].parse::<f64>().unwrap();
+    fn check_crossed_book(&mut self) -> Result<(), DepthIntegrityError> {
+        if !self.is_complete || !self.integrity.check_crossed_book {
+            return Ok(());
+        }
 
-                if amount == f64::zero() {
-                    self.bids.remove(price);
-                } else {
-                    self.bids.insert(*price, amount);
-                }
+        match (self.best_bid(), self.best_ask()) {
+            (Some((best_bid, _)), Some((best_ask, _))) if best_bid >= best_ask => {
+                let err = DepthIntegrityError::CrossedBook {
+                    best_bid: *best_bid,
+                    best_ask: *best_ask,
+                };
+                self.invalidate(err)
             }
+            _ => Ok(()),
+        }
+    }
+
+    fn invalidate<T>(&mut self, err: DepthIntegrityError) -> Result<T, DepthIntegrityError> {
+        self.is_complete = false;
+        Err(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diff(sequence_id: &str, asks: &[(&str, &str)], bids: &[(&str, &str)]) -> BitbankDepthDiff {
+        let levels = |levels: &[(&str, &str)]| {
+            levels
+                .iter()
+                .map(|(price, amount)| vec![price.to_string(), amount.to_string()])
+                .collect()
+        };
+        BitbankDepthDiff {
+            a: levels(asks),
+            b: levels(bids),
+            ao: None,
+            bu: None,
+            au: None,
+            bo: None,
+            am: None,
+            bm: None,
+            t: 1000,
+            s: sequence_id.to_owned(),
         }
     }
+
+    fn whole(sequence_id: &str) -> BitbankDepthWhole {
+        serde_json::from_value(serde_json::json!({
+            "asks": [["101", "1"]],
+            "bids": [["100", "1"]],
+            "asks_over": "0",
+            "bids_under": "0",
+            "asks_under": "0",
+            "bids_over": "0",
+            "ask_market": "0",
+            "bid_market": "0",
+            "timestamp": 1000,
+            "sequenceId": sequence_id
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn depth_compares_sequence_ids_numerically() {
+        let mut depth = BitbankDepth::new();
+        // 文字列比較では"10" < "9"になってしまう
+        depth.insert_diff(diff("9", &[("102", "1")], &[])).unwrap();
+        depth.insert_diff(diff("10", &[("103", "1")], &[])).unwrap();
+        depth.update_whole(whole("10")).unwrap();
+
+        assert!(depth.is_complete());
+        assert_eq!(depth.asks().get(&Decimal::new(102, 0)), None);
+        assert_eq!(depth.asks().get(&Decimal::new(103, 0)), Some(&Decimal::ONE));
+        assert_eq!(depth.last_sequence_id(), Some(10));
+
+        // 全体板に含まれている差分は無視する
+        depth.insert_diff(diff("8", &[("104", "1")], &[])).unwrap();
+        assert_eq!(depth.asks().get(&Decimal::new(104, 0)), None);
+    }
+
+    #[test]
+    fn depth_snapshots_share_levels_until_next_update() {
+        use crate::market_event::MarketDepthSnapshot;
+
+        let mut depth = BitbankDepth::new();
+        depth.update_whole(whole("1")).unwrap();
+
+        let snapshot = MarketDepthSnapshot::from(&depth);
+        assert!(std::ptr::eq(snapshot.asks(), depth.asks()));
+
+        let delta = depth
+            .insert_diff(diff("2", &[("101", "0")], &[]))
+            .unwrap()
+            .unwrap();
+        assert!(delta.best_price_changed);
+        assert_eq!(delta.asks, vec![(Decimal::new(101, 0), Decimal::ZERO)]);
+
+        // 更新後もスナップショットは元の板のまま
+        assert_eq!(snapshot.best_ask().unwrap().0, &Decimal::new(101, 0));
+        assert_eq!(depth.best_ask(), None);
+    }
+
+    #[test]
+    fn depth_diff_copies_only_the_side_it_changes() {
+        let mut depth = BitbankDepth::new();
+        depth.update_whole(whole("1")).unwrap();
+        let (asks, bids) = depth.shared_levels();
+
+        depth
+            .insert_diff(diff("2", &[("102", "1")], &[]))
+            .unwrap()
+            .unwrap();
+
+        // 売り気配だけが変わったので、買い気配はスナップショットと共有したまま
+        let (updated_asks, updated_bids) = depth.shared_levels();
+        assert!(!Arc::ptr_eq(&asks, &updated_asks));
+        assert!(Arc::ptr_eq(&bids, &updated_bids));
+    }
+
+    #[test]
+    fn depth_detects_duplicate_and_out_of_order_diffs() {
+        let mut depth = BitbankDepth::new();
+        depth.update_whole(whole("1")).unwrap();
+        depth.insert_diff(diff("5", &[], &[("99", "1")])).unwrap();
+
+        let err = depth.insert_diff(diff("5", &[], &[])).unwrap_err();
+        assert_eq!(
+            err,
+            DepthIntegrityError::DuplicateSequence { sequence_id: 5 }
+        );
+        assert!(!err.requires_resnapshot());
+        assert!(depth.is_complete());
+
+        let err = depth
+            .insert_diff(diff("3", &[], &[("98", "1")]))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            DepthIntegrityError::OutOfOrder {
+                sequence_id: 3,
+                last_sequence_id: 5
+            }
+        );
+        assert!(!depth.is_complete());
+
+        // 次の全体板で復帰し、バッファの差分は順番に反映される
+        depth.update_whole(whole("2")).unwrap();
+        assert!(depth.is_complete());
+        assert_eq!(depth.bids().get(&Decimal::new(98, 0)), Some(&Decimal::ONE));
+        assert_eq!(depth.bids().get(&Decimal::new(99, 0)), Some(&Decimal::ONE));
+    }
+
+    #[test]
+    fn depth_detects_sequence_gap_when_configured() {
+        let mut depth = BitbankDepth::with_integrity_config(DepthIntegrityConfig {
+            max_sequence_gap: Some(1),
+            ..DepthIntegrityConfig::default()
+        });
+        depth.update_whole(whole("1")).unwrap();
+        depth.insert_diff(diff("2", &[], &[])).unwrap();
+        depth.insert_diff(diff("3", &[], &[])).unwrap();
+
+        let err = depth.insert_diff(diff("5", &[], &[])).unwrap_err();
+        assert_eq!(
+            err,
+            DepthIntegrityError::SequenceGap {
+                sequence_id: 5,
+                last_sequence_id: 3
+            }
+        );
+        assert!(!depth.is_complete());
+
+        let mut depth = BitbankDepth::new();
+        depth.update_whole(whole("1")).unwrap();
+        depth.insert_diff(diff("2", &[], &[])).unwrap();
+        depth.insert_diff(diff("100", &[], &[])).unwrap();
+    }
+
+    #[test]
+    fn depth_detects_crossed_book_and_invalid_levels() {
+        let mut depth = BitbankDepth::new();
+        depth.update_whole(whole("1")).unwrap();
+
+        let err = depth
+            .insert_diff(diff("2", &[], &[("101", "0.5")]))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            DepthIntegrityError::CrossedBook {
+                best_bid: Decimal::new(101, 0),
+                best_ask: Decimal::new(101, 0)
+            }
+        );
+        assert!(!depth.is_complete());
+
+        let err = depth
+            .insert_diff(diff("3", &[("abc", "1")], &[]))
+            .unwrap_err();
+        assert!(matches!(err, DepthIntegrityError::InvalidLevel(_)));
+
+        let err = depth.insert_diff(diff("x", &[], &[])).unwrap_err();
+        assert_eq!(err, DepthIntegrityError::InvalidSequenceId("x".to_owned()));
+    }
 }
@@ -0,0 +1,88 @@
+use serde::Deserialize;
+use serde_json::Number;
+
+/// Private Streamで配信されるメッセージ。`params`の中身は`method`ごとに異なる。
+///
+/// 仕様: <https://github.com/bitbankinc/bitbank-api-docs/blob/master/private-stream.md>
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "strict-validation", serde(deny_unknown_fields))]
+pub struct BitbankPrivateStreamMessage {
+    /// メッセージの種類。`asset_update`、`spot_order_new`、`spot_trade`など。
+    pub method: String,
+    /// メッセージ本体の配列。
+    pub params: Vec<serde_json::Value>,
+}
+
+/// `asset_update`で配信される資産情報。
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "strict-validation", serde(deny_unknown_fields))]
+pub struct BitbankPrivateAssetUpdate {
+    /// 通貨コード。
+    pub asset: String,
+    /// 数量の精度。
+    pub amount_precision: Number,
+    /// 利用可能数量。
+    pub free_amount: String,
+    /// ロック中数量。
+    pub locked_amount: String,
+    /// 保有数量。
+    pub onhand_amount: String,
+    /// 出金中数量。
+    pub withdrawing_amount: String,
+}
+
+/// `spot_order_new`、`spot_order`で配信される注文情報。
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "strict-validation", serde(deny_unknown_fields))]
+pub struct BitbankPrivateOrder {
+    /// 注文ID。
+    pub order_id: Number,
+    /// 通貨ペア。
+    pub pair: String,
+    /// "buy" または "sell"。
+    pub side: String,
+    /// 文字列またはnull。
+    pub position_side: Option<String>,
+    /// "limit"、"market"、"stop"、"stop_limit"、"take_profit"、"stop_loss"、"losscut"。
+    pub r#type: String,
+    /// 発注時の注文数量。
+    pub start_amount: Option<String>,
+    /// 未約定の数量。
+    pub remaining_amount: Option<String>,
+    /// 約定済み数量。
+    pub executed_amount: String,
+    /// 注文価格。
+    pub price: Option<String>,
+    /// ポストオンリーかどうか。
+    pub post_only: Option<bool>,
+    /// キャンセル可能な注文かどうか。
+    pub user_cancelable: Option<bool>,
+    /// 平均約定価格。
+    pub average_price: String,
+    /// 発注時のUnixタイムスタンプ（ミリ秒）。
+    pub ordered_at: Number,
+    /// 有効期限のUnixタイムスタンプ（ミリ秒）。
+    pub expire_at: Option<Number>,
+    /// 最後に約定したUnixタイムスタンプ（ミリ秒）。
+    pub executed_at: Option<Number>,
+    /// キャンセル時のUnixタイムスタンプ（ミリ秒）。
+    pub canceled_at: Option<Number>,
+    /// 全約定またはキャンセルで注文が終了したUnixタイムスタンプ（ミリ秒）。
+    pub finished_at: Option<Number>,
+    /// トリガー時のUnixタイムスタンプ（ミリ秒）。
+    pub triggered_at: Option<Number>,
+    /// トリガー価格。
+    pub trigger_price: Option<String>,
+    /// このメッセージでトリガーされたかどうか。
+    pub is_just_triggered: Option<bool>,
+    /// ステータス: `INACTIVE`、`UNFILLED`、`PARTIALLY_FILLED`、`FULLY_FILLED`、`CANCELED_UNFILLED`、`CANCELED_PARTIALLY_FILLED`。
+    pub status: String,
+}
+
+/// `spot_order_invalidation`で配信される、無効になった注文ID。
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "strict-validation", serde(deny_unknown_fields))]
+pub struct BitbankPrivateOrderInvalidation {
+    /// 注文IDの一覧。
+    pub order_id: Vec<Number>,
+}
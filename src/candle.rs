@@ -0,0 +1,528 @@
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+
+use crate::bitbank_public::BitbankPublicApiClient;
+use crate::bitbank_structs::{BitbankCandlestickResponse, BitbankOhlcv};
+use crate::error::BitbankError;
+use crate::market_event::MarketTrade;
+
+/// 時間足の長さ。`OneSecond`以外はbitbankのローソク足の種類と対応する。
+/// 足の区切りはbitbankのローソク足と同じく日本時間（UTC+9）の0時から数える。
+/// 1時間以下の足では、Unix時間（UTC）の倍数と同じになる。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CandleInterval {
+    OneSecond,
+    OneMinute,
+    FiveMinutes,
+    FifteenMinutes,
+    ThirtyMinutes,
+    OneHour,
+    FourHours,
+    EightHours,
+    TwelveHours,
+    OneDay,
+}
+
+/// 足の区切りを数え始める時刻の、UTCからのずれ（ミリ秒）。日本時間の0時に揃える。
+const BAR_ALIGNMENT_OFFSET_MS: i64 = 9 * 60 * 60 * 1000;
+
+impl CandleInterval {
+    pub fn duration_ms(self) -> i64 {
+        const SECOND: i64 = 1000;
+        const MINUTE: i64 = 60 * SECOND;
+        const HOUR: i64 = 60 * MINUTE;
+
+        match self {
+            CandleInterval::OneSecond => SECOND,
+            CandleInterval::OneMinute => MINUTE,
+            CandleInterval::FiveMinutes => 5 * MINUTE,
+            CandleInterval::FifteenMinutes => 15 * MINUTE,
+            CandleInterval::ThirtyMinutes => 30 * MINUTE,
+            CandleInterval::OneHour => HOUR,
+            CandleInterval::FourHours => 4 * HOUR,
+            CandleInterval::EightHours => 8 * HOUR,
+            CandleInterval::TwelveHours => 12 * HOUR,
+            CandleInterval::OneDay => 24 * HOUR,
+        }
+    }
+
+    // `get_candlestick`の`candle_type`。bitbankに無い長さは`None`。
+    pub fn bitbank_candle_type(self) -> Option<&'static str> {
+        match self {
+            CandleInterval::OneSecond => None,
+            CandleInterval::OneMinute => Some("1min"),
+            CandleInterval::FiveMinutes => Some("5min"),
+            CandleInterval::FifteenMinutes => Some("15min"),
+            CandleInterval::ThirtyMinutes => Some("30min"),
+            CandleInterval::OneHour => Some("1hour"),
+            CandleInterval::FourHours => Some("4hour"),
+            CandleInterval::EightHours => Some("8hour"),
+            CandleInterval::TwelveHours => Some("12hour"),
+            CandleInterval::OneDay => Some("1day"),
+        }
+    }
+
+    // `timestamp`（ミリ秒）を含む足の開始時刻。
+    pub fn bar_start(self, timestamp: i64) -> i64 {
+        timestamp - (timestamp + BAR_ALIGNMENT_OFFSET_MS).rem_euclid(self.duration_ms())
+    }
+}
+
+impl FromStr for CandleInterval {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "1sec" => Ok(CandleInterval::OneSecond),
+            "1min" => Ok(CandleInterval::OneMinute),
+            "5min" => Ok(CandleInterval::FiveMinutes),
+            "15min" => Ok(CandleInterval::FifteenMinutes),
+            "30min" => Ok(CandleInterval::ThirtyMinutes),
+            "1hour" => Ok(CandleInterval::OneHour),
+            "4hour" => Ok(CandleInterval::FourHours),
+            "8hour" => Ok(CandleInterval::EightHours),
+            "12hour" => Ok(CandleInterval::TwelveHours),
+            "1day" => Ok(CandleInterval::OneDay),
+            other => Err(format!("unsupported candle type: {}", other)),
+        }
+    }
+}
+
+/// 足の区切り方。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BarKind {
+    /// 一定時間ごとの足。
+    Time(CandleInterval),
+    /// 出来高がこの数量に達するごとに区切る足。区切りをまたぐ約定は分割せず、その約定で足を閉じる。
+    Volume(Decimal),
+    /// 約定がこの回数に達するごとに区切る足。
+    Tick(u32),
+}
+
+/// OHLCVの足。
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candle {
+    pub kind: BarKind,
+    /// 足の開始時刻（ミリ秒）。時間足では区切りの時刻、それ以外では最初の約定の時刻。
+    pub open_time: i64,
+    /// 足の終了時刻（ミリ秒）。時間足では次の足の開始時刻、それ以外では最後の約定の時刻。
+    pub close_time: i64,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+    pub volume: Decimal,
+    /// 足に含まれる約定の数。REST APIの履歴から作った足では0。
+    pub trade_count: u64,
+}
+
+impl Candle {
+    fn open_with(kind: BarKind, trade: &MarketTrade) -> Self {
+        let open_time = match kind {
+            BarKind::Time(interval) => interval.bar_start(trade.executed_at),
+            BarKind::Volume(_) | BarKind::Tick(_) => trade.executed_at,
+        };
+        let close_time = match kind {
+            BarKind::Time(interval) => open_time + interval.duration_ms(),
+            BarKind::Volume(_) | BarKind::Tick(_) => trade.executed_at,
+        };
+
+        Self {
+            kind,
+            open_time,
+            close_time,
+            open: trade.price,
+            high: trade.price,
+            low: trade.price,
+            close: trade.price,
+            volume: trade.amount,
+            trade_count: 1,
+        }
+    }
+
+    fn add_trade(&mut self, trade: &MarketTrade) {
+        self.high = self.high.max(trade.price);
+        self.low = self.low.min(trade.price);
+        self.close = trade.price;
+        self.volume += trade.amount;
+        self.trade_count += 1;
+        if !matches!(self.kind, BarKind::Time(_)) {
+            self.close_time = self.close_time.max(trade.executed_at);
+        }
+    }
+
+    // 約定を加えた後、足を閉じるべきかどうか。
+    fn is_full(&self) -> bool {
+        match self.kind {
+            BarKind::Time(_) => false,
+            BarKind::Volume(threshold) => self.volume >= threshold,
+            BarKind::Tick(count) => self.trade_count >= u64::from(count),
+        }
+    }
+
+    // bitbankのローソク足から作る。
+    pub fn from_bitbank(
+        ohlcv: &BitbankOhlcv,
+        interval: CandleInterval,
+    ) -> Result<Self, rust_decimal::Error> {
+        Ok(Self {
+            kind: BarKind::Time(interval),
+            open_time: ohlcv.timestamp,
+            close_time: ohlcv.timestamp + interval.duration_ms(),
+            open: ohlcv.open.parse()?,
+            high: ohlcv.high.parse()?,
+            low: ohlcv.low.parse()?,
+            close: ohlcv.close.parse()?,
+            volume: ohlcv.volume.parse()?,
+            trade_count: 0,
+        })
+    }
+}
+
+/// 約定から足を作る。閉じた足は[`CandleAggregator::history`]に`max_history`本まで残す。
+#[derive(Debug, Clone)]
+pub struct CandleAggregator {
+    kind: BarKind,
+    current: Option<Candle>,
+    /// 時間足で、最後に閉じた足の終了時刻。これより前の約定は閉じた足に含まれるので捨てる。
+    closed_until: Option<i64>,
+    history: VecDeque<Candle>,
+    max_history: usize,
+}
+
+impl CandleAggregator {
+    pub fn new(kind: BarKind) -> Self {
+        Self {
+            kind,
+            current: None,
+            closed_until: None,
+            history: VecDeque::new(),
+            max_history: 1000,
+        }
+    }
+
+    pub fn with_max_history(mut self, max_history: usize) -> Self {
+        self.max_history = max_history;
+        self.truncate_history();
+        self
+    }
+
+    pub fn kind(&self) -> BarKind {
+        self.kind
+    }
+
+    // 作成中の足。
+    pub fn current(&self) -> Option<&Candle> {
+        self.current.as_ref()
+    }
+
+    // 閉じた足（古い順）。
+    pub fn history(&self) -> &VecDeque<Candle> {
+        &self.history
+    }
+
+    // 過去の足を履歴に入れる。起動時に指標を計算できるようにするため。
+    // 作成中の足より新しい足は無視する。
+    pub fn seed(&mut self, candles: impl IntoIterator<Item = Candle>) {
+        let mut candles = candles
+            .into_iter()
+            .filter(|candle| candle.kind == self.kind)
+            .filter(|candle| {
+                self.current
+                    .as_ref()
+                    .is_none_or(|current| candle.open_time < current.open_time)
+            })
+            .collect::<Vec<_>>();
+        candles.extend(self.history.drain(..));
+        candles.sort_by_key(|candle| candle.open_time);
+        candles.dedup_by_key(|candle| candle.open_time);
+
+        self.history = candles.into();
+        self.truncate_history();
+    }
+
+    // `get_candlestick`のレスポンスから、この足の種類に合うものを履歴に入れる。入れた本数を返す。
+    pub fn seed_from_bitbank(
+        &mut self,
+        response: &BitbankCandlestickResponse,
+    ) -> Result<usize, rust_decimal::Error> {
+        let BarKind::Time(interval) = self.kind else {
+            return Ok(0);
+        };
+        let Some(candle_type) = interval.bitbank_candle_type() else {
+            return Ok(0);
+        };
+
+        let candles = response
+            .candlestick
+            .iter()
+            .filter(|entry| entry.r#type == candle_type)
+            .flat_map(|entry| entry.ohlcv.iter())
+            .map(|ohlcv| Candle::from_bitbank(ohlcv, interval))
+            .collect::<Result<Vec<_>, _>>()?;
+        let count = candles.len();
+        self.seed(candles);
+        Ok(count)
+    }
+
+    // REST APIで過去の足を取得して履歴に入れる。`date`は`get_candlestick`の`YYYY`または`YYYYMMDD`。
+    pub async fn seed_from_rest(
+        &mut self,
+        client: &BitbankPublicApiClient,
+        pair: &str,
+        date: &str,
+    ) -> Result<usize, BitbankError> {
+        let BarKind::Time(interval) = self.kind else {
+            return Ok(0);
+        };
+        let Some(candle_type) = interval.bitbank_candle_type() else {
+            return Ok(0);
+        };
+
+        let response = client.get_candlestick(pair, candle_type, date).await?;
+        self.seed_from_bitbank(&response)
+            .map_err(|err| BitbankError::Decode {
+                api_name: "get_candlestick".to_owned(),
+                message: err.to_string(),
+                raw: serde_json::Value::Null,
+            })
+    }
+
+    // 約定を加え、閉じた足があれば返す。時間足では、次の足の約定を受け取った時点で前の足が閉じる。
+    // 作成中の足や、閉じた足より古い約定は無視する。
+    pub fn push_trade(&mut self, trade: &MarketTrade) -> Option<Candle> {
+        let mut closed = None;
+
+        if self
+            .closed_until
+            .is_some_and(|closed_until| trade.executed_at < closed_until)
+        {
+            log::trace!("ignoring trade {} for a closed bar", trade.transaction_id);
+            return None;
+        }
+
+        if let (BarKind::Time(interval), Some(current)) = (self.kind, &self.current) {
+            let bar_start = interval.bar_start(trade.executed_at);
+            if bar_start < current.open_time {
+                log::trace!("ignoring late trade {}", trade.transaction_id);
+                return None;
+            }
+            if bar_start > current.open_time {
+                closed = self.close_current();
+            }
+        }
+
+        match &mut self.current {
+            Some(current) => current.add_trade(trade),
+            None => self.current = Some(Candle::open_with(self.kind, trade)),
+        }
+
+        if self.current.as_ref().is_some_and(Candle::is_full) {
+            closed = self.close_current();
+        }
+
+        closed
+    }
+
+    // 時間足で、`now`（ミリ秒）までに終わった作成中の足を閉じる。約定が途切れても足を確定させたい場合に使う。
+    pub fn close_until(&mut self, now: i64) -> Option<Candle> {
+        match &self.current {
+            Some(current) if matches!(self.kind, BarKind::Time(_)) && current.close_time <= now => {
+                self.close_current()
+            }
+            _ => None,
+        }
+    }
+
+    fn close_current(&mut self) -> Option<Candle> {
+        let candle = self.current.take()?;
+        if matches!(self.kind, BarKind::Time(_)) {
+            self.closed_until = Some(candle.close_time);
+        }
+        self.history.push_back(candle.clone());
+        self.truncate_history();
+        Some(candle)
+    }
+
+    fn truncate_history(&mut self) {
+        while self.history.len() > self.max_history {
+            self.history.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order_domain::OrderSide;
+
+    fn trade(transaction_id: i64, executed_at: i64, price: i64, amount: Decimal) -> MarketTrade {
+        MarketTrade {
+            amount,
+            executed_at,
+            price: Decimal::new(price, 0),
+            side: OrderSide::Buy,
+            transaction_id,
+        }
+    }
+
+    #[test]
+    fn time_bars_close_on_next_interval() {
+        let mut aggregator = CandleAggregator::new(BarKind::Time(CandleInterval::OneMinute));
+
+        assert_eq!(
+            aggregator.push_trade(&trade(1, 60_500, 100, Decimal::ONE)),
+            None
+        );
+        assert_eq!(
+            aggregator.push_trade(&trade(2, 61_000, 105, Decimal::ONE)),
+            None
+        );
+        assert_eq!(
+            aggregator.push_trade(&trade(3, 119_999, 98, Decimal::TWO)),
+            None
+        );
+
+        let candle = aggregator
+            .push_trade(&trade(4, 125_000, 101, Decimal::ONE))
+            .unwrap();
+        assert_eq!(candle.open_time, 60_000);
+        assert_eq!(candle.close_time, 120_000);
+        assert_eq!(
+            (candle.open, candle.high, candle.low, candle.close),
+            (
+                Decimal::new(100, 0),
+                Decimal::new(105, 0),
+                Decimal::new(98, 0),
+                Decimal::new(98, 0)
+            )
+        );
+        assert_eq!(candle.volume, Decimal::new(4, 0));
+        assert_eq!(candle.trade_count, 3);
+
+        // 閉じた足より古い約定は無視する
+        assert_eq!(
+            aggregator.push_trade(&trade(5, 100_000, 1, Decimal::ONE)),
+            None
+        );
+        assert_eq!(aggregator.current().unwrap().low, Decimal::new(101, 0));
+
+        assert_eq!(aggregator.close_until(179_999), None);
+        assert!(aggregator.close_until(180_000).is_some());
+        assert_eq!(aggregator.history().len(), 2);
+    }
+
+    #[test]
+    fn late_trades_do_not_reopen_a_bar_closed_by_the_timer() {
+        let mut aggregator = CandleAggregator::new(BarKind::Time(CandleInterval::OneMinute));
+        aggregator.push_trade(&trade(1, 60_500, 100, Decimal::ONE));
+
+        // 約定が途切れたのでタイマーで閉じた後に、その足の時刻の約定が遅れて届く
+        let closed = aggregator.close_until(120_000).unwrap();
+        assert_eq!(closed.open_time, 60_000);
+        assert_eq!(
+            aggregator.push_trade(&trade(2, 119_000, 90, Decimal::ONE)),
+            None
+        );
+        assert_eq!(aggregator.current(), None);
+
+        aggregator.push_trade(&trade(3, 120_500, 101, Decimal::ONE));
+        let next = aggregator.close_until(180_000).unwrap();
+        assert_eq!(next.open_time, 120_000);
+        assert_eq!(next.trade_count, 1);
+        assert_eq!(
+            aggregator
+                .history()
+                .iter()
+                .map(|candle| candle.open_time)
+                .collect::<Vec<_>>(),
+            vec![60_000, 120_000]
+        );
+    }
+
+    #[test]
+    fn long_time_bars_start_at_midnight_jst() {
+        // 2024-01-01 00:00 JST = 2023-12-31 15:00 UTC
+        let midnight_jst = 1_704_034_800_000;
+        let hour = 60 * 60 * 1000;
+
+        assert_eq!(
+            CandleInterval::OneDay.bar_start(midnight_jst + 23 * hour),
+            midnight_jst
+        );
+        assert_eq!(
+            CandleInterval::FourHours.bar_start(midnight_jst + 5 * hour),
+            midnight_jst + 4 * hour
+        );
+        assert_eq!(
+            CandleInterval::TwelveHours.bar_start(midnight_jst - 1),
+            midnight_jst - 12 * hour
+        );
+        // 1時間以下の足はUTCの倍数のまま
+        assert_eq!(CandleInterval::OneHour.bar_start(hour + 1), hour);
+    }
+
+    #[test]
+    fn volume_and_tick_bars_close_on_threshold() {
+        let mut volume = CandleAggregator::new(BarKind::Volume(Decimal::new(3, 0)));
+        assert_eq!(volume.push_trade(&trade(1, 1000, 100, Decimal::TWO)), None);
+        let candle = volume
+            .push_trade(&trade(2, 2000, 101, Decimal::TWO))
+            .unwrap();
+        assert_eq!(candle.volume, Decimal::new(4, 0));
+        assert_eq!((candle.open_time, candle.close_time), (1000, 2000));
+
+        let mut tick = CandleAggregator::new(BarKind::Tick(2));
+        assert_eq!(tick.push_trade(&trade(1, 1000, 100, Decimal::ONE)), None);
+        assert!(tick
+            .push_trade(&trade(2, 1000, 100, Decimal::ONE))
+            .is_some());
+        assert_eq!(tick.current(), None);
+    }
+
+    #[test]
+    fn seeds_history_from_bitbank_candles() {
+        let response: BitbankCandlestickResponse = serde_json::from_value(serde_json::json!({
+            "candlestick": [
+                {"type": "1min", "ohlcv": [
+                    ["100", "110", "90", "105", "1.5", 0],
+                    ["105", "106", "104", "104", "0.5", 60000]
+                ]},
+                {"type": "5min", "ohlcv": [["1", "1", "1", "1", "1", 0]]}
+            ],
+            "timestamp": 60000
+        }))
+        .unwrap();
+
+        let mut aggregator =
+            CandleAggregator::new(BarKind::Time(CandleInterval::OneMinute)).with_max_history(1);
+        assert_eq!(aggregator.seed_from_bitbank(&response), Ok(2));
+        assert_eq!(aggregator.history().len(), 1);
+        assert_eq!(aggregator.history()[0].open_time, 60000);
+        assert_eq!(aggregator.history()[0].close, Decimal::new(104, 0));
+    }
+
+    #[cfg(feature = "mock-server")]
+    #[tokio::test]
+    async fn seeds_history_via_rest() {
+        let server = crate::mock_server::MockBitbankServer::new();
+        let mut aggregator = CandleAggregator::new(BarKind::Time(CandleInterval::OneHour));
+
+        let count = aggregator
+            .seed_from_rest(&server.public_client(), "btc_jpy", "20240101")
+            .await
+            .unwrap();
+
+        assert_eq!(count, 1);
+        assert_eq!(aggregator.history()[0].close, Decimal::new(5000000, 0));
+
+        let mut seconds = CandleAggregator::new(BarKind::Time(CandleInterval::OneSecond));
+        let count = seconds
+            .seed_from_rest(&server.public_client(), "btc_jpy", "20240101")
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+}
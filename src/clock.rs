@@ -0,0 +1,140 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::market_event::MarketEvent;
+
+/// まだ一度も進めていない[`SimulatedClock`]の値。
+const UNSTARTED: i64 = i64::MIN;
+
+/// 現在時刻（UNIXエポックからのミリ秒）の取得元。
+/// ライブでは実時刻を使い、リプレイやバックテストでは受け取ったイベントの時刻を使うことで、
+/// 同じ記録からは毎回同じ結果が得られる。
+#[derive(Debug, Clone, Default)]
+pub enum Clock {
+    /// システムの時刻。
+    #[default]
+    System,
+    /// イベントの時刻で進む時計。
+    Simulated(SimulatedClock),
+}
+
+impl Clock {
+    pub fn simulated() -> Self {
+        Clock::Simulated(SimulatedClock::new())
+    }
+
+    pub fn is_simulated(&self) -> bool {
+        matches!(self, Clock::Simulated(_))
+    }
+
+    pub fn now_millis(&self) -> i64 {
+        match self {
+            Clock::System => system_now_millis(),
+            Clock::Simulated(clock) => clock.now_millis(),
+        }
+    }
+
+    // シミュレーションの時計を`millis`まで進める。実時刻の場合は何もしない。
+    pub fn advance_to(&self, millis: i64) {
+        if let Clock::Simulated(clock) = self {
+            clock.advance_to(millis);
+        }
+    }
+}
+
+/// イベントの時刻で進む時計。時刻が戻ることはない。cloneしたものは同じ時刻を共有する。
+#[derive(Debug, Clone)]
+pub struct SimulatedClock {
+    now: Arc<AtomicI64>,
+}
+
+impl Default for SimulatedClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimulatedClock {
+    pub fn new() -> Self {
+        Self {
+            now: Arc::new(AtomicI64::new(UNSTARTED)),
+        }
+    }
+
+    // 一度も進めていない間は0を返す。
+    pub fn now_millis(&self) -> i64 {
+        match self.now.load(Ordering::Acquire) {
+            UNSTARTED => 0,
+            now => now,
+        }
+    }
+
+    // 一度でも進めたかどうか。
+    pub fn is_started(&self) -> bool {
+        self.now.load(Ordering::Acquire) != UNSTARTED
+    }
+
+    // `millis`が現在時刻より前の場合は何もしない。
+    pub fn advance_to(&self, millis: i64) {
+        self.now.fetch_max(millis, Ordering::AcqRel);
+    }
+}
+
+pub(crate) fn system_now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+/// [`Clock::Simulated`]を進めるのに使う、イベントが起きた時刻。
+pub trait EventTimestamp {
+    // イベントが起きた時刻（ミリ秒）。接続状態の変化など、時刻を持たないイベントは`None`。
+    fn timestamp_millis(&self) -> Option<i64>;
+}
+
+impl EventTimestamp for MarketEvent {
+    fn timestamp_millis(&self) -> Option<i64> {
+        match self {
+            MarketEvent::Ticker { ticker, .. } => Some(ticker.timestamp),
+            MarketEvent::Transactions { transactions, .. } => {
+                transactions.iter().map(|trade| trade.executed_at).max()
+            }
+            MarketEvent::DepthUpdated { depth, .. } => Some(depth.last_timestamp()),
+            MarketEvent::CircuitBreakInfo { info, .. } => Some(info.timestamp),
+            MarketEvent::DepthDelta { delta, .. } => Some(delta.timestamp),
+            MarketEvent::Candle { candle, .. } => Some(candle.close_time),
+            MarketEvent::FeedError { .. }
+            | MarketEvent::DepthIntegrityError { .. }
+            | MarketEvent::Connected { .. }
+            | MarketEvent::Disconnected { .. }
+            | MarketEvent::Reconnected { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simulated_clock_only_moves_forward() {
+        let clock = Clock::simulated();
+        let shared = clock.clone();
+        assert_eq!(clock.now_millis(), 0);
+
+        shared.advance_to(1_000);
+        shared.advance_to(500);
+        assert_eq!(clock.now_millis(), 1_000);
+
+        let Clock::Simulated(simulated) = &clock else {
+            unreachable!();
+        };
+        assert!(simulated.is_started());
+
+        let system = Clock::System;
+        system.advance_to(0);
+        assert!(system.now_millis() > 1_000);
+    }
+}
@@ -0,0 +1,302 @@
+use std::fmt;
+
+/// bitbankのAPI呼び出しで発生するエラー。
+#[derive(Debug, Clone, PartialEq)]
+pub enum BitbankError {
+    /// リクエストの送信、またはレスポンスの受信に失敗した。
+    Transport(String),
+    /// リクエストを組み立てられなかった（APIキーが設定されていないなど）。
+    BuildRequest(String),
+    /// HTTPステータスがエラーだった。
+    HttpStatus { status: u16, message: String },
+    /// bitbankがエラーコードを返した。
+    Api(BitbankApiErrorCode),
+    /// レート制限を超えた。
+    RateLimited,
+    /// レスポンスを期待する型に変換できなかった。`raw`は受け取ったJSONそのもの。
+    Decode {
+        api_name: String,
+        message: String,
+        raw: serde_json::Value,
+    },
+    /// crypto-botters側のレスポンス処理で発生したエラー。
+    Handler(String),
+    /// `enable_withdrawal`していないクライアントで出金しようとした。
+    WithdrawalDisabled,
+    /// 履歴のページングで、1ページすべてが同じタイムスタンプ（ミリ秒）のエントリだった。
+    /// `since`/`end`ではそれより細かく区切れないため、取りこぼさずに続きを取得できない。
+    HistoryPageOverflow { timestamp: u64 },
+}
+
+impl BitbankError {
+    // エラーレスポンスの本体（`{"success": 0, "data": {"code": ...}}`または`data`部分）から作る。
+    pub fn from_error_body(body: &serde_json::Value) -> Self {
+        let code = body
+            .get("data")
+            .unwrap_or(body)
+            .get("code")
+            .and_then(serde_json::Value::as_u64)
+            .and_then(|code| u32::try_from(code).ok());
+
+        match code {
+            Some(code) => Self::from_code(code),
+            None => Self::Decode {
+                api_name: String::new(),
+                message: "error response without code".to_owned(),
+                raw: body.clone(),
+            },
+        }
+    }
+
+    pub fn from_code(code: u32) -> Self {
+        match BitbankApiErrorCode::from_code(code) {
+            BitbankApiErrorCode::TooManyRequests => Self::RateLimited,
+            code => Self::Api(code),
+        }
+    }
+
+    pub fn from_http_status(status: u16, message: String) -> Self {
+        if status == 429 {
+            return Self::RateLimited;
+        }
+
+        Self::HttpStatus { status, message }
+    }
+
+    // 同じリクエストをしばらく待ってから再送すれば成功する可能性があるかどうか。
+    // 発注のような冪等でないリクエストを再送するかどうかは、呼び出し側で判断すること。
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            BitbankError::Transport(_) | BitbankError::RateLimited => true,
+            BitbankError::HttpStatus { status, .. } => *status >= 500,
+            BitbankError::Api(code) => code.is_retryable(),
+            BitbankError::BuildRequest(_)
+            | BitbankError::Decode { .. }
+            | BitbankError::Handler(_)
+            | BitbankError::WithdrawalDisabled
+            | BitbankError::HistoryPageOverflow { .. } => false,
+        }
+    }
+
+    pub fn api_error_code(&self) -> Option<BitbankApiErrorCode> {
+        match self {
+            BitbankError::Api(code) => Some(*code),
+            BitbankError::RateLimited => Some(BitbankApiErrorCode::TooManyRequests),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BitbankError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BitbankError::Transport(err) => write!(f, "transport error: {}", err),
+            BitbankError::BuildRequest(err) => write!(f, "failed to build request: {}", err),
+            BitbankError::HttpStatus { status, message } => {
+                write!(f, "http status {}: {}", status, message)
+            }
+            BitbankError::Api(code) => write!(f, "bitbank api error: {:?} ({})", code, code.code()),
+            BitbankError::RateLimited => f.write_str("rate limited"),
+            BitbankError::Decode {
+                api_name, message, ..
+            } => write!(f, "failed to decode {} response: {}", api_name, message),
+            BitbankError::Handler(err) => write!(f, "response handler error: {}", err),
+            BitbankError::WithdrawalDisabled => f.write_str("withdrawal is not enabled"),
+            BitbankError::HistoryPageOverflow { timestamp } => write!(
+                f,
+                "history entries at {} do not fit in one page and cannot be paged further",
+                timestamp
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BitbankError {}
+
+impl<'a>
+    From<
+        crypto_botters::generic_api_client::http::RequestError<
+            &'a str,
+            crypto_botters::bitbank::BitbankHandleError,
+        >,
+    > for BitbankError
+{
+    // HTTPステータスやbitbankのエラーコードが分かる場合は、それに対応するエラーにする。
+    fn from(
+        value: crypto_botters::generic_api_client::http::RequestError<
+            &'a str,
+            crypto_botters::bitbank::BitbankHandleError,
+        >,
+    ) -> Self {
+        use crypto_botters::bitbank::BitbankHandleError;
+        use crypto_botters::generic_api_client::http::RequestError;
+
+        match value {
+            RequestError::SendRequest(error) | RequestError::ReceiveResponse(error) => {
+                match error.status() {
+                    Some(status) => {
+                        BitbankError::from_http_status(status.as_u16(), error.to_string())
+                    }
+                    None => BitbankError::Transport(error.to_string()),
+                }
+            }
+            RequestError::BuildRequestError(error) => {
+                BitbankError::BuildRequest(format!("{:?}", error))
+            }
+            RequestError::ResponseHandleError(BitbankHandleError::ApiError(body)) => {
+                BitbankError::from_error_body(&body)
+            }
+            RequestError::ResponseHandleError(error) => {
+                BitbankError::Handler(format!("{:?}", error))
+            }
+        }
+    }
+}
+
+/// bitbankのエラーコード。よく使うものだけ名前を付け、それ以外は`Other`に入れる。
+///
+/// 仕様: <https://github.com/bitbankinc/bitbank-api-docs/blob/master/errors.md>
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BitbankApiErrorCode {
+    SystemError(u32),
+    Timeout,
+    Maintenance,
+    ServerBusy,
+    TooManyRequests,
+    AuthenticationFailed(u32),
+    OrderNotFound,
+    OrderNotCancelable,
+    InsufficientFunds,
+    TooManyOpenOrders,
+    TradingSuspended,
+    Overloaded(u32),
+    Other(u32),
+}
+
+impl BitbankApiErrorCode {
+    pub fn from_code(code: u32) -> Self {
+        match code {
+            10001 | 10003 | 70001 => Self::SystemError(code),
+            10005 => Self::Timeout,
+            10007 => Self::Maintenance,
+            10008 => Self::ServerBusy,
+            10009 => Self::TooManyRequests,
+            20001..=20005 => Self::AuthenticationFailed(code),
+            50009 => Self::OrderNotFound,
+            50010 => Self::OrderNotCancelable,
+            60001 => Self::InsufficientFunds,
+            60011 => Self::TooManyOpenOrders,
+            70004 => Self::TradingSuspended,
+            70009..=70011 => Self::Overloaded(code),
+            _ => Self::Other(code),
+        }
+    }
+
+    pub fn code(self) -> u32 {
+        match self {
+            Self::SystemError(code) => code,
+            Self::Timeout => 10005,
+            Self::Maintenance => 10007,
+            Self::ServerBusy => 10008,
+            Self::TooManyRequests => 10009,
+            Self::AuthenticationFailed(code) => code,
+            Self::OrderNotFound => 50009,
+            Self::OrderNotCancelable => 50010,
+            Self::InsufficientFunds => 60001,
+            Self::TooManyOpenOrders => 60011,
+            Self::TradingSuspended => 70004,
+            Self::Overloaded(code) => code,
+            Self::Other(code) => code,
+        }
+    }
+
+    pub fn is_retryable(self) -> bool {
+        matches!(
+            self,
+            Self::SystemError(_)
+                | Self::Timeout
+                | Self::Maintenance
+                | Self::ServerBusy
+                | Self::TooManyRequests
+                | Self::Overloaded(_)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn maps_error_body_code_to_named_variant() {
+        assert_eq!(
+            BitbankError::from_error_body(&json!({"success": 0, "data": {"code": 60001}})),
+            BitbankError::Api(BitbankApiErrorCode::InsufficientFunds)
+        );
+        assert_eq!(
+            BitbankError::from_error_body(&json!({"code": 50009})),
+            BitbankError::Api(BitbankApiErrorCode::OrderNotFound)
+        );
+        assert_eq!(
+            BitbankError::from_error_body(&json!({"code": 10009})),
+            BitbankError::RateLimited
+        );
+        assert_eq!(BitbankApiErrorCode::from_code(99999).code(), 99999);
+    }
+
+    #[test]
+    fn retryable_errors_are_distinguished_from_fatal_ones() {
+        assert!(BitbankError::Transport("connection reset".to_owned()).is_retryable());
+        assert!(BitbankError::RateLimited.is_retryable());
+        assert!(BitbankError::from_code(10008).is_retryable());
+        assert!(BitbankError::from_http_status(503, "bad gateway".to_owned()).is_retryable());
+
+        assert!(!BitbankError::from_code(60001).is_retryable());
+        assert!(!BitbankError::from_code(20001).is_retryable());
+        assert!(!BitbankError::WithdrawalDisabled.is_retryable());
+    }
+
+    #[test]
+    fn http_status_429_is_rate_limited() {
+        assert_eq!(
+            BitbankError::from_http_status(429, String::new()),
+            BitbankError::RateLimited
+        );
+        assert!(matches!(
+            BitbankError::from_http_status(502, "bad gateway".to_owned()),
+            BitbankError::HttpStatus { status: 502, .. }
+        ));
+    }
+
+    type RequestError = crypto_botters::generic_api_client::http::RequestError<
+        &'static str,
+        crypto_botters::bitbank::BitbankHandleError,
+    >;
+
+    #[test]
+    fn request_errors_keep_bitbank_error_code() {
+        use crypto_botters::bitbank::BitbankHandleError;
+
+        let api_error = |body| {
+            BitbankError::from(RequestError::ResponseHandleError(
+                BitbankHandleError::ApiError(body),
+            ))
+        };
+        assert_eq!(
+            api_error(json!({"success": 0, "data": {"code": 10009}})),
+            BitbankError::RateLimited
+        );
+        assert_eq!(
+            api_error(json!({"success": 0, "data": {"code": 70010}})),
+            BitbankError::Api(BitbankApiErrorCode::Overloaded(70010))
+        );
+        assert!(api_error(json!({"success": 0, "data": {"code": 10008}})).is_retryable());
+        assert!(matches!(
+            BitbankError::from(RequestError::ResponseHandleError(
+                BitbankHandleError::ParseError
+            )),
+            BitbankError::Handler(_)
+        ));
+    }
+}
@@ -0,0 +1,490 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::market_event::MarketEvent;
+
+/// 未配送のイベントの数の標準の上限。
+pub const DEFAULT_MAX_PENDING_EVENTS: usize = 4096;
+
+/// 戦略の処理が追いつかず、未配送のイベントが溜まったときの扱い。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryPolicy {
+    /// すべて順番どおりに届ける。溜まりすぎた場合は[`BackpressureConfig::max_pending`]に従う。
+    DeliverAll,
+    /// 未配送のものは最新の1つだけを残す。
+    KeepLatest,
+    /// 未配送のものが`capacity`を超えたら古いものから捨てる。
+    DropOldest { capacity: usize },
+    /// 捨てずに、未配送のものに続けてまとめる。板の差分は1つの差分に、約定は1つの約定リストにまとめる。
+    /// 板全体・接続状態の変化・足などをまたいではまとめない。ほかの種類では`KeepLatest`と同じ。
+    Coalesce,
+}
+
+/// 間引いたりまとめたりする対象になりうるイベントの種類。ここにないイベント（接続状態の変化など）は、
+/// 捨てると戦略の状態が壊れるので、常にすべて届ける。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MarketEventKind {
+    Ticker,
+    Transactions,
+    /// [`MarketEvent::DepthUpdated`]。
+    Depth,
+    /// [`MarketEvent::DepthDelta`]。
+    DepthDelta,
+    CircuitBreakInfo,
+}
+
+impl MarketEventKind {
+    const ALL: [MarketEventKind; 5] = [
+        MarketEventKind::Ticker,
+        MarketEventKind::Transactions,
+        MarketEventKind::Depth,
+        MarketEventKind::DepthDelta,
+        MarketEventKind::CircuitBreakInfo,
+    ];
+
+    pub fn of(event: &MarketEvent) -> Option<Self> {
+        match event {
+            MarketEvent::Ticker { .. } => Some(MarketEventKind::Ticker),
+            MarketEvent::Transactions { .. } => Some(MarketEventKind::Transactions),
+            MarketEvent::DepthUpdated { .. } => Some(MarketEventKind::Depth),
+            MarketEvent::DepthDelta { .. } => Some(MarketEventKind::DepthDelta),
+            MarketEvent::CircuitBreakInfo { .. } => Some(MarketEventKind::CircuitBreakInfo),
+            _ => None,
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            MarketEventKind::Ticker => 0,
+            MarketEventKind::Transactions => 1,
+            MarketEventKind::Depth => 2,
+            MarketEventKind::DepthDelta => 3,
+            MarketEventKind::CircuitBreakInfo => 4,
+        }
+    }
+
+    // 後から来たイベントをこれより前のイベントにまとめても、戦略から見た状態が変わらない種類。
+    fn is_coalescible_across(self) -> bool {
+        matches!(
+            self,
+            MarketEventKind::Ticker | MarketEventKind::Transactions | MarketEventKind::DepthDelta
+        )
+    }
+}
+
+/// ペアごとのフィードから戦略にイベントを渡すときの、種類ごとの[`DeliveryPolicy`]。
+/// デフォルトでは、約定はすべて届け、板は最新のものだけ、板の差分はまとめ、ティッカーは古いものから捨てる。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackpressureConfig {
+    pub ticker: DeliveryPolicy,
+    pub transactions: DeliveryPolicy,
+    pub depth: DeliveryPolicy,
+    /// 板の差分は捨てると板が壊れるので、`KeepLatest`や`DropOldest`は指定しないこと。
+    pub depth_delta: DeliveryPolicy,
+    pub circuit_break_info: DeliveryPolicy,
+    /// フィードごとの未配送のイベントの上限。これに達すると、フィードは戦略に渡せるまで新しいメッセージを受け取らない。
+    /// その間、WebSocketの受信側では約定などは空きができるまで待ち、板の差分などは捨てて板を作り直す。
+    pub max_pending: usize,
+}
+
+impl Default for BackpressureConfig {
+    fn default() -> Self {
+        Self {
+            ticker: DeliveryPolicy::DropOldest { capacity: 8 },
+            transactions: DeliveryPolicy::DeliverAll,
+            depth: DeliveryPolicy::KeepLatest,
+            depth_delta: DeliveryPolicy::Coalesce,
+            circuit_break_info: DeliveryPolicy::DeliverAll,
+            max_pending: DEFAULT_MAX_PENDING_EVENTS,
+        }
+    }
+}
+
+impl BackpressureConfig {
+    // 以前の動作と同じく、すべてのイベントを届ける。
+    pub fn deliver_all() -> Self {
+        Self {
+            ticker: DeliveryPolicy::DeliverAll,
+            transactions: DeliveryPolicy::DeliverAll,
+            depth: DeliveryPolicy::DeliverAll,
+            depth_delta: DeliveryPolicy::DeliverAll,
+            circuit_break_info: DeliveryPolicy::DeliverAll,
+            max_pending: DEFAULT_MAX_PENDING_EVENTS,
+        }
+    }
+
+    pub fn policy(&self, kind: MarketEventKind) -> DeliveryPolicy {
+        match kind {
+            MarketEventKind::Ticker => self.ticker,
+            MarketEventKind::Transactions => self.transactions,
+            MarketEventKind::Depth => self.depth,
+            MarketEventKind::DepthDelta => self.depth_delta,
+            MarketEventKind::CircuitBreakInfo => self.circuit_break_info,
+        }
+    }
+}
+
+/// フィードの未配送イベントの統計。cloneしたものは同じ値を共有するので、すべてのペアのフィードで合計される。
+#[derive(Debug, Clone, Default)]
+pub struct EventQueueMetrics {
+    inner: Arc<MetricsInner>,
+}
+
+#[derive(Debug, Default)]
+struct MetricsInner {
+    queued: AtomicUsize,
+    max_queued: AtomicUsize,
+    delivered: AtomicU64,
+    dropped: [AtomicU64; 5],
+    coalesced: AtomicU64,
+}
+
+/// [`EventQueueMetrics`]のある時点の値。
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct EventQueueStats {
+    /// フィードに溜まっている未配送のイベントの数。
+    pub queued: usize,
+    /// これまでの`queued`の最大値。
+    pub max_queued: usize,
+    /// 戦略のチャネルに渡したイベントの数。
+    pub delivered: u64,
+    /// [`DeliveryPolicy`]によって捨てたイベントの数（種類ごと）。
+    pub dropped: Vec<(MarketEventKind, u64)>,
+    /// [`DeliveryPolicy::Coalesce`]によって前のイベントにまとめたイベントの数。
+    pub coalesced: u64,
+    /// 戦略のチャネルに入っていて、まだ処理されていないイベントの数。
+    /// [`BitbankBotRuntime::event_queue_stats`](crate::bitbank_bot::BitbankBotRuntime::event_queue_stats)でのみ設定される。
+    pub channel_len: usize,
+}
+
+impl EventQueueStats {
+    pub fn dropped_total(&self) -> u64 {
+        self.dropped.iter().map(|(_, count)| count).sum()
+    }
+}
+
+impl EventQueueMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stats(&self) -> EventQueueStats {
+        EventQueueStats {
+            queued: self.inner.queued.load(Ordering::Relaxed),
+            max_queued: self.inner.max_queued.load(Ordering::Relaxed),
+            delivered: self.inner.delivered.load(Ordering::Relaxed),
+            dropped: MarketEventKind::ALL
+                .iter()
+                .map(|kind| {
+                    (
+                        *kind,
+                        self.inner.dropped[kind.index()].load(Ordering::Relaxed),
+                    )
+                })
+                .collect(),
+            coalesced: self.inner.coalesced.load(Ordering::Relaxed),
+            channel_len: 0,
+        }
+    }
+
+    fn queued(&self) {
+        let queued = self.inner.queued.fetch_add(1, Ordering::Relaxed) + 1;
+        self.inner.max_queued.fetch_max(queued, Ordering::Relaxed);
+    }
+
+    fn delivered(&self) {
+        self.inner.queued.fetch_sub(1, Ordering::Relaxed);
+        self.inner.delivered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn dropped(&self, kind: MarketEventKind) {
+        self.inner.queued.fetch_sub(1, Ordering::Relaxed);
+        self.inner.dropped[kind.index()].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn coalesced(&self) {
+        self.inner.coalesced.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// 1つのフィードの未配送イベント。積むときに[`BackpressureConfig`]に従って古いものを捨てたり、まとめたりする。
+#[derive(Debug)]
+pub(crate) struct EventQueue {
+    pending: VecDeque<MarketEvent>,
+    pending_by_kind: [usize; 5],
+    config: BackpressureConfig,
+    metrics: EventQueueMetrics,
+}
+
+impl EventQueue {
+    pub(crate) fn new(config: BackpressureConfig, metrics: EventQueueMetrics) -> Self {
+        Self {
+            pending: VecDeque::new(),
+            pending_by_kind: [0; 5],
+            config,
+            metrics,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    // 未配送のイベントが`max_pending`に達しているか。フィードはこの間、新しいメッセージを受け取らない。
+    pub(crate) fn is_full(&self) -> bool {
+        self.pending.len() >= self.config.max_pending.max(1)
+    }
+
+    pub(crate) fn push(&mut self, mut event: MarketEvent) {
+        if let Some(kind) = MarketEventKind::of(&event) {
+            let limit = match self.config.policy(kind) {
+                DeliveryPolicy::DeliverAll => usize::MAX,
+                DeliveryPolicy::KeepLatest => 1,
+                DeliveryPolicy::DropOldest { capacity } => capacity.max(1),
+                DeliveryPolicy::Coalesce => match self.coalesce(kind, event) {
+                    Ok(()) => return,
+                    Err(rest) => {
+                        event = rest;
+                        1
+                    }
+                },
+            };
+            while self.pending_by_kind[kind.index()] >= limit {
+                self.drop_oldest(kind);
+            }
+            self.pending_by_kind[kind.index()] += 1;
+        }
+
+        self.pending.push_back(event);
+        self.metrics.queued();
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<MarketEvent> {
+        let event = self.pending.pop_front()?;
+        if let Some(kind) = MarketEventKind::of(&event) {
+            self.pending_by_kind[kind.index()] -= 1;
+        }
+        self.metrics.delivered();
+        Some(event)
+    }
+
+    // `event`を同じ種類の未配送のイベントにまとめる。まとめられる相手がない場合は`event`を返す。
+    // 後ろから探し、まとめても順序が問題にならない種類のイベントだけを飛ばす。
+    // まとめられない種類（ティッカーなど）の場合、`KeepLatest`と同じになるよう`event`を返す。
+    fn coalesce(&mut self, kind: MarketEventKind, event: MarketEvent) -> Result<(), MarketEvent> {
+        let pending = self
+            .pending
+            .iter_mut()
+            .rev()
+            .take_while(|pending| {
+                MarketEventKind::of(pending).is_some_and(|k| k.is_coalescible_across())
+            })
+            .find(|pending| MarketEventKind::of(pending) == Some(kind));
+        let Some(pending) = pending else {
+            return Err(event);
+        };
+
+        match (pending, event) {
+            (
+                MarketEvent::DepthDelta { delta, .. },
+                MarketEvent::DepthDelta { delta: later, .. },
+            ) => delta.merge(later),
+            (
+                MarketEvent::Transactions { transactions, .. },
+                MarketEvent::Transactions {
+                    transactions: later,
+                    ..
+                },
+            ) => transactions.extend(later),
+            (_, event) => return Err(event),
+        }
+        self.metrics.coalesced();
+        Ok(())
+    }
+
+    fn drop_oldest(&mut self, kind: MarketEventKind) {
+        let Some(index) = self
+            .pending
+            .iter()
+            .position(|event| MarketEventKind::of(event) == Some(kind))
+        else {
+            return;
+        };
+
+        self.pending.remove(index);
+        self.pending_by_kind[kind.index()] -= 1;
+        self.metrics.dropped(kind);
+    }
+}
+
+impl Drop for EventQueue {
+    // 配送されずに捨てられるイベントを`queued`から除く。
+    fn drop(&mut self) {
+        self.metrics
+            .inner
+            .queued
+            .fetch_sub(self.pending.len(), Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitbank_structs::DepthDelta;
+    use crate::market_event::{MarketDepthSnapshot, MarketTicker};
+    use rust_decimal::Decimal;
+
+    fn ticker(timestamp: i64) -> MarketEvent {
+        MarketEvent::Ticker {
+            pair: "btc_jpy".to_owned(),
+            ticker: MarketTicker {
+                sell: None,
+                buy: None,
+                high: Decimal::ONE,
+                low: Decimal::ONE,
+                open: Decimal::ONE,
+                last: Decimal::ONE,
+                vol: Decimal::ONE,
+                timestamp,
+            },
+        }
+    }
+
+    fn depth(last_timestamp: i64) -> MarketEvent {
+        MarketEvent::DepthUpdated {
+            pair: "btc_jpy".to_owned(),
+            depth: MarketDepthSnapshot::new(Default::default(), Default::default(), last_timestamp),
+        }
+    }
+
+    #[test]
+    fn coalesces_depth_and_drops_oldest_tickers() {
+        let metrics = EventQueueMetrics::new();
+        let mut queue = EventQueue::new(
+            BackpressureConfig {
+                ticker: DeliveryPolicy::DropOldest { capacity: 2 },
+                ..BackpressureConfig::default()
+            },
+            metrics.clone(),
+        );
+
+        queue.push(depth(1));
+        queue.push(ticker(1));
+        queue.push(MarketEvent::Reconnected {
+            pair: "btc_jpy".to_owned(),
+        });
+        queue.push(ticker(2));
+        queue.push(depth(2));
+        queue.push(ticker(3));
+
+        let stats = metrics.stats();
+        assert_eq!((stats.queued, stats.max_queued), (4, 4));
+        assert_eq!(stats.dropped_total(), 2);
+
+        let mut events = Vec::new();
+        while let Some(event) = queue.pop() {
+            events.push(event);
+        }
+        assert_eq!(
+            events,
+            vec![
+                MarketEvent::Reconnected {
+                    pair: "btc_jpy".to_owned()
+                },
+                ticker(2),
+                depth(2),
+                ticker(3),
+            ]
+        );
+        assert!(queue.is_empty());
+
+        let stats = metrics.stats();
+        assert_eq!((stats.queued, stats.delivered), (0, 4));
+        assert!(stats.dropped.contains(&(MarketEventKind::Depth, 1)));
+    }
+
+    fn delta(sequence_id: u64, bids: Vec<(i64, i64)>) -> MarketEvent {
+        MarketEvent::DepthDelta {
+            pair: "btc_jpy".to_owned(),
+            delta: DepthDelta {
+                asks: Vec::new(),
+                bids: bids
+                    .into_iter()
+                    .map(|(price, amount)| (Decimal::from(price), Decimal::from(amount)))
+                    .collect(),
+                best_price_changed: sequence_id == 2,
+                sequence_id,
+                timestamp: sequence_id as i64,
+            },
+        }
+    }
+
+    #[test]
+    fn coalesces_depth_deltas_until_barrier() {
+        let metrics = EventQueueMetrics::new();
+        let mut queue = EventQueue::new(BackpressureConfig::default(), metrics.clone());
+
+        queue.push(delta(1, vec![(100, 1), (99, 2)]));
+        queue.push(ticker(1));
+        queue.push(delta(2, vec![(100, 0), (98, 3)]));
+        // 接続状態の変化をまたいではまとめない
+        queue.push(MarketEvent::Reconnected {
+            pair: "btc_jpy".to_owned(),
+        });
+        queue.push(delta(3, vec![(97, 1)]));
+
+        let mut events = Vec::new();
+        while let Some(event) = queue.pop() {
+            events.push(event);
+        }
+        // 2つ目の差分は1つ目にまとまり、同じ価格の気配は後の数量になる
+        assert_eq!(
+            events,
+            vec![
+                delta(2, vec![(100, 0), (99, 2), (98, 3)]),
+                ticker(1),
+                MarketEvent::Reconnected {
+                    pair: "btc_jpy".to_owned()
+                },
+                delta(3, vec![(97, 1)]),
+            ]
+        );
+
+        let stats = metrics.stats();
+        assert_eq!((stats.coalesced, stats.dropped_total()), (1, 0));
+        assert_eq!(stats.delivered, 4);
+    }
+
+    #[test]
+    fn reports_full_at_max_pending() {
+        let mut queue = EventQueue::new(
+            BackpressureConfig {
+                max_pending: 2,
+                ..BackpressureConfig::deliver_all()
+            },
+            EventQueueMetrics::new(),
+        );
+        queue.push(depth(1));
+        assert!(!queue.is_full());
+        queue.push(depth(2));
+        assert!(queue.is_full());
+        queue.pop();
+        assert!(!queue.is_full());
+    }
+
+    #[test]
+    fn deliver_all_keeps_every_event() {
+        let metrics = EventQueueMetrics::new();
+        let mut queue = EventQueue::new(BackpressureConfig::deliver_all(), metrics.clone());
+        for timestamp in 0..10 {
+            queue.push(depth(timestamp));
+        }
+        drop(queue);
+
+        let stats = metrics.stats();
+        assert_eq!(stats.dropped_total(), 0);
+        assert_eq!(stats.queued, 0);
+        assert_eq!(stats.max_queued, 10);
+    }
+}
@@ -1,43 +1,109 @@
+pub mod backtest;
 pub mod bitbank_bot;
 pub mod bitbank_private;
+pub mod bitbank_private_stream;
 pub mod bitbank_public;
 pub mod bitbank_structs;
+pub mod candle;
+pub mod clock;
+pub mod error;
+pub mod event_queue;
 pub mod market_event;
+#[cfg(feature = "mock-server")]
+pub mod mock_server;
 pub mod order_domain;
 pub mod order_executor;
 pub mod order_manager;
+pub mod pair_spec;
 pub mod paper_execution;
+pub mod performance;
+pub mod private_event;
+pub mod recorder;
+pub mod replay;
+pub mod request_policy;
 pub mod response_handler;
+pub mod supervision;
+pub mod transport;
 pub mod websocket_handler;
 
 pub mod depth {
     use core::fmt;
     use rust_decimal::prelude::*;
     use std::collections::BTreeMap;
+
+    use crate::order_domain::OrderSide;
+
+    const BPS: Decimal = Decimal::from_parts(10000, 0, 0, false, 0);
+
+    /// 板を成行で食ったと仮定したときの約定見込み。[`Depth::sweep_base`]と[`Depth::sweep_quote`]が返す。
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct SweepResult {
+        /// 成行注文の方向。`Buy`なら売り板を食う。
+        pub side: OrderSide,
+        /// 約定する数量（base）。板が足りない場合は要求より少ない。
+        pub filled_amount: Decimal,
+        /// 約定代金（quote）。
+        pub filled_notional: Decimal,
+        /// 平均約定価格。
+        pub vwap: Decimal,
+        /// 到達した最も不利な価格。
+        pub worst_price: Decimal,
+        /// 約定した気配の数。
+        pub levels_consumed: usize,
+        /// 板が足りず、要求した全量は約定できない。
+        pub is_partial: bool,
+        /// 食う側の最良気配。
+        pub touch_price: Decimal,
+        /// 仲値。反対側の板が空の場合は`None`。
+        pub mid_price: Option<Decimal>,
+    }
+
+    impl SweepResult {
+        // 最良気配に対するスリッページ（bps）。不利な方向が正。
+        pub fn slippage_bps_vs_touch(&self) -> Decimal {
+            self.slippage_bps(self.touch_price)
+        }
+
+        // 仲値に対するスリッページ（bps）。不利な方向が正。
+        pub fn slippage_bps_vs_mid(&self) -> Option<Decimal> {
+            self.mid_price.map(|mid| self.slippage_bps(mid))
+        }
+
+        fn slippage_bps(&self, reference: Decimal) -> Decimal {
+            let diff = match self.side {
+                OrderSide::Buy => self.vwap - reference,
+                OrderSide::Sell => reference - self.vwap,
+            };
+            diff / reference * BPS
+        }
+    }
+
+    /// 板。価格・数量ともに`Decimal`で持ち、集計も誤差なしで行う。
+    /// 数量を`f64`で扱いたい場合は[`Depth::asks_f64`]などを使う。
     pub trait Depth {
-        fn asks(&self) -> &BTreeMap<Decimal, f64>;
-        fn bids(&self) -> &BTreeMap<Decimal, f64>;
+        fn asks(&self) -> &BTreeMap<Decimal, Decimal>;
+        fn bids(&self) -> &BTreeMap<Decimal, Decimal>;
 
-        fn best_ask(&self) -> Option<(&Decimal, &f64)> {
+        fn best_ask(&self) -> Option<(&Decimal, &Decimal)> {
             self.asks().iter().next()
         }
 
-        fn best_bid(&self) -> Option<(&Decimal, &f64)> {
+        fn best_bid(&self) -> Option<(&Decimal, &Decimal)> {
             self.bids().iter().next_back()
         }
 
-        fn kth_best_ask(&self, k: usize) -> Option<(&Decimal, &f64)> {
+        fn kth_best_ask(&self, k: usize) -> Option<(&Decimal, &Decimal)> {
             self.asks().iter().nth(k)
         }
 
-        fn kth_best_bid(&self, k: usize) -> Option<(&Decimal, &f64)> {
+        fn kth_best_bid(&self, k: usize) -> Option<(&Decimal, &Decimal)> {
             self.bids().iter().nth_back(k)
         }
 
         // 最小価格pを返す。ここで、Sum_{bestask <= price <= p} (amount) >= rである。
         // 直感的に考えると、サイズrの成行買い注文を実行したときの最高価格である。
-        fn r_depth_ask_price(&self, r: f64) -> Option<&Decimal> {
-            let mut sum = f64::zero();
+        fn r_depth_ask_price(&self, r: Decimal) -> Option<&Decimal> {
+            let mut sum = Decimal::ZERO;
             for (price, amount) in self.asks().iter() {
                 sum += amount;
                 if sum >= r {
@@ -50,8 +116,8 @@ pub mod depth {
 
         // 最大価格pを返す。ここで、Sum_{p <= price <= bestbid} (amount) >= rである。
         // 直感的に考えると、サイズrの成行売り注文を実行したときの最低価格である。
-        fn r_depth_bid_price(&self, r: f64) -> Option<&Decimal> {
-            let mut sum = f64::zero();
+        fn r_depth_bid_price(&self, r: Decimal) -> Option<&Decimal> {
+            let mut sum = Decimal::ZERO;
             for (price, amount) in self.bids().iter().rev() {
                 sum += amount;
                 if sum >= r {
@@ -64,10 +130,10 @@ pub mod depth {
 
         // 最小価格pを返す。ここで、Sum_{bestask <= price <= p} (amount * price) >= sである。
         // 直感的に考えると、サイズs（ドル建て）の成行買い注文を実行したときの最高価格である。
-        fn s_depth_ask_price(&self, s: f64) -> Option<&Decimal> {
-            let mut sum = f64::zero();
+        fn s_depth_ask_price(&self, s: Decimal) -> Option<&Decimal> {
+            let mut sum = Decimal::ZERO;
             for (price, amount) in self.asks().iter() {
-                sum += price.to_f64().unwrap() * *amount;
+                sum += price * amount;
                 if sum >= s {
                     return Some(price);
                 }
@@ -78,10 +144,10 @@ pub mod depth {
 
         // 最大価格pを返す。ここで、Sum_{p <= price <= bestbid} (amount * price) >= sである。
         // 直感的に考えると、サイズs（ドル建て）の成行売り注文を実行したときの最低価格である。
-        fn s_depth_bid_price(&self, s: f64) -> Option<&Decimal> {
-            let mut sum = f64::zero();
+        fn s_depth_bid_price(&self, s: Decimal) -> Option<&Decimal> {
+            let mut sum = Decimal::ZERO;
             for (price, amount) in self.bids().iter().rev() {
-                sum += price.to_f64().unwrap() * *amount;
+                sum += price * amount;
                 if sum >= s {
                     return Some(price);
                 }
@@ -91,7 +157,7 @@ pub mod depth {
         }
 
         // log(r-depth ask price) - log(best ask price)を返す。
-        fn r_depth_ask_logdiff(&self, r: f64) -> Option<f64> {
+        fn r_depth_ask_logdiff(&self, r: Decimal) -> Option<f64> {
             let ask_price = self.r_depth_ask_price(r)?;
             let best_ask_price = self.best_ask()?.0;
 
@@ -102,7 +168,7 @@ pub mod depth {
         }
 
         // log(r-depth bid price) - log(best bid price)を返す。
-        fn r_depth_bid_logdiff(&self, r: f64) -> Option<f64> {
+        fn r_depth_bid_logdiff(&self, r: Decimal) -> Option<f64> {
             let bid_price = self.r_depth_bid_price(r)?;
             let best_bid_price = self.best_bid()?.0;
 
@@ -113,7 +179,7 @@ pub mod depth {
         }
 
         // log(s-depth ask price) - log(best ask price)を返す。
-        fn s_depth_ask_logdiff(&self, s: f64) -> Option<f64> {
+        fn s_depth_ask_logdiff(&self, s: Decimal) -> Option<f64> {
             let ask_price = self.s_depth_ask_price(s)?;
             let best_ask_price = self.best_ask()?.0;
 
@@ -124,7 +190,7 @@ pub mod depth {
         }
 
         // log(s-depth bid price) - log(best bid price)を返す。
-        fn s_depth_bid_logdiff(&self, s: f64) -> Option<f64> {
+        fn s_depth_bid_logdiff(&self, s: Decimal) -> Option<f64> {
             let bid_price = self.s_depth_bid_price(s)?;
             let best_bid_price = self.best_bid()?.0;
 
@@ -142,7 +208,7 @@ pub mod depth {
             }
         }
 
-        fn bidask_imbalance(&self) -> Option<f64> {
+        fn bidask_imbalance(&self) -> Option<Decimal> {
             if self.best_ask().is_some() && self.best_bid().is_some() {
                 let ask = self.best_ask().unwrap().1;
                 let bid = self.best_bid().unwrap().1;
@@ -152,6 +218,82 @@ pub mod depth {
             }
         }
 
+        fn mid_price(&self) -> Option<Decimal> {
+            let (ask, _) = self.best_ask()?;
+            let (bid, _) = self.best_bid()?;
+            Some((ask + bid) / Decimal::TWO)
+        }
+
+        // 最良気配の数量で重み付けした仲値。買い板が厚いほど売り気配に近づく。
+        fn microprice(&self) -> Option<Decimal> {
+            self.weighted_mid(1)
+        }
+
+        // 上位`levels`本の気配それぞれの加重平均価格を、反対側の数量で重み付けした仲値。
+        // `levels == 1`のときは[`Depth::microprice`]と同じ。
+        fn weighted_mid(&self, levels: usize) -> Option<Decimal> {
+            let (ask_notional, ask_amount) = self
+                .asks()
+                .iter()
+                .take(levels)
+                .fold((Decimal::ZERO, Decimal::ZERO), |(n, a), (price, amount)| {
+                    (n + price * amount, a + amount)
+                });
+            let (bid_notional, bid_amount) = self
+                .bids()
+                .iter()
+                .rev()
+                .take(levels)
+                .fold((Decimal::ZERO, Decimal::ZERO), |(n, a), (price, amount)| {
+                    (n + price * amount, a + amount)
+                });
+            if ask_amount.is_zero() || bid_amount.is_zero() {
+                return None;
+            }
+
+            let ask_vwap = ask_notional / ask_amount;
+            let bid_vwap = bid_notional / bid_amount;
+            Some((ask_vwap * bid_amount + bid_vwap * ask_amount) / (ask_amount + bid_amount))
+        }
+
+        // 最良売り気配から順に(価格, その価格までの累積数量)を返す。
+        fn cumulative_asks(&self, levels: usize) -> Vec<(Decimal, Decimal)> {
+            cumulative(self.asks().iter().take(levels))
+        }
+
+        // 最良買い気配から順に(価格, その価格までの累積数量)を返す。
+        fn cumulative_bids(&self, levels: usize) -> Vec<(Decimal, Decimal)> {
+            cumulative(self.bids().iter().rev().take(levels))
+        }
+
+        // 数量`amount`（base）の成行注文で板を食った場合の約定見込み。
+        // 板が空、または`amount`が0以下なら`None`。
+        fn sweep_base(&self, side: OrderSide, amount: Decimal) -> Option<SweepResult> {
+            sweep(self, side, SweepTarget::Base(amount))
+        }
+
+        // 金額`notional`（quote）の成行注文で板を食った場合の約定見込み。
+        // 板が空、または`notional`が0以下なら`None`。
+        fn sweep_quote(&self, side: OrderSide, notional: Decimal) -> Option<SweepResult> {
+            sweep(self, side, SweepTarget::Quote(notional))
+        }
+
+        // 数量を`f64`に変換した売り板を返す。`f64`で数量を扱う既存のコード向け。
+        fn asks_f64(&self) -> BTreeMap<Decimal, f64> {
+            self.asks()
+                .iter()
+                .map(|(price, amount)| (*price, amount.to_f64().unwrap()))
+                .collect()
+        }
+
+        // 数量を`f64`に変換した買い板を返す。`f64`で数量を扱う既存のコード向け。
+        fn bids_f64(&self) -> BTreeMap<Decimal, f64> {
+            self.bids()
+                .iter()
+                .map(|(price, amount)| (*price, amount.to_f64().unwrap()))
+                .collect()
+        }
+
         // 上位kレベルのデプスデータをフォーマットする。kがnoneの場合、20レベルをフォーマットする。
         fn format_depth(&self, k: Option<usize>, f: &mut fmt::Formatter<'_>) -> fmt::Result {
             let k2 = k.unwrap_or(20);
@@ -189,6 +331,216 @@ pub mod depth {
             Ok(())
         }
     }
+
+    fn cumulative<'a>(
+        levels: impl Iterator<Item = (&'a Decimal, &'a Decimal)>,
+    ) -> Vec<(Decimal, Decimal)> {
+        let mut sum = Decimal::ZERO;
+        levels
+            .map(|(price, amount)| {
+                sum += amount;
+                (*price, sum)
+            })
+            .collect()
+    }
+
+    enum SweepTarget {
+        Base(Decimal),
+        Quote(Decimal),
+    }
+
+    fn sweep<D: Depth + ?Sized>(
+        depth: &D,
+        side: OrderSide,
+        target: SweepTarget,
+    ) -> Option<SweepResult> {
+        let book: Box<dyn Iterator<Item = (&Decimal, &Decimal)>> = match side {
+            OrderSide::Buy => Box::new(depth.asks().iter()),
+            OrderSide::Sell => Box::new(depth.bids().iter().rev()),
+        };
+
+        let mut touch_price = None;
+        let mut filled_amount = Decimal::ZERO;
+        let mut filled_notional = Decimal::ZERO;
+        let mut worst_price = Decimal::ZERO;
+        let mut levels_consumed = 0;
+        let mut is_partial = true;
+
+        for (price, level_amount) in book {
+            touch_price.get_or_insert(*price);
+
+            let remaining = match target {
+                SweepTarget::Base(amount) => amount - filled_amount,
+                SweepTarget::Quote(notional) => (notional - filled_notional) / price,
+            };
+            if remaining <= Decimal::ZERO {
+                is_partial = false;
+                break;
+            }
+
+            let amount = remaining.min(*level_amount);
+            filled_amount += amount;
+            filled_notional += price * amount;
+            worst_price = *price;
+            levels_consumed += 1;
+
+            if remaining <= *level_amount {
+                is_partial = false;
+                break;
+            }
+        }
+
+        let touch_price = touch_price?;
+        if filled_amount.is_zero() {
+            return None;
+        }
+
+        Some(SweepResult {
+            side,
+            filled_amount,
+            filled_notional,
+            vwap: filled_notional / filled_amount,
+            worst_price,
+            levels_consumed,
+            is_partial,
+            touch_price,
+            mid_price: depth.mid_price(),
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        struct TestDepth {
+            asks: BTreeMap<Decimal, Decimal>,
+            bids: BTreeMap<Decimal, Decimal>,
+        }
+
+        impl Depth for TestDepth {
+            fn asks(&self) -> &BTreeMap<Decimal, Decimal> {
+                &self.asks
+            }
+
+            fn bids(&self) -> &BTreeMap<Decimal, Decimal> {
+                &self.bids
+            }
+        }
+
+        #[test]
+        fn depth_helpers_sum_amounts_exactly() {
+            let depth = TestDepth {
+                asks: BTreeMap::from([
+                    (Decimal::new(100, 0), Decimal::new(1, 1)),
+                    (Decimal::new(101, 0), Decimal::new(2, 1)),
+                ]),
+                bids: BTreeMap::from([
+                    (Decimal::new(99, 0), Decimal::new(1, 1)),
+                    (Decimal::new(98, 0), Decimal::new(2, 1)),
+                ]),
+            };
+
+            // 累積数量・金額がちょうど境界の値になる場合
+            assert_eq!(
+                depth.r_depth_ask_price(Decimal::new(3, 1)),
+                Some(&Decimal::new(101, 0))
+            );
+            assert_eq!(
+                depth.r_depth_bid_price(Decimal::new(3, 1)),
+                Some(&Decimal::new(98, 0))
+            );
+            assert_eq!(
+                depth.s_depth_ask_price(Decimal::new(302, 1)),
+                Some(&Decimal::new(101, 0))
+            );
+            assert_eq!(depth.s_depth_ask_price(Decimal::new(303, 1)), None);
+            assert_eq!(depth.bidask_imbalance(), Some(Decimal::ZERO));
+            assert_eq!(depth.asks_f64().get(&Decimal::new(101, 0)), Some(&0.2));
+        }
+
+        fn sample_depth() -> TestDepth {
+            TestDepth {
+                asks: BTreeMap::from([
+                    (Decimal::new(101, 0), Decimal::new(1, 0)),
+                    (Decimal::new(102, 0), Decimal::new(2, 0)),
+                ]),
+                bids: BTreeMap::from([
+                    (Decimal::new(99, 0), Decimal::new(3, 0)),
+                    (Decimal::new(98, 0), Decimal::new(1, 0)),
+                ]),
+            }
+        }
+
+        #[test]
+        fn sweep_computes_vwap_and_slippage() {
+            let depth = sample_depth();
+
+            let buy = depth
+                .sweep_base(OrderSide::Buy, Decimal::new(2, 0))
+                .unwrap();
+            assert_eq!(buy.filled_amount, Decimal::new(2, 0));
+            assert_eq!(buy.filled_notional, Decimal::new(203, 0));
+            assert_eq!(buy.vwap, Decimal::new(1015, 1));
+            assert_eq!(buy.worst_price, Decimal::new(102, 0));
+            assert_eq!(buy.levels_consumed, 2);
+            assert!(!buy.is_partial);
+            // (101.5 - 101) / 101 * 10000
+            assert_eq!(
+                buy.slippage_bps_vs_touch().round_dp(4),
+                Decimal::new(495050, 4)
+            );
+            // (101.5 - 100) / 100 * 10000
+            assert_eq!(buy.slippage_bps_vs_mid(), Some(Decimal::new(150, 0)));
+
+            let sell = depth
+                .sweep_quote(OrderSide::Sell, Decimal::new(346, 0))
+                .unwrap();
+            assert_eq!(sell.filled_amount, Decimal::new(35, 1));
+            assert_eq!(sell.worst_price, Decimal::new(98, 0));
+            assert!(!sell.is_partial);
+            assert!(sell.slippage_bps_vs_touch() > Decimal::ZERO);
+
+            let partial = depth
+                .sweep_base(OrderSide::Buy, Decimal::new(5, 0))
+                .unwrap();
+            assert!(partial.is_partial);
+            assert_eq!(partial.filled_amount, Decimal::new(3, 0));
+
+            // 板をちょうど食い切る場合は部分約定ではない
+            let exact = depth
+                .sweep_base(OrderSide::Buy, Decimal::new(3, 0))
+                .unwrap();
+            assert!(!exact.is_partial);
+
+            assert_eq!(depth.sweep_base(OrderSide::Buy, Decimal::ZERO), None);
+        }
+
+        #[test]
+        fn depth_curves_and_weighted_mids() {
+            let depth = sample_depth();
+
+            assert_eq!(
+                depth.cumulative_asks(5),
+                vec![
+                    (Decimal::new(101, 0), Decimal::new(1, 0)),
+                    (Decimal::new(102, 0), Decimal::new(3, 0)),
+                ]
+            );
+            assert_eq!(
+                depth.cumulative_bids(1),
+                vec![(Decimal::new(99, 0), Decimal::new(3, 0))]
+            );
+
+            assert_eq!(depth.mid_price(), Some(Decimal::new(100, 0)));
+            // (101 * 3 + 99 * 1) / 4
+            assert_eq!(depth.microprice(), Some(Decimal::new(1005, 1)));
+            // ask: vwap 101.666.., 数量3 / bid: vwap 98.75, 数量4
+            assert_eq!(
+                depth.weighted_mid(2).map(|mid| mid.round_dp(4)),
+                Some(Decimal::new(1004167, 4))
+            );
+        }
+    }
 }
 
 pub mod bybit {
@@ -247,16 +599,16 @@ pub mod bybit {
     }
 
     pub struct BybitDepth {
-        asks: BTreeMap<Decimal, f64>,
-        bids: BTreeMap<Decimal, f64>,
+        asks: BTreeMap<Decimal, Decimal>,
+        bids: BTreeMap<Decimal, Decimal>,
     }
 
     impl Depth for BybitDepth {
-        fn asks(&self) -> &BTreeMap<Decimal, f64> {
+        fn asks(&self) -> &BTreeMap<Decimal, Decimal> {
             &self.asks
         }
 
-        fn bids(&self) -> &BTreeMap<Decimal, f64> {
+        fn bids(&self) -> &BTreeMap<Decimal, Decimal> {
             &self.bids
         }
     }
@@ -285,7 +637,7 @@ pub mod bybit {
         pub fn update(&mut self, data: BybitOrderbookData) {
             for ask in data.a.iter() {
                 let price = &ask[0].parse::<Decimal>().unwrap();
-                let size = ask[1].parse::<f64>().unwrap();
+                let size = ask[1].parse::<Decimal>().unwrap();
 
                 if size.is_zero() {
                     self.asks.remove(price);
@@ -296,7 +648,7 @@ pub mod bybit {
 
             for bid in data.b.iter() {
                 let price = &bid[0].parse::<Decimal>().unwrap();
-                let size = bid[1].parse::<f64>().unwrap();
+                let size = bid[1].parse::<Decimal>().unwrap();
 
                 if size.is_zero() {
                     self.bids.remove(price);
@@ -1,37 +1,43 @@
 use crate::bitbank_structs::{
     BitbankCircuitBreakInfo, BitbankDepth, BitbankTickerResponse, BitbankTransactionDatum,
+    DepthDelta, DepthIntegrityError, SharedLevels,
 };
+use crate::candle::Candle;
 use crate::depth::Depth;
 use crate::order_domain::{OrderSide, ParseOrderError};
+use crate::websocket_handler::WebSocketDecodeError;
 use rust_decimal::Decimal;
 use serde_json::Number;
 use std::collections::BTreeMap;
 use std::fmt;
+use std::sync::Arc;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct MarketTicker {
-    pub sell: Option<String>,
-    pub buy: Option<String>,
-    pub high: String,
-    pub low: String,
-    pub open: String,
-    pub last: String,
-    pub vol: String,
-    pub timestamp: Number,
-}
-
-impl From<BitbankTickerResponse> for MarketTicker {
-    fn from(ticker: BitbankTickerResponse) -> Self {
-        Self {
-            sell: ticker.sell,
-            buy: ticker.buy,
-            high: ticker.high,
-            low: ticker.low,
-            open: ticker.open,
-            last: ticker.last,
-            vol: ticker.vol,
-            timestamp: ticker.timestamp,
-        }
+    pub sell: Option<Decimal>,
+    pub buy: Option<Decimal>,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub open: Decimal,
+    pub last: Decimal,
+    pub vol: Decimal,
+    pub timestamp: i64,
+}
+
+impl TryFrom<BitbankTickerResponse> for MarketTicker {
+    type Error = MarketEventConversionError;
+
+    fn try_from(ticker: BitbankTickerResponse) -> Result<Self, Self::Error> {
+        Ok(Self {
+            sell: parse_optional_decimal("sell", ticker.sell)?,
+            buy: parse_optional_decimal("buy", ticker.buy)?,
+            high: parse_decimal("high", ticker.high)?,
+            low: parse_decimal("low", ticker.low)?,
+            open: parse_decimal("open", ticker.open)?,
+            last: parse_decimal("last", ticker.last)?,
+            vol: parse_decimal("vol", ticker.vol)?,
+            timestamp: parse_timestamp("timestamp", &ticker.timestamp)?,
+        })
     }
 }
 
@@ -58,23 +64,24 @@ impl TryFrom<BitbankTransactionDatum> for MarketTrade {
     }
 }
 
+/// 板のスナップショット。板は`Arc`で共有しているので、cloneしても気配はコピーされない。
 #[derive(Debug, Clone, PartialEq)]
 pub struct MarketDepthSnapshot {
-    asks: BTreeMap<Decimal, f64>,
-    bids: BTreeMap<Decimal, f64>,
+    asks: SharedLevels,
+    bids: SharedLevels,
     last_timestamp: i64,
     is_complete: bool,
 }
 
 impl MarketDepthSnapshot {
     pub fn new(
-        asks: BTreeMap<Decimal, f64>,
-        bids: BTreeMap<Decimal, f64>,
+        asks: BTreeMap<Decimal, Decimal>,
+        bids: BTreeMap<Decimal, Decimal>,
         last_timestamp: i64,
     ) -> Self {
         Self {
-            asks,
-            bids,
+            asks: Arc::new(asks),
+            bids: Arc::new(bids),
             last_timestamp,
             is_complete: true,
         }
@@ -82,8 +89,8 @@ impl MarketDepthSnapshot {
 
     pub fn empty() -> Self {
         Self {
-            asks: BTreeMap::new(),
-            bids: BTreeMap::new(),
+            asks: Arc::new(BTreeMap::new()),
+            bids: Arc::new(BTreeMap::new()),
             last_timestamp: 0,
             is_complete: false,
         }
@@ -96,14 +103,20 @@ impl MarketDepthSnapshot {
     pub fn is_complete(&self) -> bool {
         self.is_complete
     }
+
+    // 古くなった可能性がある板として扱う。気配値は参照できるが、`is_complete()`は`false`になる。
+    pub fn into_stale(mut self) -> Self {
+        self.is_complete = false;
+        self
+    }
 }
 
 impl Depth for MarketDepthSnapshot {
-    fn asks(&self) -> &BTreeMap<Decimal, f64> {
+    fn asks(&self) -> &BTreeMap<Decimal, Decimal> {
         &self.asks
     }
 
-    fn bids(&self) -> &BTreeMap<Decimal, f64> {
+    fn bids(&self) -> &BTreeMap<Decimal, Decimal> {
         &self.bids
     }
 }
@@ -117,43 +130,167 @@ impl fmt::Display for MarketDepthSnapshot {
 
 impl From<&BitbankDepth> for MarketDepthSnapshot {
     fn from(depth: &BitbankDepth) -> Self {
+        let (asks, bids) = depth.shared_levels();
         Self {
-            asks: depth.asks().clone(),
-            bids: depth.bids().clone(),
+            asks,
+            bids,
             last_timestamp: depth.last_timestamp(),
             is_complete: depth.is_complete(),
         }
     }
 }
 
+/// サーキットブレイクの状態。
+///
+/// 仕様: <https://github.com/bitbankinc/bitbank-api-docs/blob/master/public-api.md#circuit-break-info>
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CircuitBreakMode {
+    /// 通常の取引中。
+    None,
+    /// サーキットブレイク中。板寄せの価格範囲が決まっている。
+    CircuitBreak,
+    /// 価格範囲を制限しないサーキットブレイク中。
+    FullRangeCircuitBreak,
+    /// 取引再開のための板寄せ中。
+    Resumption,
+    /// 新規上場の板寄せ中。
+    Listing,
+    /// bitbankが後から追加したなど、このクレートが知らないモード。受け取った文字列をそのまま持つ。
+    Unknown(String),
+}
+
+impl CircuitBreakMode {
+    pub fn as_str(&self) -> &str {
+        match self {
+            CircuitBreakMode::None => "NONE",
+            CircuitBreakMode::CircuitBreak => "CIRCUIT_BREAK",
+            CircuitBreakMode::FullRangeCircuitBreak => "FULL_RANGE_CIRCUIT_BREAK",
+            CircuitBreakMode::Resumption => "RESUMPTION",
+            CircuitBreakMode::Listing => "LISTING",
+            CircuitBreakMode::Unknown(mode) => mode,
+        }
+    }
+
+    // ザラ場（連続して約定する状態）かどうか。`false`の間は注文が板寄せで約定するので、板が交差することもある。
+    // 知らないモードはザラ場とはみなさない。
+    pub fn is_continuous_trading(&self) -> bool {
+        *self == CircuitBreakMode::None
+    }
+}
+
+impl fmt::Display for CircuitBreakMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<&str> for CircuitBreakMode {
+    fn from(value: &str) -> Self {
+        match value {
+            "NONE" => CircuitBreakMode::None,
+            "CIRCUIT_BREAK" => CircuitBreakMode::CircuitBreak,
+            "FULL_RANGE_CIRCUIT_BREAK" => CircuitBreakMode::FullRangeCircuitBreak,
+            "RESUMPTION" => CircuitBreakMode::Resumption,
+            "LISTING" => CircuitBreakMode::Listing,
+            other => CircuitBreakMode::Unknown(other.to_owned()),
+        }
+    }
+}
+
+/// 手数料の種類。
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum FeeType {
+    Normal,
+    /// 売りのメイカーが手数料を受け取る。
+    SellMaker,
+    /// 買いのメイカーが手数料を受け取る。
+    BuyMaker,
+    Dynamic,
+    /// このクレートが知らない種類。受け取った文字列をそのまま持つ。
+    Unknown(String),
+}
+
+impl FeeType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            FeeType::Normal => "NORMAL",
+            FeeType::SellMaker => "SELL_MAKER",
+            FeeType::BuyMaker => "BUY_MAKER",
+            FeeType::Dynamic => "DYNAMIC",
+            FeeType::Unknown(fee_type) => fee_type,
+        }
+    }
+}
+
+impl fmt::Display for FeeType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<&str> for FeeType {
+    fn from(value: &str) -> Self {
+        match value {
+            "NORMAL" => FeeType::Normal,
+            "SELL_MAKER" => FeeType::SellMaker,
+            "BUY_MAKER" => FeeType::BuyMaker,
+            "DYNAMIC" => FeeType::Dynamic,
+            other => FeeType::Unknown(other.to_owned()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct MarketCircuitBreakInfo {
-    pub mode: String,
-    pub estimated_itayose_price: Option<String>,
-    pub estimated_itayose_amount: Option<String>,
-    pub itayose_upper_price: Option<String>,
-    pub itayose_lower_price: Option<String>,
-    pub upper_trigger_price: Option<String>,
-    pub lower_trigger_price: Option<String>,
-    pub fee_type: String,
-    pub reopen_timestamp: Option<Number>,
-    pub timestamp: Number,
-}
-
-impl From<BitbankCircuitBreakInfo> for MarketCircuitBreakInfo {
-    fn from(info: BitbankCircuitBreakInfo) -> Self {
-        Self {
-            mode: info.mode,
-            estimated_itayose_price: info.estimated_itayose_price,
-            estimated_itayose_amount: info.estimated_itayose_amount,
-            itayose_upper_price: info.itayose_upper_price,
-            itayose_lower_price: info.itayose_lower_price,
-            upper_trigger_price: info.upper_trigger_price,
-            lower_trigger_price: info.lower_trigger_price,
-            fee_type: info.fee_type,
-            reopen_timestamp: info.reopen_timestamp,
-            timestamp: info.timestamp,
-        }
+    pub mode: CircuitBreakMode,
+    pub estimated_itayose_price: Option<Decimal>,
+    pub estimated_itayose_amount: Option<Decimal>,
+    pub itayose_upper_price: Option<Decimal>,
+    pub itayose_lower_price: Option<Decimal>,
+    pub upper_trigger_price: Option<Decimal>,
+    pub lower_trigger_price: Option<Decimal>,
+    pub fee_type: FeeType,
+    pub reopen_timestamp: Option<i64>,
+    pub timestamp: i64,
+}
+
+impl TryFrom<BitbankCircuitBreakInfo> for MarketCircuitBreakInfo {
+    type Error = MarketEventConversionError;
+
+    fn try_from(info: BitbankCircuitBreakInfo) -> Result<Self, Self::Error> {
+        Ok(Self {
+            mode: CircuitBreakMode::from(info.mode.as_str()),
+            estimated_itayose_price: parse_optional_decimal(
+                "estimated_itayose_price",
+                info.estimated_itayose_price,
+            )?,
+            estimated_itayose_amount: parse_optional_decimal(
+                "estimated_itayose_amount",
+                info.estimated_itayose_amount,
+            )?,
+            itayose_upper_price: parse_optional_decimal(
+                "itayose_upper_price",
+                info.itayose_upper_price,
+            )?,
+            itayose_lower_price: parse_optional_decimal(
+                "itayose_lower_price",
+                info.itayose_lower_price,
+            )?,
+            upper_trigger_price: parse_optional_decimal(
+                "upper_trigger_price",
+                info.upper_trigger_price,
+            )?,
+            lower_trigger_price: parse_optional_decimal(
+                "lower_trigger_price",
+                info.lower_trigger_price,
+            )?,
+            fee_type: FeeType::from(info.fee_type.as_str()),
+            reopen_timestamp: info
+                .reopen_timestamp
+                .map(|timestamp| parse_timestamp("reopen_timestamp", &timestamp))
+                .transpose()?,
+            timestamp: parse_timestamp("timestamp", &info.timestamp)?,
+        })
     }
 }
 
@@ -175,11 +312,47 @@ pub enum MarketEvent {
         pair: String,
         info: MarketCircuitBreakInfo,
     },
+    FeedError {
+        pair: String,
+        error: WebSocketDecodeError,
+    },
+    DepthDelta {
+        pair: String,
+        delta: DepthDelta,
+    },
+    DepthIntegrityError {
+        pair: String,
+        error: DepthIntegrityError,
+    },
+    Connected {
+        pair: String,
+    },
+    Disconnected {
+        pair: String,
+        last_depth: Option<MarketDepthSnapshot>,
+    },
+    Reconnected {
+        pair: String,
+    },
+    Candle {
+        pair: String,
+        candle: Candle,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum MarketEventConversionError {
     InvalidTradeSide(ParseOrderError),
+    /// 数値のフィールドを`Decimal`として解釈できなかった。
+    InvalidDecimal {
+        field: &'static str,
+        value: String,
+    },
+    /// タイムスタンプのフィールドが`i64`に収まる整数ではなかった。
+    InvalidTimestamp {
+        field: &'static str,
+        value: String,
+    },
 }
 
 impl From<ParseOrderError> for MarketEventConversionError {
@@ -187,3 +360,110 @@ impl From<ParseOrderError> for MarketEventConversionError {
         Self::InvalidTradeSide(err)
     }
 }
+
+fn parse_decimal(
+    field: &'static str,
+    value: String,
+) -> Result<Decimal, MarketEventConversionError> {
+    value
+        .parse()
+        .map_err(|_| MarketEventConversionError::InvalidDecimal { field, value })
+}
+
+fn parse_optional_decimal(
+    field: &'static str,
+    value: Option<String>,
+) -> Result<Option<Decimal>, MarketEventConversionError> {
+    value.map(|value| parse_decimal(field, value)).transpose()
+}
+
+fn parse_timestamp(field: &'static str, value: &Number) -> Result<i64, MarketEventConversionError> {
+    value
+        .as_i64()
+        .ok_or_else(|| MarketEventConversionError::InvalidTimestamp {
+            field,
+            value: value.to_string(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parses_ticker_prices_as_decimals() {
+        let ticker: BitbankTickerResponse = serde_json::from_value(json!({
+            "sell": "101", "buy": null, "open": "90", "high": "110", "low": "80",
+            "last": "100.5", "vol": "1.5", "timestamp": 1234
+        }))
+        .unwrap();
+
+        let ticker = MarketTicker::try_from(ticker).unwrap();
+        assert_eq!(ticker.sell, Some(Decimal::new(101, 0)));
+        assert_eq!(ticker.buy, None);
+        assert_eq!(ticker.last, Decimal::new(1005, 1));
+        assert_eq!(ticker.timestamp, 1234);
+
+        let invalid: BitbankTickerResponse = serde_json::from_value(json!({
+            "sell": "101", "buy": "100", "open": "90", "high": "110", "low": "80",
+            "last": "", "vol": "1.5", "timestamp": 1234
+        }))
+        .unwrap();
+        assert_eq!(
+            MarketTicker::try_from(invalid),
+            Err(MarketEventConversionError::InvalidDecimal {
+                field: "last",
+                value: String::new(),
+            })
+        );
+    }
+
+    #[test]
+    fn parses_circuit_break_mode_and_fee_type() {
+        let info: BitbankCircuitBreakInfo = serde_json::from_value(json!({
+            "mode": "CIRCUIT_BREAK",
+            "estimated_itayose_price": "5000000",
+            "estimated_itayose_amount": "1.25",
+            "itayose_upper_price": "5500000",
+            "itayose_lower_price": "4500000",
+            "upper_trigger_price": null,
+            "lower_trigger_price": null,
+            "fee_type": "SELL_MAKER",
+            "reopen_timestamp": null,
+            "timestamp": 1234
+        }))
+        .unwrap();
+
+        let info = MarketCircuitBreakInfo::try_from(info.clone()).unwrap();
+        assert_eq!(info.mode, CircuitBreakMode::CircuitBreak);
+        assert!(!info.mode.is_continuous_trading());
+        assert_eq!(info.fee_type, FeeType::SellMaker);
+        assert_eq!(info.estimated_itayose_amount, Some(Decimal::new(125, 2)));
+        assert_eq!(info.upper_trigger_price, None);
+    }
+
+    #[test]
+    fn unknown_circuit_break_mode_and_fee_type_keep_the_event() {
+        let info: BitbankCircuitBreakInfo = serde_json::from_value(json!({
+            "mode": "HALTED",
+            "estimated_itayose_price": null,
+            "estimated_itayose_amount": null,
+            "itayose_upper_price": null,
+            "itayose_lower_price": null,
+            "upper_trigger_price": null,
+            "lower_trigger_price": null,
+            "fee_type": "FREE",
+            "reopen_timestamp": null,
+            "timestamp": 1234
+        }))
+        .unwrap();
+
+        let info = MarketCircuitBreakInfo::try_from(info).unwrap();
+        assert_eq!(info.mode, CircuitBreakMode::Unknown("HALTED".to_owned()));
+        assert!(!info.mode.is_continuous_trading());
+        assert_eq!(info.mode.to_string(), "HALTED");
+        assert_eq!(info.fee_type, FeeType::Unknown("FREE".to_owned()));
+        assert_eq!(info.timestamp, 1234);
+    }
+}
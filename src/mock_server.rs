@@ -0,0 +1,955 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use rust_decimal::Decimal;
+use serde_json::{json, Value};
+
+use crate::bitbank_private::BitbankPrivateApiClient;
+use crate::bitbank_public::BitbankPublicApiClient;
+use crate::bitbank_structs::BitbankApiResponse;
+use crate::error::BitbankError;
+use crate::transport::{BitbankTransport, TransportFuture};
+
+// モックの時刻の初期値（Unixタイムスタンプ、ミリ秒）。
+const MOCK_START_TIME: u64 = 1_700_000_000_000;
+// 成行注文を約定させるときの価格。`/{pair}/ticker`の`last`と同じ。
+const MOCK_LAST_PRICE: &str = "5000000";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MockMethod {
+    Get,
+    Post,
+}
+
+/// モックが受け取ったリクエスト。
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockRequest {
+    pub method: MockMethod,
+    pub path: String,
+    /// GETのクエリ、またはPOSTのボディ。
+    pub params: Option<Value>,
+}
+
+/// [`MockBitbankServer::push_response`]で差し込むレスポンス。
+#[derive(Debug, Clone)]
+pub enum MockResponse {
+    /// `{"success": 1, "data": ...}`を返す。
+    Data(Value),
+    /// `{"success": 0, "data": {"code": ...}}`を返す。
+    ErrorCode(u32),
+    /// 通信エラーなど、レスポンスそのものが返らない場合。
+    Error(BitbankError),
+}
+
+/// bitbankのREST APIを真似る、プロセス内のモックサーバー。
+///
+/// [`BitbankTransport`]を実装しているので、`with_transport`でクライアントに渡して使う。
+/// 注文・約定・残高・入出金は状態を持ち、発注やキャンセルの結果が以降のレスポンスに反映される。
+/// それ以外のエンドポイントは固定のレスポンスを返す。
+/// cloneしたものは状態を共有するので、クライアントに渡した後もテストから状態を操作できる。
+#[derive(Debug, Clone, Default)]
+pub struct MockBitbankServer {
+    state: Arc<Mutex<MockState>>,
+}
+
+#[derive(Debug, Clone)]
+struct MockOrder {
+    order_id: u64,
+    pair: String,
+    side: String,
+    r#type: String,
+    position_side: Option<String>,
+    start_amount: Decimal,
+    executed_amount: Decimal,
+    executed_value: Decimal,
+    price: Option<Decimal>,
+    post_only: Option<bool>,
+    trigger_price: Option<String>,
+    ordered_at: u64,
+    canceled_at: Option<u64>,
+    is_canceled: bool,
+}
+
+impl MockOrder {
+    fn remaining_amount(&self) -> Decimal {
+        self.start_amount - self.executed_amount
+    }
+
+    fn is_active(&self) -> bool {
+        !self.is_canceled && !self.remaining_amount().is_zero()
+    }
+
+    fn status(&self) -> &'static str {
+        match (
+            self.is_canceled,
+            self.executed_amount.is_zero(),
+            self.remaining_amount().is_zero(),
+        ) {
+            (true, true, _) => "CANCELED_UNFILLED",
+            (true, false, _) => "CANCELED_PARTIALLY_FILLED",
+            (false, _, true) => "FULLY_FILLED",
+            (false, true, false) => "UNFILLED",
+            (false, false, false) => "PARTIALLY_FILLED",
+        }
+    }
+
+    fn average_price(&self) -> Decimal {
+        if self.executed_amount.is_zero() {
+            Decimal::ZERO
+        } else {
+            self.executed_value / self.executed_amount
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "order_id": self.order_id,
+            "pair": self.pair,
+            "side": self.side,
+            "position_side": self.position_side,
+            "type": self.r#type,
+            "start_amount": self.start_amount.to_string(),
+            "remaining_amount": self.remaining_amount().to_string(),
+            "executed_amount": self.executed_amount.to_string(),
+            "price": self.price.map(|price| price.to_string()),
+            "post_only": self.post_only,
+            "user_cancelable": true,
+            "average_price": self.average_price().to_string(),
+            "ordered_at": self.ordered_at,
+            "expire_at": null,
+            "trigger_price": self.trigger_price,
+            "status": self.status(),
+        })
+    }
+
+    fn to_cancel_json(&self) -> Value {
+        let mut value = self.to_json();
+        value["canceled_at"] = json!(self.canceled_at);
+        value["triggered_at"] = Value::Null;
+        value
+    }
+}
+
+#[derive(Debug)]
+struct MockState {
+    now: u64,
+    next_order_id: u64,
+    next_trade_id: u64,
+    onhand: BTreeMap<String, Decimal>,
+    orders: BTreeMap<u64, MockOrder>,
+    trades: Vec<Value>,
+    deposits: Vec<Value>,
+    unconfirmed_deposits: Vec<Value>,
+    withdrawals: Vec<Value>,
+    queued: HashMap<String, VecDeque<MockResponse>>,
+    requests: Vec<MockRequest>,
+}
+
+impl Default for MockState {
+    fn default() -> Self {
+        let onhand = [
+            ("jpy", Decimal::new(10_000_000, 0)),
+            ("btc", Decimal::new(1, 0)),
+            ("eth", Decimal::new(10, 0)),
+            ("xrp", Decimal::new(10_000, 0)),
+        ]
+        .into_iter()
+        .map(|(asset, amount)| (asset.to_owned(), amount))
+        .collect();
+
+        Self {
+            now: MOCK_START_TIME,
+            next_order_id: 1,
+            next_trade_id: 1,
+            onhand,
+            orders: BTreeMap::new(),
+            trades: Vec::new(),
+            deposits: vec![json!({
+                "uuid": "mock-deposit-1",
+                "asset": "btc",
+                "network": "bitcoin",
+                "amount": "0.1",
+                "txid": "mock-txid-1",
+                "status": "DONE",
+                "found_at": MOCK_START_TIME - 86_400_000,
+                "confirmed_at": MOCK_START_TIME - 86_000_000,
+            })],
+            unconfirmed_deposits: vec![json!({
+                "uuid": "mock-unconfirmed-deposit-1",
+                "asset": "btc",
+                "amount": "0.05",
+                "network": "bitcoin",
+                "txid": "mock-txid-2",
+                "created_at": MOCK_START_TIME - 3_600_000,
+            })],
+            withdrawals: Vec::new(),
+            queued: HashMap::new(),
+            requests: Vec::new(),
+        }
+    }
+}
+
+impl MockBitbankServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // このモックにリクエストを送るPrivate APIクライアントを作る。
+    pub fn private_client(&self) -> BitbankPrivateApiClient {
+        BitbankPrivateApiClient::with_transport(Arc::new(self.clone()))
+    }
+
+    // このモックにリクエストを送るPublic APIクライアントを作る。
+    pub fn public_client(&self) -> BitbankPublicApiClient {
+        BitbankPublicApiClient::with_transport(Arc::new(self.clone()))
+    }
+
+    // `path`への次のリクエストに、通常の処理の代わりに`response`を返す。複数回呼ぶと順番に返す。
+    pub fn push_response(&self, path: &str, response: MockResponse) {
+        self.lock()
+            .queued
+            .entry(path.to_owned())
+            .or_default()
+            .push_back(response);
+    }
+
+    // これまでに受け取ったリクエスト。
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.lock().requests.clone()
+    }
+
+    pub fn set_balance(&self, asset: &str, onhand_amount: Decimal) {
+        self.lock().onhand.insert(asset.to_owned(), onhand_amount);
+    }
+
+    // 注文を`amount`だけ約定させる。指値注文は注文価格、成行注文は`MOCK_LAST_PRICE`で約定する。
+    // 注文が存在しないか、有効でない場合は`false`を返す。
+    pub fn fill_order(&self, order_id: u64, amount: Decimal) -> bool {
+        self.lock().fill_order(order_id, amount)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MockState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn respond(
+        &self,
+        method: MockMethod,
+        path: &str,
+        params: Option<&Value>,
+    ) -> TransportFuture<'_> {
+        let response = {
+            let mut state = self.lock();
+            state.requests.push(MockRequest {
+                method,
+                path: path.to_owned(),
+                params: params.cloned(),
+            });
+
+            match state
+                .queued
+                .get_mut(path)
+                .and_then(|responses| responses.pop_front())
+            {
+                Some(response) => response,
+                None => state.handle(method, path, params.unwrap_or(&Value::Null)),
+            }
+        };
+
+        Box::pin(async move {
+            match response {
+                MockResponse::Data(data) => Ok(BitbankApiResponse {
+                    success: 1.into(),
+                    data,
+                }),
+                MockResponse::ErrorCode(code) => Ok(BitbankApiResponse {
+                    success: 0.into(),
+                    data: json!({ "code": code }),
+                }),
+                MockResponse::Error(err) => Err(err),
+            }
+        })
+    }
+}
+
+impl BitbankTransport for MockBitbankServer {
+    fn get<'a>(&'a self, path: &'a str, query: Option<&'a Value>) -> TransportFuture<'a> {
+        self.respond(MockMethod::Get, path, query)
+    }
+
+    fn post<'a>(&'a self, path: &'a str, body: Option<&'a Value>) -> TransportFuture<'a> {
+        self.respond(MockMethod::Post, path, body)
+    }
+}
+
+impl MockState {
+    fn tick(&mut self) -> u64 {
+        self.now += 1;
+        self.now
+    }
+
+    fn handle(&mut self, method: MockMethod, path: &str, params: &Value) -> MockResponse {
+        match (method, path) {
+            (MockMethod::Get, "/user/assets") => MockResponse::Data(self.assets()),
+            (MockMethod::Get, "/user/spot/order") => match self.order_param(params) {
+                Ok(order) => MockResponse::Data(order.to_json()),
+                Err(code) => MockResponse::ErrorCode(code),
+            },
+            (MockMethod::Post, "/user/spot/order") => self.create_order(params),
+            (MockMethod::Get, "/user/spot/trade_history") => {
+                MockResponse::Data(json!({ "trades": self.trade_history(params) }))
+            }
+            (MockMethod::Post, "/user/spot/cancel_order") => {
+                let order_id = params["order_id"].as_u64().unwrap_or_default();
+                match self.cancel_order(order_id) {
+                    Ok(order) => MockResponse::Data(order),
+                    Err(code) => MockResponse::ErrorCode(code),
+                }
+            }
+            (MockMethod::Post, "/user/spot/cancel_orders") => {
+                // 複数キャンセルでは、キャンセルできなかった注文は結果に含まれない。
+                let orders = order_ids_param(params)
+                    .into_iter()
+                    .filter_map(|order_id| self.cancel_order(order_id).ok())
+                    .collect::<Vec<_>>();
+                MockResponse::Data(json!({ "orders": orders }))
+            }
+            (MockMethod::Post, "/user/spot/orders_info") => {
+                let orders = order_ids_param(params)
+                    .into_iter()
+                    .filter_map(|order_id| self.orders.get(&order_id))
+                    .map(MockOrder::to_json)
+                    .collect::<Vec<_>>();
+                MockResponse::Data(json!({ "orders": orders }))
+            }
+            (MockMethod::Get, "/user/spot/active_orders") => {
+                let pair = params["pair"].as_str();
+                let count = count_param(params).unwrap_or(usize::MAX);
+                let orders = self
+                    .orders
+                    .values()
+                    .rev()
+                    .filter(|order| order.is_active())
+                    .filter(|order| pair.is_none_or(|pair| order.pair == pair))
+                    .take(count)
+                    .map(MockOrder::to_json)
+                    .collect::<Vec<_>>();
+                MockResponse::Data(json!({ "orders": orders }))
+            }
+            (MockMethod::Get, "/user/margin/positions") => MockResponse::Data(json!({
+                "notice": {"what": null, "occurred_at": null, "amount": null, "due_date_at": null},
+                "payables": {"amount": "0"},
+                "positions": [],
+                "losscut_threshold": {"individual": "20", "company": "25"},
+            })),
+            (MockMethod::Get, "/user/deposit_history") => MockResponse::Data(json!({
+                "deposits": filter_history(&self.deposits, params, "found_at"),
+            })),
+            (MockMethod::Get, "/user/unconfirmed_deposits") => {
+                MockResponse::Data(json!({ "deposits": self.unconfirmed_deposits }))
+            }
+            (MockMethod::Get, "/user/deposit_originators") => MockResponse::Data(json!({
+                "deposit_originators": [{
+                    "uuid": "mock-originator-1",
+                    "label": "mock originator",
+                    "deposit_type": "self",
+                    "deposit_purpose": "investment",
+                    "status": "CONFIRMED",
+                    "originator_type": null,
+                    "originator_name": null,
+                }],
+            })),
+            (MockMethod::Post, "/user/confirm_deposits") => {
+                let uuids = params["deposits"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|deposit| deposit["uuid"].as_str())
+                    .map(str::to_owned)
+                    .collect::<Vec<_>>();
+                self.confirm_deposits(|deposit| {
+                    uuids.iter().any(|uuid| deposit["uuid"] == uuid.as_str())
+                });
+                MockResponse::Data(json!({}))
+            }
+            (MockMethod::Post, "/user/confirm_deposits_all") => {
+                self.confirm_deposits(|_| true);
+                MockResponse::Data(json!({}))
+            }
+            (MockMethod::Get, "/user/withdrawal_account") => MockResponse::Data(json!({
+                "accounts": [withdrawal_account(params["asset"].as_str().unwrap_or("btc"))],
+            })),
+            (MockMethod::Post, "/user/request_withdrawal") => self.request_withdrawal(params),
+            (MockMethod::Get, "/user/withdrawal_history") => MockResponse::Data(json!({
+                "withdrawals": filter_history(&self.withdrawals, params, "requested_at"),
+            })),
+            (MockMethod::Get, "/spot/status") => MockResponse::Data(json!({
+                "statuses": MOCK_PAIRS
+                    .iter()
+                    .map(|(pair, _, _, min_amount)| json!({
+                        "pair": pair,
+                        "status": "NORMAL",
+                        "min_amount": min_amount,
+                    }))
+                    .collect::<Vec<_>>(),
+            })),
+            (MockMethod::Get, "/spot/pairs") => MockResponse::Data(json!({
+                "pairs": MOCK_PAIRS.iter().map(pair_datum).collect::<Vec<_>>(),
+            })),
+            (MockMethod::Get, "/user/subscribe") => MockResponse::Data(json!({
+                "pubnub_channel": "mock_channel",
+                "pubnub_token": "mock_token",
+            })),
+            (MockMethod::Get, "/tickers") | (MockMethod::Get, "/tickers_jpy") => {
+                MockResponse::Data(json!(MOCK_PAIRS
+                    .iter()
+                    .map(|(pair, _, _, _)| {
+                        let mut ticker = self.ticker();
+                        ticker["pair"] = json!(pair);
+                        ticker
+                    })
+                    .collect::<Vec<_>>()))
+            }
+            (MockMethod::Get, path) => self.handle_public_pair(path),
+            _ => MockResponse::ErrorCode(10000),
+        }
+    }
+
+    // `/{pair}/...`の形のPublic API。
+    fn handle_public_pair(&mut self, path: &str) -> MockResponse {
+        let segments = path.trim_start_matches('/').split('/').collect::<Vec<_>>();
+        match segments.as_slice() {
+            [_, "ticker"] => MockResponse::Data(self.ticker()),
+            [_, "depth"] => MockResponse::Data(json!({
+                "asks": [["5000100", "0.1"], ["5000200", "0.5"]],
+                "bids": [["4999900", "0.2"], ["4999800", "1"]],
+                "asks_over": "0",
+                "bids_under": "0",
+                "asks_under": "0",
+                "bids_over": "0",
+                "ask_market": "0",
+                "bid_market": "0",
+                "timestamp": self.now,
+                "sequenceId": self.now.to_string(),
+            })),
+            [_, "transactions"] | [_, "transactions", _] => MockResponse::Data(json!({
+                "transactions": [
+                    {"transaction_id": 2, "side": "buy", "price": "5000100", "amount": "0.01", "executed_at": self.now},
+                    {"transaction_id": 1, "side": "sell", "price": "4999900", "amount": "0.02", "executed_at": self.now - 1000},
+                ],
+            })),
+            [_, "candlestick", candle_type, _] => MockResponse::Data(json!({
+                "candlestick": [{
+                    "type": candle_type,
+                    "ohlcv": [["4900000", "5100000", "4800000", "5000000", "12.3456", MOCK_START_TIME]],
+                }],
+                "timestamp": self.now,
+            })),
+            [_, "circuit_break_info"] => MockResponse::Data(json!({
+                "mode": "NONE",
+                "estimated_itayose_price": null,
+                "estimated_itayose_amount": null,
+                "itayose_upper_price": null,
+                "itayose_lower_price": null,
+                "upper_trigger_price": "5500000",
+                "lower_trigger_price": "4500000",
+                "fee_type": "NORMAL",
+                "reopen_timestamp": null,
+                "timestamp": self.now,
+            })),
+            _ => MockResponse::ErrorCode(10000),
+        }
+    }
+
+    fn ticker(&self) -> Value {
+        json!({
+            "sell": "5000100",
+            "buy": "4999900",
+            "high": "5100000",
+            "low": "4800000",
+            "open": "4900000",
+            "last": MOCK_LAST_PRICE,
+            "vol": "123.4567",
+            "timestamp": self.now,
+        })
+    }
+
+    fn assets(&self) -> Value {
+        let mut locked = BTreeMap::<String, Decimal>::new();
+        for order in self.orders.values().filter(|order| order.is_active()) {
+            let Some((base, quote)) = order.pair.split_once('_') else {
+                continue;
+            };
+            match (order.side.as_str(), order.price) {
+                ("buy", Some(price)) => {
+                    *locked.entry(quote.to_owned()).or_default() += order.remaining_amount() * price
+                }
+                ("sell", _) => {
+                    *locked.entry(base.to_owned()).or_default() += order.remaining_amount()
+                }
+                _ => {}
+            }
+        }
+
+        let assets = self
+            .onhand
+            .iter()
+            .map(|(asset, onhand)| {
+                let locked = locked.get(asset).copied().unwrap_or_default();
+                json!({
+                    "asset": asset,
+                    "free_amount": (*onhand - locked).to_string(),
+                    "amount_precision": 4,
+                    "onhand_amount": onhand.to_string(),
+                    "locked_amount": locked.to_string(),
+                    "withdrawing_amount": "0",
+                    "withdrawal_fee": {"min": "0", "max": "0"},
+                    "stop_deposit": false,
+                    "stop_withdrawal": false,
+                    "network_list": null,
+                    "collateral_ratio": "0",
+                })
+            })
+            .collect::<Vec<_>>();
+
+        json!({ "assets": assets })
+    }
+
+    fn free_amount(&self, asset: &str) -> Decimal {
+        let assets = self.assets();
+        assets["assets"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|datum| datum["asset"] == asset)
+            .and_then(|datum| datum["free_amount"].as_str())
+            .and_then(|amount| amount.parse().ok())
+            .unwrap_or_default()
+    }
+
+    fn order_param(&self, params: &Value) -> Result<&MockOrder, u32> {
+        let order_id = params["order_id"].as_u64().ok_or(30006_u32)?;
+        self.orders.get(&order_id).ok_or(50009)
+    }
+
+    fn create_order(&mut self, params: &Value) -> MockResponse {
+        let Some(pair) = params["pair"].as_str() else {
+            return MockResponse::ErrorCode(30009);
+        };
+        let Some((base, quote)) = pair
+            .split_once('_')
+            .filter(|_| MOCK_PAIRS.iter().any(|(name, _, _, _)| *name == pair))
+        else {
+            return MockResponse::ErrorCode(40020);
+        };
+        let Some(amount) = decimal_param(params, "amount").filter(|amount| *amount > Decimal::ZERO)
+        else {
+            return MockResponse::ErrorCode(40001);
+        };
+        let side = params["side"].as_str().unwrap_or_default();
+        if side != "buy" && side != "sell" {
+            return MockResponse::ErrorCode(40021);
+        }
+        let r#type = params["type"].as_str().unwrap_or_default();
+        let price = decimal_param(params, "price");
+        if (r#type == "limit" || r#type == "stop_limit") && price.is_none() {
+            return MockResponse::ErrorCode(30012);
+        }
+
+        let required = match (side, price) {
+            ("buy", Some(price)) => (quote, amount * price),
+            ("buy", None) => (quote, Decimal::ZERO),
+            _ => (base, amount),
+        };
+        if self.free_amount(required.0) < required.1 {
+            return MockResponse::ErrorCode(60001);
+        }
+
+        let order_id = self.next_order_id;
+        self.next_order_id += 1;
+        let ordered_at = self.tick();
+        let order = MockOrder {
+            order_id,
+            pair: pair.to_owned(),
+            side: side.to_owned(),
+            r#type: r#type.to_owned(),
+            position_side: params["position_side"].as_str().map(str::to_owned),
+            start_amount: amount,
+            executed_amount: Decimal::ZERO,
+            executed_value: Decimal::ZERO,
+            price,
+            post_only: params["post_only"].as_bool(),
+            trigger_price: params["trigger_price"].as_str().map(str::to_owned),
+            ordered_at,
+            canceled_at: None,
+            is_canceled: false,
+        };
+        let response = order.to_json();
+        self.orders.insert(order_id, order);
+
+        MockResponse::Data(response)
+    }
+
+    fn cancel_order(&mut self, order_id: u64) -> Result<Value, u32> {
+        let now = self.tick();
+        let order = self.orders.get_mut(&order_id).ok_or(50009_u32)?;
+        if !order.is_active() {
+            return Err(50010);
+        }
+
+        order.is_canceled = true;
+        order.canceled_at = Some(now);
+        Ok(order.to_cancel_json())
+    }
+
+    fn fill_order(&mut self, order_id: u64, amount: Decimal) -> bool {
+        let executed_at = self.tick();
+        let Some(order) = self
+            .orders
+            .get_mut(&order_id)
+            .filter(|order| order.is_active())
+        else {
+            return false;
+        };
+        let Some((base, quote)) = order.pair.split_once('_') else {
+            return false;
+        };
+
+        let amount = amount.min(order.remaining_amount());
+        let price = order
+            .price
+            .unwrap_or_else(|| MOCK_LAST_PRICE.parse().unwrap_or_default());
+        order.executed_amount += amount;
+        order.executed_value += amount * price;
+
+        let (base_delta, quote_delta) = if order.side == "buy" {
+            (amount, -amount * price)
+        } else {
+            (-amount, amount * price)
+        };
+        *self.onhand.entry(base.to_owned()).or_default() += base_delta;
+        *self.onhand.entry(quote.to_owned()).or_default() += quote_delta;
+
+        let trade = json!({
+            "trade_id": self.next_trade_id,
+            "pair": order.pair,
+            "order_id": order.order_id,
+            "side": order.side,
+            "position_side": order.position_side,
+            "type": order.r#type,
+            "amount": amount.to_string(),
+            "price": price.to_string(),
+            "maker_taker": if order.r#type == "limit" { "maker" } else { "taker" },
+            "fee_amount_base": "0",
+            "fee_amount_quote": "0",
+            "fee_occurred_amount_quote": "0",
+            "profit_loss": null,
+            "interest": null,
+            "executed_at": executed_at,
+        });
+        self.next_trade_id += 1;
+        self.trades.push(trade);
+
+        true
+    }
+
+    fn trade_history(&self, params: &Value) -> Vec<Value> {
+        let pair = params["pair"].as_str();
+        let order_id = params["order_id"].as_u64();
+        let since = params["since"].as_u64();
+        let end = params["end"].as_u64();
+
+        let mut trades = self
+            .trades
+            .iter()
+            .filter(|trade| pair.is_none_or(|pair| trade["pair"] == pair))
+            .filter(|trade| order_id.is_none_or(|order_id| trade["order_id"] == order_id))
+            .filter(|trade| in_range(trade["executed_at"].as_u64(), since, end))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if params["order"].as_str() != Some("asc") {
+            trades.reverse();
+        }
+        trades.truncate(count_param(params).unwrap_or(1000));
+        trades
+    }
+
+    fn confirm_deposits(&mut self, mut should_confirm: impl FnMut(&Value) -> bool) {
+        let confirmed_at = self.tick();
+        let (confirmed, unconfirmed) = std::mem::take(&mut self.unconfirmed_deposits)
+            .into_iter()
+            .partition::<Vec<_>, _>(|deposit| should_confirm(deposit));
+
+        self.unconfirmed_deposits = unconfirmed;
+        for deposit in confirmed {
+            self.deposits.push(json!({
+                "uuid": deposit["uuid"],
+                "asset": deposit["asset"],
+                "network": deposit["network"],
+                "amount": deposit["amount"],
+                "txid": deposit["txid"],
+                "status": "DONE",
+                "found_at": deposit["created_at"],
+                "confirmed_at": confirmed_at,
+            }));
+        }
+    }
+
+    fn request_withdrawal(&mut self, params: &Value) -> MockResponse {
+        let asset = params["asset"].as_str().unwrap_or_default().to_owned();
+        let Some(amount) = decimal_param(params, "amount").filter(|amount| *amount > Decimal::ZERO)
+        else {
+            return MockResponse::ErrorCode(40001);
+        };
+        if self.free_amount(&asset) < amount {
+            return MockResponse::ErrorCode(60001);
+        }
+
+        let requested_at = self.tick();
+        *self.onhand.entry(asset.clone()).or_default() -= amount;
+
+        let account = withdrawal_account(&asset);
+        let withdrawal = json!({
+            "uuid": format!("mock-withdrawal-{}", self.withdrawals.len() + 1),
+            "asset": asset,
+            "account_uuid": params["uuid"],
+            "amount": amount.to_string(),
+            "fee": "0",
+            "label": account["label"],
+            "address": account["address"],
+            "network": account["network"],
+            "txid": null,
+            "destination_tag": null,
+            "bank_name": null,
+            "branch_name": null,
+            "account_type": null,
+            "account_number": null,
+            "account_owner": null,
+            "status": "CONFIRMING",
+            "requested_at": requested_at,
+        });
+        self.withdrawals.push(withdrawal.clone());
+
+        MockResponse::Data(withdrawal)
+    }
+}
+
+// (ペア名, 価格の桁数, 数量の桁数, 最小注文数量)
+const MOCK_PAIRS: [(&str, u32, u32, &str); 3] = [
+    ("btc_jpy", 0, 4, "0.0001"),
+    ("eth_jpy", 0, 4, "0.0001"),
+    ("xrp_jpy", 3, 4, "0.0001"),
+];
+
+fn pair_datum(&(name, price_digits, amount_digits, unit_amount): &(&str, u32, u32, &str)) -> Value {
+    let (base, quote) = name.split_once('_').unwrap_or((name, "jpy"));
+    json!({
+        "name": name,
+        "base_asset": base,
+        "quote_asset": quote,
+        "maker_fee_rate_base": "0",
+        "taker_fee_rate_base": "0",
+        "maker_fee_rate_quote": "-0.0002",
+        "taker_fee_rate_quote": "0.0012",
+        "margin_open_maker_fee_rate_quote": null,
+        "margin_open_taker_fee_rate_quote": null,
+        "margin_close_maker_fee_rate_quote": null,
+        "margin_close_taker_fee_rate_quote": null,
+        "margin_long_interest": null,
+        "margin_short_interest": null,
+        "margin_current_individual_ratio": null,
+        "margin_current_individual_until": null,
+        "margin_current_company_ratio": null,
+        "margin_current_company_until": null,
+        "margin_next_individual_ratio": null,
+        "margin_next_individual_until": null,
+        "margin_next_company_ratio": null,
+        "margin_next_company_until": null,
+        "unit_amount": unit_amount,
+        "limit_max_amount": "1000",
+        "market_max_amount": "10",
+        "market_allowance_rate": "0.2",
+        "price_digits": price_digits,
+        "amount_digits": amount_digits,
+        "is_enabled": true,
+        "stop_order": false,
+        "stop_order_and_cancel": false,
+        "stop_market_order": false,
+        "stop_stop_order": false,
+        "stop_stop_limit_order": false,
+        "stop_margin_long_order": false,
+        "stop_margin_short_order": false,
+        "stop_buy_order": false,
+        "stop_sell_order": false,
+    })
+}
+
+fn withdrawal_account(asset: &str) -> Value {
+    // 日本円の出金先は銀行口座なので、ネットワークとアドレスがない。
+    let (network, address) = if asset == "jpy" {
+        (None, None)
+    } else {
+        (Some("mock_network"), Some("mock_address"))
+    };
+
+    json!({
+        "uuid": format!("mock-{}-account", asset),
+        "label": format!("mock {} account", asset),
+        "network": network,
+        "address": address,
+        "destination_tag": null,
+        "bank_name": null,
+        "branch_name": null,
+        "account_type": null,
+        "account_number": null,
+        "account_owner": null,
+    })
+}
+
+fn filter_history(entries: &[Value], params: &Value, timestamp_field: &str) -> Vec<Value> {
+    let asset = params["asset"].as_str();
+    let since = params["since"].as_u64();
+    let end = params["end"].as_u64();
+
+    let mut entries = entries
+        .iter()
+        .filter(|entry| asset.is_none_or(|asset| entry["asset"] == asset))
+        .filter(|entry| in_range(entry[timestamp_field].as_u64(), since, end))
+        .cloned()
+        .collect::<Vec<_>>();
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry[timestamp_field].as_u64()));
+    entries.truncate(count_param(params).unwrap_or(25));
+    entries
+}
+
+fn in_range(timestamp: Option<u64>, since: Option<u64>, end: Option<u64>) -> bool {
+    let timestamp = timestamp.unwrap_or_default();
+    since.is_none_or(|since| since <= timestamp) && end.is_none_or(|end| timestamp <= end)
+}
+
+fn order_ids_param(params: &Value) -> Vec<u64> {
+    params["order_ids"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_u64)
+        .collect()
+}
+
+// 数値・文字列のどちらでも受け付ける。
+fn count_param(params: &Value) -> Option<usize> {
+    match &params["count"] {
+        Value::Number(count) => count.as_u64().map(|count| count as usize),
+        Value::String(count) => count.parse().ok(),
+        _ => None,
+    }
+}
+
+fn decimal_param(params: &Value, key: &str) -> Option<Decimal> {
+    params[key].as_str().and_then(|value| value.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::BitbankApiErrorCode;
+    use crate::request_policy::RetryPolicy;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn order_lifecycle_is_reflected_in_later_responses() {
+        let mock = MockBitbankServer::new();
+        let client = mock.private_client();
+
+        let order = client
+            .post_order(
+                "btc_jpy",
+                "0.1",
+                Some("4000000"),
+                "buy",
+                "limit",
+                Some(true),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        let order_id = order.order_id.as_u64().unwrap();
+        assert_eq!(order.status, "UNFILLED");
+
+        let assets = client.get_assets().await.unwrap();
+        let jpy = assets
+            .assets
+            .iter()
+            .find(|asset| asset.asset == "jpy")
+            .unwrap();
+        assert_eq!(jpy.locked_amount, "400000.0");
+
+        assert!(mock.fill_order(order_id, Decimal::new(4, 2)));
+        let order = client.get_order("btc_jpy", order_id).await.unwrap();
+        assert_eq!(order.status, "PARTIALLY_FILLED");
+        let trades = client
+            .get_trade_history(Some("btc_jpy"), None, None, None, None, None)
+            .await
+            .unwrap();
+        assert_eq!(trades.trades.len(), 1);
+
+        let canceled = client.post_cancel_order("btc_jpy", order_id).await.unwrap();
+        assert_eq!(canceled.status, "CANCELED_PARTIALLY_FILLED");
+        let active = client
+            .get_active_orders(Some("btc_jpy"), None, None, None, None, None)
+            .await
+            .unwrap();
+        assert!(active.orders.is_empty());
+
+        assert_eq!(
+            client
+                .post_cancel_order("btc_jpy", order_id)
+                .await
+                .unwrap_err(),
+            BitbankError::Api(BitbankApiErrorCode::OrderNotCancelable)
+        );
+        assert_eq!(
+            client.get_order("btc_jpy", 999).await.unwrap_err(),
+            BitbankError::Api(BitbankApiErrorCode::OrderNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn queued_responses_take_precedence_and_are_retried() {
+        let mock = MockBitbankServer::new();
+        let client = mock.private_client().with_retry_policy(RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            ..RetryPolicy::exponential(2)
+        });
+
+        mock.push_response("/user/assets", MockResponse::ErrorCode(10009));
+        mock.push_response(
+            "/user/assets",
+            MockResponse::Error(BitbankError::Transport("reset".to_owned())),
+        );
+
+        assert!(client.get_assets().await.is_ok());
+        assert_eq!(mock.requests().len(), 3);
+
+        assert_eq!(
+            client
+                .post_order(
+                    "btc_jpy",
+                    "100",
+                    Some("5000000"),
+                    "buy",
+                    "limit",
+                    None,
+                    None,
+                    None
+                )
+                .await
+                .unwrap_err(),
+            BitbankError::Api(BitbankApiErrorCode::InsufficientFunds)
+        );
+    }
+}
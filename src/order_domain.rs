@@ -2,7 +2,14 @@ use std::{fmt, str::FromStr};
 
 use rust_decimal::Decimal;
 
-use crate::bitbank_structs::{BitbankAssetDatum, BitbankGetOrderResponse};
+use crate::{
+    bitbank_structs::{
+        private_stream_struct::{BitbankPrivateAssetUpdate, BitbankPrivateOrder},
+        BitbankAssetDatum, BitbankGetOrderResponse, BitbankMarginPositionDatum,
+        BitbankTradeHistoryDatum,
+    },
+    pair_spec::{PairSpec, PairSpecViolation},
+};
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Hash)]
 pub enum OrderSide {
@@ -37,6 +44,55 @@ impl FromStr for OrderSide {
     }
 }
 
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Hash)]
+pub enum PositionSide {
+    Long,
+    Short,
+}
+
+impl PositionSide {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PositionSide::Long => "long",
+            PositionSide::Short => "short",
+        }
+    }
+
+    // このポジションを建てる注文のサイド。
+    pub fn opening_side(self) -> OrderSide {
+        match self {
+            PositionSide::Long => OrderSide::Buy,
+            PositionSide::Short => OrderSide::Sell,
+        }
+    }
+
+    // このポジションを決済する注文のサイド。
+    pub fn closing_side(self) -> OrderSide {
+        match self {
+            PositionSide::Long => OrderSide::Sell,
+            PositionSide::Short => OrderSide::Buy,
+        }
+    }
+}
+
+impl fmt::Display for PositionSide {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for PositionSide {
+    type Err = ParseOrderError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "long" => Ok(PositionSide::Long),
+            "short" => Ok(PositionSide::Short),
+            _ => Err(ParseOrderError::UnknownPositionSide(value.to_owned())),
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Hash)]
 pub enum OrderType {
     Limit,
@@ -85,6 +141,59 @@ impl FromStr for OrderType {
     }
 }
 
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Hash)]
+pub enum OrderStatus {
+    Inactive,
+    Unfilled,
+    PartiallyFilled,
+    FullyFilled,
+    CanceledUnfilled,
+    CanceledPartiallyFilled,
+}
+
+impl OrderStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OrderStatus::Inactive => "INACTIVE",
+            OrderStatus::Unfilled => "UNFILLED",
+            OrderStatus::PartiallyFilled => "PARTIALLY_FILLED",
+            OrderStatus::FullyFilled => "FULLY_FILLED",
+            OrderStatus::CanceledUnfilled => "CANCELED_UNFILLED",
+            OrderStatus::CanceledPartiallyFilled => "CANCELED_PARTIALLY_FILLED",
+        }
+    }
+
+    // 板に残っている（またはトリガー待ちの）注文かどうか。
+    pub fn is_active(self) -> bool {
+        matches!(
+            self,
+            OrderStatus::Inactive | OrderStatus::Unfilled | OrderStatus::PartiallyFilled
+        )
+    }
+}
+
+impl fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for OrderStatus {
+    type Err = ParseOrderError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "INACTIVE" => Ok(OrderStatus::Inactive),
+            "UNFILLED" => Ok(OrderStatus::Unfilled),
+            "PARTIALLY_FILLED" => Ok(OrderStatus::PartiallyFilled),
+            "FULLY_FILLED" => Ok(OrderStatus::FullyFilled),
+            "CANCELED_UNFILLED" => Ok(OrderStatus::CanceledUnfilled),
+            "CANCELED_PARTIALLY_FILLED" => Ok(OrderStatus::CanceledPartiallyFilled),
+            _ => Err(ParseOrderError::UnknownStatus(value.to_owned())),
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Hash)]
 pub struct OrderId(pub u64);
 
@@ -95,6 +204,8 @@ pub struct DesiredLimitOrder {
     pub amount: Decimal,
     pub price: Decimal,
     pub post_only: Option<bool>,
+    /// 信用取引の注文の場合のポジションサイド。現物注文では`None`。
+    pub position_side: Option<PositionSide>,
 }
 
 impl DesiredLimitOrder {
@@ -105,19 +216,64 @@ impl DesiredLimitOrder {
             amount,
             price,
             post_only: Some(true),
+            position_side: None,
+        }
+    }
+
+    // 信用取引の新規建て注文を作る。ロングなら買い、ショートなら売りになる。
+    pub fn open_position(
+        pair: String,
+        position_side: PositionSide,
+        amount: Decimal,
+        price: Decimal,
+    ) -> Self {
+        Self {
+            position_side: Some(position_side),
+            ..Self::limit(pair, position_side.opening_side(), amount, price)
+        }
+    }
+
+    // 信用取引の決済注文を作る。ロングなら売り、ショートなら買いになる。
+    pub fn close_position(
+        pair: String,
+        position_side: PositionSide,
+        amount: Decimal,
+        price: Decimal,
+    ) -> Self {
+        Self {
+            position_side: Some(position_side),
+            ..Self::limit(pair, position_side.closing_side(), amount, price)
         }
     }
 
+    pub fn is_margin(&self) -> bool {
+        self.position_side.is_some()
+    }
+
     pub fn limit_price(&self) -> Decimal {
         self.price
     }
 
+    // 価格を呼値に、数量を数量の桁数に丸める。
+    pub fn round_to_pair_spec(self, pair_spec: &PairSpec) -> Self {
+        Self {
+            price: pair_spec.round_price(self.side, self.price),
+            amount: pair_spec.round_amount(self.amount),
+            ..self
+        }
+    }
+
+    pub fn validate_pair_spec(&self, pair_spec: &PairSpec) -> Result<(), PairSpecViolation> {
+        pair_spec.validate_limit_order(self)
+    }
+
     pub fn matches_open_order(&self, open_order: &OpenOrder) -> bool {
         self.pair == open_order.pair
             && self.side == open_order.side
             && open_order.order_type == OrderType::Limit
             && self.amount == open_order.remaining_amount
             && Some(self.price) == open_order.price
+            && self.position_side == open_order.position_side
             && post_only_matches(self.post_only, open_order.post_only)
     }
 }
@@ -135,6 +291,7 @@ pub struct OpenOrder {
     pub remaining_amount: Decimal,
     pub price: Option<Decimal>,
     pub post_only: Option<bool>,
+    pub position_side: Option<PositionSide>,
 }
 
 impl OpenOrder {
@@ -149,6 +306,7 @@ impl OpenOrder {
             amount: self.remaining_amount,
             price: self.price?,
             post_only: self.post_only,
+            position_side: self.position_side,
         })
     }
 }
@@ -176,6 +334,11 @@ impl TryFrom<&BitbankGetOrderResponse> for OpenOrder {
                     .map_err(|_| ParseOrderError::InvalidDecimal("price".to_owned()))
             })
             .transpose()?;
+        let position_side = value
+            .position_side
+            .as_deref()
+            .map(str::parse::<PositionSide>)
+            .transpose()?;
 
         Ok(OpenOrder {
             order_id: OrderId(order_id),
@@ -185,6 +348,153 @@ impl TryFrom<&BitbankGetOrderResponse> for OpenOrder {
             remaining_amount,
             price,
             post_only: value.post_only,
+            position_side,
+        })
+    }
+}
+
+/// Private Streamで配信された注文の状態変化。
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct OrderUpdate {
+    /// 更新後の注文。終了した注文では`remaining_amount`が0になる。
+    pub order: OpenOrder,
+    pub status: OrderStatus,
+    pub executed_amount: Decimal,
+    pub average_price: Decimal,
+}
+
+impl TryFrom<&BitbankPrivateOrder> for OrderUpdate {
+    type Error = ParseOrderError;
+
+    fn try_from(value: &BitbankPrivateOrder) -> Result<Self, Self::Error> {
+        let order_id = value
+            .order_id
+            .as_u64()
+            .ok_or(ParseOrderError::InvalidOrderId)?;
+        let remaining_amount = value
+            .remaining_amount
+            .as_deref()
+            .ok_or(ParseOrderError::MissingRemainingAmount)?;
+        let price = value
+            .price
+            .as_deref()
+            .map(|price| parse_decimal_field("price", price))
+            .transpose()?;
+        let position_side = value
+            .position_side
+            .as_deref()
+            .map(str::parse::<PositionSide>)
+            .transpose()?;
+
+        Ok(Self {
+            order: OpenOrder {
+                order_id: OrderId(order_id),
+                pair: value.pair.clone(),
+                side: value.side.parse()?,
+                order_type: value.r#type.parse()?,
+                remaining_amount: parse_decimal_field("remaining_amount", remaining_amount)?,
+                price,
+                post_only: value.post_only,
+                position_side,
+            },
+            status: value.status.parse()?,
+            executed_amount: parse_decimal_field("executed_amount", &value.executed_amount)?,
+            average_price: parse_decimal_field("average_price", &value.average_price)?,
+        })
+    }
+}
+
+/// 自分の注文の約定。
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Execution {
+    pub trade_id: u64,
+    pub order_id: OrderId,
+    pub pair: String,
+    pub side: OrderSide,
+    pub position_side: Option<PositionSide>,
+    pub order_type: OrderType,
+    pub amount: Decimal,
+    pub price: Decimal,
+    pub is_maker: bool,
+    pub fee_amount_base: Decimal,
+    pub fee_amount_quote: Decimal,
+    pub executed_at: u64,
+}
+
+impl TryFrom<&BitbankTradeHistoryDatum> for Execution {
+    type Error = ParseOrderError;
+
+    fn try_from(value: &BitbankTradeHistoryDatum) -> Result<Self, Self::Error> {
+        let trade_id = value
+            .trade_id
+            .as_u64()
+            .ok_or(ParseOrderError::InvalidOrderId)?;
+        let order_id = value
+            .order_id
+            .as_u64()
+            .ok_or(ParseOrderError::InvalidOrderId)?;
+        let position_side = value
+            .position_side
+            .as_deref()
+            .map(str::parse::<PositionSide>)
+            .transpose()?;
+
+        Ok(Self {
+            trade_id,
+            order_id: OrderId(order_id),
+            pair: value.pair.clone(),
+            side: value.side.parse()?,
+            position_side,
+            order_type: value.r#type.parse()?,
+            amount: parse_decimal_field("amount", &value.amount)?,
+            price: parse_decimal_field("price", &value.price)?,
+            is_maker: value.maker_taker == "maker",
+            fee_amount_base: parse_decimal_field("fee_amount_base", &value.fee_amount_base)?,
+            fee_amount_quote: parse_decimal_field("fee_amount_quote", &value.fee_amount_quote)?,
+            executed_at: value.executed_at.as_u64().unwrap_or_default(),
+        })
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct MarginPosition {
+    pub pair: String,
+    pub position_side: PositionSide,
+    pub open_amount: Decimal,
+    pub average_price: Decimal,
+    pub unrealized_fee_amount: Decimal,
+    pub unrealized_interest_amount: Decimal,
+}
+
+impl MarginPosition {
+    // ポジションを全て決済する指値注文を作る。
+    pub fn closing_order(&self, price: Decimal) -> DesiredLimitOrder {
+        DesiredLimitOrder::close_position(
+            self.pair.clone(),
+            self.position_side,
+            self.open_amount,
+            price,
+        )
+    }
+}
+
+impl TryFrom<&BitbankMarginPositionDatum> for MarginPosition {
+    type Error = ParseOrderError;
+
+    fn try_from(value: &BitbankMarginPositionDatum) -> Result<Self, Self::Error> {
+        Ok(Self {
+            pair: value.pair.clone(),
+            position_side: value.position_side.parse()?,
+            open_amount: parse_decimal_field("open_amount", &value.open_amount)?,
+            average_price: parse_decimal_field("average_price", &value.average_price)?,
+            unrealized_fee_amount: parse_decimal_field(
+                "unrealized_fee_amount",
+                &value.unrealized_fee_amount,
+            )?,
+            unrealized_interest_amount: parse_decimal_field(
+                "unrealized_interest_amount",
+                &value.unrealized_interest_amount,
+            )?,
         })
     }
 }
@@ -210,10 +520,25 @@ impl TryFrom<&BitbankAssetDatum> for BalanceSnapshot {
     }
 }
 
+impl TryFrom<&BitbankPrivateAssetUpdate> for BalanceSnapshot {
+    type Error = ParseOrderError;
+
+    fn try_from(value: &BitbankPrivateAssetUpdate) -> Result<Self, Self::Error> {
+        Ok(Self {
+            asset: value.asset.clone(),
+            free_amount: parse_decimal_field("free_amount", &value.free_amount)?,
+            locked_amount: parse_decimal_field("locked_amount", &value.locked_amount)?,
+            onhand_amount: parse_decimal_field("onhand_amount", &value.onhand_amount)?,
+        })
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ParseOrderError {
     UnknownSide(String),
     UnknownType(String),
+    UnknownPositionSide(String),
+    UnknownStatus(String),
     InvalidOrderId,
     MissingRemainingAmount,
     InvalidDecimal(String),
@@ -329,6 +654,7 @@ mod tests {
             remaining_amount: Decimal::new(25, 2),
             price: Some(Decimal::new(4_900_000, 0)),
             post_only: Some(true),
+            position_side: None,
         };
 
         assert_eq!(
@@ -358,6 +684,7 @@ mod tests {
             remaining_amount: Decimal::new(25, 2),
             price: Some(Decimal::new(4_900_000, 0)),
             post_only: None,
+            position_side: None,
         };
 
         assert!(desired_order.matches_open_order(&open_order));
@@ -379,8 +706,81 @@ mod tests {
             remaining_amount: Decimal::new(25, 2),
             price: Some(Decimal::new(4_900_000, 0)),
             post_only: Some(false),
+            position_side: None,
         };
 
         assert!(!desired_order.matches_open_order(&open_order));
     }
+
+    #[test]
+    fn converts_bitbank_margin_order_response_with_position_side() {
+        let response: BitbankGetOrderResponse = serde_json::from_value(json!({
+            "order_id": 12345,
+            "pair": "btc_jpy",
+            "side": "sell",
+            "position_side": "short",
+            "type": "limit",
+            "start_amount": "0.2",
+            "remaining_amount": "0.2",
+            "executed_amount": "0",
+            "price": "5000000",
+            "post_only": true,
+            "user_cancelable": true,
+            "average_price": "0",
+            "ordered_at": 1710000000000_u64,
+            "expire_at": null,
+            "trigger_price": null,
+            "status": "UNFILLED"
+        }))
+        .unwrap();
+
+        let order = OpenOrder::try_from(&response).unwrap();
+
+        assert_eq!(order.position_side, Some(PositionSide::Short));
+        assert!(DesiredLimitOrder::open_position(
+            "btc_jpy".to_owned(),
+            PositionSide::Short,
+            Decimal::new(2, 1),
+            Decimal::new(5_000_000, 0),
+        )
+        .matches_open_order(&order));
+        assert!(!DesiredLimitOrder::limit(
+            "btc_jpy".to_owned(),
+            OrderSide::Sell,
+            Decimal::new(2, 1),
+            Decimal::new(5_000_000, 0),
+        )
+        .matches_open_order(&order));
+    }
+
+    #[test]
+    fn margin_position_closing_order_uses_opposite_side() {
+        let datum: BitbankMarginPositionDatum = serde_json::from_value(json!({
+            "pair": "btc_jpy",
+            "position_side": "long",
+            "open_amount": "0.3",
+            "product": "btc",
+            "average_price": "5000000",
+            "unrealized_fee_amount": "12.5",
+            "unrealized_interest_amount": "3"
+        }))
+        .unwrap();
+
+        let position = MarginPosition::try_from(&datum).unwrap();
+        let closing_order = position.closing_order(Decimal::new(5_100_000, 0));
+
+        assert_eq!(position.position_side, PositionSide::Long);
+        assert_eq!(position.open_amount, Decimal::new(3, 1));
+        assert_eq!(closing_order.side, OrderSide::Sell);
+        assert_eq!(closing_order.position_side, Some(PositionSide::Long));
+        assert_eq!(closing_order.amount, Decimal::new(3, 1));
+    }
+
+    #[test]
+    fn rejects_unknown_margin_position_side() {
+        assert_eq!(
+            "flat".parse::<PositionSide>().unwrap_err(),
+            ParseOrderError::UnknownPositionSide("flat".to_owned())
+        );
+    }
 }
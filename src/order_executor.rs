@@ -2,7 +2,8 @@ use std::{future::Future, pin::Pin};
 
 use crate::{
     bitbank_private::BitbankPrivateApiClient,
-    order_domain::{DesiredLimitOrder, OrderId, OrderType},
+    error::BitbankError,
+    order_domain::{DesiredLimitOrder, OrderId, OrderType, PositionSide},
 };
 
 pub type OrderExecutorFuture<'a, T> =
@@ -10,10 +11,20 @@ pub type OrderExecutorFuture<'a, T> =
 
 #[derive(Debug)]
 pub enum OrderExecutionError {
-    Bitbank(Option<crypto_botters::bitbank::BitbankHandleError>),
+    Bitbank(BitbankError),
     Other(String),
 }
 
+impl OrderExecutionError {
+    // 時間をおいて同じ操作を再実行すれば成功する可能性があるかどうか。
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            OrderExecutionError::Bitbank(err) => err.is_retryable(),
+            OrderExecutionError::Other(_) => false,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PlacementRequest {
     pub order: DesiredLimitOrder,
@@ -71,6 +82,7 @@ impl OrderExecutor for BitbankOrderExecutor {
                     OrderType::Limit.as_str(),
                     order.post_only,
                     None,
+                    order.position_side.map(PositionSide::as_str),
                 )
                 .await
                 .map_err(OrderExecutionError::Bitbank)?;
@@ -112,6 +124,7 @@ impl OrderExecutor for BitbankPrivateApiClient {
                     OrderType::Limit.as_str(),
                     request.order.post_only,
                     None,
+                    request.order.position_side.map(PositionSide::as_str),
                 )
                 .await
                 .map_err(OrderExecutionError::Bitbank)?;
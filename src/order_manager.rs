@@ -1,17 +1,29 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
 use crate::{
     bitbank_structs::BitbankGetOrderResponse,
     order_domain::{DesiredLimitOrder, OpenOrder, OrderId, OrderSide},
-    order_executor::{OrderExecutor, PlacementRequest},
+    order_executor::{OrderExecutionError, OrderExecutor, PlacementRequest},
+    pair_spec::{PairSpec, PairSpecViolation},
 };
 use rust_decimal::Decimal;
 use tokio::{task::JoinSet, time::Instant};
 
+// bitbankの`cancel_orders`で一度にキャンセルできる注文の数。
+const MAX_CANCEL_ORDERS_PER_REQUEST: usize = 30;
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct OrderPlan {
     pub cancels: Vec<OrderId>,
     pub placements: Vec<DesiredLimitOrder>,
+    /// 取引所のルールに違反するため、発注しない希望注文。
+    pub rejections: Vec<RejectedOrder>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct RejectedOrder {
+    pub order: DesiredLimitOrder,
+    pub violation: PairSpecViolation,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -49,6 +61,42 @@ pub fn plan_orders(
     OrderPlan {
         cancels,
         placements: wanna_place_orders,
+        rejections: vec![],
+    }
+}
+
+// 希望注文を`pair_spec`の呼値・数量の桁数に丸めてから`plan_orders`を行う。
+// 丸めた後も取引所のルールに違反する注文は発注せず、`rejections`に入れる。
+pub fn plan_orders_with_pair_spec(
+    wanna_place_orders: Vec<DesiredLimitOrder>,
+    current_orders: Vec<OpenOrder>,
+    pair_spec: &PairSpec,
+) -> OrderPlan {
+    let mut valid_orders = vec![];
+    let mut rejections = vec![];
+
+    for wanna_order in wanna_place_orders {
+        let rounded_order = wanna_order.round_to_pair_spec(pair_spec);
+
+        match rounded_order.validate_pair_spec(pair_spec) {
+            Ok(()) => valid_orders.push(rounded_order),
+            Err(violation) => {
+                log::warn!(
+                    "this order will not be placed: {:?}, violation: {:?}",
+                    rounded_order,
+                    violation
+                );
+                rejections.push(RejectedOrder {
+                    order: rounded_order,
+                    violation,
+                });
+            }
+        }
+    }
+
+    OrderPlan {
+        rejections,
+        ..plan_orders(valid_orders, current_orders, &pair_spec.pair)
     }
 }
 
@@ -58,11 +106,18 @@ fn plan_concurrent_orders_from_open_orders(
     btc_free_amount: Decimal,
     jpy_free_amount: Decimal,
     pair: &str,
+    pair_spec: Option<&PairSpec>,
 ) -> ConcurrentOrderPlan {
     let OrderPlan {
         cancels,
         placements,
-    } = plan_orders(wanna_place_orders, current_orders, pair);
+        ..
+    } = match pair_spec {
+        Some(pair_spec) => {
+            plan_orders_with_pair_spec(wanna_place_orders, current_orders, pair_spec)
+        }
+        None => plan_orders(wanna_place_orders, current_orders, pair),
+    };
 
     let mut next_btc_free_amount = btc_free_amount;
     let mut next_jpy_free_amount = jpy_free_amount;
@@ -130,16 +185,39 @@ pub async fn place_wanna_orders(
     pair: String,
     executor: impl OrderExecutor,
 ) {
+    let order_plan = plan_orders(
+        wanna_place_orders.into_iter().collect(),
+        open_orders_from_bitbank_responses(current_orders),
+        &pair,
+    );
+
+    execute_order_plan(order_plan, pair, executor).await;
+}
+
+// `place_wanna_orders`と同じだが、希望注文を`pair_spec`に合わせて丸め、ルール違反の注文は発注しない。
+pub async fn place_wanna_orders_with_pair_spec(
+    wanna_place_orders: BTreeSet<DesiredLimitOrder>,
+    current_orders: Vec<BitbankGetOrderResponse>,
+    pair_spec: &PairSpec,
+    executor: impl OrderExecutor,
+) {
+    let order_plan = plan_orders_with_pair_spec(
+        wanna_place_orders.into_iter().collect(),
+        open_orders_from_bitbank_responses(current_orders),
+        pair_spec,
+    );
+
+    execute_order_plan(order_plan, pair_spec.pair.clone(), executor).await;
+}
+
+async fn execute_order_plan(order_plan: OrderPlan, pair: String, executor: impl OrderExecutor) {
     let start = Instant::now();
     let mut js = JoinSet::new();
     let OrderPlan {
         cancels,
         placements,
-    } = plan_orders(
-        wanna_place_orders.into_iter().collect(),
-        open_orders_from_bitbank_responses(current_orders),
-        &pair,
-    );
+        ..
+    } = order_plan;
 
     if !cancels.is_empty() {
         let cancel_order_response_result = executor.cancel_orders(&pair, cancels).await;
@@ -170,6 +248,33 @@ pub async fn place_wanna_orders(
     log::debug!("Replaced orders within {} ms.", start.elapsed().as_millis());
 }
 
+// `current_orders`をすべてキャンセルする。ボットの停止時に注文を残さないために使う。
+// ペアごと・`cancel_orders`の上限ごとに分けて送り、途中で失敗しても残りは送る。失敗した場合は最初のエラーを返す。
+pub async fn cancel_all_orders(
+    current_orders: Vec<OpenOrder>,
+    executor: impl OrderExecutor,
+) -> Result<(), OrderExecutionError> {
+    let mut order_ids_by_pair: BTreeMap<String, Vec<OrderId>> = BTreeMap::new();
+    for order in current_orders {
+        order_ids_by_pair
+            .entry(order.pair)
+            .or_default()
+            .push(order.order_id);
+    }
+
+    let mut first_error = None;
+    for (pair, order_ids) in order_ids_by_pair {
+        for chunk in order_ids.chunks(MAX_CANCEL_ORDERS_PER_REQUEST) {
+            if let Err(err) = executor.cancel_orders(&pair, chunk.to_vec()).await {
+                log::error!("failed to cancel orders {:?} in {}: {:?}", chunk, pair, err);
+                first_error.get_or_insert(err);
+            }
+        }
+    }
+
+    first_error.map_or(Ok(()), Err)
+}
+
 /* 現在発注済みの注文（`current_orders`）と希望する注文状態（`wanna_place_orders`）を受け取り、新規注文または注文のキャンセルを実行する。
 可能な場合（新規注文を発注してから注文をキャンセルするのに十分な資金がある場合）、注文のキャンセルと新規注文は並行して処理される。
 wanna_place_orders
@@ -188,19 +293,51 @@ pub async fn place_wanna_orders_concurrent(
     pair: String,
     executor: impl OrderExecutor,
 ) {
-    let start = Instant::now();
-    let ConcurrentOrderPlan {
-        cancels,
-        first_placements,
-        second_placements,
-    } = plan_concurrent_orders_from_open_orders(
+    let concurrent_plan = plan_concurrent_orders_from_open_orders(
         wanna_place_orders,
         open_orders_from_bitbank_responses(current_orders),
         btc_free_amount,
         jpy_free_amount,
         &pair,
+        None,
     );
 
+    execute_concurrent_order_plan(concurrent_plan, pair, executor).await;
+}
+
+// `place_wanna_orders_concurrent`と同じだが、希望注文を`pair_spec`に合わせて丸め、ルール違反の注文は発注しない。
+pub async fn place_wanna_orders_concurrent_with_pair_spec(
+    wanna_place_orders: Vec<DesiredLimitOrder>,
+    current_orders: Vec<BitbankGetOrderResponse>,
+    btc_free_amount: Decimal,
+    jpy_free_amount: Decimal,
+    pair_spec: &PairSpec,
+    executor: impl OrderExecutor,
+) {
+    let concurrent_plan = plan_concurrent_orders_from_open_orders(
+        wanna_place_orders,
+        open_orders_from_bitbank_responses(current_orders),
+        btc_free_amount,
+        jpy_free_amount,
+        &pair_spec.pair,
+        Some(pair_spec),
+    );
+
+    execute_concurrent_order_plan(concurrent_plan, pair_spec.pair.clone(), executor).await;
+}
+
+async fn execute_concurrent_order_plan(
+    concurrent_plan: ConcurrentOrderPlan,
+    pair: String,
+    executor: impl OrderExecutor,
+) {
+    let start = Instant::now();
+    let ConcurrentOrderPlan {
+        cancels,
+        first_placements,
+        second_placements,
+    } = concurrent_plan;
+
     enum FirstJoinSetResponse {
         CancelResponse(Result<(), crate::order_executor::OrderExecutionError>),
         PostResponse(
@@ -352,6 +489,7 @@ mod tests {
             remaining_amount: amount,
             price: Some(price),
             post_only,
+            position_side: None,
         }
     }
 
@@ -507,6 +645,7 @@ mod tests {
             Decimal::new(1, 0),
             Decimal::new(1_000_000, 0),
             "btc_jpy",
+            None,
         );
 
         assert_eq!(plan.cancels, vec![OrderId(11)]);
@@ -552,6 +691,7 @@ mod tests {
             Decimal::new(5, 1),
             Decimal::new(100_000, 0),
             "btc_jpy",
+            None,
         );
 
         assert!(plan.cancels.is_empty());
@@ -585,6 +725,7 @@ mod tests {
             Decimal::ZERO,
             Decimal::ZERO,
             "btc_jpy",
+            None,
         );
 
         assert!(plan.cancels.is_empty());
@@ -592,6 +733,91 @@ mod tests {
         assert!(plan.second_placements.is_empty());
     }
 
+    #[test]
+    fn plan_orders_with_pair_spec_rounds_orders_and_rejects_rule_violations() {
+        let unrounded = desired_order(
+            "btc_jpy",
+            OrderSide::Buy,
+            Decimal::new(100_009, 6),
+            Decimal::new(50_000_005, 1),
+        );
+        let too_small = desired_order(
+            "btc_jpy",
+            OrderSide::Sell,
+            Decimal::new(5, 5),
+            Decimal::new(5_100_000, 0),
+        );
+        let current_orders = vec![open_order(
+            10,
+            "btc_jpy",
+            OrderSide::Buy,
+            Decimal::new(1, 1),
+            Decimal::new(5_000_000, 0),
+            Some(true),
+        )];
+
+        let plan = plan_orders_with_pair_spec(
+            vec![unrounded, too_small],
+            current_orders,
+            &crate::pair_spec::btc_jpy_spec(),
+        );
+
+        assert!(plan.cancels.is_empty());
+        assert!(plan.placements.is_empty());
+        assert_eq!(plan.rejections.len(), 1);
+        assert!(matches!(
+            plan.rejections[0].violation,
+            PairSpecViolation::AmountBelowMinimum { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn cancel_all_orders_groups_by_pair_and_request_limit() {
+        let executor = FakeOrderExecutor::default();
+        let mut current_orders = (1..=31)
+            .map(|order_id| {
+                open_order(
+                    order_id,
+                    "btc_jpy",
+                    OrderSide::Buy,
+                    Decimal::new(1, 4),
+                    Decimal::new(5000000, 0),
+                    Some(true),
+                )
+            })
+            .collect::<Vec<_>>();
+        current_orders.push(open_order(
+            100,
+            "eth_jpy",
+            OrderSide::Sell,
+            Decimal::new(1, 2),
+            Decimal::new(300000, 0),
+            None,
+        ));
+
+        cancel_all_orders(current_orders, executor.clone())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            executor.calls(),
+            vec![
+                ExecutorCall::Cancel {
+                    pair: "btc_jpy".to_owned(),
+                    order_ids: (1..=30).map(OrderId).collect(),
+                },
+                ExecutorCall::Cancel {
+                    pair: "btc_jpy".to_owned(),
+                    order_ids: vec![OrderId(31)],
+                },
+                ExecutorCall::Cancel {
+                    pair: "eth_jpy".to_owned(),
+                    order_ids: vec![OrderId(100)],
+                },
+            ]
+        );
+    }
+
     #[tokio::test]
     async fn place_wanna_orders_executes_order_plan_through_executor() {
         let desired = desired_order(
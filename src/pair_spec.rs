@@ -0,0 +1,422 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bitbank_structs::{BitbankPairDatum, BitbankPairsResponse},
+    order_domain::{DesiredLimitOrder, OrderSide, PositionSide},
+};
+
+/// 通貨ペアごとの取引ルール。`/spot/pairs`のレスポンス、またはキャッシュしたJSONから作る。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PairSpec {
+    pub pair: String,
+    pub base_asset: String,
+    pub quote_asset: String,
+    /// 価格の小数点以下の桁数。呼値は`10^-price_digits`になる。
+    pub price_digits: u32,
+    /// 数量の小数点以下の桁数。
+    pub amount_digits: u32,
+    /// 最小注文数量。
+    pub min_amount: Decimal,
+    /// 指値注文の最大数量。
+    pub max_limit_amount: Decimal,
+    pub maker_fee_rate_quote: Decimal,
+    pub taker_fee_rate_quote: Decimal,
+    pub is_enabled: bool,
+    pub stop_order: bool,
+    pub stop_buy_order: bool,
+    pub stop_sell_order: bool,
+    pub stop_margin_long_order: bool,
+    pub stop_margin_short_order: bool,
+}
+
+impl PairSpec {
+    pub fn tick_size(&self) -> Decimal {
+        Decimal::new(1, self.price_digits)
+    }
+
+    pub fn amount_step(&self) -> Decimal {
+        Decimal::new(1, self.amount_digits)
+    }
+
+    // 呼値に丸める。買いは切り捨て、売りは切り上げで、どちらも元の価格より不利な側には動かさない。
+    pub fn round_price(&self, side: OrderSide, price: Decimal) -> Decimal {
+        let strategy = match side {
+            OrderSide::Buy => RoundingStrategy::ToNegativeInfinity,
+            OrderSide::Sell => RoundingStrategy::ToPositiveInfinity,
+        };
+        price.round_dp_with_strategy(self.price_digits, strategy)
+    }
+
+    // 数量の桁数に切り捨てる。
+    pub fn round_amount(&self, amount: Decimal) -> Decimal {
+        amount.round_dp_with_strategy(self.amount_digits, RoundingStrategy::ToZero)
+    }
+
+    // 取引所のルールに違反している場合、最初に見つかった違反を返す。
+    pub fn validate_limit_order(&self, order: &DesiredLimitOrder) -> Result<(), PairSpecViolation> {
+        if order.pair != self.pair {
+            return Err(PairSpecViolation::PairMismatch {
+                expected: self.pair.clone(),
+                actual: order.pair.clone(),
+            });
+        }
+
+        if !self.is_enabled || self.stop_order {
+            return Err(PairSpecViolation::TradingSuspended(self.pair.clone()));
+        }
+
+        let side_suspended = match order.side {
+            OrderSide::Buy => self.stop_buy_order,
+            OrderSide::Sell => self.stop_sell_order,
+        };
+        if side_suspended {
+            return Err(PairSpecViolation::SideSuspended(order.side));
+        }
+
+        if let Some(position_side) = order.position_side {
+            let margin_suspended = match position_side {
+                PositionSide::Long => self.stop_margin_long_order,
+                PositionSide::Short => self.stop_margin_short_order,
+            };
+            if margin_suspended {
+                return Err(PairSpecViolation::MarginSuspended(position_side));
+            }
+        }
+
+        if order.price <= Decimal::ZERO || order.price.round_dp(self.price_digits) != order.price {
+            return Err(PairSpecViolation::InvalidPrice {
+                price: order.price,
+                tick_size: self.tick_size(),
+            });
+        }
+
+        if order.amount.round_dp(self.amount_digits) != order.amount {
+            return Err(PairSpecViolation::InvalidAmountPrecision {
+                amount: order.amount,
+                amount_step: self.amount_step(),
+            });
+        }
+
+        if order.amount < self.min_amount {
+            return Err(PairSpecViolation::AmountBelowMinimum {
+                amount: order.amount,
+                min_amount: self.min_amount,
+            });
+        }
+
+        if order.amount > self.max_limit_amount {
+            return Err(PairSpecViolation::AmountAboveMaximum {
+                amount: order.amount,
+                max_amount: self.max_limit_amount,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl TryFrom<&BitbankPairDatum> for PairSpec {
+    type Error = PairSpecError;
+
+    fn try_from(value: &BitbankPairDatum) -> Result<Self, Self::Error> {
+        Ok(Self {
+            pair: value.name.clone(),
+            base_asset: value.base_asset.clone(),
+            quote_asset: value.quote_asset.clone(),
+            price_digits: parse_digits_field("price_digits", &value.price_digits)?,
+            amount_digits: parse_digits_field("amount_digits", &value.amount_digits)?,
+            min_amount: parse_decimal_field("unit_amount", &value.unit_amount)?,
+            max_limit_amount: parse_decimal_field("limit_max_amount", &value.limit_max_amount)?,
+            maker_fee_rate_quote: parse_decimal_field(
+                "maker_fee_rate_quote",
+                &value.maker_fee_rate_quote,
+            )?,
+            taker_fee_rate_quote: parse_decimal_field(
+                "taker_fee_rate_quote",
+                &value.taker_fee_rate_quote,
+            )?,
+            is_enabled: value.is_enabled,
+            stop_order: value.stop_order,
+            stop_buy_order: value.stop_buy_order,
+            stop_sell_order: value.stop_sell_order,
+            stop_margin_long_order: value.stop_margin_long_order,
+            stop_margin_short_order: value.stop_margin_short_order,
+        })
+    }
+}
+
+/// 通貨ペア名から[`PairSpec`]を引くためのレジストリ。
+/// `/spot/pairs`のレスポンスは、APIキーのいらない[`crate::bitbank_public::BitbankPublicApiClient::get_pairs`]でも取得できる。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PairRegistry {
+    specs: BTreeMap<String, PairSpec>,
+}
+
+impl PairRegistry {
+    pub fn new(specs: Vec<PairSpec>) -> Self {
+        Self {
+            specs: specs
+                .into_iter()
+                .map(|spec| (spec.pair.clone(), spec))
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, pair: &str) -> Option<&PairSpec> {
+        self.specs.get(pair)
+    }
+
+    pub fn spec(&self, pair: &str) -> Result<&PairSpec, PairSpecError> {
+        self.get(pair)
+            .ok_or_else(|| PairSpecError::UnknownPair(pair.to_owned()))
+    }
+
+    pub fn pairs(&self) -> impl Iterator<Item = &str> {
+        self.specs.keys().map(String::as_str)
+    }
+
+    pub fn from_json_str(json: &str) -> Result<Self, PairSpecError> {
+        let specs: Vec<PairSpec> =
+            serde_json::from_str(json).map_err(|err| PairSpecError::Json(err.to_string()))?;
+        Ok(Self::new(specs))
+    }
+
+    pub fn to_json_string(&self) -> Result<String, PairSpecError> {
+        let specs = self.specs.values().collect::<Vec<_>>();
+        serde_json::to_string_pretty(&specs).map_err(|err| PairSpecError::Json(err.to_string()))
+    }
+
+    // `save_json_file`で書き出したキャッシュを読み込む。
+    pub fn load_json_file(path: impl AsRef<Path>) -> Result<Self, PairSpecError> {
+        let json = fs::read_to_string(path).map_err(|err| PairSpecError::Io(err.to_string()))?;
+        Self::from_json_str(&json)
+    }
+
+    pub fn save_json_file(&self, path: impl AsRef<Path>) -> Result<(), PairSpecError> {
+        fs::write(path, self.to_json_string()?).map_err(|err| PairSpecError::Io(err.to_string()))
+    }
+}
+
+impl TryFrom<&BitbankPairsResponse> for PairRegistry {
+    type Error = PairSpecError;
+
+    fn try_from(value: &BitbankPairsResponse) -> Result<Self, Self::Error> {
+        let specs = value
+            .pairs
+            .iter()
+            .map(PairSpec::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::new(specs))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PairSpecViolation {
+    PairMismatch {
+        expected: String,
+        actual: String,
+    },
+    TradingSuspended(String),
+    SideSuspended(OrderSide),
+    MarginSuspended(PositionSide),
+    InvalidPrice {
+        price: Decimal,
+        tick_size: Decimal,
+    },
+    InvalidAmountPrecision {
+        amount: Decimal,
+        amount_step: Decimal,
+    },
+    AmountBelowMinimum {
+        amount: Decimal,
+        min_amount: Decimal,
+    },
+    AmountAboveMaximum {
+        amount: Decimal,
+        max_amount: Decimal,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PairSpecError {
+    InvalidDecimal(String),
+    InvalidDigits(String),
+    UnknownPair(String),
+    Io(String),
+    Json(String),
+}
+
+fn parse_decimal_field(field: &str, value: &str) -> Result<Decimal, PairSpecError> {
+    value
+        .parse::<Decimal>()
+        .map_err(|_| PairSpecError::InvalidDecimal(field.to_owned()))
+}
+
+fn parse_digits_field(field: &str, value: &serde_json::Number) -> Result<u32, PairSpecError> {
+    value
+        .as_u64()
+        .and_then(|digits| u32::try_from(digits).ok())
+        // rust_decimalで表現できる桁数を超える値は不正とみなす。
+        .filter(|digits| *digits <= 28)
+        .ok_or_else(|| PairSpecError::InvalidDigits(field.to_owned()))
+}
+
+// テストで使うbtc_jpyの取引ルール。
+#[cfg(test)]
+pub(crate) fn btc_jpy_spec() -> PairSpec {
+    PairSpec {
+        pair: "btc_jpy".to_owned(),
+        base_asset: "btc".to_owned(),
+        quote_asset: "jpy".to_owned(),
+        price_digits: 0,
+        amount_digits: 4,
+        min_amount: Decimal::new(1, 4),
+        max_limit_amount: Decimal::new(1000, 0),
+        maker_fee_rate_quote: Decimal::new(-2, 4),
+        taker_fee_rate_quote: Decimal::new(12, 4),
+        is_enabled: true,
+        stop_order: false,
+        stop_buy_order: false,
+        stop_sell_order: false,
+        stop_margin_long_order: false,
+        stop_margin_short_order: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn btc_jpy_datum() -> BitbankPairDatum {
+        serde_json::from_value(json!({
+            "name": "btc_jpy",
+            "base_asset": "btc",
+            "quote_asset": "jpy",
+            "maker_fee_rate_base": "0",
+            "taker_fee_rate_base": "0",
+            "maker_fee_rate_quote": "-0.0002",
+            "taker_fee_rate_quote": "0.0012",
+            "margin_open_maker_fee_rate_quote": null,
+            "margin_open_taker_fee_rate_quote": null,
+            "margin_close_maker_fee_rate_quote": null,
+            "margin_close_taker_fee_rate_quote": null,
+            "margin_long_interest": null,
+            "margin_short_interest": null,
+            "margin_current_individual_ratio": null,
+            "margin_current_individual_until": null,
+            "margin_current_company_ratio": null,
+            "margin_current_company_until": null,
+            "margin_next_individual_ratio": null,
+            "margin_next_individual_until": null,
+            "margin_next_company_ratio": null,
+            "margin_next_company_until": null,
+            "unit_amount": "0.0001",
+            "limit_max_amount": "1000",
+            "market_max_amount": "10",
+            "market_allowance_rate": "0.2",
+            "price_digits": 0,
+            "amount_digits": 4,
+            "is_enabled": true,
+            "stop_order": false,
+            "stop_order_and_cancel": false,
+            "stop_market_order": false,
+            "stop_stop_order": false,
+            "stop_stop_limit_order": false,
+            "stop_margin_long_order": false,
+            "stop_margin_short_order": false,
+            "stop_buy_order": false,
+            "stop_sell_order": false
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn converts_bitbank_pair_datum_to_pair_spec() {
+        let spec = PairSpec::try_from(&btc_jpy_datum()).unwrap();
+        assert_eq!(spec, btc_jpy_spec());
+
+        assert_eq!(spec.pair, "btc_jpy");
+        assert_eq!(spec.tick_size(), Decimal::ONE);
+        assert_eq!(spec.amount_step(), Decimal::new(1, 4));
+        assert_eq!(spec.min_amount, Decimal::new(1, 4));
+        assert_eq!(spec.max_limit_amount, Decimal::new(1000, 0));
+        assert_eq!(spec.maker_fee_rate_quote, Decimal::new(-2, 4));
+    }
+
+    #[test]
+    fn rounds_price_away_from_crossing_and_amount_down() {
+        let spec = btc_jpy_spec();
+
+        assert_eq!(
+            spec.round_price(OrderSide::Buy, Decimal::new(50_000_005, 1)),
+            Decimal::new(5_000_000, 0)
+        );
+        assert_eq!(
+            spec.round_price(OrderSide::Sell, Decimal::new(50_000_005, 1)),
+            Decimal::new(5_000_001, 0)
+        );
+        assert_eq!(
+            spec.round_amount(Decimal::new(123_456, 6)),
+            Decimal::new(1234, 4)
+        );
+    }
+
+    #[test]
+    fn validate_limit_order_rejects_exchange_rule_violations() {
+        let spec = btc_jpy_spec();
+        let order = |amount: Decimal, price: Decimal| {
+            DesiredLimitOrder::limit("btc_jpy".to_owned(), OrderSide::Buy, amount, price)
+        };
+
+        assert_eq!(
+            spec.validate_limit_order(&order(Decimal::new(1, 3), Decimal::new(5_000_000, 0))),
+            Ok(())
+        );
+        assert!(matches!(
+            spec.validate_limit_order(&order(Decimal::new(1, 3), Decimal::new(50_000_005, 1))),
+            Err(PairSpecViolation::InvalidPrice { .. })
+        ));
+        assert!(matches!(
+            spec.validate_limit_order(&order(Decimal::new(1, 5), Decimal::new(5_000_000, 0))),
+            Err(PairSpecViolation::InvalidAmountPrecision { .. })
+        ));
+        assert!(matches!(
+            spec.validate_limit_order(&order(Decimal::ZERO, Decimal::new(5_000_000, 0))),
+            Err(PairSpecViolation::AmountBelowMinimum { .. })
+        ));
+        assert!(matches!(
+            spec.validate_limit_order(&order(Decimal::new(1001, 0), Decimal::new(5_000_000, 0))),
+            Err(PairSpecViolation::AmountAboveMaximum { .. })
+        ));
+
+        let suspended = PairSpec {
+            stop_buy_order: true,
+            ..spec
+        };
+        assert_eq!(
+            suspended.validate_limit_order(&order(Decimal::new(1, 3), Decimal::new(5_000_000, 0))),
+            Err(PairSpecViolation::SideSuspended(OrderSide::Buy))
+        );
+    }
+
+    #[test]
+    fn pair_registry_round_trips_through_json_cache() {
+        let registry = PairRegistry::try_from(&BitbankPairsResponse {
+            pairs: vec![btc_jpy_datum()],
+        })
+        .unwrap();
+
+        let restored = PairRegistry::from_json_str(&registry.to_json_string().unwrap()).unwrap();
+
+        assert_eq!(restored, registry);
+        assert_eq!(restored.spec("btc_jpy").unwrap(), &btc_jpy_spec());
+        assert_eq!(
+            restored.spec("eth_jpy").unwrap_err(),
+            PairSpecError::UnknownPair("eth_jpy".to_owned())
+        );
+    }
+}
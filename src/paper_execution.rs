@@ -6,11 +6,16 @@ use std::{
 use rust_decimal::Decimal;
 
 use crate::{
+    clock::Clock,
     market_event::{MarketEvent, MarketTrade},
-    order_domain::{BalanceSnapshot, DesiredLimitOrder, OpenOrder, OrderId, OrderSide, OrderType},
+    order_domain::{
+        BalanceSnapshot, DesiredLimitOrder, Execution, OpenOrder, OrderId, OrderSide, OrderType,
+        PositionSide,
+    },
     order_executor::{
         OrderExecutionError, OrderExecutor, OrderExecutorFuture, PlacedOrder, PlacementRequest,
     },
+    pair_spec::{PairSpec, PairSpecViolation},
 };
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -18,6 +23,8 @@ pub struct PaperExecutionConfig {
     pub pair: String,
     pub fee_schedule: PaperFeeSchedule,
     pub next_order_id: OrderId,
+    /// 指定した場合、取引所のルールに違反する注文を拒否する。
+    pub pair_spec: Option<PairSpec>,
 }
 
 impl PaperExecutionConfig {
@@ -27,8 +34,22 @@ impl PaperExecutionConfig {
             fee_schedule: PaperFeeSchedule::bitbank_spot_default(&pair)?,
             pair,
             next_order_id: OrderId(1),
+            pair_spec: None,
         })
     }
+
+    // `/spot/pairs`から取得した手数料と取引ルールを使う。
+    pub fn from_pair_spec(pair_spec: PairSpec) -> Self {
+        Self {
+            pair: pair_spec.pair.clone(),
+            fee_schedule: PaperFeeSchedule::new(
+                pair_spec.maker_fee_rate_quote,
+                pair_spec.taker_fee_rate_quote,
+            ),
+            next_order_id: OrderId(1),
+            pair_spec: Some(pair_spec),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -58,21 +79,26 @@ impl PaperFeeSchedule {
     }
 }
 
+/// ペーパー取引で起きたこと。`timestamp`は起きた時刻で、エンジンの[`Clock`]から取る（ミリ秒）。
 #[derive(Debug, Clone, PartialEq)]
 pub enum PaperEvent {
     OrderAccepted {
+        timestamp: i64,
         order_id: OrderId,
         order: DesiredLimitOrder,
     },
     OrderRejected {
+        timestamp: i64,
         order: DesiredLimitOrder,
         reason: PaperRejectReason,
     },
     OrderCancelled {
+        timestamp: i64,
         order_id: OrderId,
         order: OpenOrder,
     },
     OrderFilled {
+        timestamp: i64,
         order_id: OrderId,
         order: DesiredLimitOrder,
         price: Decimal,
@@ -82,6 +108,39 @@ pub enum PaperEvent {
     },
 }
 
+impl PaperEvent {
+    // 約定を[`Execution`]に変換する。約定以外は`None`。ペーパー取引の約定はすべてメイカー約定になる。
+    pub fn execution(&self) -> Option<Execution> {
+        let PaperEvent::OrderFilled {
+            timestamp,
+            order_id,
+            order,
+            price,
+            amount,
+            fee_amount_quote,
+            trade,
+        } = self
+        else {
+            return None;
+        };
+
+        Some(Execution {
+            trade_id: trade.transaction_id as u64,
+            order_id: *order_id,
+            pair: order.pair.clone(),
+            side: order.side,
+            position_side: order.position_side,
+            order_type: OrderType::Limit,
+            amount: *amount,
+            price: *price,
+            is_maker: true,
+            fee_amount_base: Decimal::ZERO,
+            fee_amount_quote: *fee_amount_quote,
+            executed_at: *timestamp as u64,
+        })
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum PaperRejectReason {
     PairMismatch {
@@ -97,7 +156,9 @@ pub enum PaperRejectReason {
         required: Decimal,
         free: Decimal,
     },
+    UnsupportedMarginOrder(PositionSide),
     UnsupportedPair(String),
+    PairSpecViolation(PairSpecViolation),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -116,7 +177,9 @@ pub enum PaperExecutionError {
         free: Decimal,
     },
     MissingBalance(String),
+    UnsupportedMarginOrder(PositionSide),
     UnsupportedPair(String),
+    PairSpecViolation(PairSpecViolation),
 }
 
 #[derive(Debug, Clone)]
@@ -127,6 +190,7 @@ pub struct PaperExecutionEngine {
     balances: BTreeMap<String, BalanceSnapshot>,
     open_orders: BTreeMap<OrderId, OpenOrder>,
     event_history: Vec<PaperEvent>,
+    clock: Clock,
 }
 
 impl PaperExecutionEngine {
@@ -154,9 +218,20 @@ impl PaperExecutionEngine {
             balances,
             open_orders: BTreeMap::new(),
             event_history: Vec::new(),
+            clock: Clock::System,
         })
     }
 
+    // [`PaperEvent`]の時刻に使う時計。リプレイでは戦略と同じ[`Clock::Simulated`]を渡す。
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
     pub fn place_order(
         &mut self,
         order: DesiredLimitOrder,
@@ -167,6 +242,7 @@ impl PaperExecutionEngine {
                 actual: order.pair.clone(),
             };
             self.record_event(PaperEvent::OrderRejected {
+                timestamp: self.clock.now_millis(),
                 order,
                 reason: reason.clone(),
             });
@@ -179,6 +255,31 @@ impl PaperExecutionEngine {
                 price: order.price,
             };
             self.record_event(PaperEvent::OrderRejected {
+                timestamp: self.clock.now_millis(),
+                order,
+                reason: reason.clone(),
+            });
+            return Err(PaperExecutionError::from(reason));
+        }
+
+        // 実際の取引所と同じく、呼値や最小注文数量に違反する注文は丸めずに拒否する。
+        if let Some(pair_spec) = &self.config.pair_spec {
+            if let Err(violation) = order.validate_pair_spec(pair_spec) {
+                let reason = PaperRejectReason::PairSpecViolation(violation);
+                self.record_event(PaperEvent::OrderRejected {
+                    timestamp: self.clock.now_millis(),
+                    order,
+                    reason: reason.clone(),
+                });
+                return Err(PaperExecutionError::from(reason));
+            }
+        }
+
+        // 信用取引の証拠金や建玉はまだモデル化していないため、margin注文は受け付けない。
+        if let Some(position_side) = order.position_side {
+            let reason = PaperRejectReason::UnsupportedMarginOrder(position_side);
+            self.record_event(PaperEvent::OrderRejected {
+                timestamp: self.clock.now_millis(),
                 order,
                 reason: reason.clone(),
             });
@@ -188,6 +289,7 @@ impl PaperExecutionEngine {
         let lock_result = self.lock_funds_for_order(&order);
         if let Err(err) = lock_result {
             self.record_event(PaperEvent::OrderRejected {
+                timestamp: self.clock.now_millis(),
                 order,
                 reason: err.clone(),
             });
@@ -210,9 +312,11 @@ impl PaperExecutionEngine {
                 remaining_amount: order.amount,
                 price: Some(order.price),
                 post_only: order.post_only,
+                position_side: None,
             },
         );
         self.record_event(PaperEvent::OrderAccepted {
+            timestamp: self.clock.now_millis(),
             order_id,
             order: order.clone(),
         });
@@ -235,6 +339,7 @@ impl PaperExecutionEngine {
 
             self.unlock_funds_for_open_order(&open_order);
             self.record_event(PaperEvent::OrderCancelled {
+                timestamp: self.clock.now_millis(),
                 order_id,
                 order: open_order,
             });
@@ -396,6 +501,7 @@ impl PaperExecutionEngine {
         }
 
         PaperEvent::OrderFilled {
+            timestamp: self.clock.now_millis(),
             order_id,
             order,
             price,
@@ -545,7 +651,11 @@ impl From<PaperRejectReason> for PaperExecutionError {
                 required,
                 free,
             },
+            PaperRejectReason::UnsupportedMarginOrder(position_side) => {
+                Self::UnsupportedMarginOrder(position_side)
+            }
             PaperRejectReason::UnsupportedPair(pair) => Self::UnsupportedPair(pair),
+            PaperRejectReason::PairSpecViolation(violation) => Self::PairSpecViolation(violation),
         }
     }
 }
@@ -679,7 +789,8 @@ mod tests {
             engine.drain_events().as_slice(),
             [PaperEvent::OrderRejected {
                 order: rejected_order,
-                reason: PaperRejectReason::InsufficientFunds { .. }
+                reason: PaperRejectReason::InsufficientFunds { .. },
+                ..
             }] if rejected_order == &order
         ));
     }
@@ -736,6 +847,64 @@ mod tests {
         }
     }
 
+    #[test]
+    fn margin_order_is_rejected_without_locking_funds() {
+        let mut engine = engine_with_balances(Decimal::new(1, 0), Decimal::new(1_000_000, 0));
+
+        let result = engine.place_order(DesiredLimitOrder::open_position(
+            "btc_jpy".to_owned(),
+            PositionSide::Short,
+            Decimal::new(1, 1),
+            Decimal::new(5_000_000, 0),
+        ));
+
+        assert_eq!(
+            result,
+            Err(PaperExecutionError::UnsupportedMarginOrder(
+                PositionSide::Short
+            ))
+        );
+        assert!(engine.open_orders().is_empty());
+        assert_eq!(
+            balance_of(&engine, "btc"),
+            balance("btc", Decimal::new(1, 0))
+        );
+    }
+
+    #[test]
+    fn pair_spec_violation_rejects_order_without_locking_funds() {
+        let pair_spec = crate::pair_spec::btc_jpy_spec();
+        let mut engine = PaperExecutionEngine::new(
+            PaperExecutionConfig::from_pair_spec(pair_spec),
+            vec![
+                balance("btc", Decimal::ZERO),
+                balance("jpy", Decimal::new(1_000_000, 0)),
+            ],
+        )
+        .unwrap();
+
+        let result = engine.place_order(order(
+            OrderSide::Buy,
+            Decimal::new(1, 1),
+            Decimal::new(50_000_005, 1),
+        ));
+
+        assert!(matches!(
+            result,
+            Err(PaperExecutionError::PairSpecViolation(
+                PairSpecViolation::InvalidPrice { .. }
+            ))
+        ));
+        assert_eq!(
+            balance_of(&engine, "jpy").free_amount,
+            Decimal::new(1_000_000, 0)
+        );
+        assert_eq!(
+            engine.config().fee_schedule,
+            PaperFeeSchedule::new(Decimal::new(-2, 4), Decimal::new(12, 4))
+        );
+    }
+
     #[test]
     fn cancel_order_unlocks_balance_and_missing_id_succeeds() {
         let mut engine = engine_with_balances(Decimal::ZERO, Decimal::new(1_000_000, 0));
@@ -840,6 +1009,7 @@ mod tests {
                 pair: "btc_jpy".to_owned(),
                 fee_schedule: PaperFeeSchedule::new(Decimal::new(1, 3), Decimal::ZERO),
                 next_order_id: OrderId(1),
+                pair_spec: None,
             },
             vec![
                 balance("btc", Decimal::ZERO),
@@ -1004,6 +1174,40 @@ mod tests {
         assert_eq!(fill_ids, vec![OrderId(2), OrderId(3), OrderId(1)]);
     }
 
+    #[test]
+    fn paper_events_use_engine_clock() {
+        let clock = Clock::simulated();
+        let mut engine =
+            engine_with_balances(Decimal::new(1, 1), Decimal::ZERO).with_clock(clock.clone());
+
+        clock.advance_to(1_000);
+        engine
+            .place_order(order(
+                OrderSide::Sell,
+                Decimal::new(1, 1),
+                Decimal::new(5_000_000, 0),
+            ))
+            .unwrap();
+        clock.advance_to(trade(OrderSide::Buy, Decimal::ONE, Decimal::ONE, 1).executed_at);
+        engine.apply_market_event(&transactions(vec![trade(
+            OrderSide::Buy,
+            Decimal::new(1, 1),
+            Decimal::new(5_000_000, 0),
+            1,
+        )]));
+
+        let timestamps = engine
+            .drain_events()
+            .into_iter()
+            .map(|event| match event {
+                PaperEvent::OrderAccepted { timestamp, .. }
+                | PaperEvent::OrderFilled { timestamp, .. } => timestamp,
+                _ => panic!("unexpected paper event"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(timestamps, vec![1_000, 1_710_000_000_000]);
+    }
+
     #[tokio::test]
     async fn paper_order_executor_places_and_cancels_through_order_executor_trait() {
         let executor = PaperOrderExecutor::new(engine_with_balances(
@@ -1104,6 +1308,7 @@ mod tests {
                     remaining_amount: Decimal::new(2, 2),
                     price: Some(Decimal::new(5_000_000, 0)),
                     post_only: Some(true),
+                    position_side: None,
                 },
                 OpenOrder {
                     order_id: OrderId(2),
@@ -1113,6 +1318,7 @@ mod tests {
                     remaining_amount: Decimal::new(1, 1),
                     price: Some(Decimal::new(5_000_000, 0)),
                     post_only: Some(true),
+                    position_side: None,
                 },
                 OpenOrder {
                     order_id: OrderId(3),
@@ -1122,6 +1328,7 @@ mod tests {
                     remaining_amount: Decimal::new(1, 1),
                     price: Some(Decimal::new(5_000_000, 0)),
                     post_only: Some(true),
+                    position_side: None,
                 },
             ]
         );
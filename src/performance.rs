@@ -0,0 +1,812 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, Write};
+use std::time::Duration;
+
+use rust_decimal::prelude::*;
+use serde::Serialize;
+
+use crate::bitbank_structs::BitbankTradeHistoryDatum;
+use crate::order_domain::{Execution, OrderSide, ParseOrderError};
+use crate::paper_execution::PaperEvent;
+
+const DAY_MILLIS: i64 = 86_400_000;
+const YEAR_MILLIS: f64 = 365.0 * DAY_MILLIS as f64;
+
+/// 実現損益を計算するときの取得価格の決め方。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CostBasis {
+    /// 先に建てた分から順に決済する。
+    Fifo,
+    /// 建玉をすべて平均価格で持っているとみなす。
+    AverageCost,
+}
+
+/// [`PerformanceTracker`]の計算方法。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PerformanceConfig {
+    pub cost_basis: CostBasis,
+    /// 損益をリターンに換算するときの元手（quote）。シャープレシオ・ソルティノレシオとドローダウン率に使う。
+    pub initial_capital: Decimal,
+    /// シャープレシオ・ソルティノレシオのリターンを計算する間隔。標準は1日。
+    pub return_interval: Duration,
+    /// 日ごとの集計で日付を区切るときのUTCからのずれ（分）。標準は日本時間の540。
+    pub utc_offset_minutes: i32,
+}
+
+impl PerformanceConfig {
+    pub fn new(cost_basis: CostBasis, initial_capital: Decimal) -> Self {
+        Self {
+            cost_basis,
+            initial_capital,
+            return_interval: Duration::from_secs(24 * 60 * 60),
+            utc_offset_minutes: 9 * 60,
+        }
+    }
+}
+
+/// ある時点のペアごとの建玉。買い持ちが正、売り持ちが負。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InventoryPoint {
+    /// UNIXエポックからのミリ秒。
+    pub timestamp: i64,
+    pub pair: String,
+    pub position: Decimal,
+}
+
+/// ある時点の損益の合計（実現損益＋評価損益－手数料）。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EquityPoint {
+    /// UNIXエポックからのミリ秒。
+    pub timestamp: i64,
+    pub equity: Decimal,
+}
+
+/// ペアごとの最後の建玉。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PositionSummary {
+    pub position: Decimal,
+    /// 建玉の平均取得価格。建玉がない場合は`None`。
+    pub average_price: Option<Decimal>,
+    /// 評価に使った最後の価格。
+    pub mark_price: Option<Decimal>,
+    pub unrealized_pnl: Decimal,
+}
+
+/// 期間全体の集計。
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PerformanceSummary {
+    pub cost_basis: CostBasis,
+    pub realized_pnl: Decimal,
+    pub unrealized_pnl: Decimal,
+    /// 支払った手数料（quote）。
+    pub fees_paid: Decimal,
+    /// メイカーのリベートなど、受け取った手数料（quote）。
+    pub fees_earned: Decimal,
+    /// `realized_pnl + unrealized_pnl - fees_paid + fees_earned`。
+    pub net_pnl: Decimal,
+    /// 約定代金の合計（quote）。
+    pub turnover: Decimal,
+    pub fills: u64,
+    /// 約定代金に占めるメイカー約定の割合。約定がない場合は`None`。
+    pub maker_ratio: Option<f64>,
+    /// 損益の合計の、それまでの最大値からの最大の下落幅（quote）。
+    pub max_drawdown: Decimal,
+    /// `max_drawdown`を、そのときの元手と損益の合計の最大値で割ったもの。元手が0以下の場合は`None`。
+    pub max_drawdown_ratio: Option<f64>,
+    /// 年率換算したシャープレシオ。リターンが2つ未満、または変動がない場合は`None`。
+    pub sharpe_ratio: Option<f64>,
+    /// 年率換算したソルティノレシオ。リターンが2つ未満、または負のリターンがない場合は`None`。
+    pub sortino_ratio: Option<f64>,
+    pub positions: BTreeMap<String, PositionSummary>,
+}
+
+/// 1日分の集計。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DailyPerformance {
+    /// `YYYY-MM-DD`。[`PerformanceConfig::utc_offset_minutes`]の時間帯での日付。
+    pub date: String,
+    pub realized_pnl: Decimal,
+    pub fees_paid: Decimal,
+    pub fees_earned: Decimal,
+    pub turnover: Decimal,
+    pub fills: u64,
+    /// 前日の終わりからの損益の合計の変化。評価損益の変化も含む。
+    pub pnl: Decimal,
+    /// その日の終わりの損益の合計。
+    pub ending_equity: Decimal,
+}
+
+/// 約定から計算した成績。[`Self::to_json`]や[`Self::write_daily_csv`]で書き出せる。
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PerformanceReport {
+    pub summary: PerformanceSummary,
+    pub daily: Vec<DailyPerformance>,
+    pub inventory: Vec<InventoryPoint>,
+    pub equity: Vec<EquityPoint>,
+}
+
+impl PerformanceReport {
+    // 約定を時刻の順に並べて計算する。評価損益は各ペアの最後の約定価格で計算する。
+    pub fn from_fills<'a>(
+        config: PerformanceConfig,
+        fills: impl IntoIterator<Item = &'a Execution>,
+    ) -> Self {
+        let mut fills: Vec<&Execution> = fills.into_iter().collect();
+        fills.sort_by_key(|fill| fill.executed_at);
+
+        let mut tracker = PerformanceTracker::new(config);
+        for fill in fills {
+            tracker.record_fill(fill);
+        }
+        tracker.report()
+    }
+
+    // ペーパー取引の[`PaperEvent::OrderFilled`]から計算する。約定以外のイベントは無視する。
+    pub fn from_paper_events<'a>(
+        config: PerformanceConfig,
+        events: impl IntoIterator<Item = &'a PaperEvent>,
+    ) -> Self {
+        let fills: Vec<Execution> = events
+            .into_iter()
+            .filter_map(PaperEvent::execution)
+            .collect();
+        Self::from_fills(config, &fills)
+    }
+
+    // `/user/spot/trade_history`の約定履歴から計算する。
+    pub fn from_trade_history(
+        config: PerformanceConfig,
+        trades: &[BitbankTradeHistoryDatum],
+    ) -> Result<Self, ParseOrderError> {
+        let fills = trades
+            .iter()
+            .map(Execution::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::from_fills(config, &fills))
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    // 日ごとの集計をヘッダ付きのCSVで書き出す。
+    pub fn write_daily_csv(&self, mut writer: impl Write) -> io::Result<()> {
+        writeln!(
+            writer,
+            "date,realized_pnl,fees_paid,fees_earned,turnover,fills,pnl,ending_equity"
+        )?;
+        for day in &self.daily {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{}",
+                day.date,
+                day.realized_pnl,
+                day.fees_paid,
+                day.fees_earned,
+                day.turnover,
+                day.fills,
+                day.pnl,
+                day.ending_equity
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// 戦略の設定ごとの成績を、1行に1つずつ並べたヘッダ付きのCSVで書き出す。`name`で行を区別する。
+pub fn write_summary_csv(
+    mut writer: impl Write,
+    reports: &[(&str, &PerformanceReport)],
+) -> io::Result<()> {
+    writeln!(
+        writer,
+        "name,cost_basis,realized_pnl,unrealized_pnl,fees_paid,fees_earned,net_pnl,turnover,fills,\
+         maker_ratio,max_drawdown,max_drawdown_ratio,sharpe_ratio,sortino_ratio"
+    )?;
+    for (name, report) in reports {
+        let summary = &report.summary;
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            csv_field(name),
+            match summary.cost_basis {
+                CostBasis::Fifo => "fifo",
+                CostBasis::AverageCost => "average_cost",
+            },
+            summary.realized_pnl,
+            summary.unrealized_pnl,
+            summary.fees_paid,
+            summary.fees_earned,
+            summary.net_pnl,
+            summary.turnover,
+            summary.fills,
+            optional_field(summary.maker_ratio),
+            summary.max_drawdown,
+            optional_field(summary.max_drawdown_ratio),
+            optional_field(summary.sharpe_ratio),
+            optional_field(summary.sortino_ratio),
+        )?;
+    }
+    Ok(())
+}
+
+/// 建玉の一部。`amount`は買い持ちが正、売り持ちが負。
+#[derive(Debug, Clone)]
+struct Lot {
+    amount: Decimal,
+    price: Decimal,
+}
+
+#[derive(Debug, Clone, Default)]
+struct PairBook {
+    lots: VecDeque<Lot>,
+    mark_price: Option<Decimal>,
+}
+
+impl PairBook {
+    fn position(&self) -> Decimal {
+        self.lots.iter().map(|lot| lot.amount).sum()
+    }
+
+    fn unrealized_pnl(&self) -> Decimal {
+        let Some(mark_price) = self.mark_price else {
+            return Decimal::ZERO;
+        };
+        self.lots
+            .iter()
+            .map(|lot| (mark_price - lot.price) * lot.amount)
+            .sum()
+    }
+
+    fn average_price(&self) -> Option<Decimal> {
+        let position = self.position();
+        if position.is_zero() {
+            return None;
+        }
+        let cost: Decimal = self.lots.iter().map(|lot| lot.price * lot.amount).sum();
+        Some(cost / position)
+    }
+
+    // `amount`（買いが正、売りが負）を`price`で約定させ、反対側の建玉を決済した分の実現損益を返す。
+    fn apply(&mut self, mut amount: Decimal, price: Decimal, cost_basis: CostBasis) -> Decimal {
+        let mut realized = Decimal::ZERO;
+        while !amount.is_zero() {
+            let Some(lot) = self.lots.front_mut() else {
+                break;
+            };
+            if lot.amount.is_sign_positive() == amount.is_sign_positive() {
+                break;
+            }
+
+            let closed = lot.amount.abs().min(amount.abs());
+            let closed_signed = if lot.amount.is_sign_positive() {
+                closed
+            } else {
+                -closed
+            };
+            realized += (price - lot.price) * closed_signed;
+            lot.amount -= closed_signed;
+            amount += closed_signed;
+            if lot.amount.is_zero() {
+                self.lots.pop_front();
+            }
+        }
+
+        if !amount.is_zero() {
+            match (cost_basis, self.lots.front_mut()) {
+                (CostBasis::AverageCost, Some(lot)) => {
+                    let total = lot.amount + amount;
+                    lot.price = (lot.price * lot.amount + price * amount) / total;
+                    lot.amount = total;
+                }
+                _ => self.lots.push_back(Lot { amount, price }),
+            }
+        }
+
+        self.mark_price = Some(price);
+        realized
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct DailyTotals {
+    realized_pnl: Decimal,
+    fees_paid: Decimal,
+    fees_earned: Decimal,
+    turnover: Decimal,
+    fills: u64,
+    ending_equity: Decimal,
+}
+
+/// 約定と時価を時刻の順に受け取り、成績を計算する。
+/// ライブでは[`Execution`]を受け取るたびに[`Self::record_fill`]を呼び、任意の時点で[`Self::report`]を取れる。
+#[derive(Debug, Clone)]
+pub struct PerformanceTracker {
+    config: PerformanceConfig,
+    books: BTreeMap<String, PairBook>,
+    realized_pnl: Decimal,
+    fees_paid: Decimal,
+    fees_earned: Decimal,
+    turnover: Decimal,
+    maker_turnover: Decimal,
+    fills: u64,
+    inventory: Vec<InventoryPoint>,
+    equity: Vec<EquityPoint>,
+    daily: BTreeMap<i64, DailyTotals>,
+}
+
+impl PerformanceTracker {
+    pub fn new(config: PerformanceConfig) -> Self {
+        Self {
+            config,
+            books: BTreeMap::new(),
+            realized_pnl: Decimal::ZERO,
+            fees_paid: Decimal::ZERO,
+            fees_earned: Decimal::ZERO,
+            turnover: Decimal::ZERO,
+            maker_turnover: Decimal::ZERO,
+            fills: 0,
+            inventory: Vec::new(),
+            equity: Vec::new(),
+            daily: BTreeMap::new(),
+        }
+    }
+
+    // 約定を反映する。約定価格はそのペアの時価としても使う。
+    pub fn record_fill(&mut self, fill: &Execution) {
+        let timestamp = fill.executed_at as i64;
+        let signed_amount = match fill.side {
+            OrderSide::Buy => fill.amount,
+            OrderSide::Sell => -fill.amount,
+        };
+        let book = self.books.entry(fill.pair.clone()).or_default();
+        let realized = book.apply(signed_amount, fill.price, self.config.cost_basis);
+        let position = book.position();
+
+        let notional = fill.amount * fill.price;
+        // 基軸資産で払った手数料は約定価格でquoteに換算する
+        let fee = fill.fee_amount_quote + fill.fee_amount_base * fill.price;
+        let (fee_paid, fee_earned) = if fee.is_sign_positive() {
+            (fee, Decimal::ZERO)
+        } else {
+            (Decimal::ZERO, -fee)
+        };
+
+        self.realized_pnl += realized;
+        self.fees_paid += fee_paid;
+        self.fees_earned += fee_earned;
+        self.turnover += notional;
+        if fill.is_maker {
+            self.maker_turnover += notional;
+        }
+        self.fills += 1;
+
+        let day = self.daily.entry(self.day_of(timestamp)).or_default();
+        day.realized_pnl += realized;
+        day.fees_paid += fee_paid;
+        day.fees_earned += fee_earned;
+        day.turnover += notional;
+        day.fills += 1;
+
+        self.inventory.push(InventoryPoint {
+            timestamp,
+            pair: fill.pair.clone(),
+            position,
+        });
+        self.record_equity(timestamp);
+    }
+
+    // `pair`の時価を更新する。建玉がある場合は評価損益が変わる。
+    pub fn record_mark(&mut self, timestamp: i64, pair: &str, price: Decimal) {
+        self.books.entry(pair.to_owned()).or_default().mark_price = Some(price);
+        self.record_equity(timestamp);
+    }
+
+    pub fn report(&self) -> PerformanceReport {
+        let unrealized_pnl: Decimal = self.books.values().map(PairBook::unrealized_pnl).sum();
+        let (max_drawdown, max_drawdown_ratio) = self.max_drawdown();
+        let returns = self.interval_returns();
+
+        PerformanceReport {
+            summary: PerformanceSummary {
+                cost_basis: self.config.cost_basis,
+                realized_pnl: self.realized_pnl,
+                unrealized_pnl,
+                fees_paid: self.fees_paid,
+                fees_earned: self.fees_earned,
+                net_pnl: self.realized_pnl + unrealized_pnl - self.fees_paid + self.fees_earned,
+                turnover: self.turnover,
+                fills: self.fills,
+                maker_ratio: ratio(self.maker_turnover, self.turnover),
+                max_drawdown,
+                max_drawdown_ratio,
+                sharpe_ratio: self.annualize(sharpe(&returns)),
+                sortino_ratio: self.annualize(sortino(&returns)),
+                positions: self
+                    .books
+                    .iter()
+                    .map(|(pair, book)| {
+                        (
+                            pair.clone(),
+                            PositionSummary {
+                                position: book.position(),
+                                average_price: book.average_price(),
+                                mark_price: book.mark_price,
+                                unrealized_pnl: book.unrealized_pnl(),
+                            },
+                        )
+                    })
+                    .collect(),
+            },
+            daily: self.daily_breakdown(),
+            inventory: self.inventory.clone(),
+            equity: self.equity.clone(),
+        }
+    }
+
+    fn record_equity(&mut self, timestamp: i64) {
+        let unrealized_pnl: Decimal = self.books.values().map(PairBook::unrealized_pnl).sum();
+        let equity = self.realized_pnl + unrealized_pnl - self.fees_paid + self.fees_earned;
+        self.equity.push(EquityPoint { timestamp, equity });
+        self.daily
+            .entry(self.day_of(timestamp))
+            .or_default()
+            .ending_equity = equity;
+    }
+
+    fn day_of(&self, timestamp: i64) -> i64 {
+        (timestamp + i64::from(self.config.utc_offset_minutes) * 60_000).div_euclid(DAY_MILLIS)
+    }
+
+    fn daily_breakdown(&self) -> Vec<DailyPerformance> {
+        let mut previous_equity = Decimal::ZERO;
+        self.daily
+            .iter()
+            .map(|(day, totals)| {
+                let pnl = totals.ending_equity - previous_equity;
+                previous_equity = totals.ending_equity;
+                let (year, month, date) = civil_date(*day);
+                DailyPerformance {
+                    date: format!("{:04}-{:02}-{:02}", year, month, date),
+                    realized_pnl: totals.realized_pnl,
+                    fees_paid: totals.fees_paid,
+                    fees_earned: totals.fees_earned,
+                    turnover: totals.turnover,
+                    fills: totals.fills,
+                    pnl,
+                    ending_equity: totals.ending_equity,
+                }
+            })
+            .collect()
+    }
+
+    // 損益の合計が0から始まるとみなしたときの、最大ドローダウンとその比率。
+    fn max_drawdown(&self) -> (Decimal, Option<f64>) {
+        let capital = self.config.initial_capital;
+        let mut peak = Decimal::ZERO;
+        let mut max_drawdown = Decimal::ZERO;
+        let mut max_ratio: Option<f64> = None;
+        for point in &self.equity {
+            peak = peak.max(point.equity);
+            let drawdown = peak - point.equity;
+            max_drawdown = max_drawdown.max(drawdown);
+            if let Some(ratio) = ratio(drawdown, capital + peak).filter(|_| capital > Decimal::ZERO)
+            {
+                max_ratio = Some(max_ratio.map_or(ratio, |max| max.max(ratio)));
+            }
+        }
+        (max_drawdown, max_ratio)
+    }
+
+    // 最初の点から`return_interval`ごとに区切った、区間の終わりの損益の合計の変化率。
+    // 約定のない区間は変化なしとして数える。それより前の点より古い時刻の点は、前の点と同じ時刻とみなす。
+    fn interval_returns(&self) -> IntervalReturns {
+        let capital = self.config.initial_capital;
+        let interval = (self.config.return_interval.as_millis() as i64).max(1);
+        let Some(first) = self.equity.first() else {
+            return IntervalReturns::default();
+        };
+        if capital <= Decimal::ZERO {
+            return IntervalReturns::default();
+        }
+
+        let mut closes = BTreeMap::new();
+        let mut latest = first.timestamp;
+        for point in &self.equity {
+            latest = latest.max(point.timestamp);
+            closes.insert((latest - first.timestamp) / interval, point.equity);
+        }
+
+        let mut returns = IntervalReturns::default();
+        let mut previous = Decimal::ZERO;
+        let mut next_index = 0;
+        for (index, close) in closes {
+            let base = capital + previous;
+            if base > Decimal::ZERO {
+                returns.flat += (index - next_index) as usize;
+                returns
+                    .changed
+                    .push(((close - previous) / base).to_f64().unwrap_or_default());
+            }
+            previous = close;
+            next_index = index + 1;
+        }
+        returns
+    }
+
+    fn annualize(&self, ratio: Option<f64>) -> Option<f64> {
+        let interval = self.config.return_interval.as_millis().max(1) as f64;
+        ratio.map(|ratio| ratio * (YEAR_MILLIS / interval).sqrt())
+    }
+}
+
+fn ratio(numerator: Decimal, denominator: Decimal) -> Option<f64> {
+    if denominator.is_zero() {
+        return None;
+    }
+    (numerator / denominator).to_f64()
+}
+
+/// 区間ごとの変化率。約定のない区間は変化率が0なので、区間が長く空いても1つずつは持たずに数だけ数える。
+#[derive(Debug, Default)]
+struct IntervalReturns {
+    /// 点のあった区間の変化率。
+    changed: Vec<f64>,
+    /// 点のなかった区間の数。
+    flat: usize,
+}
+
+impl IntervalReturns {
+    fn len(&self) -> usize {
+        self.changed.len() + self.flat
+    }
+
+    fn mean(&self) -> f64 {
+        self.changed.iter().sum::<f64>() / self.len() as f64
+    }
+}
+
+fn sharpe(returns: &IntervalReturns) -> Option<f64> {
+    if returns.len() < 2 {
+        return None;
+    }
+    let mean = returns.mean();
+    let squared_deviation = returns
+        .changed
+        .iter()
+        .map(|r| (r - mean).powi(2))
+        .sum::<f64>()
+        + returns.flat as f64 * mean.powi(2);
+    let variance = squared_deviation / (returns.len() - 1) as f64;
+    (variance > 0.0).then(|| mean / variance.sqrt())
+}
+
+fn sortino(returns: &IntervalReturns) -> Option<f64> {
+    if returns.len() < 2 {
+        return None;
+    }
+    let downside = returns
+        .changed
+        .iter()
+        .map(|r| r.min(0.0).powi(2))
+        .sum::<f64>()
+        / returns.len() as f64;
+    (downside > 0.0).then(|| returns.mean() / downside.sqrt())
+}
+
+// 1970-01-01からの日数を(年, 月, 日)に変換する。
+fn civil_date(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month as u32, day as u32)
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+fn optional_field(value: Option<f64>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order_domain::{OrderId, OrderType};
+
+    // 2024-03-10 00:00 JST
+    const MARCH_10_JST: i64 = 1_709_996_400_000;
+
+    fn fill(side: OrderSide, amount: i64, price: i64, fee_quote: Decimal, at: i64) -> Execution {
+        Execution {
+            trade_id: at as u64,
+            order_id: OrderId(1),
+            pair: "btc_jpy".to_owned(),
+            side,
+            position_side: None,
+            order_type: OrderType::Limit,
+            amount: Decimal::new(amount, 0),
+            price: Decimal::new(price, 0),
+            is_maker: fee_quote.is_sign_negative(),
+            fee_amount_base: Decimal::ZERO,
+            fee_amount_quote: fee_quote,
+            executed_at: at as u64,
+        }
+    }
+
+    fn fills() -> Vec<Execution> {
+        vec![
+            fill(OrderSide::Buy, 1, 100, Decimal::new(-1, 1), MARCH_10_JST),
+            fill(
+                OrderSide::Buy,
+                1,
+                200,
+                Decimal::new(-1, 1),
+                MARCH_10_JST + 1,
+            ),
+            fill(
+                OrderSide::Sell,
+                1,
+                300,
+                Decimal::new(3, 1),
+                MARCH_10_JST + 2,
+            ),
+        ]
+    }
+
+    #[test]
+    fn fifo_and_average_cost_split_realized_and_unrealized_pnl() {
+        let config = |cost_basis| PerformanceConfig::new(cost_basis, Decimal::new(10_000, 0));
+        let mut fifo = PerformanceTracker::new(config(CostBasis::Fifo));
+        let mut average = PerformanceTracker::new(config(CostBasis::AverageCost));
+        for tracker in [&mut fifo, &mut average] {
+            for fill in fills() {
+                tracker.record_fill(&fill);
+            }
+            tracker.record_mark(MARCH_10_JST + 3, "btc_jpy", Decimal::new(250, 0));
+        }
+
+        let fifo = fifo.report().summary;
+        assert_eq!(fifo.realized_pnl, Decimal::new(200, 0));
+        assert_eq!(fifo.unrealized_pnl, Decimal::new(50, 0));
+        assert_eq!(
+            fifo.positions["btc_jpy"].average_price,
+            Some(Decimal::new(200, 0))
+        );
+
+        let average = average.report().summary;
+        assert_eq!(average.realized_pnl, Decimal::new(150, 0));
+        assert_eq!(average.unrealized_pnl, Decimal::new(100, 0));
+        assert_eq!(
+            average.positions["btc_jpy"].average_price,
+            Some(Decimal::new(150, 0))
+        );
+
+        for summary in [&fifo, &average] {
+            assert_eq!(summary.fees_paid, Decimal::new(3, 1));
+            assert_eq!(summary.fees_earned, Decimal::new(2, 1));
+            assert_eq!(summary.net_pnl, Decimal::new(2499, 1));
+            assert_eq!(summary.turnover, Decimal::new(600, 0));
+            assert_eq!(summary.maker_ratio, Some(0.5));
+            assert_eq!(summary.positions["btc_jpy"].position, Decimal::ONE);
+        }
+    }
+
+    #[test]
+    fn short_positions_drawdown_and_daily_breakdown() {
+        let mut tracker = PerformanceTracker::new(PerformanceConfig::new(
+            CostBasis::Fifo,
+            Decimal::new(1_000, 0),
+        ));
+        // 1日目: 売り持ちにして値上がりで評価損、2日目: 買い戻して確定、3日目: 約定なし
+        tracker.record_fill(&fill(OrderSide::Sell, 2, 100, Decimal::ZERO, MARCH_10_JST));
+        tracker.record_mark(MARCH_10_JST + 1_000, "btc_jpy", Decimal::new(150, 0));
+        tracker.record_fill(&fill(
+            OrderSide::Buy,
+            2,
+            120,
+            Decimal::ZERO,
+            MARCH_10_JST + DAY_MILLIS,
+        ));
+        tracker.record_mark(MARCH_10_JST + 2 * DAY_MILLIS, "btc_jpy", Decimal::ONE);
+
+        let report = tracker.report();
+        assert_eq!(report.summary.realized_pnl, Decimal::new(-40, 0));
+        assert_eq!(report.summary.unrealized_pnl, Decimal::ZERO);
+        assert_eq!(report.summary.max_drawdown, Decimal::new(100, 0));
+        assert_eq!(report.summary.max_drawdown_ratio, Some(0.1));
+        assert_eq!(
+            report
+                .inventory
+                .iter()
+                .map(|point| point.position)
+                .collect::<Vec<_>>(),
+            vec![Decimal::new(-2, 0), Decimal::ZERO]
+        );
+        assert_eq!(
+            report
+                .daily
+                .iter()
+                .map(|day| (day.date.as_str(), day.pnl, day.fills))
+                .collect::<Vec<_>>(),
+            vec![
+                ("2024-03-10", Decimal::new(-100, 0), 1),
+                ("2024-03-11", Decimal::new(60, 0), 1),
+                ("2024-03-12", Decimal::ZERO, 0),
+            ]
+        );
+
+        // -10%, +6.67%, 0%
+        let sharpe = report.summary.sharpe_ratio.unwrap();
+        let sortino = report.summary.sortino_ratio.unwrap();
+        assert!(sharpe < 0.0 && sortino < sharpe, "{} {}", sharpe, sortino);
+    }
+
+    #[test]
+    fn interval_returns_clamp_out_of_order_points_and_count_long_gaps() {
+        let mut config = PerformanceConfig::new(CostBasis::Fifo, Decimal::new(1_000, 0));
+        config.return_interval = Duration::from_millis(1);
+        let mut tracker = PerformanceTracker::new(config);
+        // 最初の約定より前の時刻の時価は、最初の区間に入る
+        tracker.record_fill(&fill(OrderSide::Sell, 1, 100, Decimal::ZERO, MARCH_10_JST));
+        tracker.record_mark(MARCH_10_JST - 1_000, "btc_jpy", Decimal::new(90, 0));
+        tracker.record_mark(
+            MARCH_10_JST + 30 * DAY_MILLIS,
+            "btc_jpy",
+            Decimal::new(80, 0),
+        );
+
+        let returns = tracker.interval_returns();
+        assert_eq!(returns.changed.len(), 2);
+        assert_eq!(returns.changed[0], 0.01);
+        assert!((returns.changed[1] - 10.0 / 1_010.0).abs() < 1e-12);
+        assert_eq!(returns.flat, (30 * DAY_MILLIS - 1) as usize);
+
+        let sharpe = tracker.report().summary.sharpe_ratio.unwrap();
+        assert!(sharpe > 0.0 && sharpe.is_finite(), "{}", sharpe);
+    }
+
+    #[test]
+    fn exports_json_and_csv() {
+        let config = PerformanceConfig::new(CostBasis::Fifo, Decimal::new(10_000, 0));
+        let report = PerformanceReport::from_fills(config, fills().iter().rev());
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(json["summary"]["cost_basis"], "fifo");
+        assert_eq!(json["summary"]["realized_pnl"], "200");
+        assert_eq!(json["daily"][0]["date"], "2024-03-10");
+
+        let mut daily = Vec::new();
+        report.write_daily_csv(&mut daily).unwrap();
+        assert_eq!(
+            String::from_utf8(daily).unwrap(),
+            "date,realized_pnl,fees_paid,fees_earned,turnover,fills,pnl,ending_equity\n\
+             2024-03-10,200,0.3,0.2,600,3,299.9,299.9\n"
+        );
+
+        let mut summary = Vec::new();
+        write_summary_csv(&mut summary, &[("spread=1,size=2", &report)]).unwrap();
+        let summary = String::from_utf8(summary).unwrap();
+        let rows: Vec<&str> = summary.lines().collect();
+        assert_eq!(rows.len(), 2);
+        assert!(rows[0].starts_with("name,cost_basis,realized_pnl,"));
+        assert!(rows[1].starts_with("\"spread=1,size=2\",fifo,200,100,0.3,0.2,299.9,600,3,"));
+    }
+}
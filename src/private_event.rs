@@ -0,0 +1,330 @@
+use serde::de::DeserializeOwned;
+
+use crate::bitbank_structs::private_stream_struct::{
+    BitbankPrivateAssetUpdate, BitbankPrivateOrder, BitbankPrivateOrderInvalidation,
+    BitbankPrivateStreamMessage,
+};
+use crate::bitbank_structs::{
+    BitbankDepositDatum, BitbankMarginNotice, BitbankMarginPayables, BitbankMarginPositionDatum,
+    BitbankTradeHistoryDatum, BitbankWithdrawalDatum,
+};
+use crate::order_domain::{
+    BalanceSnapshot, Execution, MarginPosition, OrderId, OrderUpdate, ParseOrderError,
+};
+
+/// Private Streamから戦略に公開されるイベント。
+#[derive(Debug, Clone)]
+pub enum PrivateEvent {
+    AssetUpdated(Vec<BalanceSnapshot>),
+    /// `spot_order_new`と`spot_order`の両方がこのイベントになる。
+    OrderUpdated(Vec<OrderUpdate>),
+    /// 取引所側の都合で注文が無効になった。
+    OrdersInvalidated(Vec<OrderId>),
+    Executed(Vec<Execution>),
+    MarginPositionUpdated(Vec<MarginPosition>),
+    MarginPayablesUpdated(Vec<BitbankMarginPayables>),
+    MarginNoticeUpdated(Vec<BitbankMarginNotice>),
+    DepositUpdated(Vec<BitbankDepositDatum>),
+    /// `withdrawal_requested`と`withdrawal`の両方がこのイベントになる。
+    WithdrawalUpdated(Vec<BitbankWithdrawalDatum>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrivateEventConversionError {
+    InvalidParams { method: String, error: String },
+    InvalidOrder(ParseOrderError),
+}
+
+impl From<ParseOrderError> for PrivateEventConversionError {
+    fn from(value: ParseOrderError) -> Self {
+        Self::InvalidOrder(value)
+    }
+}
+
+/// [`PrivateEvent::from_stream_message`]の結果。
+/// 1つのメッセージに含まれる要素のうち、変換できたものは`event`に、できなかったものは`errors`に入る。
+#[derive(Debug, Clone)]
+pub struct PrivateEventConversion {
+    /// 未対応の`method`だった場合や、すべての要素が変換できなかった場合は`None`。
+    pub event: Option<PrivateEvent>,
+    pub errors: Vec<PrivateEventConversionError>,
+}
+
+impl PrivateEvent {
+    // 要素ごとに変換し、変換できなかった要素があっても残りの要素からイベントを作る。
+    pub fn from_stream_message(message: BitbankPrivateStreamMessage) -> PrivateEventConversion {
+        let mut errors = Vec::new();
+        let method = message.method.as_str();
+        let event = match method {
+            "asset_update" => {
+                let assets: Vec<BitbankPrivateAssetUpdate> = parse_params(&message, &mut errors);
+                PrivateEvent::AssetUpdated(convert_items(
+                    assets.iter(),
+                    BalanceSnapshot::try_from,
+                    &mut errors,
+                ))
+            }
+            "spot_order_new" | "spot_order" => {
+                let orders: Vec<BitbankPrivateOrder> = parse_params(&message, &mut errors);
+                PrivateEvent::OrderUpdated(convert_items(
+                    orders.iter(),
+                    OrderUpdate::try_from,
+                    &mut errors,
+                ))
+            }
+            "spot_order_invalidation" => {
+                let invalidations: Vec<BitbankPrivateOrderInvalidation> =
+                    parse_params(&message, &mut errors);
+                let order_ids = invalidations
+                    .iter()
+                    .flat_map(|invalidation| invalidation.order_id.iter());
+                PrivateEvent::OrdersInvalidated(convert_items(
+                    order_ids,
+                    |order_id| {
+                        order_id
+                            .as_u64()
+                            .map(OrderId)
+                            .ok_or(ParseOrderError::InvalidOrderId)
+                    },
+                    &mut errors,
+                ))
+            }
+            "spot_trade" => {
+                let trades: Vec<BitbankTradeHistoryDatum> = parse_params(&message, &mut errors);
+                PrivateEvent::Executed(convert_items(
+                    trades.iter(),
+                    Execution::try_from,
+                    &mut errors,
+                ))
+            }
+            "margin_position_update" => {
+                let positions: Vec<BitbankMarginPositionDatum> =
+                    parse_params(&message, &mut errors);
+                PrivateEvent::MarginPositionUpdated(convert_items(
+                    positions.iter(),
+                    MarginPosition::try_from,
+                    &mut errors,
+                ))
+            }
+            "margin_payable_update" => {
+                PrivateEvent::MarginPayablesUpdated(parse_params(&message, &mut errors))
+            }
+            "margin_notice_update" => {
+                PrivateEvent::MarginNoticeUpdated(parse_params(&message, &mut errors))
+            }
+            "deposit" => PrivateEvent::DepositUpdated(parse_params(&message, &mut errors)),
+            "withdrawal_requested" | "withdrawal" => {
+                PrivateEvent::WithdrawalUpdated(parse_params(&message, &mut errors))
+            }
+            _ => {
+                return PrivateEventConversion {
+                    event: None,
+                    errors,
+                }
+            }
+        };
+
+        let event = (errors.is_empty() || !event.is_empty()).then_some(event);
+        PrivateEventConversion { event, errors }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            PrivateEvent::AssetUpdated(items) => items.is_empty(),
+            PrivateEvent::OrderUpdated(items) => items.is_empty(),
+            PrivateEvent::OrdersInvalidated(items) => items.is_empty(),
+            PrivateEvent::Executed(items) => items.is_empty(),
+            PrivateEvent::MarginPositionUpdated(items) => items.is_empty(),
+            PrivateEvent::MarginPayablesUpdated(items) => items.is_empty(),
+            PrivateEvent::MarginNoticeUpdated(items) => items.is_empty(),
+            PrivateEvent::DepositUpdated(items) => items.is_empty(),
+            PrivateEvent::WithdrawalUpdated(items) => items.is_empty(),
+        }
+    }
+}
+
+// 変換できた要素だけを返し、変換できなかった要素のエラーは`errors`に積む。
+fn convert_items<S, T, E>(
+    items: impl IntoIterator<Item = S>,
+    convert: impl Fn(S) -> Result<T, E>,
+    errors: &mut Vec<PrivateEventConversionError>,
+) -> Vec<T>
+where
+    E: Into<PrivateEventConversionError>,
+{
+    items
+        .into_iter()
+        .filter_map(|item| convert(item).map_err(|err| errors.push(err.into())).ok())
+        .collect()
+}
+
+fn parse_params<T: DeserializeOwned>(
+    message: &BitbankPrivateStreamMessage,
+    errors: &mut Vec<PrivateEventConversionError>,
+) -> Vec<T> {
+    convert_items(
+        message.params.iter(),
+        |param| {
+            serde_json::from_value(param.clone()).map_err(|err| {
+                PrivateEventConversionError::InvalidParams {
+                    method: message.method.clone(),
+                    error: err.to_string(),
+                }
+            })
+        },
+        errors,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order_domain::{OrderSide, OrderStatus};
+    use rust_decimal::Decimal;
+    use serde_json::json;
+
+    fn stream_message(value: serde_json::Value) -> BitbankPrivateStreamMessage {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn converts_spot_order_message_to_order_update() {
+        let message = stream_message(json!({
+            "method": "spot_order",
+            "params": [{
+                "average_price": "5000000",
+                "canceled_at": null,
+                "executed_amount": "0.05",
+                "executed_at": 1710000001000_u64,
+                "expire_at": null,
+                "finished_at": null,
+                "order_id": 12345,
+                "ordered_at": 1710000000000_u64,
+                "pair": "btc_jpy",
+                "position_side": null,
+                "post_only": true,
+                "price": "5000000",
+                "remaining_amount": "0.05",
+                "side": "buy",
+                "start_amount": "0.1",
+                "status": "PARTIALLY_FILLED",
+                "trigger_price": null,
+                "triggered_at": null,
+                "type": "limit",
+                "user_cancelable": true,
+                "is_just_triggered": false
+            }]
+        }));
+
+        let Some(PrivateEvent::OrderUpdated(updates)) =
+            PrivateEvent::from_stream_message(message).event
+        else {
+            panic!("expected order update");
+        };
+
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].order.order_id, OrderId(12345));
+        assert_eq!(updates[0].order.side, OrderSide::Buy);
+        assert_eq!(updates[0].status, OrderStatus::PartiallyFilled);
+        assert!(updates[0].status.is_active());
+        assert_eq!(updates[0].executed_amount, Decimal::new(5, 2));
+    }
+
+    #[test]
+    fn converts_asset_update_and_invalidation_messages() {
+        let assets = stream_message(json!({
+            "method": "asset_update",
+            "params": [{
+                "asset": "jpy",
+                "amount_precision": 4,
+                "free_amount": "1000",
+                "locked_amount": "500",
+                "onhand_amount": "1500",
+                "withdrawing_amount": "0"
+            }]
+        }));
+        let invalidation = stream_message(json!({
+            "method": "spot_order_invalidation",
+            "params": [{"order_id": [1, 2]}]
+        }));
+
+        let Some(PrivateEvent::AssetUpdated(balances)) =
+            PrivateEvent::from_stream_message(assets).event
+        else {
+            panic!("expected asset update");
+        };
+        assert_eq!(balances[0].onhand_amount, Decimal::new(1500, 0));
+
+        let Some(PrivateEvent::OrdersInvalidated(order_ids)) =
+            PrivateEvent::from_stream_message(invalidation).event
+        else {
+            panic!("expected order invalidation");
+        };
+        assert_eq!(order_ids, vec![OrderId(1), OrderId(2)]);
+    }
+
+    #[test]
+    fn unknown_method_is_ignored_and_broken_params_are_reported() {
+        let unknown = stream_message(json!({
+            "method": "dealer_order_new",
+            "params": [{}]
+        }));
+        let broken = stream_message(json!({
+            "method": "spot_trade",
+            "params": [{"trade_id": 1}]
+        }));
+
+        let unknown = PrivateEvent::from_stream_message(unknown);
+        assert!(unknown.event.is_none());
+        assert!(unknown.errors.is_empty());
+
+        let broken = PrivateEvent::from_stream_message(broken);
+        assert!(broken.event.is_none());
+        assert!(matches!(
+            broken.errors.as_slice(),
+            [PrivateEventConversionError::InvalidParams { .. }]
+        ));
+    }
+
+    #[test]
+    fn invalid_items_are_reported_without_dropping_valid_ones() {
+        let assets = stream_message(json!({
+            "method": "asset_update",
+            "params": [
+                {
+                    "asset": "jpy",
+                    "amount_precision": 4,
+                    "free_amount": "1000",
+                    "locked_amount": "500",
+                    "onhand_amount": "1500",
+                    "withdrawing_amount": "0"
+                },
+                {"asset": "btc"}
+            ]
+        }));
+        let invalidation = stream_message(json!({
+            "method": "spot_order_invalidation",
+            "params": [{"order_id": [1, -1, 3]}]
+        }));
+
+        let assets = PrivateEvent::from_stream_message(assets);
+        let Some(PrivateEvent::AssetUpdated(balances)) = assets.event else {
+            panic!("expected asset update");
+        };
+        assert_eq!(balances.len(), 1);
+        assert_eq!(balances[0].onhand_amount, Decimal::new(1500, 0));
+        assert_eq!(assets.errors.len(), 1);
+
+        let invalidation = PrivateEvent::from_stream_message(invalidation);
+        let Some(PrivateEvent::OrdersInvalidated(order_ids)) = invalidation.event else {
+            panic!("expected order invalidation");
+        };
+        assert_eq!(order_ids, vec![OrderId(1), OrderId(3)]);
+        assert_eq!(
+            invalidation.errors,
+            vec![PrivateEventConversionError::InvalidOrder(
+                ParseOrderError::InvalidOrderId
+            )]
+        );
+    }
+}
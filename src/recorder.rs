@@ -0,0 +1,604 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc as std_mpsc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+
+use crate::bitbank_bot::BitbankInboundMessage;
+use crate::clock::system_now_millis;
+use crate::websocket_handler::{decode_websocket_message, WebSocketDecodeError};
+
+/// 記録したものをファイルにフラッシュする間隔。記録が途切れずに続いていても、この間隔でフラッシュする。
+/// 異常終了しても、最後にフラッシュした時点までは読み出せる。
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 書き込み用のスレッドに渡すまで溜めておける記録の数。書き込みが追いつかずこれを超えた記録は捨てる。
+const RECORD_BUFFER: usize = 65536;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// 記録したメッセージの中身。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum RecordedPayload {
+    /// WebSocketで受け取ったJSONそのもの。解釈できなかったメッセージもそのまま残す。
+    WebSocket(serde_json::Value),
+    Connected,
+    Disconnected,
+    Reconnected,
+}
+
+/// 記録ファイルの1行。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedMessage {
+    /// ローカルで受け取った時刻（UNIXエポックからのミリ秒）。
+    pub received_at: i64,
+    pub pair: String,
+    pub payload: RecordedPayload,
+}
+
+impl RecordedMessage {
+    // 受け取ったときと同じ[`BitbankInboundMessage`]に戻す。
+    pub fn into_inbound(self) -> Result<BitbankInboundMessage, WebSocketDecodeError> {
+        match self.payload {
+            RecordedPayload::WebSocket(val) => decode_websocket_message(val),
+            RecordedPayload::Connected => Ok(BitbankInboundMessage::Connected),
+            RecordedPayload::Disconnected => Ok(BitbankInboundMessage::Disconnected),
+            RecordedPayload::Reconnected => Ok(BitbankInboundMessage::Reconnected),
+        }
+    }
+}
+
+/// [`MarketDataRecorder`]の書き込み先。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecorderConfig {
+    pub directory: PathBuf,
+    /// ファイル名は`{file_prefix}-{期間の開始時刻（ミリ秒）}.jsonl`（圧縮する場合は`.jsonl.gz`）になる。
+    pub file_prefix: String,
+    /// この間隔（UNIXエポック基準）ごとに新しいファイルに切り替える。
+    pub rotate_every: Duration,
+    /// gzipで圧縮するかどうか。
+    pub compress: bool,
+}
+
+impl RecorderConfig {
+    // 1時間ごとに切り替え、gzipで圧縮する設定。
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            file_prefix: "bitbank".to_owned(),
+            rotate_every: Duration::from_secs(60 * 60),
+            compress: true,
+        }
+    }
+
+    fn file_path(&self, period_start: i64) -> PathBuf {
+        let extension = if self.compress { "jsonl.gz" } else { "jsonl" };
+        self.directory.join(format!(
+            "{}-{}.{}",
+            self.file_prefix, period_start, extension
+        ))
+    }
+
+    fn period_start(&self, timestamp: i64) -> i64 {
+        let period = (self.rotate_every.as_millis() as i64).max(1);
+        timestamp - timestamp.rem_euclid(period)
+    }
+}
+
+enum Command {
+    Record(RecordedMessage),
+    Flush(oneshot::Sender<io::Result<()>>),
+    Close(oneshot::Sender<io::Result<()>>),
+}
+
+/// WebSocketで受け取った生のメッセージを、受信時刻とペアをつけてJSON Linesのファイルに書き出す。
+/// 書き込みは専用のスレッドで行うので、記録してもフィードは待たされない。
+/// 書き込みが追いつかず、溜まった記録が一定数を超えた場合は記録を捨てる（[`Self::dropped`]）。
+/// cloneしたものは同じファイルに書き込む。すべてドロップするか[`Self::close`]を呼ぶと、ファイルを閉じる。
+///
+/// REST APIで取り直した板（[`DepthResync::Rest`](crate::bitbank_bot::DepthResync::Rest)）は記録しない。
+#[derive(Debug, Clone)]
+pub struct MarketDataRecorder {
+    tx: std_mpsc::SyncSender<Command>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl MarketDataRecorder {
+    // `config.directory`がなければ作り、書き込み用のスレッドを起動する。
+    pub fn start(config: RecorderConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.directory)?;
+
+        let (tx, rx) = std_mpsc::sync_channel(RECORD_BUFFER);
+        std::thread::Builder::new()
+            .name("bitbank-recorder".to_owned())
+            .spawn(move || RecordingWriter::new(config).run(rx))?;
+
+        Ok(Self {
+            tx,
+            dropped: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    // 現在時刻を受信時刻として記録する。
+    pub fn record(&self, pair: &str, payload: RecordedPayload) {
+        self.record_message(RecordedMessage {
+            received_at: system_now_millis(),
+            pair: pair.to_owned(),
+            payload,
+        });
+    }
+
+    // 閉じた後に記録しようとしたものは捨てる。書き込みが追いついていない場合も待たずに捨てる。
+    pub fn record_message(&self, message: RecordedMessage) {
+        if let Err(std_mpsc::TrySendError::Full(_)) = self.tx.try_send(Command::Record(message)) {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            if dropped % RECORD_BUFFER as u64 == 1 {
+                log::warn!(
+                    "market data recorder is falling behind. dropping records ({} so far)",
+                    dropped
+                );
+            }
+        }
+    }
+
+    // 書き込みが追いつかずに捨てた記録の数。cloneしたものの分も含む。
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    // ここまでに記録したものをファイルに書き出す。前回から書き込みに失敗していた場合はそのエラーを返す。
+    pub async fn flush(&self) -> io::Result<()> {
+        self.request(Command::Flush).await
+    }
+
+    // ファイルを閉じて書き込み用のスレッドを止める。他のcloneからの記録も以降は捨てられる。
+    pub async fn close(&self) -> io::Result<()> {
+        self.request(Command::Close).await
+    }
+
+    async fn request(
+        &self,
+        command: impl FnOnce(oneshot::Sender<io::Result<()>>) -> Command,
+    ) -> io::Result<()> {
+        let (ack_tx, ack_rx) = oneshot::channel();
+        let closed = || io::Error::new(io::ErrorKind::BrokenPipe, "recorder is closed");
+        // 溜まっている記録が多いと空くまで待つので、ランタイムのスレッドを止めないよう別のスレッドで送る
+        let tx = self.tx.clone();
+        let command = command(ack_tx);
+        tokio::task::spawn_blocking(move || tx.send(command))
+            .await
+            .map_err(|_| closed())?
+            .map_err(|_| closed())?;
+        ack_rx.await.map_err(|_| closed())?
+    }
+}
+
+enum RecordingFile {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+impl RecordingFile {
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            RecordingFile::Plain(writer) => writer,
+            RecordingFile::Gzip(writer) => writer,
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            RecordingFile::Plain(mut writer) => writer.flush(),
+            RecordingFile::Gzip(writer) => writer.finish()?.flush(),
+        }
+    }
+}
+
+struct RecordingWriter {
+    config: RecorderConfig,
+    current: Option<(i64, RecordingFile)>,
+    dirty: bool,
+    error: Option<io::Error>,
+}
+
+impl RecordingWriter {
+    fn new(config: RecorderConfig) -> Self {
+        Self {
+            config,
+            current: None,
+            dirty: false,
+            error: None,
+        }
+    }
+
+    fn run(mut self, rx: std_mpsc::Receiver<Command>) {
+        let mut last_flush = Instant::now();
+        loop {
+            match rx.recv_timeout(FLUSH_INTERVAL.saturating_sub(last_flush.elapsed())) {
+                Ok(Command::Record(message)) => {
+                    if let Err(err) = self.write(&message) {
+                        self.failed(err);
+                    }
+                }
+                Ok(Command::Flush(ack)) => {
+                    let res = self.flush();
+                    let _ = ack.send(res);
+                    last_flush = Instant::now();
+                }
+                Ok(Command::Close(ack)) => {
+                    let res = self.flush().and(self.finish());
+                    let _ = ack.send(res);
+                    return;
+                }
+                Err(std_mpsc::RecvTimeoutError::Timeout) => {}
+                Err(std_mpsc::RecvTimeoutError::Disconnected) => {
+                    if let Err(err) = self.finish() {
+                        log::error!("failed to close market data recording: {}", err);
+                    }
+                    return;
+                }
+            }
+
+            // 記録が続いていてタイムアウトしない間も、前回から`FLUSH_INTERVAL`たったらフラッシュする
+            if last_flush.elapsed() >= FLUSH_INTERVAL {
+                if self.dirty {
+                    if let Err(err) = self.flush_file() {
+                        log::error!("failed to flush market data recording: {}", err);
+                    }
+                }
+                last_flush = Instant::now();
+            }
+        }
+    }
+
+    fn write(&mut self, message: &RecordedMessage) -> io::Result<()> {
+        let period_start = self.config.period_start(message.received_at);
+        // 時計が戻った場合は、前のファイルに戻らずそのまま書き続ける
+        let rotate = match &self.current {
+            Some((current_start, _)) => period_start > *current_start,
+            None => true,
+        };
+        if rotate {
+            self.finish()?;
+            let file = self.open(period_start)?;
+            self.current = Some((period_start, file));
+        }
+
+        let Some((_, file)) = &mut self.current else {
+            unreachable!("recording file was just opened");
+        };
+        let writer = file.writer();
+        serde_json::to_writer(&mut *writer, message)?;
+        writer.write_all(b"\n")?;
+        self.dirty = true;
+        Ok(())
+    }
+
+    // 同じ期間のファイルがすでにある場合（再起動したときなど）は追記する。
+    // gzipの場合は別のメンバーとして追記され、[`RecordingReader`]はまとめて読み出す。
+    fn open(&self, period_start: i64) -> io::Result<RecordingFile> {
+        let path = self.config.file_path(period_start);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        log::info!("recording market data to {}", path.display());
+
+        let writer = BufWriter::new(file);
+        Ok(if self.config.compress {
+            RecordingFile::Gzip(GzEncoder::new(writer, Compression::default()))
+        } else {
+            RecordingFile::Plain(writer)
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => self.flush_file(),
+        }
+    }
+
+    fn flush_file(&mut self) -> io::Result<()> {
+        if let Some((_, file)) = &mut self.current {
+            if let Err(err) = file.writer().flush() {
+                self.current = None;
+                return Err(err);
+            }
+        }
+        self.dirty = false;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.dirty = false;
+        match self.current.take() {
+            Some((_, file)) => file.finish(),
+            None => Ok(()),
+        }
+    }
+
+    // 書き込みに失敗したファイルは捨て、次のレコードでファイルを開き直す。
+    // エラーは次の`flush`で呼び出し元に返す。
+    fn failed(&mut self, err: io::Error) {
+        log::error!("failed to record market data: {}", err);
+        self.current = None;
+        self.error.get_or_insert(err);
+    }
+}
+
+/// 記録ファイルを読み出せなかった理由。
+#[derive(Debug)]
+pub enum RecordingReadError {
+    Io(io::Error),
+    /// `line`行目（1始まり）を[`RecordedMessage`]として解釈できなかった。
+    Json {
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for RecordingReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordingReadError::Io(err) => write!(f, "failed to read recording: {}", err),
+            RecordingReadError::Json { line, message } => {
+                write!(f, "invalid record at line {}: {}", line, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RecordingReadError {}
+
+impl From<io::Error> for RecordingReadError {
+    fn from(err: io::Error) -> Self {
+        RecordingReadError::Io(err)
+    }
+}
+
+/// [`MarketDataRecorder`]が書いたファイルを1行ずつ[`RecordedMessage`]として読み出す。
+/// gzipかどうかはファイルの中身から判断する。
+///
+/// 異常終了などでgzipの末尾が欠けている場合は、読み出せたところまでを返した後に
+/// [`RecordingReadError::Io`]を1回返して終わる。
+pub struct RecordingReader {
+    lines: io::Lines<Box<dyn BufRead + Send>>,
+    line: usize,
+    done: bool,
+}
+
+impl RecordingReader {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let reader: Box<dyn BufRead + Send> = if reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+            Box::new(BufReader::new(MultiGzDecoder::new(reader)))
+        } else {
+            Box::new(reader)
+        };
+
+        Ok(Self {
+            lines: reader.lines(),
+            line: 0,
+            done: false,
+        })
+    }
+}
+
+impl Iterator for RecordingReader {
+    type Item = Result<RecordedMessage, RecordingReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err.into()));
+                }
+            };
+            self.line += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            return Some(
+                serde_json::from_str(&line).map_err(|err| RecordingReadError::Json {
+                    line: self.line,
+                    message: err.to_string(),
+                }),
+            );
+        }
+        None
+    }
+}
+
+// `directory`にある`file_prefix`の記録ファイルを、古い順に返す。
+pub fn list_recordings(directory: impl AsRef<Path>, file_prefix: &str) -> io::Result<Vec<PathBuf>> {
+    let mut recordings = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let Some(rest) = name
+            .strip_prefix(file_prefix)
+            .and_then(|rest| rest.strip_prefix('-'))
+        else {
+            continue;
+        };
+        let period_start = rest
+            .strip_suffix(".jsonl.gz")
+            .or_else(|| rest.strip_suffix(".jsonl"))
+            .and_then(|start| start.parse::<i64>().ok());
+        if let Some(period_start) = period_start {
+            recordings.push((period_start, path));
+        }
+    }
+
+    recordings.sort();
+    Ok(recordings.into_iter().map(|(_, path)| path).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "bitbankutil-recorder-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn ticker_message(last: &str) -> serde_json::Value {
+        json!(["message", {
+            "room_name": "ticker_btc_jpy",
+            "message": {"data": {
+                "sell": "101", "buy": "100", "open": "90", "high": "110", "low": "80",
+                "last": last, "vol": "1.5", "timestamp": 1234
+            }}
+        }])
+    }
+
+    fn record(received_at: i64, payload: RecordedPayload) -> RecordedMessage {
+        RecordedMessage {
+            received_at,
+            pair: "btc_jpy".to_owned(),
+            payload,
+        }
+    }
+
+    #[tokio::test]
+    async fn rotates_files_and_reads_them_back() {
+        let dir = temp_dir("rotate");
+        let recorder = MarketDataRecorder::start(RecorderConfig {
+            rotate_every: Duration::from_secs(60),
+            ..RecorderConfig::new(&dir)
+        })
+        .unwrap();
+
+        recorder.record_message(record(60_000, RecordedPayload::Connected));
+        recorder.record_message(record(
+            60_001,
+            RecordedPayload::WebSocket(ticker_message("100")),
+        ));
+        recorder.record_message(record(
+            120_000,
+            RecordedPayload::WebSocket(json!({"unexpected": true})),
+        ));
+        recorder.close().await.unwrap();
+        assert!(recorder.flush().await.is_err());
+
+        let files = list_recordings(&dir, "bitbank").unwrap();
+        assert_eq!(
+            files,
+            vec![
+                dir.join("bitbank-60000.jsonl.gz"),
+                dir.join("bitbank-120000.jsonl.gz")
+            ]
+        );
+
+        let first: Vec<_> = RecordingReader::open(&files[0])
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(first.len(), 2);
+        assert_eq!(first[0], record(60_000, RecordedPayload::Connected));
+
+        let mut inbound = first.into_iter().map(RecordedMessage::into_inbound);
+        assert!(matches!(
+            inbound.next(),
+            Some(Ok(BitbankInboundMessage::Connected))
+        ));
+        let Some(Ok(BitbankInboundMessage::Ticker(ticker))) = inbound.next() else {
+            panic!("expected ticker");
+        };
+        assert_eq!(ticker.last, "100");
+
+        // 解釈できないメッセージもそのまま残っている
+        let second = RecordingReader::open(&files[1])
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert!(second.into_inbound().is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn flushes_periodically_while_records_keep_coming() {
+        let dir = temp_dir("flush");
+        let recorder = MarketDataRecorder::start(RecorderConfig {
+            compress: false,
+            ..RecorderConfig::new(&dir)
+        })
+        .unwrap();
+
+        // `FLUSH_INTERVAL`より短い間隔で記録し続ける
+        let started = Instant::now();
+        while started.elapsed() < FLUSH_INTERVAL * 3 / 2 {
+            recorder.record_message(record(
+                1_000,
+                RecordedPayload::WebSocket(ticker_message("100")),
+            ));
+            tokio::time::sleep(FLUSH_INTERVAL / 10).await;
+        }
+
+        // 閉じる前でも、フラッシュした分は読み出せる
+        let files = list_recordings(&dir, "bitbank").unwrap();
+        assert_eq!(files.len(), 1);
+        assert!(RecordingReader::open(&files[0]).unwrap().next().is_some());
+
+        recorder.close().await.unwrap();
+        assert_eq!(recorder.dropped(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn appends_to_existing_file_after_restart() {
+        let dir = temp_dir("append");
+        for (compress, last) in [(true, "1"), (true, "2"), (false, "3")] {
+            let recorder = MarketDataRecorder::start(RecorderConfig {
+                compress,
+                ..RecorderConfig::new(&dir)
+            })
+            .unwrap();
+            recorder.record_message(record(
+                1_000,
+                RecordedPayload::WebSocket(ticker_message(last)),
+            ));
+            recorder.close().await.unwrap();
+        }
+
+        let files = list_recordings(&dir, "bitbank").unwrap();
+        assert_eq!(files.len(), 2);
+
+        let mut lasts = Vec::new();
+        for file in files {
+            for message in RecordingReader::open(file).unwrap() {
+                let Ok(BitbankInboundMessage::Ticker(ticker)) = message.unwrap().into_inbound()
+                else {
+                    panic!("expected ticker");
+                };
+                lasts.push(ticker.last);
+            }
+        }
+        lasts.sort();
+        assert_eq!(lasts, vec!["1", "2", "3"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
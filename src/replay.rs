@@ -0,0 +1,352 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::bitbank_bot::BitbankInboundMessage;
+use crate::recorder::{list_recordings, RecordedMessage, RecordingReadError, RecordingReader};
+
+/// 記録したメッセージを流す速さ。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// 記録したときと同じ間隔で流す。
+    Original,
+    /// 記録したときの`n`倍の速さで流す。0以下や無限大の場合は`AsFastAsPossible`と同じ。
+    Multiplier(f64),
+    /// 待たずに流す。受け手が詰まっている間だけ待つ。
+    AsFastAsPossible,
+}
+
+impl ReplaySpeed {
+    // 最初のメッセージからの記録時刻の差を、実際に待つ時間に変換する。待たない場合は`None`。
+    fn delay(self, elapsed_ms: i64) -> Option<Duration> {
+        let multiplier = match self {
+            ReplaySpeed::Original => 1.0,
+            ReplaySpeed::Multiplier(multiplier) if multiplier > 0.0 && multiplier.is_finite() => {
+                multiplier
+            }
+            ReplaySpeed::Multiplier(_) | ReplaySpeed::AsFastAsPossible => return None,
+        };
+        Some(Duration::from_secs_f64(
+            elapsed_ms.max(0) as f64 / 1000.0 / multiplier,
+        ))
+    }
+}
+
+/// 記録ファイルの一部を読み出せなかった。
+#[derive(Debug)]
+pub struct ReplayReadError {
+    pub path: PathBuf,
+    pub error: RecordingReadError,
+}
+
+impl fmt::Display for ReplayReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.error)
+    }
+}
+
+impl std::error::Error for ReplayReadError {}
+
+/// 複数の記録ファイルを受信時刻の順に1つにまとめて読み出す。
+/// 受信時刻が同じメッセージは、渡したファイルの順、ファイル内の順に並ぶ。
+/// 解釈できなかった行はエラーとして返し、読み出しは続ける。
+pub struct MergedRecordings {
+    readers: Vec<(PathBuf, RecordingReader)>,
+    heads: Vec<Option<RecordedMessage>>,
+    order: BinaryHeap<Reverse<(i64, usize)>>,
+    errors: VecDeque<ReplayReadError>,
+}
+
+impl MergedRecordings {
+    pub fn new(readers: Vec<(PathBuf, RecordingReader)>) -> Self {
+        let mut merged = Self {
+            heads: readers.iter().map(|_| None).collect(),
+            readers,
+            order: BinaryHeap::new(),
+            errors: VecDeque::new(),
+        };
+        for index in 0..merged.readers.len() {
+            merged.advance(index);
+        }
+        merged
+    }
+
+    pub fn open<P: AsRef<Path>>(paths: impl IntoIterator<Item = P>) -> io::Result<Self> {
+        let readers = paths
+            .into_iter()
+            .map(|path| {
+                let path = path.as_ref().to_path_buf();
+                RecordingReader::open(&path).map(|reader| (path, reader))
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(Self::new(readers))
+    }
+
+    // `index`番目のファイルの次のメッセージを読み、順番待ちに入れる。
+    fn advance(&mut self, index: usize) {
+        let (path, reader) = &mut self.readers[index];
+        for res in reader.by_ref() {
+            match res {
+                Ok(message) => {
+                    self.order.push(Reverse((message.received_at, index)));
+                    self.heads[index] = Some(message);
+                    return;
+                }
+                Err(error) => self.errors.push_back(ReplayReadError {
+                    path: path.clone(),
+                    error,
+                }),
+            }
+        }
+    }
+}
+
+impl Iterator for MergedRecordings {
+    type Item = Result<RecordedMessage, ReplayReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.errors.pop_front() {
+            return Some(Err(err));
+        }
+
+        let Reverse((_, index)) = self.order.pop()?;
+        let message = self.heads[index]
+            .take()
+            .expect("queued recording has a pending message");
+        self.advance(index);
+        Some(Ok(message))
+    }
+}
+
+/// [`ReplaySource::run`]の結果。
+#[derive(Debug, Default)]
+pub struct ReplayStats {
+    /// 受け手に渡したメッセージの数。
+    pub replayed: u64,
+    /// 受け手のいないペアのメッセージで、捨てたものの数。
+    pub skipped: u64,
+    /// 読み出せなかった行。
+    pub errors: Vec<ReplayReadError>,
+}
+
+/// 記録ファイルのメッセージを、受信時刻の順にペアごとの[`BitbankInboundMessage`]のチャネルへ流す。
+/// [`BitbankBotBuilder::replay`](crate::bitbank_bot::BitbankBotBuilder::replay)に渡すと、
+/// WebSocketの代わりにボットのフィードになる。
+/// [`forward_bitbank_messages`](crate::bitbank_bot::forward_bitbank_messages)と組み合わせる場合は[`Self::run`]を使う。
+pub struct ReplaySource {
+    recordings: MergedRecordings,
+    speed: ReplaySpeed,
+}
+
+impl ReplaySource {
+    /// 記録ファイルを開く。ファイルの順番は問わない。速さは[`ReplaySpeed::Original`]。
+    pub fn open<P: AsRef<Path>>(paths: impl IntoIterator<Item = P>) -> io::Result<Self> {
+        Ok(Self::new(MergedRecordings::open(paths)?))
+    }
+
+    /// `directory`にある`file_prefix`の記録ファイルをすべて開く。
+    pub fn from_directory(directory: impl AsRef<Path>, file_prefix: &str) -> io::Result<Self> {
+        Self::open(list_recordings(directory, file_prefix)?)
+    }
+
+    pub fn new(recordings: MergedRecordings) -> Self {
+        Self {
+            recordings,
+            speed: ReplaySpeed::Original,
+        }
+    }
+
+    pub fn speed(mut self, speed: ReplaySpeed) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// すべてのメッセージを流し終えるか、すべての受け手が閉じるまで流す。
+    /// `senders`にないペアのメッセージは捨てる。解釈できないWebSocketのメッセージは、ライブと同じく
+    /// [`BitbankInboundMessage::DecodeError`]として流す。
+    pub async fn run(
+        self,
+        mut senders: HashMap<String, mpsc::Sender<BitbankInboundMessage>>,
+    ) -> ReplayStats {
+        let ReplaySource { recordings, speed } = self;
+
+        // ファイルの読み出しと展開はブロックするので、別のスレッドで行う
+        let (tx, mut rx) = mpsc::channel(1024);
+        let reader = tokio::task::spawn_blocking(move || {
+            for res in recordings {
+                if tx.blocking_send(res).is_err() {
+                    return;
+                }
+            }
+        });
+
+        let mut stats = ReplayStats::default();
+        let mut origin: Option<(i64, Instant)> = None;
+        while let Some(res) = rx.recv().await {
+            let message = match res {
+                Ok(message) => message,
+                Err(err) => {
+                    log::warn!("skipping unreadable recording: {}", err);
+                    stats.errors.push(err);
+                    continue;
+                }
+            };
+            let Some(sender) = senders.get(&message.pair) else {
+                stats.skipped += 1;
+                continue;
+            };
+
+            let (first_received_at, started) =
+                *origin.get_or_insert((message.received_at, Instant::now()));
+            if let Some(delay) = speed.delay(message.received_at - first_received_at) {
+                tokio::time::sleep_until(started + delay).await;
+            }
+
+            let pair = message.pair.clone();
+            let inbound = message
+                .into_inbound()
+                .unwrap_or_else(BitbankInboundMessage::DecodeError);
+            if sender.send(inbound).await.is_err() {
+                log::debug!("stopping replay for {}; receiver hung up", pair);
+                senders.remove(&pair);
+                if senders.is_empty() {
+                    break;
+                }
+                continue;
+            }
+            stats.replayed += 1;
+        }
+
+        drop(rx);
+        let _ = reader.await;
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recorder::RecordedPayload;
+    use serde_json::json;
+    use std::fs;
+
+    fn write_recording(path: &Path, records: &[(i64, &str)]) {
+        let lines: Vec<String> = records
+            .iter()
+            .map(|(received_at, pair)| {
+                serde_json::to_string(&RecordedMessage {
+                    received_at: *received_at,
+                    pair: (*pair).to_owned(),
+                    payload: RecordedPayload::Connected,
+                })
+                .unwrap()
+            })
+            .collect();
+        fs::write(path, lines.join("\n") + "\n").unwrap();
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "bitbankutil-replay-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn merges_files_by_received_time() {
+        let dir = temp_dir("merge");
+        let btc = dir.join("btc.jsonl");
+        let eth = dir.join("eth.jsonl");
+        write_recording(&btc, &[(1, "btc_jpy"), (3, "btc_jpy"), (3, "btc_jpy")]);
+        write_recording(&eth, &[(2, "eth_jpy"), (3, "eth_jpy")]);
+        fs::write(
+            dir.join("broken.jsonl"),
+            format!(
+                "{}\nnot json\n",
+                json!({"received_at": 0, "pair": "xrp_jpy", "payload": {"type": "connected"}})
+            ),
+        )
+        .unwrap();
+
+        let merged = MergedRecordings::open([btc, eth, dir.join("broken.jsonl")]).unwrap();
+        let mut order = Vec::new();
+        let mut errors = 0;
+        for res in merged {
+            match res {
+                Ok(message) => order.push((message.received_at, message.pair)),
+                Err(err) => {
+                    assert!(matches!(
+                        err.error,
+                        RecordingReadError::Json { line: 2, .. }
+                    ));
+                    errors += 1;
+                }
+            }
+        }
+
+        assert_eq!(errors, 1);
+        assert_eq!(
+            order,
+            vec![
+                (0, "xrp_jpy".to_owned()),
+                (1, "btc_jpy".to_owned()),
+                (2, "eth_jpy".to_owned()),
+                (3, "btc_jpy".to_owned()),
+                (3, "btc_jpy".to_owned()),
+                (3, "eth_jpy".to_owned()),
+            ]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn replays_at_scaled_speed_and_skips_unknown_pairs() {
+        let dir = temp_dir("speed");
+        let path = dir.join("bitbank-0.jsonl");
+        write_recording(
+            &path,
+            &[(1_000, "btc_jpy"), (1_100, "eth_jpy"), (1_200, "btc_jpy")],
+        );
+
+        let (tx, mut rx) = mpsc::channel(8);
+        let started = Instant::now();
+        let stats = ReplaySource::open([&path])
+            .unwrap()
+            .speed(ReplaySpeed::Multiplier(4.0))
+            .run(HashMap::from([("btc_jpy".to_owned(), tx)]))
+            .await;
+        let elapsed = started.elapsed();
+
+        assert_eq!((stats.replayed, stats.skipped), (2, 1));
+        // 時間を止めているので、記録上の200msを4倍速で流すとちょうど50ms進む
+        assert_eq!(elapsed, Duration::from_millis(50));
+        assert!(matches!(
+            rx.recv().await,
+            Some(BitbankInboundMessage::Connected)
+        ));
+        assert!(matches!(
+            rx.recv().await,
+            Some(BitbankInboundMessage::Connected)
+        ));
+        assert!(rx.recv().await.is_none());
+
+        assert_eq!(ReplaySpeed::Multiplier(0.0).delay(100), None);
+        assert_eq!(
+            ReplaySpeed::Original.delay(100),
+            Some(Duration::from_millis(100))
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,372 @@
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::error::BitbankError;
+
+/// bitbankのレート制限の区分。参照系と更新系で別々に制限されている。
+/// cf: https://github.com/bitbankinc/bitbank-api-docs/blob/master/rest-api.md#rate-limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EndpointClass {
+    /// 残高・注文情報の取得など、何度送っても結果が変わらないリクエスト。
+    Query,
+    /// 発注・キャンセル・出金など、取引所の状態を変えるリクエスト。
+    Update,
+}
+
+/// トークンバケットの設定。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TokenBucketConfig {
+    /// 一度に送れるリクエスト数の上限。
+    pub capacity: u32,
+    /// 1秒あたりに回復するトークン数。
+    pub refill_per_sec: f64,
+}
+
+/// [`RateLimiter`]の設定。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitConfig {
+    pub query: TokenBucketConfig,
+    pub update: TokenBucketConfig,
+}
+
+impl Default for RateLimitConfig {
+    // bitbankの制限（参照系10回/秒、更新系6回/秒）
+    fn default() -> Self {
+        Self {
+            query: TokenBucketConfig {
+                capacity: 10,
+                refill_per_sec: 10.0,
+            },
+            update: TokenBucketConfig {
+                capacity: 6,
+                refill_per_sec: 6.0,
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    config: TokenBucketConfig,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(config: TokenBucketConfig, now: Instant) -> Self {
+        Self {
+            config,
+            tokens: f64::from(config.capacity),
+            last_refill: now,
+        }
+    }
+
+    // トークンを1つ取る。足りない場合は、次のトークンが貯まるまでの時間を返す。
+    fn try_acquire(&mut self, now: Instant) -> Result<(), Duration> {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.config.refill_per_sec)
+            .min(f64::from(self.config.capacity));
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - self.tokens) / self.config.refill_per_sec,
+            ))
+        }
+    }
+}
+
+/// [`EndpointClass`]ごとのトークンバケットでリクエストの送信間隔を制御する。
+/// cloneしたものは同じバケットを共有するので、複数のクライアントに渡すとそれらの合計で制限できる。
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    query: Option<Arc<Mutex<TokenBucket>>>,
+    update: Option<Arc<Mutex<TokenBucket>>>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(RateLimitConfig::default())
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        let now = Instant::now();
+        Self {
+            query: Some(Arc::new(Mutex::new(TokenBucket::new(config.query, now)))),
+            update: Some(Arc::new(Mutex::new(TokenBucket::new(config.update, now)))),
+        }
+    }
+
+    // 制限しない。
+    pub fn unlimited() -> Self {
+        Self {
+            query: None,
+            update: None,
+        }
+    }
+
+    // `class`のトークンが取れるまで待つ。
+    pub async fn acquire(&self, class: EndpointClass) {
+        let bucket = match class {
+            EndpointClass::Query => &self.query,
+            EndpointClass::Update => &self.update,
+        };
+        let Some(bucket) = bucket else {
+            return;
+        };
+
+        loop {
+            let wait = match bucket
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .try_acquire(Instant::now())
+            {
+                Ok(()) => return,
+                Err(wait) => wait,
+            };
+
+            log::trace!("rate limited ({:?}). waiting {:?}", class, wait);
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// 失敗したリクエストを再送するかどうかの方針。デフォルトでは再送しない。
+///
+/// [`EndpointClass::Query`]は[`BitbankError::is_retryable`]なエラー全般で再送する。
+/// [`EndpointClass::Update`]は、取引所が処理していないことが確実なエラー（レート制限・混雑）でのみ再送する。
+/// 通信エラーの場合、発注が通っているかもしれないので再送しない。
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// 最初の送信に加えて再送する最大回数。0なら再送しない。
+    pub max_retries: u32,
+    /// 1回目の再送までの待ち時間。再送ごとに倍になる。
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// 待ち時間をランダムに短くする割合（0.0〜1.0）。複数のタスクが同時に再送するのを避ける。
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::disabled()
+    }
+}
+
+impl RetryPolicy {
+    pub fn disabled() -> Self {
+        Self {
+            max_retries: 0,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(10),
+            jitter: 0.5,
+        }
+    }
+
+    pub fn exponential(max_retries: u32) -> Self {
+        Self {
+            max_retries,
+            ..Self::disabled()
+        }
+    }
+
+    pub fn should_retry(&self, class: EndpointClass, attempt: u32, err: &BitbankError) -> bool {
+        if attempt >= self.max_retries {
+            return false;
+        }
+
+        match class {
+            EndpointClass::Query => err.is_retryable(),
+            EndpointClass::Update => matches!(
+                err,
+                BitbankError::RateLimited
+                    | BitbankError::Api(
+                        crate::error::BitbankApiErrorCode::ServerBusy
+                            | crate::error::BitbankApiErrorCode::Overloaded(_)
+                    )
+            ),
+        }
+    }
+
+    // `attempt`回目（0始まり）の再送までの待ち時間。
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        self.backoff_with_random(attempt, random)
+    }
+
+    fn backoff_with_random(&self, attempt: u32, random: f64) -> Duration {
+        let base = self
+            .initial_backoff
+            .saturating_mul(2_u32.saturating_pow(attempt))
+            .min(self.max_backoff);
+        base.mul_f64(1.0 - self.jitter.clamp(0.0, 1.0) * random.clamp(0.0, 1.0))
+    }
+}
+
+/// REST APIクライアントが各リクエストに適用するレート制限と再送の方針。
+#[derive(Debug, Clone, Default)]
+pub struct RequestPolicy {
+    pub rate_limiter: RateLimiter,
+    pub retry: RetryPolicy,
+}
+
+impl RequestPolicy {
+    // レート制限に従って`request`を実行し、再送の方針に従って失敗したものを再送する。
+    pub(crate) async fn execute<T, F, Fut>(
+        &self,
+        class: EndpointClass,
+        api_name: &str,
+        mut request: F,
+    ) -> Result<T, BitbankError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, BitbankError>>,
+    {
+        let mut attempt = 0;
+
+        loop {
+            self.rate_limiter.acquire(class).await;
+
+            match request().await {
+                Err(err) if self.retry.should_retry(class, attempt, &err) => {
+                    let backoff = self.retry.backoff(attempt);
+                    log::warn!(
+                        "{} failed: {}. retry {}/{} after {:?}",
+                        api_name,
+                        err,
+                        attempt + 1,
+                        self.retry.max_retries,
+                        backoff
+                    );
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::BitbankApiErrorCode;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn token_bucket_refills_over_time() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(
+            TokenBucketConfig {
+                capacity: 2,
+                refill_per_sec: 4.0,
+            },
+            start,
+        );
+
+        assert!(bucket.try_acquire(start).is_ok());
+        assert!(bucket.try_acquire(start).is_ok());
+        assert_eq!(bucket.try_acquire(start), Err(Duration::from_millis(250)));
+
+        let later = start + Duration::from_millis(250);
+        assert!(bucket.try_acquire(later).is_ok());
+        assert!(bucket.try_acquire(later).is_err());
+
+        // capacityを超えては貯まらない
+        let much_later = later + Duration::from_secs(10);
+        assert!(bucket.try_acquire(much_later).is_ok());
+        assert!(bucket.try_acquire(much_later).is_ok());
+        assert!(bucket.try_acquire(much_later).is_err());
+    }
+
+    #[test]
+    fn backoff_grows_exponentially_with_jitter_and_cap() {
+        let policy = RetryPolicy {
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+            jitter: 0.5,
+        };
+
+        assert_eq!(
+            policy.backoff_with_random(0, 0.0),
+            Duration::from_millis(100)
+        );
+        assert_eq!(
+            policy.backoff_with_random(2, 0.0),
+            Duration::from_millis(400)
+        );
+        assert_eq!(
+            policy.backoff_with_random(3, 0.0),
+            Duration::from_millis(500)
+        );
+        assert_eq!(
+            policy.backoff_with_random(1, 1.0),
+            Duration::from_millis(100)
+        );
+        assert!(policy.backoff(1) <= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn update_requests_are_retried_only_when_not_processed() {
+        let policy = RetryPolicy::exponential(3);
+        let transport = BitbankError::Transport("timeout".to_owned());
+
+        assert!(policy.should_retry(EndpointClass::Query, 0, &transport));
+        assert!(!policy.should_retry(EndpointClass::Update, 0, &transport));
+        assert!(policy.should_retry(EndpointClass::Update, 0, &BitbankError::RateLimited));
+        assert!(policy.should_retry(
+            EndpointClass::Update,
+            2,
+            &BitbankError::Api(BitbankApiErrorCode::ServerBusy)
+        ));
+        assert!(!policy.should_retry(EndpointClass::Query, 3, &transport));
+        assert!(!RetryPolicy::disabled().should_retry(EndpointClass::Query, 0, &transport));
+    }
+
+    #[tokio::test]
+    async fn execute_retries_until_success() {
+        let policy = RequestPolicy {
+            rate_limiter: RateLimiter::unlimited(),
+            retry: RetryPolicy {
+                initial_backoff: Duration::from_millis(1),
+                ..RetryPolicy::exponential(3)
+            },
+        };
+        let attempts = &AtomicU32::new(0);
+
+        let res = policy
+            .execute(EndpointClass::Query, "test", || async move {
+                if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                    Err(BitbankError::RateLimited)
+                } else {
+                    Ok(42)
+                }
+            })
+            .await;
+
+        assert_eq!(res, Ok(42));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        let attempts = &AtomicU32::new(0);
+        let res: Result<(), _> = policy
+            .execute(EndpointClass::Query, "test", || async move {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(BitbankError::Api(BitbankApiErrorCode::InsufficientFunds))
+            })
+            .await;
+
+        assert!(res.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,18 +1,21 @@
 use serde::de::IntoDeserializer;
 use serde_json::Value;
 
+use crate::error::BitbankError;
+
 pub fn handle_response<T: serde::de::DeserializeOwned>(
     api_name: &str,
-    res: Result<
-        crate::bitbank_structs::BitbankApiResponse,
-        crypto_botters::generic_api_client::http::RequestError<
-            &str,
-            crypto_botters::bitbank::BitbankHandleError,
-        >,
-    >,
-) -> Result<T, Option<crypto_botters::bitbank::BitbankHandleError>> {
+    res: Result<crate::bitbank_structs::BitbankApiResponse, BitbankError>,
+) -> Result<T, BitbankError> {
     match res {
         Ok(api_response) => {
+            // success: 0 の場合、dataにはエラーコードが入っている
+            if api_response.success.as_u64() != Some(1) {
+                let err = BitbankError::from_error_body(&api_response.data);
+                log::error!("Bitbank api error on {}. error: {:?}", api_name, err);
+                return Err(with_api_name(err, api_name));
+            }
+
             // デシリアライズ
             let de: Value = api_response.data.clone().into_deserializer();
             let mut ignored = Vec::new();
@@ -41,28 +44,67 @@ pub fn handle_response<T: serde::de::DeserializeOwned>(
                         err
                     );
 
-                    Err(None)
+                    Err(BitbankError::Decode {
+                        api_name: api_name.to_owned(),
+                        message: err.to_string(),
+                        raw: api_response.data,
+                    })
                 }
             }
         }
-        Err(err) => match err {
-            crypto_botters::generic_api_client::http::RequestError::SendRequest(error) => {
-                log::error!("Send request error on {}. error: {:?}", api_name, error);
+        Err(err) => {
+            log::error!("Request error on {}. error: {:?}", api_name, err);
+            Err(err)
+        }
+    }
+}
 
-                Err(None)
-            }
-            crypto_botters::generic_api_client::http::RequestError::ReceiveResponse(error) => {
-                log::error!("Receive response error on {}. error: {:?}", api_name, error);
-                Err(None)
-            }
-            crypto_botters::generic_api_client::http::RequestError::BuildRequestError(error) => {
-                log::error!("Build request error on {}. error: {:?}", api_name, error);
-                Err(None)
-            }
-            crypto_botters::generic_api_client::http::RequestError::ResponseHandleError(error) => {
-                log::error!("Bitbank handle error on {}. error : {:?}", api_name, error);
-                Err(Some(error))
-            }
+fn with_api_name(err: BitbankError, api_name: &str) -> BitbankError {
+    match err {
+        BitbankError::Decode { message, raw, .. } => BitbankError::Decode {
+            api_name: api_name.to_owned(),
+            message,
+            raw,
         },
+        err => err,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitbank_structs::{BitbankApiResponse, BitbankAssetsData};
+    use crate::error::BitbankApiErrorCode;
+    use serde_json::json;
+
+    fn response(value: Value) -> Result<BitbankApiResponse, BitbankError> {
+        Ok(serde_json::from_value(value).unwrap())
+    }
+
+    #[test]
+    fn error_code_in_successful_http_response_is_mapped() {
+        let res: Result<BitbankAssetsData, _> = handle_response(
+            "test",
+            response(json!({"success": 0, "data": {"code": 60001}})),
+        );
+
+        assert_eq!(
+            res.unwrap_err(),
+            BitbankError::Api(BitbankApiErrorCode::InsufficientFunds)
+        );
+    }
+
+    #[test]
+    fn decode_failure_keeps_raw_json() {
+        let res: Result<BitbankAssetsData, _> = handle_response(
+            "test",
+            response(json!({"success": 1, "data": {"assets": "broken"}})),
+        );
+
+        let Err(BitbankError::Decode { api_name, raw, .. }) = res else {
+            panic!("expected decode error");
+        };
+        assert_eq!(api_name, "test");
+        assert_eq!(raw, json!({"assets": "broken"}));
     }
 }
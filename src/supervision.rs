@@ -0,0 +1,410 @@
+use std::any::Any;
+use std::collections::BTreeSet;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+use crate::request_policy::RetryPolicy;
+
+/// 戦略の`handle_event`がpanicしたときの扱い。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StrategyPanicPolicy {
+    /// panicしたイベントを捨て、[`BotStrategy::on_start`](crate::bitbank_bot::BotStrategy::on_start)を
+    /// 呼び直してから処理を続ける。それまでに登録されたタイマーは、呼び直す前にすべてキャンセルされる。`max_restarts`回を超えてpanicした場合は`StopRuntime`と同じく停止する。
+    Restart { max_restarts: u32 },
+    /// [`BotStrategy::on_stop`](crate::bitbank_bot::BotStrategy::on_stop)を呼んでから停止する。
+    /// 停止するとフィードも止まる。
+    StopRuntime,
+    /// `on_stop`を呼ばずにpanicをそのまま伝える。
+    /// [`BitbankBotRuntime::shutdown`](crate::bitbank_bot::BitbankBotRuntime::shutdown)がpanicした`JoinError`を返す。
+    Escalate,
+}
+
+/// ボットのアクターとフィードタスクの監視方針。
+#[derive(Debug, Clone, PartialEq)]
+pub struct SupervisionConfig {
+    pub strategy_panic: StrategyPanicPolicy,
+    /// panicした、または予期せず終了したフィードタスクを再起動する間隔と回数。`max_retries`回を超えたフィードは再起動しない。
+    /// フィードが`max_backoff`以上動いてから止まった場合、待ち時間は最初からやり直す。
+    pub feed_restart: RetryPolicy,
+}
+
+impl Default for SupervisionConfig {
+    fn default() -> Self {
+        Self {
+            strategy_panic: StrategyPanicPolicy::StopRuntime,
+            feed_restart: RetryPolicy::exponential(u32::MAX),
+        }
+    }
+}
+
+/// ボットの稼働状態。[`BitbankBotRuntime::health`](crate::bitbank_bot::BitbankBotRuntime::health)で取得する。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BotHealth {
+    /// 戦略とすべてのフィードが動いている。
+    Running,
+    /// 戦略は動いているが、panicした、または予期せず終了したフィードがある。
+    /// `feeds`は再起動待ち、再起動してからまだ接続できていない、または再起動をあきらめたフィード。
+    Degraded { feeds: Vec<String> },
+    /// 戦略が停止した。
+    Stopped,
+    /// 戦略がpanicして停止した。`message`はpanicのメッセージ。
+    Failed { message: String },
+}
+
+impl BotHealth {
+    pub fn is_stopped(&self) -> bool {
+        matches!(self, BotHealth::Stopped | BotHealth::Failed { .. })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+enum ActorState {
+    #[default]
+    Running,
+    Stopped,
+    Failed(String),
+}
+
+#[derive(Debug, Clone, Default)]
+struct HealthState {
+    actor: ActorState,
+    down_feeds: BTreeSet<String>,
+}
+
+impl HealthState {
+    fn health(&self) -> BotHealth {
+        match &self.actor {
+            ActorState::Stopped => BotHealth::Stopped,
+            ActorState::Failed(message) => BotHealth::Failed {
+                message: message.clone(),
+            },
+            ActorState::Running if self.down_feeds.is_empty() => BotHealth::Running,
+            ActorState::Running => BotHealth::Degraded {
+                feeds: self.down_feeds.iter().cloned().collect(),
+            },
+        }
+    }
+}
+
+/// アクターとフィードタスクが稼働状態を書き込む先。cloneしたものは同じ状態を共有する。
+#[derive(Debug, Clone)]
+pub(crate) struct HealthMonitor {
+    state: watch::Sender<HealthState>,
+}
+
+impl HealthMonitor {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: watch::Sender::new(HealthState::default()),
+        }
+    }
+
+    pub(crate) fn health(&self) -> BotHealth {
+        self.state.borrow().health()
+    }
+
+    // 戦略が停止するまで待ち、最終的な状態を返す。
+    pub(crate) async fn stopped(&self) -> BotHealth {
+        let mut rx = self.state.subscribe();
+        let res = rx
+            .wait_for(|state| !matches!(state.actor, ActorState::Running))
+            .await
+            .map(|state| state.health());
+        // 送信側は`self`が持っているので、閉じることはない
+        res.unwrap_or(BotHealth::Stopped)
+    }
+
+    pub(crate) fn actor_stopped(&self) {
+        self.state.send_modify(|state| {
+            if matches!(state.actor, ActorState::Running) {
+                state.actor = ActorState::Stopped;
+            }
+        });
+    }
+
+    pub(crate) fn actor_failed(&self, message: String) {
+        self.state
+            .send_modify(|state| state.actor = ActorState::Failed(message));
+    }
+
+    fn feed_down(&self, name: &str) {
+        self.state.send_modify(|state| {
+            state.down_feeds.insert(name.to_owned());
+        });
+    }
+
+    fn feed_up(&self, name: &str) {
+        self.state.send_modify(|state| {
+            state.down_feeds.remove(name);
+        });
+    }
+}
+
+/// `future`の実行中に起きたpanicを`Err`として返すFuture。[`catch_unwind`]で作る。
+pub(crate) struct CatchUnwind<F: Future> {
+    future: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for CatchUnwind<F> {
+    type Output = Result<F::Output, Box<dyn Any + Send>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let future = self.future.as_mut();
+        match std::panic::catch_unwind(AssertUnwindSafe(|| future.poll(cx))) {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Err(payload) => Poll::Ready(Err(payload)),
+        }
+    }
+}
+
+// panicした後の状態を使い続けるかどうかは呼び出し側の責任で判断すること。
+pub(crate) fn catch_unwind<F: Future>(future: F) -> CatchUnwind<F> {
+    CatchUnwind {
+        future: Box::pin(future),
+    }
+}
+
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic payload".to_owned()
+    }
+}
+
+// ドロップされたときにタスクを中止する。監視タスクが中止されたときに、監視していたタスクも止めるため。
+struct AbortOnDrop(tokio::task::AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// 監視中のフィードが接続できたことを[`HealthMonitor`]に伝えるハンドル。[`supervise_feed`]がフィードごとに渡す。
+#[derive(Debug, Clone)]
+pub(crate) struct FeedStatus {
+    health: HealthMonitor,
+    name: String,
+}
+
+impl FeedStatus {
+    // 接続・再接続できたときに呼ぶ。再起動したフィードはこれが呼ばれるまで稼働中に戻らない。
+    pub(crate) fn connected(&self) {
+        self.health.feed_up(&self.name);
+    }
+}
+
+/// フィードタスクが終了した理由。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FeedExit {
+    /// 戦略側のチャネルが閉じた。ランタイムが停止したので再起動しない。
+    DownstreamClosed,
+    /// 接続に失敗した、または再接続をあきらめたなどで、入力が終わった。
+    Ended,
+}
+
+// `spawn_feed`で作ったフィードを動かし、panicしたり入力が終わったりしたら`restart`に従って作り直す。
+// 下流のチャネルが閉じた場合と、監視タスクが中止された（ランタイムの停止）場合だけは作り直さずに終了する。
+// 止まったフィードは、作り直したフィードが[`FeedStatus::connected`]を呼ぶまで停止中として扱う。
+pub(crate) async fn supervise_feed<F, Fut>(
+    name: String,
+    restart: RetryPolicy,
+    health: HealthMonitor,
+    spawn_feed: F,
+) where
+    F: Fn(FeedStatus) -> Fut,
+    Fut: Future<Output = FeedExit> + Send + 'static,
+{
+    let status = FeedStatus {
+        health: health.clone(),
+        name: name.clone(),
+    };
+    let mut attempt = 0;
+
+    loop {
+        let started = Instant::now();
+        let feed = tokio::spawn(spawn_feed(status.clone()));
+        let _guard = AbortOnDrop(feed.abort_handle());
+
+        let reason = match feed.await {
+            Ok(FeedExit::DownstreamClosed) => return,
+            Ok(FeedExit::Ended) => "ended unexpectedly".to_owned(),
+            Err(err) if err.is_cancelled() => return,
+            Err(err) => format!("panicked: {}", panic_message(&*err.into_panic())),
+        };
+
+        health.feed_down(&name);
+        if started.elapsed() >= restart.max_backoff {
+            attempt = 0;
+        }
+        if attempt >= restart.max_retries {
+            log::error!(
+                "feed {} {}. giving up after {} restarts",
+                name,
+                reason,
+                attempt
+            );
+            return;
+        }
+
+        let backoff = restart.backoff(attempt);
+        log::error!("feed {} {}. restarting after {:?}", name, reason, backoff);
+        tokio::time::sleep(backoff).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::sync::Notify;
+
+    #[tokio::test]
+    async fn catch_unwind_returns_panic_message() {
+        let res = catch_unwind(async {
+            tokio::task::yield_now().await;
+            panic!("boom");
+        })
+        .await;
+        assert_eq!(panic_message(&*res.unwrap_err()), "boom");
+
+        assert_eq!(catch_unwind(async { 42 }).await.unwrap(), 42);
+    }
+
+    fn crash_feed() -> FeedExit {
+        panic!("feed crashed");
+    }
+
+    #[tokio::test]
+    async fn supervise_feed_restarts_ended_feed_until_downstream_closes() {
+        let health = HealthMonitor::new();
+        let runs = Arc::new(AtomicU32::new(0));
+        let restart = RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_secs(10),
+            ..RetryPolicy::exponential(5)
+        };
+
+        let feed_runs = runs.clone();
+        supervise_feed(
+            "btc_jpy".to_owned(),
+            restart,
+            health.clone(),
+            move |status| {
+                let runs = feed_runs.clone();
+                async move {
+                    // 2回は接続に失敗して終わり、3回目は接続できてから戦略が停止する
+                    match runs.fetch_add(1, Ordering::SeqCst) {
+                        0 | 1 => FeedExit::Ended,
+                        _ => {
+                            status.connected();
+                            FeedExit::DownstreamClosed
+                        }
+                    }
+                }
+            },
+        )
+        .await;
+
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        assert_eq!(health.health(), BotHealth::Running);
+    }
+
+    #[tokio::test]
+    async fn supervise_feed_keeps_restarted_feed_down_until_connected() {
+        let health = HealthMonitor::new();
+        let runs = Arc::new(AtomicU32::new(0));
+        let started = Arc::new(Notify::new());
+        let connect = Arc::new(Notify::new());
+        let restart = RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_secs(10),
+            ..RetryPolicy::exponential(5)
+        };
+
+        let feed_runs = runs.clone();
+        let feed_started = started.clone();
+        let feed_connect = connect.clone();
+        let supervisor = tokio::spawn(supervise_feed(
+            "btc_jpy".to_owned(),
+            restart,
+            health.clone(),
+            move |status| {
+                let runs = feed_runs.clone();
+                let started = feed_started.clone();
+                let connect = feed_connect.clone();
+                async move {
+                    if runs.fetch_add(1, Ordering::SeqCst) == 0 {
+                        return FeedExit::Ended;
+                    }
+                    started.notify_one();
+                    connect.notified().await;
+                    status.connected();
+                    FeedExit::DownstreamClosed
+                }
+            },
+        ));
+
+        // 再起動しただけでは、まだ接続できていないので停止中のまま
+        started.notified().await;
+        assert_eq!(
+            health.health(),
+            BotHealth::Degraded {
+                feeds: vec!["btc_jpy".to_owned()]
+            }
+        );
+
+        connect.notify_one();
+        supervisor.await.unwrap();
+        assert_eq!(health.health(), BotHealth::Running);
+    }
+
+    #[tokio::test]
+    async fn supervise_feed_restarts_panicked_feed_until_limit() {
+        let health = HealthMonitor::new();
+        let runs = Arc::new(AtomicU32::new(0));
+        let restart = RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_secs(10),
+            ..RetryPolicy::exponential(2)
+        };
+
+        let feed_runs = runs.clone();
+        supervise_feed("btc_jpy".to_owned(), restart, health.clone(), move |_| {
+            let runs = feed_runs.clone();
+            async move {
+                runs.fetch_add(1, Ordering::SeqCst);
+                crash_feed()
+            }
+        })
+        .await;
+
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        assert_eq!(
+            health.health(),
+            BotHealth::Degraded {
+                feeds: vec!["btc_jpy".to_owned()]
+            }
+        );
+
+        health.actor_failed("strategy crashed".to_owned());
+        assert_eq!(
+            health.stopped().await,
+            BotHealth::Failed {
+                message: "strategy crashed".to_owned()
+            }
+        );
+    }
+}
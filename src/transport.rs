@@ -0,0 +1,83 @@
+use std::{future::Future, pin::Pin};
+
+use crypto_botters::{
+    bitbank::{BitbankHandleError, BitbankOption},
+    Client,
+};
+
+use crate::bitbank_structs::BitbankApiResponse;
+use crate::error::BitbankError;
+
+pub type TransportFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<BitbankApiResponse, BitbankError>> + Send + 'a>>;
+
+/// REST APIクライアントがリクエストを送る先。
+/// `path`は`/user/assets`のようなbitbankのパスで、ホスト名やバージョンは含まない。
+/// 実際のbitbankに送る[`CryptoBottersTransport`]の代わりに、テスト用のモックなどを差し込める。
+pub trait BitbankTransport: Send + Sync + 'static {
+    fn get<'a>(
+        &'a self,
+        path: &'a str,
+        query: Option<&'a serde_json::Value>,
+    ) -> TransportFuture<'a>;
+
+    fn post<'a>(
+        &'a self,
+        path: &'a str,
+        body: Option<&'a serde_json::Value>,
+    ) -> TransportFuture<'a>;
+}
+
+/// crypto-bottersの`Client`でbitbankにリクエストを送る。認証やURLは`Client`のデフォルトオプションに従う。
+pub struct CryptoBottersTransport {
+    client: Client,
+}
+
+impl CryptoBottersTransport {
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+}
+
+impl BitbankTransport for CryptoBottersTransport {
+    fn get<'a>(
+        &'a self,
+        path: &'a str,
+        query: Option<&'a serde_json::Value>,
+    ) -> TransportFuture<'a> {
+        Box::pin(async move {
+            let res: Result<
+                BitbankApiResponse,
+                crypto_botters::generic_api_client::http::RequestError<&str, BitbankHandleError>,
+            > = match query {
+                Some(query) => {
+                    self.client
+                        .get(path, Some(query), [BitbankOption::Default])
+                        .await
+                }
+                None => {
+                    self.client
+                        .get_no_query(path, [BitbankOption::Default])
+                        .await
+                }
+            };
+
+            res.map_err(BitbankError::from)
+        })
+    }
+
+    fn post<'a>(
+        &'a self,
+        path: &'a str,
+        body: Option<&'a serde_json::Value>,
+    ) -> TransportFuture<'a> {
+        Box::pin(async move {
+            let res: Result<
+                BitbankApiResponse,
+                crypto_botters::generic_api_client::http::RequestError<&str, BitbankHandleError>,
+            > = self.client.post(path, body, [BitbankOption::Default]).await;
+
+            res.map_err(BitbankError::from)
+        })
+    }
+}
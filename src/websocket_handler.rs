@@ -4,17 +4,284 @@ use crate::bitbank_structs::{
     BitbankCircuitBreakInfo, BitbankDepthDiff, BitbankDepthWhole, BitbankTickerResponse,
     BitbankTransactionsData,
 };
+use crate::recorder::{MarketDataRecorder, RecordedPayload};
 use crypto_botters::{
     bitbank::BitbankOption, generic_api_client::websocket::WebSocketConfig, Client,
 };
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::RuntimeFlavor;
 use tokio::sync::mpsc;
 
+/// WebSocketが再接続中かどうかを確認する間隔。
+const RECONNECT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// 受信したメッセージを`tx`に送るまで溜めておける数。
+/// 板の差分はおおむね毎秒数十件なので、数十秒分に相当する。
+const RAW_MESSAGE_BUFFER: usize = 1024;
+
+// 捨てると戦略の状態を取り戻せないメッセージ。バッファが一杯でも、空きができるまで待って送る。
+// 板の差分などは捨てても、全体板を取り直せば元に戻る。
+fn must_deliver(message: &BitbankInboundMessage) -> bool {
+    matches!(
+        message,
+        BitbankInboundMessage::Transactions(_) | BitbankInboundMessage::CircuitBreakInfo(_)
+    )
+}
+
+// 空きができるまでこのスレッドを止めて`tx`に送る。非同期のコールバックから呼べるよう、マルチスレッドの
+// ランタイムでは`block_in_place`を使う。current_threadのランタイムでは止められないので`message`を返す。
+fn send_blocking(
+    tx: &mpsc::Sender<BitbankInboundMessage>,
+    message: BitbankInboundMessage,
+) -> Result<(), BitbankInboundMessage> {
+    match tokio::runtime::Handle::try_current() {
+        Err(_) => {
+            let _ = tx.blocking_send(message);
+            Ok(())
+        }
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            let _ = tokio::task::block_in_place(|| tx.blocking_send(message));
+            Ok(())
+        }
+        Ok(_) => Err(message),
+    }
+}
+
+/// WebSocketで受け取ったメッセージを解釈できなかった理由。
+/// `raw`には受け取ったJSONをそのまま入れるので、bitbank側のスキーマ変更などを後から調べられる。
+#[derive(Debug, Clone, PartialEq)]
+pub enum WebSocketDecodeError {
+    /// `["message", {"room_name": ..., "message": {"data": ...}}]`の形になっていない。
+    InvalidEnvelope {
+        message: String,
+        raw: serde_json::Value,
+    },
+    /// room_nameは分かったが、`data`をそのroomの型に変換できなかった。
+    InvalidPayload {
+        room_name: String,
+        message: String,
+        raw: serde_json::Value,
+    },
+    /// 購読しているどのroomにも当てはまらないroom_name。
+    UnknownRoom {
+        room_name: String,
+        raw: serde_json::Value,
+    },
+}
+
+impl WebSocketDecodeError {
+    pub fn room_name(&self) -> Option<&str> {
+        match self {
+            WebSocketDecodeError::InvalidEnvelope { .. } => None,
+            WebSocketDecodeError::InvalidPayload { room_name, .. }
+            | WebSocketDecodeError::UnknownRoom { room_name, .. } => Some(room_name),
+        }
+    }
+
+    pub fn raw(&self) -> &serde_json::Value {
+        match self {
+            WebSocketDecodeError::InvalidEnvelope { raw, .. }
+            | WebSocketDecodeError::InvalidPayload { raw, .. }
+            | WebSocketDecodeError::UnknownRoom { raw, .. } => raw,
+        }
+    }
+}
+
+impl fmt::Display for WebSocketDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebSocketDecodeError::InvalidEnvelope { message, .. } => {
+                write!(f, "invalid websocket message: {}", message)
+            }
+            WebSocketDecodeError::InvalidPayload {
+                room_name, message, ..
+            } => write!(f, "failed to decode {} message: {}", room_name, message),
+            WebSocketDecodeError::UnknownRoom { room_name, .. } => {
+                write!(f, "unknown room name: {}", room_name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for WebSocketDecodeError {}
+
+/// WebSocketで受け取ったメッセージの件数。クローンしたものは同じカウンタを共有する。
+#[derive(Debug, Clone, Default)]
+pub struct WebSocketMetrics {
+    inner: Arc<WebSocketMetricsInner>,
+}
+
+#[derive(Debug, Default)]
+struct WebSocketMetricsInner {
+    decoded: AtomicU64,
+    decode_errors: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// [`WebSocketMetrics`]のある時点の値。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WebSocketStats {
+    /// 解釈できたメッセージの数。
+    pub decoded: u64,
+    /// 解釈できなかったメッセージの数。
+    pub decode_errors: u64,
+    /// 受け手が追いつかず、溜めておける数を超えたために捨てたメッセージの数。約定などは
+    /// マルチスレッドのランタイムでは捨てずに空きを待つので含まれない。
+    pub dropped: u64,
+}
+
+impl WebSocketMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stats(&self) -> WebSocketStats {
+        WebSocketStats {
+            decoded: self.inner.decoded.load(Ordering::Relaxed),
+            decode_errors: self.inner.decode_errors.load(Ordering::Relaxed),
+            dropped: self.inner.dropped.load(Ordering::Relaxed),
+        }
+    }
+
+    fn decoded(&self) {
+        self.inner.decoded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn decode_failed(&self) -> u64 {
+        self.inner.decode_errors.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    fn dropped(&self) -> u64 {
+        self.inner.dropped.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+/// WebSocketの生メッセージを[`BitbankInboundMessage`]に振り分ける。
+/// 解釈できなかったメッセージは[`BitbankInboundMessage::DecodeError`]として返し、件数を[`WebSocketMetrics`]に数える。
+#[derive(Debug, Default)]
+pub struct WebSocketDispatcher {
+    metrics: WebSocketMetrics,
+}
+
+impl WebSocketDispatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 件数を`metrics`に数えるディスパッチャを作る。
+    pub fn with_metrics(metrics: WebSocketMetrics) -> Self {
+        Self { metrics }
+    }
+
+    pub fn metrics(&self) -> &WebSocketMetrics {
+        &self.metrics
+    }
+
+    pub fn dispatch(&self, val: serde_json::Value) -> BitbankInboundMessage {
+        match decode_websocket_message(val) {
+            Ok(message) => {
+                self.metrics.decoded();
+                message
+            }
+            Err(err) => {
+                log::warn!(
+                    "failed to decode websocket message ({} errors so far): {}",
+                    self.metrics.decode_failed(),
+                    err
+                );
+                BitbankInboundMessage::DecodeError(err)
+            }
+        }
+    }
+}
+
+// `["message", {"room_name": ..., "message": {"data": ...}}]`を解釈する。パニックしない。
+pub fn decode_websocket_message(
+    val: serde_json::Value,
+) -> Result<BitbankInboundMessage, WebSocketDecodeError> {
+    let Some(body) = val.get(1) else {
+        return Err(WebSocketDecodeError::InvalidEnvelope {
+            message: "missing message body".to_owned(),
+            raw: val,
+        });
+    };
+
+    let ws_msg: BitbankWebSocketMessage = match serde_json::from_value(body.clone()) {
+        Ok(ws_msg) => ws_msg,
+        Err(err) => {
+            return Err(WebSocketDecodeError::InvalidEnvelope {
+                message: err.to_string(),
+                raw: val,
+            })
+        }
+    };
+    let room_name = ws_msg.room_name;
+    let data = ws_msg.message.data;
+
+    // room_nameに応じてディスパッチする
+    if room_name.starts_with("ticker") {
+        decode_payload::<BitbankTickerResponse>(room_name, data).map(BitbankInboundMessage::Ticker)
+    } else if room_name.starts_with("transactions") {
+        decode_payload::<BitbankTransactionsData>(room_name, data)
+            .map(|data| BitbankInboundMessage::Transactions(data.transactions))
+    } else if room_name.starts_with("depth_diff") {
+        decode_payload::<BitbankDepthDiff>(room_name, data).map(BitbankInboundMessage::DepthDiff)
+    } else if room_name.starts_with("depth_whole") {
+        decode_payload::<BitbankDepthWhole>(room_name, data).map(BitbankInboundMessage::DepthWhole)
+    } else if room_name.starts_with("circuit_break_info") {
+        decode_payload::<BitbankCircuitBreakInfo>(room_name, data)
+            .map(BitbankInboundMessage::CircuitBreakInfo)
+    } else {
+        Err(WebSocketDecodeError::UnknownRoom {
+            room_name,
+            raw: data,
+        })
+    }
+}
+
+fn decode_payload<T: DeserializeOwned>(
+    room_name: String,
+    data: serde_json::Value,
+) -> Result<T, WebSocketDecodeError> {
+    match serde_json::from_value(data.clone()) {
+        Ok(payload) => Ok(payload),
+        Err(err) => Err(WebSocketDecodeError::InvalidPayload {
+            room_name,
+            message: err.to_string(),
+            raw: data,
+        }),
+    }
+}
+
 pub async fn run_websocket(
     pair: String,
     client_options: Vec<BitbankOption>,
     wsc: WebSocketConfig,
     tx: mpsc::Sender<BitbankInboundMessage>,
 ) {
+    run_websocket_with_recorder(pair, client_options, wsc, tx, None, WebSocketMetrics::new()).await
+}
+
+// [`run_websocket`]と同じだが、受け取った生のメッセージと接続状態の変化を`recorder`にも記録し、
+// メッセージの件数を`metrics`に数える。
+pub async fn run_websocket_with_recorder(
+    pair: String,
+    client_options: Vec<BitbankOption>,
+    wsc: WebSocketConfig,
+    tx: mpsc::Sender<BitbankInboundMessage>,
+    recorder: Option<MarketDataRecorder>,
+    metrics: WebSocketMetrics,
+) {
+    let record = |payload: RecordedPayload| {
+        if let Some(recorder) = &recorder {
+            recorder.record(&pair, payload);
+        }
+    };
+
     let mut ws_client = Client::new();
 
     for option in client_options {
@@ -31,89 +298,44 @@ pub async fn run_websocket(
         format!("circuit_break_info_{}", pair).to_owned(),
     ];
 
-    let _transactions_connection = ws_client
+    let dispatcher = WebSocketDispatcher::with_metrics(metrics);
+    // コールバックは同期なので、いったん上限のあるチャネルに積み、このタスクが順番どおりに`tx`へ送る。
+    // 受け手が追いつかずチャネルが一杯になった場合、約定などは空きができるまでWebSocketの受信を止めて待つ。
+    // 板の差分などは捨て、抜けたことを`overflowed`で知らせる。このタスクは切断・再接続と同じメッセージを送り、板を作り直させる。
+    let (raw_tx, mut raw_rx) = mpsc::channel(RAW_MESSAGE_BUFFER);
+    let overflowed = Arc::new(AtomicBool::new(false));
+    let callback_overflowed = overflowed.clone();
+    let callback_recorder = recorder.clone();
+    let callback_pair = pair.clone();
+
+    let connection = ws_client
         .websocket(
             "",
             move |val: serde_json::Value| {
-                let ws_msg: BitbankWebSocketMessage =
-                    serde_json::from_value(val[1].clone()).unwrap();
-                let room_name = ws_msg.room_name;
-
-                // room_nameに応じてディスパッチする
-                if room_name.starts_with("ticker") {
-                    let ticker: BitbankTickerResponse =
-                        serde_json::from_value(ws_msg.message.data).unwrap();
-                    let tx2 = tx.clone();
-                    tokio::spawn(async move {
-                        if tx2
-                            .send(BitbankInboundMessage::Ticker(ticker))
-                            .await
-                            .is_err()
-                        {
-                            log::debug!("dropping ticker message; receiver hung up");
-                        }
-                    });
-                } else if room_name.starts_with("transactions") {
-                    let transaction_message: BitbankTransactionsData =
-                        serde_json::from_value(ws_msg.message.data).unwrap();
-                    let transactions = transaction_message.transactions;
-
-                    let tx2 = tx.clone();
-                    tokio::spawn(async move {
-                        if tx2
-                            .send(BitbankInboundMessage::Transactions(transactions))
-                            .await
-                            .is_err()
-                        {
-                            log::debug!("dropping transactions message; receiver hung up");
-                        }
-                    });
-                } else if room_name.starts_with("depth_diff") {
-                    let depth_diff_message: BitbankDepthDiff =
-                        serde_json::from_value(ws_msg.message.data).unwrap();
-
-                    let tx2 = tx.clone();
-
-                    // `move`なしではtx2は借用されるが、`move`を追加すると、tx2はこのクロージャに移動される。
-                    tokio::spawn(async move {
-                        if tx2
-                            .send(BitbankInboundMessage::DepthDiff(depth_diff_message))
-                            .await
-                            .is_err()
-                        {
-                            log::debug!("dropping depth diff message; receiver hung up");
-                        }
-                    });
-                } else if room_name.starts_with("depth_whole") {
-                    let depth_whole_message: BitbankDepthWhole =
-                        serde_json::from_value(ws_msg.message.data).unwrap();
-                    let tx2 = tx.clone();
-
-                    // `move`なしではtx2は借用されるが、`move`を追加すると、tx2はこのクロージャに移動される。
-                    tokio::spawn(async move {
-                        if tx2
-                            .send(BitbankInboundMessage::DepthWhole(depth_whole_message))
-                            .await
-                            .is_err()
-                        {
-                            log::debug!("dropping depth snapshot; receiver hung up");
-                        }
-                    });
-                } else if room_name.starts_with("circuit_break_info") {
-                    let circuit_break_info: BitbankCircuitBreakInfo =
-                        serde_json::from_value(ws_msg.message.data).unwrap();
-                    let tx2 = tx.clone();
-                    tokio::spawn(async move {
-                        if tx2
-                            .send(BitbankInboundMessage::CircuitBreakInfo(circuit_break_info))
-                            .await
-                            .is_err()
-                        {
-                            log::debug!("dropping circuit break info; receiver hung up");
-                        }
-                    });
-                } else {
-                    panic!("unknown room name: {}", room_name);
+                if let Some(recorder) = &callback_recorder {
+                    recorder.record(&callback_pair, RecordedPayload::WebSocket(val.clone()));
+                }
+                let message = match raw_tx.try_send(dispatcher.dispatch(val)) {
+                    Err(mpsc::error::TrySendError::Full(message)) => message,
+                    _ => return,
+                };
+                if must_deliver(&message) {
+                    if send_blocking(&raw_tx, message).is_ok() {
+                        return;
+                    }
+                    log::error!(
+                        "cannot wait for websocket buffer for {} on a current_thread runtime",
+                        callback_pair
+                    );
+                }
+
+                let dropped = dispatcher.metrics().dropped();
+                if !callback_overflowed.swap(true, Ordering::Relaxed) {
+                    log::warn!(
+                        "websocket buffer for {} is full. dropping messages ({} so far)",
+                        callback_pair,
+                        dropped
+                    );
                 }
             },
             [
@@ -121,11 +343,171 @@ pub async fn run_websocket(
                 BitbankOption::WebSocketConfig(wsc),
             ],
         )
-        .await
-        .unwrap();
+        .await;
+
+    let connection = match connection {
+        Ok(connection) => connection,
+        Err(err) => {
+            log::error!("failed to connect websocket for {}: {:?}", pair, err);
+            record(RecordedPayload::Disconnected);
+            let _ = tx.send(BitbankInboundMessage::Disconnected).await;
+            return;
+        }
+    };
+
+    record(RecordedPayload::Connected);
+    if tx.send(BitbankInboundMessage::Connected).await.is_err() {
+        return;
+    }
 
-    // スリープ
+    // 受け取ったメッセージを送りつつ、再接続中かどうかを監視し、変化したらライフサイクルのメッセージを送る
+    let mut reconnecting = false;
+    let mut reconnect_poll = tokio::time::interval(RECONNECT_POLL_INTERVAL);
     loop {
-        tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
+        // メッセージを捨てた場合は板の差分が抜けているので、再接続したときと同じように板を作り直させる。
+        // チャネルが一杯になっている間は`recv`がすぐに返るので、ここで必ず気付ける。
+        if overflowed.swap(false, Ordering::Relaxed) {
+            log::warn!("resyncing {} after dropping websocket messages", pair);
+            for message in [
+                BitbankInboundMessage::Disconnected,
+                BitbankInboundMessage::Reconnected,
+            ] {
+                if tx.send(message).await.is_err() {
+                    return;
+                }
+            }
+        }
+
+        let message = tokio::select! {
+            message = raw_rx.recv() => match message {
+                Some(message) => message,
+                None => return,
+            },
+            _ = reconnect_poll.tick() => {
+                let is_reconnecting = connection.reconnect_state().is_reconnecting();
+                if is_reconnecting == reconnecting {
+                    continue;
+                }
+                reconnecting = is_reconnecting;
+
+                if reconnecting {
+                    log::warn!("websocket for {} is reconnecting", pair);
+                    record(RecordedPayload::Disconnected);
+                    BitbankInboundMessage::Disconnected
+                } else {
+                    log::info!("websocket for {} reconnected", pair);
+                    record(RecordedPayload::Reconnected);
+                    BitbankInboundMessage::Reconnected
+                }
+            }
+        };
+
+        if tx.send(message).await.is_err() {
+            log::debug!("stopping websocket for {}; receiver hung up", pair);
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn envelope(room_name: &str, data: serde_json::Value) -> serde_json::Value {
+        json!(["message", {"room_name": room_name, "message": {"data": data}}])
+    }
+
+    #[test]
+    fn decodes_known_room() {
+        let message = decode_websocket_message(envelope(
+            "ticker_btc_jpy",
+            json!({
+                "sell": "101", "buy": "100", "open": "90", "high": "110", "low": "80",
+                "last": "100", "vol": "1.5", "timestamp": 1234
+            }),
+        ))
+        .unwrap();
+
+        let BitbankInboundMessage::Ticker(ticker) = message else {
+            panic!("expected ticker, got {:?}", message);
+        };
+        assert_eq!(ticker.last, "100");
+    }
+
+    #[test]
+    fn reports_unknown_room_and_invalid_payload() {
+        let err =
+            decode_websocket_message(envelope("new_room_btc_jpy", json!({"a": 1}))).unwrap_err();
+        assert_eq!(
+            err,
+            WebSocketDecodeError::UnknownRoom {
+                room_name: "new_room_btc_jpy".to_owned(),
+                raw: json!({"a": 1}),
+            }
+        );
+
+        let err =
+            decode_websocket_message(envelope("depth_diff_btc_jpy", json!({"a": []}))).unwrap_err();
+        assert!(matches!(err, WebSocketDecodeError::InvalidPayload { .. }));
+        assert_eq!(err.room_name(), Some("depth_diff_btc_jpy"));
+
+        let err = decode_websocket_message(json!({"unexpected": true})).unwrap_err();
+        assert!(matches!(err, WebSocketDecodeError::InvalidEnvelope { .. }));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn send_blocking_waits_for_capacity() {
+        let (tx, mut rx) = mpsc::channel(1);
+        tx.try_send(BitbankInboundMessage::Connected).unwrap();
+
+        // 一杯のチャネルに送るので、受け手が読むまで戻らない
+        let sender = tokio::spawn(async move {
+            let sent = send_blocking(&tx, BitbankInboundMessage::Reconnected);
+            sent.is_ok()
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!sender.is_finished());
+
+        assert!(matches!(
+            rx.recv().await,
+            Some(BitbankInboundMessage::Connected)
+        ));
+        assert!(sender.await.unwrap());
+        assert!(matches!(
+            rx.recv().await,
+            Some(BitbankInboundMessage::Reconnected)
+        ));
+    }
+
+    #[tokio::test]
+    async fn send_blocking_gives_up_on_current_thread_runtime() {
+        let (tx, _rx) = mpsc::channel(1);
+        tx.try_send(BitbankInboundMessage::Connected).unwrap();
+        assert!(matches!(
+            send_blocking(&tx, BitbankInboundMessage::Reconnected),
+            Err(BitbankInboundMessage::Reconnected)
+        ));
+    }
+
+    #[test]
+    fn dispatcher_counts_failures_instead_of_panicking() {
+        let metrics = WebSocketMetrics::new();
+        let dispatcher = WebSocketDispatcher::with_metrics(metrics.clone());
+
+        let message = dispatcher.dispatch(json!(["message", {"room_name": 1}]));
+        assert!(matches!(message, BitbankInboundMessage::DecodeError(_)));
+        dispatcher.dispatch(envelope("unknown", json!(null)));
+
+        // ディスパッチャを手放した後も、共有している`metrics`から件数を読める
+        drop(dispatcher);
+        assert_eq!(
+            metrics.stats(),
+            WebSocketStats {
+                decoded: 0,
+                decode_errors: 2,
+                dropped: 0,
+            }
+        );
     }
 }
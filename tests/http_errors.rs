@@ -0,0 +1,71 @@
+// crypto-bottersが返す通信エラーが、HTTPステータスに応じた`BitbankError`になることを確認する。
+// ローカルにHTTPサーバーを立てて、実際のreqwestのエラーを作る。
+
+use std::io::{Read, Write};
+use std::net::TcpListener;
+
+use bitbankutil_rs::error::BitbankError;
+
+type RequestError = crypto_botters::generic_api_client::http::RequestError<
+    &'static str,
+    crypto_botters::bitbank::BitbankHandleError,
+>;
+
+// `statuses`を1つずつ、接続を受けた順に返すサーバーを立ててURLを返す。
+fn serve_statuses(statuses: &'static [&'static str]) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for status in statuses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request);
+            let response = format!(
+                "HTTP/1.1 {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                status
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    });
+    url
+}
+
+#[tokio::test]
+async fn http_error_statuses_map_to_bitbank_errors() {
+    let url = serve_statuses(&["429 Too Many Requests", "503 Service Unavailable"]);
+
+    let client = reqwest::Client::new();
+    let mut errors = Vec::new();
+    for _ in 0..2 {
+        let error = client
+            .get(&url)
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap_err();
+        errors.push(BitbankError::from(RequestError::ReceiveResponse(error)));
+    }
+    assert_eq!(errors[0], BitbankError::RateLimited);
+    assert!(matches!(
+        errors[1],
+        BitbankError::HttpStatus { status: 503, .. }
+    ));
+}
+
+#[tokio::test]
+async fn connection_failures_are_retryable_transport_errors() {
+    // 接続できなかった場合はステータスがないので通信エラーになる
+    let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+    let closed_url = format!("http://{}/", closed.local_addr().unwrap());
+    drop(closed);
+
+    let error = reqwest::Client::new()
+        .get(&closed_url)
+        .send()
+        .await
+        .unwrap_err();
+    let error = BitbankError::from(RequestError::SendRequest(error));
+    assert!(matches!(error, BitbankError::Transport(_)));
+    assert!(error.is_retryable());
+}
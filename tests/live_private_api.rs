@@ -1,11 +1,9 @@
-#![cfg(feature = "live-private-api")]
-
-use std::env;
+#![cfg(any(feature = "live-private-api", feature = "mock-server"))]
 
 use bitbankutil_rs::bitbank_private::BitbankPrivateApiClient;
 use bitbankutil_rs::bitbank_structs::{
     BitbankActiveOrdersResponse, BitbankAssetsData, BitbankChannelAndTokenResponse,
-    BitbankSpotStatusResponse, BitbankTradeHistoryResponse,
+    BitbankMarginPositionsResponse, BitbankSpotStatusResponse, BitbankTradeHistoryResponse,
 };
 
 fn logging_init() {
@@ -15,13 +13,20 @@ fn logging_init() {
         .try_init();
 }
 
+#[cfg(feature = "live-private-api")]
 fn init_client() -> BitbankPrivateApiClient {
-    let bitbank_key = env::var("BITBANK_API_KEY").unwrap();
-    let bitbank_secret = env::var("BITBANK_API_SECRET").unwrap();
+    let bitbank_key = std::env::var("BITBANK_API_KEY").unwrap();
+    let bitbank_secret = std::env::var("BITBANK_API_SECRET").unwrap();
 
     BitbankPrivateApiClient::new(bitbank_key, bitbank_secret, None)
 }
 
+// `live-private-api`を指定しない場合は、モックサーバーに対して実行する。
+#[cfg(not(feature = "live-private-api"))]
+fn init_client() -> BitbankPrivateApiClient {
+    bitbankutil_rs::mock_server::MockBitbankServer::new().private_client()
+}
+
 #[tokio::test]
 async fn test_private_get_assets() {
     logging_init();
@@ -74,3 +79,84 @@ async fn test_private_get_channel_and_token() {
         bb_client.get_channel_and_token().await.unwrap();
     log::info!("Bitbank channel and token: {:?}", channel_and_token);
 }
+
+#[tokio::test]
+async fn test_private_post_orders_info() {
+    logging_init();
+    let bb_client = init_client();
+
+    let active_orders_res: BitbankActiveOrdersResponse = bb_client
+        .get_active_orders(Some("btc_jpy"), None, None, None, None, None)
+        .await
+        .unwrap();
+    let order_ids = active_orders_res
+        .orders
+        .iter()
+        .map(|order| order.order_id.as_u64().unwrap())
+        .collect::<Vec<_>>();
+
+    let orders = bb_client
+        .post_orders_info("btc_jpy", order_ids.clone())
+        .await
+        .unwrap();
+    log::info!("orders info response: {:?}", orders);
+    assert_eq!(orders.len(), order_ids.len());
+}
+
+#[tokio::test]
+async fn test_private_get_margin_positions() {
+    logging_init();
+    let bb_client = init_client();
+
+    let positions: BitbankMarginPositionsResponse = bb_client.get_margin_positions().await.unwrap();
+    log::info!("Bitbank margin positions: {:?}", positions);
+}
+
+#[tokio::test]
+async fn test_private_get_deposit_and_withdrawal_history() {
+    logging_init();
+    let bb_client = init_client();
+
+    let deposits = bb_client
+        .get_deposit_history("btc", Some(10), None, None)
+        .await
+        .unwrap();
+    log::info!("Bitbank deposit history: {:?}", deposits);
+
+    let withdrawals = bb_client
+        .get_all_withdrawal_history("btc", None, None)
+        .await
+        .unwrap();
+    log::info!("Bitbank withdrawal history: {:?}", withdrawals);
+}
+
+#[tokio::test]
+async fn test_private_get_unconfirmed_deposits_and_originators() {
+    logging_init();
+    let bb_client = init_client();
+
+    let unconfirmed = bb_client.get_unconfirmed_deposits().await.unwrap();
+    log::info!("Bitbank unconfirmed deposits: {:?}", unconfirmed);
+
+    let originators = bb_client.get_deposit_originators().await.unwrap();
+    log::info!("Bitbank deposit originators: {:?}", originators);
+}
+
+#[tokio::test]
+async fn test_private_get_withdrawal_account() {
+    logging_init();
+    let bb_client = init_client();
+
+    let accounts = bb_client.get_withdrawal_account("btc").await.unwrap();
+    log::info!("Bitbank withdrawal accounts: {:?}", accounts);
+}
+
+#[tokio::test]
+async fn test_private_get_pairs() {
+    logging_init();
+    let bb_client = init_client();
+
+    let pairs = bb_client.get_pairs().await.unwrap();
+    let registry = bitbankutil_rs::pair_spec::PairRegistry::try_from(&pairs).unwrap();
+    log::info!("btc_jpy pair spec: {:?}", registry.spec("btc_jpy").unwrap());
+}
@@ -1,6 +1,6 @@
-#![cfg(feature = "live-private-order-api")]
+#![cfg(any(feature = "live-private-order-api", feature = "mock-server"))]
 
-use std::{env, time::Duration};
+use std::time::Duration;
 
 use bitbankutil_rs::bitbank_private::BitbankPrivateApiClient;
 use bitbankutil_rs::bitbank_structs::{
@@ -14,18 +14,26 @@ fn logging_init() {
         .try_init();
 }
 
+#[cfg(feature = "live-private-order-api")]
 fn init_client() -> BitbankPrivateApiClient {
     require_live_private_order_tests();
 
-    let bitbank_key = env::var("BITBANK_API_KEY").unwrap();
-    let bitbank_secret = env::var("BITBANK_API_SECRET").unwrap();
+    let bitbank_key = std::env::var("BITBANK_API_KEY").unwrap();
+    let bitbank_secret = std::env::var("BITBANK_API_SECRET").unwrap();
 
     BitbankPrivateApiClient::new(bitbank_key, bitbank_secret, None)
 }
 
+// `live-private-order-api`を指定しない場合は、モックサーバーに対して実行する。
+#[cfg(not(feature = "live-private-order-api"))]
+fn init_client() -> BitbankPrivateApiClient {
+    bitbankutil_rs::mock_server::MockBitbankServer::new().private_client()
+}
+
+#[cfg(feature = "live-private-order-api")]
 fn require_live_private_order_tests() {
     assert_eq!(
-        env::var("BITBANKUTIL_RUN_PRIVATE_ORDER_TESTS").as_deref(),
+        std::env::var("BITBANKUTIL_RUN_PRIVATE_ORDER_TESTS").as_deref(),
         Ok("1"),
         "set BITBANKUTIL_RUN_PRIVATE_ORDER_TESTS=1 to run order-mutating live tests"
     );
@@ -37,7 +45,16 @@ async fn test_private_get_order() {
     let bb_client = init_client();
 
     let post_order_res: BitbankCreateOrderResponse = bb_client
-        .post_order("btc_jpy", "1", Some("12"), "buy", "limit", Some(true), None)
+        .post_order(
+            "btc_jpy",
+            "1",
+            Some("12"),
+            "buy",
+            "limit",
+            Some(true),
+            None,
+            None,
+        )
         .await
         .unwrap();
     log::info!("post order: {:?}", post_order_res);
@@ -66,7 +83,16 @@ async fn test_private_post_cancel_order() {
     let bb_client = init_client();
 
     let post_order_res: BitbankCreateOrderResponse = bb_client
-        .post_order("btc_jpy", "1", Some("14"), "buy", "limit", Some(true), None)
+        .post_order(
+            "btc_jpy",
+            "1",
+            Some("14"),
+            "buy",
+            "limit",
+            Some(true),
+            None,
+            None,
+        )
         .await
         .unwrap();
 
@@ -88,14 +114,32 @@ async fn test_private_post_cancel_orders() {
     let bb_client = init_client();
 
     let post_order_res1: BitbankCreateOrderResponse = bb_client
-        .post_order("btc_jpy", "1", Some("12"), "buy", "limit", Some(true), None)
+        .post_order(
+            "btc_jpy",
+            "1",
+            Some("12"),
+            "buy",
+            "limit",
+            Some(true),
+            None,
+            None,
+        )
         .await
         .unwrap();
 
     log::info!("post_order_res1: {:?}", post_order_res1);
 
     let post_order_res2: BitbankCreateOrderResponse = bb_client
-        .post_order("btc_jpy", "1", Some("13"), "buy", "limit", Some(true), None)
+        .post_order(
+            "btc_jpy",
+            "1",
+            Some("13"),
+            "buy",
+            "limit",
+            Some(true),
+            None,
+            None,
+        )
         .await
         .unwrap();
 
@@ -127,7 +171,16 @@ async fn test_private_exceed_rate_limit() {
 
     for _ in 0..10 {
         let res = bb_client
-            .post_order("btc_jpy", "1", Some("12"), "buy", "limit", Some(true), None)
+            .post_order(
+                "btc_jpy",
+                "1",
+                Some("12"),
+                "buy",
+                "limit",
+                Some(true),
+                None,
+                None,
+            )
             .await
             .expect("post_order returned Err");
 
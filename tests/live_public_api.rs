@@ -1,4 +1,4 @@
-#![cfg(feature = "live-public-api")]
+#![cfg(any(feature = "live-public-api", feature = "mock-server"))]
 
 use bitbankutil_rs::bitbank_public::BitbankPublicApiClient;
 use bitbankutil_rs::bitbank_structs::BitbankDepth;
@@ -10,10 +10,21 @@ fn logging_init() {
         .try_init();
 }
 
+#[cfg(feature = "live-public-api")]
+fn init_client() -> BitbankPublicApiClient {
+    BitbankPublicApiClient::new()
+}
+
+// `live-public-api`を指定しない場合は、モックサーバーに対して実行する。
+#[cfg(not(feature = "live-public-api"))]
+fn init_client() -> BitbankPublicApiClient {
+    bitbankutil_rs::mock_server::MockBitbankServer::new().public_client()
+}
+
 #[tokio::test]
 async fn test_public_get_ticker() {
     logging_init();
-    let public_client = BitbankPublicApiClient::new();
+    let public_client = init_client();
     let res = public_client.get_ticker("eth_jpy").await;
 
     log::debug!("{:?}", res);
@@ -23,7 +34,7 @@ async fn test_public_get_ticker() {
 #[tokio::test]
 async fn test_public_get_tickers() {
     logging_init();
-    let public_client = BitbankPublicApiClient::new();
+    let public_client = init_client();
     let res = public_client.get_tickers().await;
     log::debug!("{:?}", res);
     assert!(res.is_ok());
@@ -32,7 +43,7 @@ async fn test_public_get_tickers() {
 #[tokio::test]
 async fn test_public_get_tickers_jpy() {
     logging_init();
-    let public_client = BitbankPublicApiClient::new();
+    let public_client = init_client();
     let res = public_client.get_tickers_jpy().await;
     log::debug!("{:?}", res);
     assert!(res.is_ok());
@@ -41,7 +52,7 @@ async fn test_public_get_tickers_jpy() {
 #[tokio::test]
 async fn test_public_get_transactions() {
     logging_init();
-    let public_client = BitbankPublicApiClient::new();
+    let public_client = init_client();
 
     let res_without_date = public_client.get_transactions("btc_jpy", None).await;
     log::debug!("{:?}", res_without_date);
@@ -57,7 +68,7 @@ async fn test_public_get_transactions() {
 #[tokio::test]
 async fn test_public_get_candlestick() {
     logging_init();
-    let public_client = BitbankPublicApiClient::new();
+    let public_client = init_client();
     let res = public_client
         .get_candlestick("btc_jpy", "1day", "2024")
         .await;
@@ -72,21 +83,30 @@ async fn test_public_get_candlestick() {
 #[tokio::test]
 async fn test_public_get_depth() {
     logging_init();
-    let public_client = BitbankPublicApiClient::new();
+    let public_client = init_client();
     let res = public_client.get_depth("eth_jpy").await;
 
     let mut depth = BitbankDepth::new();
-    depth.update_whole(res.unwrap());
+    depth.update_whole(res.unwrap()).unwrap();
     log::debug!("{}", depth);
 }
 
 #[tokio::test]
 async fn test_public_get_circuit_break_info() {
     logging_init();
-    let public_client = BitbankPublicApiClient::new();
+    let public_client = init_client();
     let res = public_client.get_circuit_break_info("eth_jpy").await;
     log::debug!("{:?}", res);
     assert!(res.is_ok());
     let circuit_break_info = res.unwrap();
     log::debug!("Circuit Break Info: {:?}", circuit_break_info);
 }
+
+#[tokio::test]
+async fn test_public_get_pairs() {
+    logging_init();
+    let public_client = init_client();
+    let pairs = public_client.get_pairs().await.unwrap();
+    let registry = bitbankutil_rs::pair_spec::PairRegistry::try_from(&pairs).unwrap();
+    log::debug!("btc_jpy pair spec: {:?}", registry.spec("btc_jpy").unwrap());
+}